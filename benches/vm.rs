@@ -0,0 +1,57 @@
+//! Run the Lox benchmarks in `benches/lox` through the VM, e.g. `cargo bench` or
+//! `cargo bench -- fib` for the ones whose name contains `fib`
+//!
+//! Each script runs once to warm up, then `RUNS` times in a new VM, and we report the fastest,
+//! the average and the slowest run. The output of the scripts is discarded
+use rustlox::VM;
+use std::time::{Duration, Instant};
+use std::{env, fs, io};
+
+const RUNS: usize = 5;
+
+fn run(source: &str) -> Duration {
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(io::sink()));
+    let started = Instant::now();
+    vm.interpret(source)
+        .expect("The benchmarks run without errors");
+    started.elapsed()
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, the other arguments filter the benchmarks by name
+    let filters: Vec<_> = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/lox");
+    let mut scripts: Vec<_> = fs::read_dir(dir)
+        .expect("The benchmarks directory exists")
+        .map(|entry| entry.expect("Read the benchmarks directory").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+
+    println!(
+        "{:<20} {:>12} {:>12} {:>12}",
+        "benchmark", "min (ms)", "mean (ms)", "max (ms)"
+    );
+    for path in scripts {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        let source = fs::read_to_string(&path).expect("Read the benchmark");
+        run(&source);
+        let times: Vec<_> = (0..RUNS).map(|_| run(&source)).collect();
+        let ms = |time: Duration| time.as_secs_f64() * 1e3;
+        let mean = times.iter().sum::<Duration>() / RUNS as u32;
+        println!(
+            "{:<20} {:>12.1} {:>12.1} {:>12.1}",
+            name,
+            ms(*times.iter().min().unwrap()),
+            ms(mean),
+            ms(*times.iter().max().unwrap())
+        );
+    }
+}
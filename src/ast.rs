@@ -0,0 +1,655 @@
+//! The syntax tree of a program, for the tools which need to see the whole code at once, e.g. to
+//! rewrite it, while the single-pass compiler only sees one token at a time. The tree keeps every
+//! token of the source code, so [`Compiler::compile_ast`] lowers it by replaying them through the
+//! compiler, which gives the same bytecode and the same lines and columns in the errors
+use crate::compiler::{Compiler, Precedence, Warnings};
+use crate::reporter::Diagnostic;
+use crate::scanner::{Scanner, Token, TokenKind, TokenType};
+use crate::shared::Rc;
+use crate::value::FunctionType;
+use std::fmt;
+
+/// What a node of the tree is. The comments show the children of the node in order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// The declarations, then the `Eof`
+    Program,
+    /// `class`, the name, maybe `<` and the superclass, `{`, the members, `}`
+    ClassDecl,
+    /// A method, a getter or a setter: maybe `set`, the name, the parameters unless it's a
+    /// getter, the body
+    Method,
+    /// `static`, the name, then `=`, the value and `;`, or the parameters and the body
+    StaticMember,
+    /// `fun`, the name, the parameters, the body
+    FunDecl,
+    /// `var`, the name, maybe `=` and the value, `;`
+    VarDecl,
+    /// `(`, the parameters separated by `,`, `)`
+    Parameters,
+    /// Maybe `...`, the name, maybe `=` and the default value
+    Parameter,
+    /// `{`, the declarations, `}`
+    Block,
+    /// `print`, the value, `;`
+    PrintStmt,
+    /// `import`, the path, `;`
+    ImportStmt,
+    /// `if`, `(`, the condition, `)`, the statement, maybe `else` and the other statement
+    IfStmt,
+    /// `while`, `(`, the condition, `)`, the body
+    WhileStmt,
+    /// `for`, `(`, the initializer or `;`, maybe the condition, `;`, maybe the increment, `)`,
+    /// the body
+    ForStmt,
+    /// `for`, `(`, maybe `var`, the name, `in`, the iterable, `)`, the body
+    ForInStmt,
+    /// `return`, maybe the value, `;`
+    ReturnStmt,
+    /// `break`, `;`
+    BreakStmt,
+    /// `continue`, `;`
+    ContinueStmt,
+    /// The expression, `;`
+    ExprStmt,
+    /// A number, a string, `true`, `false` or `nil`
+    Literal,
+    /// The name
+    Variable,
+    /// `this`
+    This,
+    /// `super`, `.`, the method name, maybe the arguments
+    Super,
+    /// `(`, the expression, `)`
+    Grouping,
+    /// The operator, the operand
+    Unary,
+    /// The left operand, the operator, the right operand
+    Binary,
+    /// The left operand, `and` or `or`, the right operand
+    Logical,
+    /// The value, `??`, the default
+    Coalesce,
+    /// The start, `..` or `..=`, the end
+    Range,
+    /// The variable, `=`, the value
+    Assign,
+    /// The object, `.`, the name
+    Get,
+    /// The object, `.`, the name, `=`, the value
+    Set,
+    /// The object, `.`, the name, the arguments
+    Invoke,
+    /// The callee, the arguments
+    Call,
+    /// `(`, the arguments separated by `,`, `)`
+    Arguments,
+    /// `[`, the elements separated by `,`, `]`
+    List,
+    /// `...`, the list spread into the arguments or the elements
+    Spread,
+    /// The list, `[`, the index, `]`
+    Index,
+    /// The list, `[`, the index, `]`, `=`, the value
+    SetIndex,
+    /// The list or the string, `[`, maybe the start, `:`, maybe the end, `]`
+    Slice,
+    /// `object?.name` and the rest of the chain after it, which is skipped when the object is
+    /// nil. The innermost `Get` or `Invoke` of the chain has the `?.` in place of the `.`
+    OptionalChain,
+    /// `++` or `--`, the operand
+    PrefixStep,
+    /// The operand, `++` or `--`
+    PostfixStep,
+    /// `fun`, the parameters, the body
+    Lambda,
+    /// `yield`, maybe the value
+    Yield,
+}
+
+#[derive(Clone, Debug)]
+pub enum Child {
+    Token(Token),
+    Node(Node),
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    /// The tokens and the nodes, in the order they are in the source code
+    pub children: Vec<Child>,
+}
+
+impl Node {
+    fn new(kind: NodeKind) -> Self {
+        Self {
+            kind,
+            children: vec![],
+        }
+    }
+
+    /// The child nodes, without the tokens
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|child| match child {
+            Child::Node(node) => Some(node),
+            Child::Token(_) => None,
+        })
+    }
+
+    /// The tokens of the node and the nodes in it, in order
+    fn collect_tokens(&self, tokens: &mut Vec<Token>) {
+        for child in &self.children {
+            match child {
+                Child::Token(token) => tokens.push(*token),
+                Child::Node(node) => node.collect_tokens(tokens),
+            }
+        }
+    }
+
+    fn push(&mut self, node: Node) {
+        self.children.push(Child::Node(node));
+    }
+}
+
+/// The tree of a whole program, with the source code its tokens refer to
+#[derive(Clone, Debug)]
+pub struct Ast {
+    source: Rc<str>,
+    pub root: Node,
+}
+
+impl Ast {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// All the tokens of the tree in order, up to the `Eof`
+    pub fn tokens(&self) -> Vec<Token> {
+        let mut tokens = vec![];
+        self.root.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn write_node(&self, f: &mut fmt::Formatter, node: &Node, depth: usize) -> fmt::Result {
+        write!(f, "{}{:?}", "  ".repeat(depth), node.kind)?;
+        for child in &node.children {
+            if let Child::Token(token) = child {
+                if is_shown(token.token_type) {
+                    write!(f, " {}", token.lexeme(&self.source))?;
+                }
+            }
+        }
+        writeln!(f)?;
+        node.nodes()
+            .try_for_each(|child| self.write_node(f, child, depth + 1))
+    }
+}
+
+/// The tree, one node per line indented under its parent, e.g.
+///
+/// ```text
+/// Program
+///   VarDecl x
+///     Binary +
+///       Literal 1
+///       Literal 2
+/// ```
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_node(f, &self.root, 0)
+    }
+}
+
+/// Whether the dump shows the token next to its node. The keywords and the punctuation are
+/// implied by the kind of the node
+fn is_shown(token_type: TokenType) -> bool {
+    match token_type {
+        TokenType::Equal => false,
+        TokenType::And | TokenType::Or | TokenType::True | TokenType::False | TokenType::Nil => {
+            true
+        }
+        _ => matches!(
+            TokenKind::from(token_type),
+            TokenKind::Identifier | TokenKind::Number | TokenKind::String | TokenKind::Operator
+        ),
+    }
+}
+
+/// Parse the source code into a syntax tree. Only the code which compiles has a tree, so the
+/// errors are the compile errors
+pub fn parse(source: &str) -> Result<Ast, Vec<Diagnostic>> {
+    Compiler::new(FunctionType::Script)
+        .warnings(Warnings::Off)
+        .compile(source)?;
+
+    let source: Rc<str> = Rc::from(source);
+    let mut scanner = Scanner::new();
+    scanner.init_scanner(Rc::clone(&source));
+    let mut tokens = vec![];
+    loop {
+        let token = scanner.scan_token();
+        tokens.push(token);
+        if token.token_type == TokenType::Eof {
+            break;
+        }
+    }
+
+    let mut parser = Parser {
+        source: &source,
+        tokens,
+        current: 0,
+    };
+    let mut root = Node::new(NodeKind::Program);
+    while !parser.check(TokenType::Eof) {
+        root.push(parser.declaration());
+    }
+    parser.token(&mut root);
+    Ok(Ast {
+        source: Rc::clone(&source),
+        root,
+    })
+}
+
+/// A recursive descent parser following the grammar of the compiler, which has checked the code
+/// already. The expressions use the precedences of the compiler's Pratt parser
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    current: usize,
+}
+
+impl Parser<'_> {
+    /// The type of the token `offset` tokens after the current one
+    fn peek(&self, offset: usize) -> TokenType {
+        let idx = (self.current + offset).min(self.tokens.len() - 1);
+        self.tokens[idx].token_type
+    }
+
+    fn check(&self, expected: TokenType) -> bool {
+        self.peek(0) == expected
+    }
+
+    /// Consume the current token. The `Eof` is never consumed
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.current];
+        if self.current < self.tokens.len() - 1 {
+            self.current += 1;
+        }
+        token
+    }
+
+    /// Move the current token into the node
+    fn token(&mut self, node: &mut Node) {
+        let token = self.advance();
+        node.children.push(Child::Token(token));
+    }
+
+    /// Move the current token into the node if it has the given type
+    fn optional(&mut self, node: &mut Node, expected: TokenType) -> bool {
+        let matched = self.check(expected);
+        if matched {
+            self.token(node);
+        }
+        matched
+    }
+
+    fn declaration(&mut self) -> Node {
+        match self.peek(0) {
+            TokenType::Class => self.class_declaration(),
+            TokenType::Var => self.var_declaration(),
+            TokenType::Fun => {
+                let mut node = Node::new(NodeKind::FunDecl);
+                self.token(&mut node);
+                self.token(&mut node);
+                self.function(&mut node);
+                node
+            }
+            _ => self.statement(),
+        }
+    }
+
+    fn class_declaration(&mut self) -> Node {
+        let mut node = Node::new(NodeKind::ClassDecl);
+        self.token(&mut node);
+        self.token(&mut node);
+        if self.optional(&mut node, TokenType::Less) {
+            self.token(&mut node);
+        }
+        self.token(&mut node);
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            let mut member = if self.check(TokenType::Static) {
+                let mut member = Node::new(NodeKind::StaticMember);
+                self.token(&mut member);
+                member
+            } else {
+                Node::new(NodeKind::Method)
+            };
+            // `set` is only special before another name
+            let is_setter = member.kind == NodeKind::Method
+                && self.tokens[self.current].lexeme(self.source) == "set"
+                && self.peek(1) == TokenType::Identifier;
+            if is_setter {
+                self.token(&mut member);
+            }
+            self.token(&mut member);
+            if member.kind == NodeKind::StaticMember && self.optional(&mut member, TokenType::Equal)
+            {
+                member.push(self.expression());
+                self.token(&mut member);
+            } else if self.check(TokenType::LeftBrace) {
+                // A getter
+                member.push(self.block());
+            } else {
+                self.function(&mut member);
+            }
+            node.push(member);
+        }
+        self.token(&mut node);
+        node
+    }
+
+    fn var_declaration(&mut self) -> Node {
+        let mut node = Node::new(NodeKind::VarDecl);
+        self.token(&mut node);
+        self.token(&mut node);
+        if self.optional(&mut node, TokenType::Equal) {
+            node.push(self.expression());
+        }
+        self.token(&mut node);
+        node
+    }
+
+    /// The parameters and the body of a function
+    fn function(&mut self, node: &mut Node) {
+        let mut parameters = Node::new(NodeKind::Parameters);
+        self.token(&mut parameters);
+        if !self.check(TokenType::RightParen) {
+            loop {
+                let mut parameter = Node::new(NodeKind::Parameter);
+                let is_rest = self.optional(&mut parameter, TokenType::DotDotDot);
+                self.token(&mut parameter);
+                if !is_rest && self.optional(&mut parameter, TokenType::Equal) {
+                    parameter.push(self.expression());
+                }
+                parameters.push(parameter);
+                if !self.optional(&mut parameters, TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.token(&mut parameters);
+        node.push(parameters);
+        node.push(self.block());
+    }
+
+    fn block(&mut self) -> Node {
+        let mut node = Node::new(NodeKind::Block);
+        self.token(&mut node);
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            node.push(self.declaration());
+        }
+        self.token(&mut node);
+        node
+    }
+
+    fn statement(&mut self) -> Node {
+        let kind = match self.peek(0) {
+            TokenType::Print => NodeKind::PrintStmt,
+            TokenType::Import => NodeKind::ImportStmt,
+            TokenType::If => NodeKind::IfStmt,
+            TokenType::While => NodeKind::WhileStmt,
+            TokenType::For => return self.for_statement(),
+            TokenType::Return => NodeKind::ReturnStmt,
+            TokenType::Break => NodeKind::BreakStmt,
+            TokenType::Continue => NodeKind::ContinueStmt,
+            TokenType::LeftBrace => return self.block(),
+            _ => {
+                let mut node = Node::new(NodeKind::ExprStmt);
+                node.push(self.expression());
+                self.token(&mut node);
+                return node;
+            }
+        };
+        let mut node = Node::new(kind);
+        self.token(&mut node);
+        match kind {
+            NodeKind::PrintStmt => node.push(self.expression()),
+            NodeKind::ImportStmt => self.token(&mut node),
+            NodeKind::IfStmt | NodeKind::WhileStmt => {
+                self.token(&mut node);
+                node.push(self.expression());
+                self.token(&mut node);
+                node.push(self.statement());
+                if kind == NodeKind::IfStmt && self.optional(&mut node, TokenType::Else) {
+                    node.push(self.statement());
+                }
+                return node;
+            }
+            NodeKind::ReturnStmt if !self.check(TokenType::Semicolon) => {
+                node.push(self.expression());
+            }
+            _ => {}
+        }
+        self.token(&mut node);
+        node
+    }
+
+    fn for_statement(&mut self) -> Node {
+        let mut node = Node::new(NodeKind::ForStmt);
+        self.token(&mut node);
+        self.token(&mut node);
+        let var = usize::from(self.check(TokenType::Var));
+        if self.peek(var) == TokenType::Identifier && self.peek(var + 1) == TokenType::In {
+            node.kind = NodeKind::ForInStmt;
+            self.optional(&mut node, TokenType::Var);
+            self.token(&mut node);
+            self.token(&mut node);
+            node.push(self.expression());
+        } else {
+            if self.check(TokenType::Var) {
+                node.push(self.var_declaration());
+            } else if !self.optional(&mut node, TokenType::Semicolon) {
+                let mut initializer = Node::new(NodeKind::ExprStmt);
+                initializer.push(self.expression());
+                self.token(&mut initializer);
+                node.push(initializer);
+            }
+            if !self.check(TokenType::Semicolon) {
+                node.push(self.expression());
+            }
+            self.token(&mut node);
+            if !self.check(TokenType::RightParen) {
+                node.push(self.expression());
+            }
+        }
+        self.token(&mut node);
+        node.push(self.statement());
+        node
+    }
+
+    fn expression(&mut self) -> Node {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Node {
+        let can_assign = precedence <= Precedence::Assignment;
+        let mut left = self.prefix(can_assign);
+        while precedence <= Precedence::of(self.peek(0)) {
+            left = self.infix(left, can_assign);
+        }
+        left
+    }
+
+    fn prefix(&mut self, can_assign: bool) -> Node {
+        let kind = match self.peek(0) {
+            TokenType::LeftParen => NodeKind::Grouping,
+            TokenType::LeftBracket => NodeKind::List,
+            TokenType::Minus | TokenType::Bang | TokenType::Tilde => NodeKind::Unary,
+            TokenType::PlusPlus | TokenType::MinusMinus => NodeKind::PrefixStep,
+            TokenType::Number
+            | TokenType::STRING
+            | TokenType::Nil
+            | TokenType::True
+            | TokenType::False => NodeKind::Literal,
+            TokenType::Yield => NodeKind::Yield,
+            TokenType::Fun => NodeKind::Lambda,
+            TokenType::Super => NodeKind::Super,
+            TokenType::This => NodeKind::This,
+            TokenType::Identifier => NodeKind::Variable,
+            _ => unreachable!("The compiler has checked the expression"),
+        };
+        let mut node = Node::new(kind);
+        if kind == NodeKind::List {
+            self.elements(&mut node, TokenType::RightBracket);
+            return node;
+        }
+        self.token(&mut node);
+        match kind {
+            NodeKind::Grouping => {
+                node.push(self.expression());
+                self.token(&mut node);
+            }
+            NodeKind::Unary | NodeKind::PrefixStep => {
+                node.push(self.parse_precedence(Precedence::Unary));
+            }
+            // `yield;` yields nil
+            NodeKind::Yield
+                if !self.check(TokenType::Semicolon) && !self.check(TokenType::RightParen) =>
+            {
+                node.push(self.expression());
+            }
+            NodeKind::Lambda => self.function(&mut node),
+            NodeKind::Super => {
+                self.token(&mut node);
+                self.token(&mut node);
+                if self.check(TokenType::LeftParen) {
+                    node.push(self.arguments());
+                }
+            }
+            NodeKind::Variable if can_assign && self.check(TokenType::Equal) => {
+                let mut assign = Node::new(NodeKind::Assign);
+                assign.push(node);
+                self.token(&mut assign);
+                assign.push(self.expression());
+                return assign;
+            }
+            _ => {}
+        }
+        node
+    }
+
+    fn infix(&mut self, left: Node, can_assign: bool) -> Node {
+        let operator = self.peek(0);
+        let kind = match operator {
+            TokenType::LeftParen => {
+                let mut node = Node::new(NodeKind::Call);
+                node.push(left);
+                node.push(self.arguments());
+                return node;
+            }
+            TokenType::LeftBracket => return self.index(left, can_assign),
+            TokenType::Dot => return self.dot(left, can_assign),
+            TokenType::QuestionDot => {
+                // The whole chain after `?.` is skipped when the object is nil
+                let mut chain = self.dot(left, false);
+                while Precedence::Call <= Precedence::of(self.peek(0)) {
+                    chain = self.infix(chain, false);
+                }
+                let mut node = Node::new(NodeKind::OptionalChain);
+                node.push(chain);
+                return node;
+            }
+            TokenType::PlusPlus | TokenType::MinusMinus => {
+                let mut node = Node::new(NodeKind::PostfixStep);
+                node.push(left);
+                self.token(&mut node);
+                return node;
+            }
+            TokenType::QuestionQuestion => NodeKind::Coalesce,
+            TokenType::DotDot | TokenType::DotDotEqual => NodeKind::Range,
+            TokenType::And | TokenType::Or => NodeKind::Logical,
+            _ => NodeKind::Binary,
+        };
+        let right_precedence = match operator {
+            // Right-associative
+            TokenType::QuestionQuestion | TokenType::And | TokenType::Or => {
+                Precedence::of(operator)
+            }
+            // The exponent may be negated, e.g. `2 ** -1`
+            TokenType::StarStar => Precedence::Unary,
+            _ => Precedence::of(operator).next(),
+        };
+        let mut node = Node::new(kind);
+        node.push(left);
+        self.token(&mut node);
+        node.push(self.parse_precedence(right_precedence));
+        node
+    }
+
+    /// `(`, the arguments, `)`
+    fn arguments(&mut self) -> Node {
+        let mut node = Node::new(NodeKind::Arguments);
+        self.elements(&mut node, TokenType::RightParen);
+        node
+    }
+
+    /// The opening bracket, the comma-separated elements up to `closing`, and `closing`
+    fn elements(&mut self, node: &mut Node, closing: TokenType) {
+        self.token(node);
+        if !self.check(closing) {
+            loop {
+                if self.check(TokenType::DotDotDot) {
+                    let mut spread = Node::new(NodeKind::Spread);
+                    self.token(&mut spread);
+                    spread.push(self.expression());
+                    node.push(spread);
+                } else {
+                    node.push(self.expression());
+                }
+                if !self.optional(node, TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.token(node);
+    }
+
+    fn index(&mut self, left: Node, can_assign: bool) -> Node {
+        let mut node = Node::new(NodeKind::Index);
+        node.push(left);
+        self.token(&mut node);
+        if !self.check(TokenType::Colon) {
+            node.push(self.expression());
+        }
+        if self.optional(&mut node, TokenType::Colon) {
+            node.kind = NodeKind::Slice;
+            if !self.check(TokenType::RightBracket) {
+                node.push(self.expression());
+            }
+            self.token(&mut node);
+            return node;
+        }
+        self.token(&mut node);
+        if can_assign && self.optional(&mut node, TokenType::Equal) {
+            node.kind = NodeKind::SetIndex;
+            node.push(self.expression());
+        }
+        node
+    }
+
+    /// `.` or `?.`, and the name after it
+    fn dot(&mut self, left: Node, can_assign: bool) -> Node {
+        let mut node = Node::new(NodeKind::Get);
+        node.push(left);
+        self.token(&mut node);
+        self.token(&mut node);
+        if can_assign && self.optional(&mut node, TokenType::Equal) {
+            node.kind = NodeKind::Set;
+            node.push(self.expression());
+        } else if self.check(TokenType::LeftParen) {
+            node.kind = NodeKind::Invoke;
+            node.push(self.arguments());
+        }
+        node
+    }
+}
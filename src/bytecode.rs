@@ -0,0 +1,279 @@
+//! The `.loxc` format, which stores a compiled script so it can run without the source code and
+//! without compiling it again
+//!
+//! A file starts with a header: the magic bytes `LOXC`, the format version (`u16`) and the
+//! FNV-1a checksum (`u32`) of the rest of the file, which is the script function. All the numbers
+//! are little-endian. The source code is left out, so runtime errors don't show the code
+//!
+//! Loading a file also checks its bytecode with [`crate::verifier`], so a file from an untrusted
+//! source can't make the VM misbehave
+use crate::chunk::Chunk;
+use crate::compiler::Upvalue;
+use crate::scanner::Span;
+use crate::shared::Rc;
+use crate::value::{Function, Value};
+use crate::verifier;
+
+/// The first bytes of every `.loxc` file
+pub const MAGIC: &[u8; 4] = b"LOXC";
+/// Bumped whenever the format or the instruction set changes, old files must be compiled again
+pub const VERSION: u16 = 15;
+
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+
+/// The functions may nest as deep as the compiler lets them
+const MAX_NESTING: usize = 256;
+
+/// The tags of the constants
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const FUNCTION: u8 = 2;
+const INT: u8 = 3;
+
+/// Why a `.loxc` file can't be loaded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// It's not a `.loxc` file at all
+    NotBytecode,
+    /// It's written by another version of rustlox
+    UnsupportedVersion(u16),
+    /// The file is damaged
+    ChecksumMismatch,
+    /// The file ends in the middle of something
+    Truncated,
+    /// The content makes no sense, e.g. an unknown constant tag, or the bytecode doesn't pass
+    /// the verifier
+    Malformed(String),
+}
+
+impl std::fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotBytecode => write!(f, "Not a compiled Lox file."),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Compiled by an unsupported version {version} of the format, expected {VERSION}."
+            ),
+            Self::ChecksumMismatch => write!(f, "The compiled file is corrupted."),
+            Self::Truncated => write!(f, "The compiled file is truncated."),
+            Self::Malformed(msg) => write!(f, "Malformed compiled file: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+/// Whether the bytes look like a `.loxc` file rather than source code
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Serialize the script function, with the functions nested in its constants
+pub fn serialize(function: &Function) -> Vec<u8> {
+    let mut payload = vec![];
+    write_function(&mut payload, function);
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.extend(checksum(&payload).to_le_bytes());
+    bytes.extend(payload);
+    bytes
+}
+
+/// Load the script function written by [`serialize`], and verify its bytecode
+pub fn deserialize(bytes: &[u8]) -> Result<Function, BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::NotBytecode);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(BytecodeError::Truncated);
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let expected = u32::from_le_bytes(bytes[6..HEADER_LEN].try_into().expect("Four bytes"));
+    let payload = &bytes[HEADER_LEN..];
+    if checksum(payload) != expected {
+        return Err(BytecodeError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader {
+        bytes: payload,
+        nesting: 0,
+    };
+    let function = reader.function()?;
+    if !reader.bytes.is_empty() {
+        return Err(BytecodeError::Malformed(
+            "Unexpected bytes after the script.".to_string(),
+        ));
+    }
+    verifier::verify(&function).map_err(|e| BytecodeError::Malformed(e.to_string()))?;
+    Ok(function)
+}
+
+/// 32-bit FNV-1a, which is enough to notice a damaged file
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend((len as u32).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_len(out, s.len());
+    out.extend(s.as_bytes());
+}
+
+fn write_function(out: &mut Vec<u8>, function: &Function) {
+    write_str(out, &function.name);
+    write_len(out, function.arity);
+    write_len(out, function.min_arity);
+    write_len(out, function.max_locals);
+    out.push(u8::from(function.is_generator));
+    out.push(u8::from(function.is_variadic));
+    write_len(out, function.upvalues.len());
+    for upvalue in &function.upvalues {
+        out.push(u8::from(upvalue.is_local));
+        write_len(out, upvalue.index);
+    }
+    write_chunk(out, &function.chunk);
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk: &Chunk) {
+    write_len(out, chunk.code.len());
+    out.extend(&chunk.code);
+    for (span, column) in chunk.spans.iter().zip(&chunk.columns) {
+        write_len(out, span.line);
+        write_len(out, *column);
+        write_len(out, span.start);
+        write_len(out, span.len);
+    }
+    write_len(out, chunk.constants.values.len());
+    for constant in &chunk.constants.values {
+        match constant {
+            Value::Int(n) => {
+                out.push(INT);
+                out.extend(n.to_le_bytes());
+            }
+            Value::Number(n) => {
+                out.push(NUMBER);
+                out.extend(n.to_le_bytes());
+            }
+            Value::String(s) => {
+                out.push(STRING);
+                write_str(out, s);
+            }
+            Value::Func(function) => {
+                out.push(FUNCTION);
+                write_function(out, function);
+            }
+            _ => unreachable!("The compiler only makes numbers, strings and functions constants"),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    /// The functions being read, which contain the one being read now
+    nesting: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], BytecodeError> {
+        if self.bytes.len() < len {
+            return Err(BytecodeError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn len(&mut self) -> Result<usize, BytecodeError> {
+        let bytes = self.take(4)?.try_into().expect("Four bytes");
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| BytecodeError::Malformed("A string isn't UTF-8.".to_string()))
+    }
+
+    fn function(&mut self) -> Result<Function, BytecodeError> {
+        let name = self.string()?;
+        let arity = self.len()?;
+        let min_arity = self.len()?;
+        let max_locals = self.len()?;
+        let is_generator = self.byte()? == 1;
+        let is_variadic = self.byte()? == 1;
+        let upvalue_cnt = self.len()?;
+        let mut upvalues = vec![];
+        for _ in 0..upvalue_cnt {
+            let is_local = self.byte()? == 1;
+            upvalues.push(Upvalue::new(is_local, self.len()?));
+        }
+        Ok(Function {
+            name,
+            arity,
+            min_arity,
+            chunk: self.chunk()?,
+            upvalues,
+            max_locals,
+            source: Rc::from(""),
+            file: None,
+            is_generator,
+            is_variadic,
+        })
+    }
+
+    fn chunk(&mut self) -> Result<Chunk, BytecodeError> {
+        let mut chunk = Chunk::default();
+        let len = self.len()?;
+        let code = self.take(len)?.to_vec();
+        for byte in code {
+            let (line, column) = (self.len()?, self.len()?);
+            let (start, len) = (self.len()?, self.len()?);
+            chunk.write(byte, Span { start, len, line }, column);
+        }
+        let constant_cnt = self.len()?;
+        for _ in 0..constant_cnt {
+            let constant = match self.byte()? {
+                NUMBER => {
+                    let bytes = self.take(8)?.try_into().expect("Eight bytes");
+                    Value::Number(f64::from_le_bytes(bytes))
+                }
+                INT => {
+                    let bytes = self.take(8)?.try_into().expect("Eight bytes");
+                    Value::Int(i64::from_le_bytes(bytes))
+                }
+                STRING => Value::string(self.string()?),
+                FUNCTION => {
+                    if self.nesting == MAX_NESTING {
+                        return Err(BytecodeError::Malformed(
+                            "Functions nested too deeply.".to_string(),
+                        ));
+                    }
+                    self.nesting += 1;
+                    let function = self.function()?;
+                    self.nesting -= 1;
+                    Value::Func(Rc::new(function))
+                }
+                tag => {
+                    return Err(BytecodeError::Malformed(format!(
+                        "Unknown constant tag {tag}."
+                    )))
+                }
+            };
+            chunk.add_constant(constant);
+        }
+        Ok(chunk)
+    }
+}
@@ -1,7 +1,8 @@
+use crate::scanner::Span;
 use crate::value::{Value, ValueArray};
 
 ///  Operation code for the Lox
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum OpCode {
     /// Return from the current function
@@ -38,6 +39,159 @@ pub enum OpCode {
     SetUpvalue,
     GetUpvalue,
     ClosedUpvalue,
+    /// Create a new class object
+    Class,
+    GetProperty,
+    SetProperty,
+    /// Add the closure on top of the stack to the class below it as a method
+    Method,
+    /// Copy the methods of the superclass down into the subclass
+    Inherit,
+    GetSuper,
+    /// `super.method(args)` without creating a bound method
+    SuperInvoke,
+    Modulo,
+    Power,
+    /// The bitwise operators work on the numbers truncated to 64-bit integers
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+    /// The wide variants take a two bytes slot, for the functions with more than 256 locals
+    GetLocalLong,
+    SetLocalLong,
+    /// The wide jumps take a four bytes offset, for the code too large for two bytes to jump over
+    JumpLong,
+    JumpIfFalseLong,
+    LoopLong,
+    /// `!=`, which the optimizer also makes of `Equal` followed by `Not`
+    NotEqual,
+    /// Add one to the number on top of the stack, which the optimizer makes of `Constant 1; Add`
+    Increment,
+    /// `receiver.method(args)` without creating a bound method
+    Invoke,
+    /// Run the module named by the constant, unless it has run already
+    Import,
+    /// Suspend the running coroutine with the value on top of the stack
+    Yield,
+    /// Store the value on top of the stack in the class below it, as a static method or a
+    /// class-level field
+    Static,
+    /// Add the closure on top of the stack to the class below it as a getter or a setter
+    Getter,
+    Setter,
+    /// Whether the caller left out the argument of the parameter in the slot, so its default
+    /// value is computed instead
+    ArgMissing,
+    /// Make a list of the elements on top of the stack
+    BuildList,
+    /// Add the value on top of the stack to the list below it
+    AppendList,
+    /// Add the elements of the list on top of the stack to the list below it
+    ExtendList,
+    /// The variants of `Call`, `Invoke` and `SuperInvoke` with the arguments packed into a list,
+    /// e.g. `f(...args)`
+    CallSpread,
+    InvokeSpread,
+    SuperInvokeSpread,
+    /// `list[index]` and `list[index] = value`
+    GetIndex,
+    SetIndex,
+    /// Make a range of the two numbers on top of the stack, the operand is one for `..=`
+    Range,
+    /// Push the next element of the list, string or range in the slot of the operand and `true`,
+    /// or `nil` and `false` after the last one. The slot after it holds where the loop is
+    IterNext,
+    /// `target[start:end]`, the start and the end on top of the stack may be nil
+    Slice,
+    /// Push whether the value on top of the stack is nil, which stays there
+    IsNil,
+    /// Push a copy of the value the operand counts down from the top of the stack, 0 being the top
+    Dup,
+    /// Move the value on top of the stack under the number of values below it given by the operand
+    Bury,
+    /// The wide variants take a two bytes constant, for the chunks with more than 256 constants
+    ConstantLong,
+    DefineGlobalLong,
+    GetGlobalLong,
+    SetGlobalLong,
+    ClosureLong,
+    ClassLong,
+    GetPropertyLong,
+    SetPropertyLong,
+    MethodLong,
+    GetSuperLong,
+    SuperInvokeLong,
+    InvokeLong,
+    ImportLong,
+    StaticLong,
+    GetterLong,
+    SetterLong,
+    InvokeSpreadLong,
+    SuperInvokeSpreadLong,
+}
+
+impl OpCode {
+    /// The wide variant of an instruction taking the slot of a local or a constant, for the
+    /// operands which don't fit in one byte
+    pub fn wide(self) -> Option<OpCode> {
+        Some(match self {
+            Self::GetLocal => Self::GetLocalLong,
+            Self::SetLocal => Self::SetLocalLong,
+            Self::Constant => Self::ConstantLong,
+            Self::DefineGlobal => Self::DefineGlobalLong,
+            Self::GetGlobal => Self::GetGlobalLong,
+            Self::SetGlobal => Self::SetGlobalLong,
+            Self::Closure => Self::ClosureLong,
+            Self::Class => Self::ClassLong,
+            Self::GetProperty => Self::GetPropertyLong,
+            Self::SetProperty => Self::SetPropertyLong,
+            Self::Method => Self::MethodLong,
+            Self::GetSuper => Self::GetSuperLong,
+            Self::SuperInvoke => Self::SuperInvokeLong,
+            Self::Invoke => Self::InvokeLong,
+            Self::Import => Self::ImportLong,
+            Self::Static => Self::StaticLong,
+            Self::Getter => Self::GetterLong,
+            Self::Setter => Self::SetterLong,
+            Self::InvokeSpread => Self::InvokeSpreadLong,
+            Self::SuperInvokeSpread => Self::SuperInvokeSpreadLong,
+            _ => return None,
+        })
+    }
+
+    /// The variant of the instruction taking its constant in one byte, e.g. `Class` for
+    /// `ClassLong`, and the instruction itself for the others
+    pub fn narrow_constant(self) -> OpCode {
+        match self {
+            Self::ConstantLong => Self::Constant,
+            Self::DefineGlobalLong => Self::DefineGlobal,
+            Self::GetGlobalLong => Self::GetGlobal,
+            Self::SetGlobalLong => Self::SetGlobal,
+            Self::ClosureLong => Self::Closure,
+            Self::ClassLong => Self::Class,
+            Self::GetPropertyLong => Self::GetProperty,
+            Self::SetPropertyLong => Self::SetProperty,
+            Self::MethodLong => Self::Method,
+            Self::GetSuperLong => Self::GetSuper,
+            Self::SuperInvokeLong => Self::SuperInvoke,
+            Self::InvokeLong => Self::Invoke,
+            Self::ImportLong => Self::Import,
+            Self::StaticLong => Self::Static,
+            Self::GetterLong => Self::Getter,
+            Self::SetterLong => Self::Setter,
+            Self::InvokeSpreadLong => Self::InvokeSpread,
+            Self::SuperInvokeSpreadLong => Self::SuperInvokeSpread,
+            op => op,
+        }
+    }
+
+    /// Whether the instruction is a wide variant taking its constant in two bytes
+    pub fn has_wide_constant(self) -> bool {
+        self.narrow_constant() != self
+    }
 }
 
 impl From<OpCode> for u8 {
@@ -46,9 +200,12 @@ impl From<OpCode> for u8 {
     }
 }
 
-impl From<u8> for OpCode {
-    fn from(value: u8) -> Self {
-        match value {
+/// A byte which isn't an opcode is the error
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
+        Ok(match value {
             0 => Self::Return,
             1 => Self::Constant,
             2 => Self::Negate,
@@ -78,8 +235,69 @@ impl From<u8> for OpCode {
             26 => Self::SetUpvalue,
             27 => Self::GetUpvalue,
             28 => Self::ClosedUpvalue,
-            _ => unimplemented!("May be later"),
-        }
+            29 => Self::Class,
+            30 => Self::GetProperty,
+            31 => Self::SetProperty,
+            32 => Self::Method,
+            33 => Self::Inherit,
+            34 => Self::GetSuper,
+            35 => Self::SuperInvoke,
+            36 => Self::Modulo,
+            37 => Self::Power,
+            38 => Self::BitAnd,
+            39 => Self::BitOr,
+            40 => Self::BitXor,
+            41 => Self::BitNot,
+            42 => Self::ShiftLeft,
+            43 => Self::ShiftRight,
+            44 => Self::GetLocalLong,
+            45 => Self::SetLocalLong,
+            46 => Self::JumpLong,
+            47 => Self::JumpIfFalseLong,
+            48 => Self::LoopLong,
+            49 => Self::NotEqual,
+            50 => Self::Increment,
+            51 => Self::Invoke,
+            52 => Self::Import,
+            53 => Self::Yield,
+            54 => Self::Static,
+            55 => Self::Getter,
+            56 => Self::Setter,
+            57 => Self::ArgMissing,
+            58 => Self::BuildList,
+            59 => Self::AppendList,
+            60 => Self::ExtendList,
+            61 => Self::CallSpread,
+            62 => Self::InvokeSpread,
+            63 => Self::SuperInvokeSpread,
+            64 => Self::GetIndex,
+            65 => Self::SetIndex,
+            66 => Self::Range,
+            67 => Self::IterNext,
+            68 => Self::Slice,
+            69 => Self::IsNil,
+            70 => Self::Dup,
+            71 => Self::Bury,
+            72 => Self::ConstantLong,
+            73 => Self::DefineGlobalLong,
+            74 => Self::GetGlobalLong,
+            75 => Self::SetGlobalLong,
+            76 => Self::ClosureLong,
+            77 => Self::ClassLong,
+            78 => Self::GetPropertyLong,
+            79 => Self::SetPropertyLong,
+            80 => Self::MethodLong,
+            81 => Self::GetSuperLong,
+            82 => Self::SuperInvokeLong,
+            83 => Self::InvokeLong,
+            84 => Self::ImportLong,
+            85 => Self::StaticLong,
+            86 => Self::GetterLong,
+            87 => Self::SetterLong,
+            88 => Self::InvokeSpreadLong,
+            89 => Self::SuperInvokeSpreadLong,
+            _ => return Err(value),
+        })
     }
 }
 
@@ -89,19 +307,157 @@ pub struct Chunk {
     pub code: Vec<u8>,
     pub constants: ValueArray,
     pub lines: Vec<usize>,
+    /// The column of the token which each instruction is compiled from
+    pub columns: Vec<usize>,
+    /// Where that token is in the source code, which maps the instruction back to its text
+    pub spans: Vec<Span>,
 }
 
 impl Chunk {
-    pub fn write<T>(&mut self, byte: T, line: usize)
+    /// Append a byte compiled from the token at `span`, which starts at `column`
+    pub fn write<T>(&mut self, byte: T, span: Span, column: usize)
     where
         T: Into<u8>,
     {
         self.code.push(byte.into());
-        self.lines.push(line);
+        self.lines.push(span.line);
+        self.columns.push(column);
+        self.spans.push(span);
+    }
+
+    /// Drop the code from `len` onwards, e.g. an instruction the compiler takes back
+    pub fn truncate(&mut self, len: usize) {
+        self.code.truncate(len);
+        self.lines.truncate(len);
+        self.columns.truncate(len);
+        self.spans.truncate(len);
     }
 
     pub fn add_constant(&mut self, val: Value) -> usize {
         self.constants.write(val);
         self.constants.values.len() - 1
     }
+
+    /// The number of bytes taken by the instruction at `offset`, including the operands. A byte
+    /// which isn't an opcode counts as an instruction of its own
+    pub fn instruction_len(&self, offset: usize) -> usize {
+        let Ok(op) = OpCode::try_from(self.code[offset]) else {
+            return 1;
+        };
+        match op {
+            OpCode::Return
+            | OpCode::Yield
+            | OpCode::AppendList
+            | OpCode::ExtendList
+            | OpCode::CallSpread
+            | OpCode::GetIndex
+            | OpCode::SetIndex
+            | OpCode::Slice
+            | OpCode::IsNil
+            | OpCode::Negate
+            | OpCode::Add
+            | OpCode::Substract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Modulo
+            | OpCode::Power
+            | OpCode::BitAnd
+            | OpCode::BitOr
+            | OpCode::BitXor
+            | OpCode::BitNot
+            | OpCode::ShiftLeft
+            | OpCode::ShiftRight
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Not
+            | OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Increment
+            | OpCode::Print
+            | OpCode::Pop
+            | OpCode::ClosedUpvalue
+            | OpCode::Inherit => 1,
+            OpCode::Constant
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetLocal
+            | OpCode::ArgMissing
+            | OpCode::BuildList
+            | OpCode::Range
+            | OpCode::Dup
+            | OpCode::Bury
+            | OpCode::InvokeSpread
+            | OpCode::SuperInvokeSpread
+            | OpCode::SetLocal
+            | OpCode::Call
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Class
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::Method
+            | OpCode::Static
+            | OpCode::Getter
+            | OpCode::Setter
+            | OpCode::Import
+            | OpCode::GetSuper => 2,
+            OpCode::GetLocalLong
+            | OpCode::SetLocalLong
+            | OpCode::ConstantLong
+            | OpCode::DefineGlobalLong
+            | OpCode::GetGlobalLong
+            | OpCode::SetGlobalLong
+            | OpCode::ClassLong
+            | OpCode::GetPropertyLong
+            | OpCode::SetPropertyLong
+            | OpCode::MethodLong
+            | OpCode::GetSuperLong
+            | OpCode::ImportLong
+            | OpCode::StaticLong
+            | OpCode::GetterLong
+            | OpCode::SetterLong
+            | OpCode::InvokeSpreadLong
+            | OpCode::SuperInvokeSpreadLong
+            | OpCode::IterNext
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop
+            | OpCode::Invoke
+            | OpCode::SuperInvoke => 3,
+            OpCode::InvokeLong | OpCode::SuperInvokeLong => 4,
+            OpCode::JumpLong | OpCode::JumpIfFalseLong | OpCode::LoopLong => 5,
+            // The function is followed by three bytes for each of its upvalues
+            OpCode::Closure | OpCode::ClosureLong => {
+                let len = if op == OpCode::Closure { 2 } else { 3 };
+                let idx = match self.code.get(offset + 1..offset + len) {
+                    Some(&[idx]) => Some(idx as usize),
+                    Some(&[high, low]) => Some(u16::from_be_bytes([high, low]) as usize),
+                    _ => None,
+                };
+                match idx.and_then(|idx| self.constants.values.get(idx)) {
+                    Some(Value::Func(func)) => len + func.upvalues.len() * 3,
+                    _ => len,
+                }
+            }
+        }
+    }
+
+    /// The line and the column of the instruction at `offset`, or zeros past the end of the
+    /// code, which only malformed bytecode jumps to
+    pub fn location(&self, offset: usize) -> (usize, usize) {
+        match (self.lines.get(offset), self.columns.get(offset)) {
+            (Some(&line), Some(&column)) => (line, column),
+            _ => (0, 0),
+        }
+    }
+
+    /// Where the token of the instruction at `offset` is in the source code, or an empty span
+    /// past the end of the code
+    pub fn span(&self, offset: usize) -> Span {
+        self.spans.get(offset).copied().unwrap_or_default()
+    }
 }
@@ -1,9 +1,19 @@
+use crate::ast::Ast;
+use crate::bytecode;
 use crate::chunk::{Chunk, OpCode};
 use crate::disassembler::disassemble_chunk;
+use crate::lint;
+use crate::optimizer;
+use crate::reporter::{Diagnostic, Severity};
 use crate::scanner::{Scanner, Token, TokenType};
-use crate::value::{Closure, Function, FunctionType, Value};
-use crate::vm::InterpretResult;
-use std::rc::Rc;
+use crate::shared::Rc;
+use crate::value::{Function, FunctionType, Value};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// How deep the expressions, the statements and the functions may nest. The compiler recurses
+/// into them, so deeper code would overflow its stack
+const MAX_NESTING: usize = 256;
 
 #[derive(Debug, Default)]
 struct Parser {
@@ -14,32 +24,52 @@ struct Parser {
 }
 
 #[derive(PartialEq, PartialOrd)]
-enum Precedence {
+pub(crate) enum Precedence {
     None,
     Assignment, // =
+    Range,      // .. ..=
+    Coalesce,   // ??
     Or,         // or
     And,        // and
     Equality,   // == !=
+    BitOr,      // |
+    BitXor,     // ^
+    BitAnd,     // &
     Comparison, // < > <= >=
+    Shift,      // << >>
     Term,       // + -
-    Factor,     // * /
+    Factor,     // * / %
     Unary,      // ! -
+    Exponent,   // **
     Call,       // . ()
     Primary,
 }
 
 impl Precedence {
+    /// How tightly the token binds as an infix operator, which the syntax tree shares with the
+    /// compiler
+    pub(crate) fn of(token_type: TokenType) -> Self {
+        ParseRule::get_rule(token_type).precedence
+    }
+
     pub fn next(self) -> Self {
         match self {
             Self::None => Self::Assignment,
-            Self::Assignment => Self::Or,
+            Self::Assignment => Self::Range,
+            Self::Range => Self::Coalesce,
+            Self::Coalesce => Self::Or,
             Self::Or => Self::And,
             Self::And => Self::Equality,
-            Self::Equality => Self::Comparison,
-            Self::Comparison => Self::Term,
+            Self::Equality => Self::BitOr,
+            Self::BitOr => Self::BitXor,
+            Self::BitXor => Self::BitAnd,
+            Self::BitAnd => Self::Comparison,
+            Self::Comparison => Self::Shift,
+            Self::Shift => Self::Term,
             Self::Term => Self::Factor,
             Self::Factor => Self::Unary,
-            Self::Unary => Self::Call,
+            Self::Unary => Self::Exponent,
+            Self::Exponent => Self::Call,
             Self::Call => Self::Primary,
             Self::Primary => panic!("Impossible"),
         }
@@ -64,6 +94,36 @@ impl ParseRule {
                 infix: Some(Compiler::call),
                 precedence: Precedence::Call,
             },
+            TokenType::LeftBracket => ParseRule {
+                prefix: Some(Compiler::list),
+                infix: Some(Compiler::index),
+                precedence: Precedence::Call,
+            },
+            TokenType::Dot => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::dot),
+                precedence: Precedence::Call,
+            },
+            TokenType::QuestionDot => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::optional_dot),
+                precedence: Precedence::Call,
+            },
+            TokenType::PlusPlus | TokenType::MinusMinus => ParseRule {
+                prefix: Some(Compiler::prefix_step),
+                infix: Some(Compiler::postfix_step),
+                precedence: Precedence::Call,
+            },
+            TokenType::QuestionQuestion => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::coalesce),
+                precedence: Precedence::Coalesce,
+            },
+            TokenType::DotDot | TokenType::DotDotEqual => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::range),
+                precedence: Precedence::Range,
+            },
             TokenType::Minus => ParseRule {
                 prefix: Some(Compiler::unary),
                 infix: Some(Compiler::binary),
@@ -74,11 +134,16 @@ impl ParseRule {
                 infix: Some(Compiler::binary),
                 precedence: Precedence::Term,
             },
-            TokenType::Slash | TokenType::Star => ParseRule {
+            TokenType::Slash | TokenType::Star | TokenType::Percent => ParseRule {
                 prefix: None,
                 infix: Some(Compiler::binary),
                 precedence: Precedence::Factor,
             },
+            TokenType::StarStar => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::Exponent,
+            },
             TokenType::Number => ParseRule {
                 prefix: Some(Compiler::number),
                 infix: None,
@@ -89,7 +154,27 @@ impl ParseRule {
                 infix: None,
                 precedence: Precedence::None,
             },
-            TokenType::Bang => ParseRule {
+            TokenType::Pipe => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::BitOr,
+            },
+            TokenType::Caret => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::BitXor,
+            },
+            TokenType::Ampersand => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::BitAnd,
+            },
+            TokenType::LessLess | TokenType::GreaterGreater => ParseRule {
+                prefix: None,
+                infix: Some(Compiler::binary),
+                precedence: Precedence::Shift,
+            },
+            TokenType::Bang | TokenType::Tilde => ParseRule {
                 prefix: Some(Compiler::unary),
                 infix: None,
                 precedence: Precedence::None,
@@ -112,6 +197,26 @@ impl ParseRule {
                 infix: None,
                 precedence: Precedence::None,
             },
+            TokenType::Yield => ParseRule {
+                prefix: Some(Compiler::yield_),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            TokenType::Fun => ParseRule {
+                prefix: Some(Compiler::lambda),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            TokenType::Super => ParseRule {
+                prefix: Some(Compiler::super_),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            TokenType::This => ParseRule {
+                prefix: Some(Compiler::this),
+                infix: None,
+                precedence: Precedence::None,
+            },
             TokenType::Identifier => ParseRule {
                 prefix: Some(Compiler::variable),
                 infix: None,
@@ -136,6 +241,22 @@ impl ParseRule {
     }
 }
 
+/// Where [`Compiler::elements`] leaves the compiled elements
+enum Elements {
+    /// On the stack one by one
+    Each(u8),
+    /// Packed into one list, e.g. because one of them spreads a list
+    Packed,
+}
+
+/// Tell if we're inside a class body, so `this` and `super` are only allowed there
+#[derive(Debug, Default)]
+struct ClassCompiler {
+    has_superclass: bool,
+    /// Static methods and class-level fields have no instance to refer to
+    in_static: bool,
+}
+
 /// A local variable in the stack
 #[derive(Debug, Default)]
 struct Local {
@@ -144,6 +265,10 @@ struct Local {
     depth: i32,
     /// Tell if a given local variable is captured by a closure
     is_captured: bool,
+    /// Whether the code reads it, otherwise it's reported as unused once it goes out of scope
+    is_read: bool,
+    is_assigned: bool,
+    is_parameter: bool,
 }
 
 impl Local {
@@ -152,6 +277,7 @@ impl Local {
             name,
             depth,
             is_captured,
+            ..Default::default()
         }
     }
 }
@@ -169,6 +295,67 @@ impl Upvalue {
     }
 }
 
+/// A constant which can be shared in a chunk. `Value` can't be a map key, and the functions are
+/// never the same anyway
+#[derive(PartialEq, Eq, Hash, Debug)]
+enum ConstantKey {
+    Int(i64),
+    /// The bits of the number, which tells `0` from `-0`
+    Number(u64),
+    String(String),
+}
+
+impl ConstantKey {
+    fn new(value: &Value) -> Option<Self> {
+        match value {
+            Value::Int(n) => Some(Self::Int(*n)),
+            Value::Number(n) => Some(Self::Number(n.to_bits())),
+            Value::String(s) => Some(Self::String(s.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// The innermost loop being compiled, which `break` and `continue` refer to
+#[derive(Debug, Default)]
+struct LoopState {
+    /// Where `continue` jumps to, i.e. the condition or the increment clause
+    start: usize,
+    /// The scope depth outside the loop body, locals deeper than it are discarded by `break` and
+    /// `continue`
+    scope_depth: i32,
+    /// The `break` jumps to be patched once the loop ends
+    break_jumps: Vec<usize>,
+    /// The slots of the for loop variable and its per-iteration copy, `continue` needs to write
+    /// the copy back
+    loop_variable: Option<(usize, usize)>,
+}
+
+/// What an instruction reads, which `++` and `--` store the new value back into
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    Variable {
+        get_op: OpCode,
+        set_op: OpCode,
+        arg: usize,
+    },
+    /// The receiver is on the stack, the operand is the name
+    Property(usize),
+    /// The list and the index are on the stack
+    Index,
+}
+
+impl Target {
+    /// How many values the instruction reads from the stack
+    fn operands(self) -> u8 {
+        match self {
+            Self::Variable { .. } => 0,
+            Self::Property(_) => 1,
+            Self::Index => 2,
+        }
+    }
+}
+
 // To handle function declaration, we need to let the compiler reset the "state" but keep scanner
 // and parser untouched. That's why I create this struct
 #[derive(Default, Debug)]
@@ -178,12 +365,29 @@ struct CompilerState {
     scope_depth: i32,
     function: Function,
     function_type: FunctionType,
+    /// The loops enclosing the current statement in this function
+    loops: Vec<LoopState>,
+    /// The constants already in the chunk, so e.g. every reference to a global shares one name
+    constants: HashMap<ConstantKey, usize>,
+    /// The last instruction, if it reads a target, with the code it spans. `++` and `--` take it
+    /// back to read and write the same place
+    last_target: Option<(std::ops::Range<usize>, Target)>,
 }
 
 impl CompilerState {
     pub fn new(function_type: FunctionType) -> Self {
+        // The VM uses slot zero for the function being called. In methods it holds the receiver,
+        // so we name it `this` and let the user refer to it
+        let slot_zero = match function_type {
+            FunctionType::Method
+            | FunctionType::Initializer
+            | FunctionType::Getter
+            | FunctionType::Setter => "this",
+            FunctionType::Function | FunctionType::Script => "",
+        };
         Self {
             function_type,
+            locals: vec![Local::new(Token::synthetic(slot_zero), 0, false)],
             ..Default::default()
         }
     }
@@ -194,16 +398,18 @@ impl CompilerState {
     ///     Ok(u8): if we find the local variable and it is initialized
     ///     Err(...): we find the local variable and it is uninitialized
     ///     Err(...): we do ont find the local variable
-    fn resolve_local(&self, token: &Token) -> Result<usize, String> {
+    fn resolve_local(&self, token: &Token, source: &str) -> Result<usize, String> {
         let mut use_uninitialized_variable = false;
         let mut local_index = None;
         for (idx, i) in self.locals.iter().enumerate().rev() {
-            if i.name.lexeme == token.lexeme {
+            if i.name.lexeme(source) == token.lexeme(source) {
                 if i.depth == -1 {
                     use_uninitialized_variable = true;
                 } else {
                     local_index = Some(idx);
                 }
+                // The innermost one shadows the others
+                break;
             }
         }
         if use_uninitialized_variable {
@@ -215,16 +421,16 @@ impl CompilerState {
 
     /// Looks for a local variable declared in any of the surrounding functions
     /// Returns the "upvalue index" if it found, else returns None
-    fn resolve_upvalue(&mut self, name: &Token) -> Option<usize> {
+    fn resolve_upvalue(&mut self, name: &Token, source: &str) -> Option<usize> {
         if let Some(enclosing) = &mut self.enclosing {
             // try to resolve the `name` as a local variable in the enclosing environment
-            if let Ok(idx) = enclosing.resolve_local(name) {
+            if let Ok(idx) = enclosing.resolve_local(name, source) {
                 enclosing.locals[idx].is_captured = true;
                 return Some(self.add_upvalue(idx, true));
             }
 
             // case 2. upvalue stores the upvalue
-            if let Some(idx) = enclosing.resolve_upvalue(name) {
+            if let Some(idx) = enclosing.resolve_upvalue(name, source) {
                 return Some(self.add_upvalue(idx, false));
             }
         }
@@ -253,47 +459,204 @@ impl CompilerState {
     }
 }
 
-#[derive(Debug)]
+/// A script which compiles without errors, to be run later by [`crate::VM::run_function`] or
+/// saved as bytecode
+#[derive(Clone, Debug)]
+pub struct CompiledScript {
+    pub function: Function,
+    /// The warnings found while compiling it, e.g. about the unused variables
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl CompiledScript {
+    /// The script in the `.loxc` format, see [`crate::bytecode`]
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        bytecode::serialize(&self.function)
+    }
+}
+
+/// What the compiler does with the warnings, e.g. about the unused variables
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Warnings {
+    /// Make them compile errors
+    Deny,
+    /// Report them, but the code still runs
+    #[default]
+    Warn,
+    /// Don't look for them
+    Off,
+}
+
+impl FromStr for Warnings {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deny" => Ok(Self::Deny),
+            "warn" => Ok(Self::Warn),
+            "off" => Ok(Self::Off),
+            _ => Err(format!("Unknown warnings level '{s}'.")),
+        }
+    }
+}
+
 pub struct Compiler {
     scanner: Scanner,
+    /// The tokens of the syntax tree being compiled, which replace the scanned ones, see
+    /// [`Compiler::compile_ast`]
+    tokens: Option<Rc<[Token]>>,
+    /// The position of the next token to replay
+    position: usize,
     parser: Parser,
     state: CompilerState,
+    /// The classes being compiled, from the outermost one to the innermost one
+    classes: Vec<ClassCompiler>,
+    /// All the compile errors found so far. The compiler keeps going after an error, so one pass
+    /// can find many of them
+    diagnostics: Vec<Diagnostic>,
+    warnings: Warnings,
+    /// The warnings found so far, which don't stop the compiler
+    warning_diagnostics: Vec<Diagnostic>,
+    /// When linting, the globals defined outside the code, e.g. the natives
+    lint: Option<HashSet<String>>,
+    /// Every compiled function keeps the source code for error reporting
+    source: Rc<str>,
+    /// The file of the source code, if any
+    file: Option<Rc<str>>,
+    /// In the REPL, the top-level expression statements print their values
+    repl: bool,
+    /// Run the peephole optimizer over every function
+    optimize: bool,
+    /// Print the bytecode of every function once it's compiled
+    disassemble: bool,
+    /// Emit the forward jumps with four bytes offsets
+    wide_jumps: bool,
+    /// Some forward jump didn't fit in two bytes, so the code must be compiled with wide jumps
+    jump_too_long: bool,
+    /// How deep the code being compiled is nested, see [`MAX_NESTING`]
+    nesting: usize,
+    /// The code is nested too deeply, so the rest of it is skipped without more errors
+    too_deep: bool,
 }
 
 impl Compiler {
     pub fn new(function_type: FunctionType) -> Self {
         Self {
             scanner: Scanner::new(),
+            tokens: None,
+            position: 0,
             parser: Parser::default(),
             state: CompilerState::new(function_type),
+            classes: vec![],
+            diagnostics: vec![],
+            warnings: Warnings::default(),
+            warning_diagnostics: vec![],
+            lint: None,
+            source: Rc::from(""),
+            file: None,
+            repl: false,
+            optimize: false,
+            disassemble: false,
+            wide_jumps: false,
+            jump_too_long: false,
+            nesting: 0,
+            too_deep: false,
         }
     }
 
+    /// Compile the code typed in the REPL, where `1 + 2` prints `3`. The `;` after the last
+    /// expression can be omitted
+    pub fn repl_mode(mut self) -> Self {
+        self.repl = true;
+        self
+    }
+
+    /// Compile the source code of a file, so the functions know where they come from
+    pub fn file(mut self, path: &str) -> Self {
+        self.file = Some(Rc::from(path));
+        self
+    }
+
+    /// Run the peephole optimizer over the compiled functions, see [`crate::optimizer`]
+    pub fn optimized(mut self) -> Self {
+        self.optimize = true;
+        self
+    }
+
+    /// Print the bytecode of every function once it's compiled, and once more after optimizing it
+    pub fn disassemble(mut self) -> Self {
+        self.disassemble = true;
+        self
+    }
+
+    /// Deny, report or ignore the warnings, see [`Warnings`]
+    pub fn warnings(mut self, warnings: Warnings) -> Self {
+        self.warnings = warnings;
+        self
+    }
+
+    /// Also warn about the locals shadowing other locals, and the globals which are never
+    /// defined, see [`crate::lint`]. `globals` are the ones defined outside the code
+    pub fn lint(mut self, globals: impl IntoIterator<Item = String>) -> Self {
+        self.lint = Some(globals.into_iter().collect());
+        self
+    }
+
     fn error_at(&mut self, token: Token, msg: &str) {
         // While the panic mode flag is set, we simply suppress any other errors that get detected
-        if self.parser.panic_mode {
+        if self.parser.panic_mode || self.too_deep {
             return;
         }
         self.parser.panic_mode = true;
-        eprint!("[line {}] Error", token.line);
-        match token.token_type {
-            TokenType::Eof => eprint!(" at end"),
-            TokenType::Error => eprint!(""),
-            _ => eprint!(" at '{}'", token.lexeme),
-        }
-        eprintln!(": {msg}");
+        let diagnostic = self.diagnostic(token, msg, Severity::Error);
+        self.diagnostics.push(diagnostic);
         self.parser.had_error = true;
     }
 
+    /// Report a warning at the token, unless the warnings are off
+    fn warning_at(&mut self, token: Token, msg: &str) {
+        if self.warnings != Warnings::Off {
+            let diagnostic = self.diagnostic(token, msg, Severity::Warning);
+            self.warning_diagnostics.push(diagnostic);
+        }
+    }
+
+    fn diagnostic(&self, token: Token, msg: &str, severity: Severity) -> Diagnostic {
+        let (location, lexeme) = match token.token_type {
+            TokenType::Eof => (" at end".to_string(), String::new()),
+            // The lexeme of an error token is the error message
+            TokenType::Error => (String::new(), String::new()),
+            _ => {
+                let lexeme = token.lexeme(&self.source).to_string();
+                (format!(" at '{lexeme}'"), lexeme)
+            }
+        };
+        let source_line = self
+            .source
+            .lines()
+            .nth(token.span.line.saturating_sub(1))
+            .unwrap_or_default()
+            .to_string();
+        Diagnostic {
+            severity,
+            file: self.file.as_deref().map(str::to_string),
+            line: token.span.line,
+            column: token.column,
+            span: token.span,
+            lexeme,
+            source_line,
+            location,
+            message: msg.to_string(),
+            notes: vec![],
+        }
+    }
+
     /// Report an error at th location of the token we just consumed
     fn error(&mut self, msg: &str) {
-        let token = std::mem::take(&mut self.parser.previous);
-        self.error_at(token, msg);
+        self.error_at(self.parser.previous, msg);
     }
 
     fn error_at_current(&mut self, msg: &str) {
-        let token = std::mem::take(&mut self.parser.current);
-        self.error_at(token, msg);
+        self.error_at(self.parser.current, msg);
     }
 
     fn advance(&mut self) {
@@ -302,21 +665,47 @@ impl Compiler {
         // Keep looping, reading tokens and reporting the errors, until we hit a non-error one or
         // reach the end
         loop {
-            self.parser.current = self.scanner.scan_token();
+            self.parser.current = self.next_token();
             // println!("prev:    {:?}", self.parser.previous);
             // println!("current: {:?}", self.parser.current);
             if self.parser.current.token_type != TokenType::Error {
                 break;
             }
-            // todo: can we avoid clone() here?
-            self.error_at_current(&self.parser.current.lexeme.clone());
+            // The text of an error token is the error message
+            let msg = self.parser.current.text.unwrap_or_default();
+            self.error_at_current(msg);
         }
     }
 
+    /// Scan the next token, or replay it from the syntax tree. The last one is the `Eof`
+    fn next_token(&mut self) -> Token {
+        let Some(tokens) = &self.tokens else {
+            return self.scanner.scan_token();
+        };
+        let token = tokens[self.position.min(tokens.len() - 1)];
+        self.position += 1;
+        token
+    }
+
     fn expression(&mut self) {
         self.parse_precedence(Precedence::Assignment);
     }
 
+    /// Compile something which may nest, unless the code is nested too deeply already
+    fn nested(&mut self, compile: impl FnOnce(&mut Self)) {
+        if self.nesting >= MAX_NESTING {
+            self.error_at_current("Code nested too deeply.");
+            self.too_deep = true;
+            while !self.check(TokenType::Eof) {
+                self.advance();
+            }
+            return;
+        }
+        self.nesting += 1;
+        compile(self);
+        self.nesting -= 1;
+    }
+
     fn consume(&mut self, token_type: TokenType, msg: &str) {
         if self.parser.current.token_type == token_type {
             self.advance();
@@ -335,8 +724,8 @@ impl Compiler {
     where
         T: Into<u8>,
     {
-        let lineno = self.parser.previous.line;
-        self.current_chunk().write(byte.into(), lineno);
+        let (span, column) = (self.parser.previous.span, self.parser.previous.column);
+        self.current_chunk().write(byte.into(), span, column);
     }
 
     // A utlity function which write two bytes (one-byte Opcode + one-byte Operand)
@@ -349,49 +738,93 @@ impl Compiler {
         self.emit_byte(byte2.into());
     }
 
+    /// Emit an instruction taking the slot of a variable or a constant. The locals beyond the
+    /// 256th one and the constants beyond the 256th one need the wide variant, the upvalues always
+    /// fit in one byte
+    fn emit_variable(&mut self, op: OpCode, arg: usize) {
+        match (u8::try_from(arg), op.wide()) {
+            (Ok(arg), _) => self.emit_bytes(op, arg),
+            (Err(_), Some(wide)) => {
+                self.emit_byte(wide);
+                self.emit_bytes((arg >> 8) as u8, arg as u8);
+            }
+            (Err(_), None) => unreachable!("Only the locals and the constants may need two bytes"),
+        }
+    }
+
     fn emit_constant(&mut self, value: Value) {
         let cosntant_idx = self.make_constant(value);
-        self.emit_bytes(OpCode::Constant, cosntant_idx);
+        self.emit_variable(OpCode::Constant, cosntant_idx);
     }
 
     fn emit_return(&mut self) {
-        // Lox will implicitly return nil
-        self.emit_byte(OpCode::Nil);
+        if self.state.function_type == FunctionType::Initializer {
+            // An initializer always returns the instance, which is in slot zero
+            self.emit_bytes(OpCode::GetLocal, 0);
+        } else if self.state.function_type == FunctionType::Setter {
+            // A setter returns its argument, which is the value of the assignment
+            self.emit_bytes(OpCode::GetLocal, 1);
+        } else {
+            // Lox will implicitly return nil
+            self.emit_byte(OpCode::Nil);
+        }
         self.emit_byte(OpCode::Return);
     }
 
     fn emit_loop(&mut self, loop_start: usize) {
-        self.emit_byte(OpCode::Loop);
-
         // Jump backwards by a given offset
-        // + 2 because we also need to consider the OP_LOOP instruction's own operands(2 bytes)
-        let offset = self.current_chunk().code.len() - loop_start + 2;
-
-        if offset > std::u16::MAX as usize {
+        // + 3 because we also need to consider the OP_LOOP instruction itself and its operands(2
+        // bytes)
+        let offset = self.current_chunk().code.len() - loop_start + 3;
+
+        if let Ok(offset) = u16::try_from(offset) {
+            self.emit_byte(OpCode::Loop);
+            for byte in offset.to_be_bytes() {
+                self.emit_byte(byte);
+            }
+            return;
+        }
+        // The loop start is known, so a long loop body gets the wide instruction right away
+        let Ok(offset) = u32::try_from(offset + 2) else {
             self.error("Loop body too large.");
+            return;
+        };
+        self.emit_byte(OpCode::LoopLong);
+        for byte in offset.to_be_bytes() {
+            self.emit_byte(byte);
         }
-
-        // Jump offset - 2 bytes operand
-        self.emit_byte((offset >> 8) as u8 & std::u8::MAX);
-        self.emit_byte(offset as u8 & std::u8::MAX);
     }
 
     fn end_compiler(&mut self) -> Function {
         self.emit_return();
+        // The parameters and the locals at the top of the function body never go out of scope
+        for local in std::mem::take(&mut self.state.locals) {
+            if local.depth > 0 {
+                self.check_unused(&local);
+            }
+        }
 
-        #[cfg(debug_assertions)]
-        {
-            if !self.parser.had_error {
-                let name = if self.state.function.name.is_empty() {
-                    "<script>".to_string()
-                } else {
-                    self.state.function.name.clone()
-                };
-                disassemble_chunk(self.current_chunk(), &name);
+        let name = if self.state.function.name.is_empty() {
+            "<script>".to_string()
+        } else {
+            self.state.function.name.clone()
+        };
+        // The code with errors isn't run, and may have jumps which aren't patched
+        let finished = !self.parser.had_error && !self.jump_too_long;
+        let disassemble = self.disassemble && finished;
+        if disassemble {
+            disassemble_chunk(self.current_chunk(), &name);
+        }
+        if self.optimize && finished {
+            optimizer::optimize(self.current_chunk());
+            if disassemble {
+                disassemble_chunk(self.current_chunk(), &format!("{name} (optimized)"));
             }
         }
 
-        let ret_function = std::mem::take(&mut self.state.function);
+        let mut ret_function = std::mem::take(&mut self.state.function);
+        ret_function.source = Rc::clone(&self.source);
+        ret_function.file = self.file.clone();
 
         if self.state.enclosing.is_some() {
             self.state = *self.state.enclosing.take().unwrap();
@@ -401,16 +834,21 @@ impl Compiler {
     }
 
     fn number(&mut self, _can_assign: bool) {
-        let value: f64 = self.parser.previous.lexeme.parse().unwrap();
-        self.emit_constant(Value::Number(value));
+        // A literal without a fraction is an integer, unless it's too large for one
+        let lexeme = self.parser.previous.lexeme(&self.source);
+        let value = match lexeme.parse() {
+            Ok(n) => Value::Int(n),
+            Err(_) => Value::Number(lexeme.parse().unwrap()),
+        };
+        self.emit_constant(value);
     }
 
     fn string(&mut self, _can_assign: bool) {
-        let end = self.parser.previous.lexeme.len() - 2;
+        // Trim the quotes
+        let lexeme = self.parser.previous.lexeme(&self.source);
+        let value = Value::string(lexeme[1..lexeme.len() - 1].to_string());
         // todo: or create a objects field for the Chunk struct
-        self.emit_constant(Value::String(
-            self.parser.previous.lexeme[1..=end].to_string(),
-        ));
+        self.emit_constant(value);
     }
 
     fn grouping(&mut self, _can_assign: bool) {
@@ -420,7 +858,7 @@ impl Compiler {
     }
 
     fn unary(&mut self, _can_assign: bool) {
-        let operator_type = self.parser.previous.token_type.clone();
+        let operator_type = self.parser.previous.token_type;
 
         // Compile the operand
         self.parse_precedence(Precedence::Unary);
@@ -429,21 +867,35 @@ impl Compiler {
         match operator_type {
             TokenType::Bang => self.emit_byte(OpCode::Not),
             TokenType::Minus => self.emit_byte(OpCode::Negate),
+            TokenType::Tilde => self.emit_byte(OpCode::BitNot),
             _ => panic!("Unreachable!"),
         }
     }
 
     fn binary(&mut self, _can_assign: bool) {
-        let operator_type = self.parser.previous.token_type.clone();
-        let rule = ParseRule::get_rule(operator_type.clone());
-        self.parse_precedence(rule.precedence.next());
+        let operator_type = self.parser.previous.token_type;
+        if operator_type == TokenType::StarStar {
+            // Right-associative, so `2 ** 3 ** 2` is `2 ** (3 ** 2)`. The exponent may be negated,
+            // e.g. `2 ** -1`
+            self.parse_precedence(Precedence::Unary);
+        } else {
+            let rule = ParseRule::get_rule(operator_type);
+            self.parse_precedence(rule.precedence.next());
+        }
 
         match operator_type {
             TokenType::Plus => self.emit_byte(OpCode::Add),
             TokenType::Minus => self.emit_byte(OpCode::Substract),
             TokenType::Star => self.emit_byte(OpCode::Multiply),
             TokenType::Slash => self.emit_byte(OpCode::Divide),
-            TokenType::BangEqual => self.emit_bytes(OpCode::Equal, OpCode::Not),
+            TokenType::Percent => self.emit_byte(OpCode::Modulo),
+            TokenType::StarStar => self.emit_byte(OpCode::Power),
+            TokenType::Ampersand => self.emit_byte(OpCode::BitAnd),
+            TokenType::Pipe => self.emit_byte(OpCode::BitOr),
+            TokenType::Caret => self.emit_byte(OpCode::BitXor),
+            TokenType::LessLess => self.emit_byte(OpCode::ShiftLeft),
+            TokenType::GreaterGreater => self.emit_byte(OpCode::ShiftRight),
+            TokenType::BangEqual => self.emit_byte(OpCode::NotEqual),
             TokenType::EqualEqual => self.emit_byte(OpCode::Equal),
             TokenType::Greater => self.emit_byte(OpCode::Greater),
             TokenType::GreaterEqual => self.emit_bytes(OpCode::Less, OpCode::Not),
@@ -455,27 +907,143 @@ impl Compiler {
 
     /// Return the number of arguments
     /// Each argument expression generates code that leaves its value on the stack
-    fn argument_list(&mut self) -> u8 {
-        let mut arg_cnt = 0;
-        if !self.check(TokenType::RightParen) {
+    fn argument_list(&mut self) -> Elements {
+        let arguments = self.elements(TokenType::RightParen, false);
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        arguments
+    }
+
+    /// Compile the comma-separated expressions of a call or a list literal up to `closing`. They
+    /// stay on the stack one by one, until a `...list` spreads into them and they are packed into
+    /// one list instead. The elements of a list literal are also packed once there are too many
+    /// of them for one `OP_BUILD_LIST`
+    fn elements(&mut self, closing: TokenType, is_list: bool) -> Elements {
+        let mut count = 0;
+        let mut packed = false;
+        if !self.check(closing) {
             loop {
+                let spread = self.my_match(TokenType::DotDotDot);
+                if !packed && (spread || (is_list && count == u8::MAX)) {
+                    self.emit_bytes(OpCode::BuildList, count);
+                    packed = true;
+                }
                 self.expression();
-                if arg_cnt == u8::MAX {
-                    self.error("Can't have more than 255 arguments.");
+                if packed {
+                    let op = if spread {
+                        OpCode::ExtendList
+                    } else {
+                        OpCode::AppendList
+                    };
+                    self.emit_byte(op);
+                } else {
+                    if count == u8::MAX {
+                        self.error("Can't have more than 255 arguments.");
+                    }
+                    count = count.saturating_add(1);
                 }
-                arg_cnt += 1;
                 if !self.my_match(TokenType::Comma) {
                     break;
                 }
             }
         }
-        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
-        arg_cnt
+        if packed {
+            Elements::Packed
+        } else {
+            Elements::Each(count)
+        }
     }
 
     fn call(&mut self, _can_assign: bool) {
-        let arg_cnt = self.argument_list();
-        self.emit_bytes(OpCode::Call, arg_cnt);
+        match self.argument_list() {
+            Elements::Each(arg_cnt) => self.emit_bytes(OpCode::Call, arg_cnt),
+            Elements::Packed => self.emit_byte(OpCode::CallSpread),
+        }
+    }
+
+    /// A list literal, e.g. `[1, 2, ...rest]`
+    fn list(&mut self, _can_assign: bool) {
+        if let Elements::Each(count) = self.elements(TokenType::RightBracket, true) {
+            self.emit_bytes(OpCode::BuildList, count);
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.");
+    }
+
+    /// A range of integers, e.g. `1..10` or `1..=10`
+    fn range(&mut self, _can_assign: bool) {
+        let inclusive = self.parser.previous.token_type == TokenType::DotDotEqual;
+        self.parse_precedence(Precedence::Range.next());
+        self.emit_bytes(OpCode::Range, u8::from(inclusive));
+    }
+
+    /// Reading or assigning an element of a list, e.g. `xs[0]` or `xs[0] = 1`, or a slice of a
+    /// list or a string, e.g. `xs[1:3]`, whose left out bounds are nil
+    fn index(&mut self, can_assign: bool) {
+        if self.check(TokenType::Colon) {
+            self.emit_byte(OpCode::Nil);
+        } else {
+            self.expression();
+        }
+        if self.my_match(TokenType::Colon) {
+            if self.check(TokenType::RightBracket) {
+                self.emit_byte(OpCode::Nil);
+            } else {
+                self.expression();
+            }
+            self.consume(TokenType::RightBracket, "Expect ']' after slice.");
+            self.emit_byte(OpCode::Slice);
+            return;
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+        if can_assign && self.my_match(TokenType::Equal) {
+            self.expression();
+            self.emit_byte(OpCode::SetIndex);
+        } else {
+            self.emit_target(Target::Index);
+        }
+    }
+
+    /// Property access (getter) or assignment (setter) on an instance
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenType::Identifier, "Expect property name after '.'.");
+        let name = self.identifier_constant(self.parser.previous);
+
+        if can_assign && self.my_match(TokenType::Equal) {
+            // e.g. foo.bar = 1
+            self.expression();
+            self.emit_variable(OpCode::SetProperty, name);
+        } else if self.my_match(TokenType::LeftParen) {
+            // e.g. foo.bar(1), which calls the method without creating a bound method first
+            match self.argument_list() {
+                Elements::Each(arg_cnt) => {
+                    self.emit_variable(OpCode::Invoke, name);
+                    self.emit_byte(arg_cnt);
+                }
+                Elements::Packed => self.emit_variable(OpCode::InvokeSpread, name),
+            }
+        } else {
+            self.emit_target(Target::Property(name));
+        }
+    }
+
+    /// `obj?.field`, which is nil when `obj` is nil. The rest of the chain is skipped too, e.g.
+    /// `obj?.field.method()`, so it's compiled here
+    fn optional_dot(&mut self, _can_assign: bool) {
+        self.emit_byte(OpCode::IsNil);
+        let present_jump = self.emit_jump(OpCode::JumpIfFalse);
+        // The nil stays as the result
+        self.emit_byte(OpCode::Pop);
+        let end_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(present_jump);
+        self.emit_byte(OpCode::Pop);
+
+        self.dot(false);
+        while Precedence::Call <= ParseRule::get_rule(self.parser.current.token_type).precedence {
+            self.advance();
+            if let Some(infix_rule) = ParseRule::get_rule(self.parser.previous.token_type).infix {
+                infix_rule(self, false);
+            }
+        }
+        self.patch_jump(end_jump);
     }
 
     fn literal(&mut self, _can_assign: bool) {
@@ -508,10 +1076,97 @@ impl Compiler {
         self.patch_jump(end_jump);
     }
 
+    /// Read the target, and remember it for `++` and `--`
+    fn emit_target(&mut self, target: Target) {
+        let start = self.current_chunk().code.len();
+        match target {
+            Target::Variable { get_op, arg, .. } => self.emit_variable(get_op, arg),
+            Target::Property(name) => self.emit_variable(OpCode::GetProperty, name),
+            Target::Index => self.emit_byte(OpCode::GetIndex),
+        }
+        let end = self.current_chunk().code.len();
+        self.state.last_target = Some((start..end, target));
+    }
+
+    /// `++x` and `--x`, which are the new value
+    fn prefix_step(&mut self, _can_assign: bool) {
+        let operator = self.parser.previous.token_type;
+        self.parse_precedence(Precedence::Unary);
+        self.step(operator, false);
+    }
+
+    /// `x++` and `x--`, which are the old value
+    fn postfix_step(&mut self, _can_assign: bool) {
+        let operator = self.parser.previous.token_type;
+        self.step(operator, true);
+    }
+
+    /// Take back the instruction reading the operand, and read, change and write it instead. The
+    /// receiver and the index are copied because both the read and the write consume them
+    fn step(&mut self, operator: TokenType, postfix: bool) {
+        let code_len = self.current_chunk().code.len();
+        let Some((code, target)) = self
+            .state
+            .last_target
+            .take()
+            .filter(|(code, _)| code.end == code_len)
+        else {
+            self.error("Invalid increment or decrement target.");
+            return;
+        };
+        self.current_chunk().truncate(code.start);
+
+        let operands = target.operands();
+        for _ in 0..operands {
+            self.emit_bytes(OpCode::Dup, operands - 1);
+        }
+        self.emit_target(target);
+        if postfix {
+            // Keep the old value under the operands of the write
+            self.emit_bytes(OpCode::Dup, 0);
+            if operands > 0 {
+                self.emit_bytes(OpCode::Bury, operands + 1);
+            }
+        }
+        self.emit_constant(Value::Int(1));
+        match operator {
+            TokenType::PlusPlus => self.emit_byte(OpCode::Add),
+            TokenType::MinusMinus => self.emit_byte(OpCode::Substract),
+            _ => panic!("Unreachable!"),
+        }
+        match target {
+            Target::Variable { set_op, arg, .. } => self.emit_variable(set_op, arg),
+            Target::Property(name) => self.emit_variable(OpCode::SetProperty, name),
+            Target::Index => self.emit_byte(OpCode::SetIndex),
+        }
+        if postfix {
+            self.emit_byte(OpCode::Pop);
+        }
+        self.state.last_target = None;
+    }
+
+    /// `value ?? default`, which only evaluates `default` when `value` is nil
+    fn coalesce(&mut self, _can_assign: bool) {
+        self.emit_byte(OpCode::IsNil);
+        let present_jump = self.emit_jump(OpCode::JumpIfFalse);
+        // The `true` and the nil
+        self.emit_bytes(OpCode::Pop, OpCode::Pop);
+        self.parse_precedence(Precedence::Coalesce);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(present_jump);
+        self.emit_byte(OpCode::Pop);
+        self.patch_jump(end_jump);
+    }
+
     fn parse_precedence(&mut self, precedence: Precedence) {
+        self.nested(|compiler| compiler.parse_precedence_nested(precedence));
+    }
+
+    fn parse_precedence_nested(&mut self, precedence: Precedence) {
         // Read the next token and look up the corresponding ParseRule
         self.advance();
-        let previous_token_type = self.parser.previous.token_type.clone();
+        let previous_token_type = self.parser.previous.token_type;
 
         // Look up a prefix parser for the current token, the first token is always going to belong
         // to some kind of prefix expression
@@ -524,14 +1179,12 @@ impl Compiler {
         let can_assign = precedence <= Precedence::Assignment;
         prefix_rule(self, can_assign);
 
-        while precedence <= ParseRule::get_rule(self.parser.current.token_type.clone()).precedence {
+        while precedence <= ParseRule::get_rule(self.parser.current.token_type).precedence {
             self.advance();
             // Look up for an infix parser for the next token
             // If we find one, it means the prefix expression we already compiled might be an
             // operand for it
-            if let Some(infix_rule) =
-                ParseRule::get_rule(self.parser.previous.token_type.clone()).infix
-            {
+            if let Some(infix_rule) = ParseRule::get_rule(self.parser.previous.token_type).infix {
                 // Usually, it will consume the right operand
                 infix_rule(self, can_assign);
             }
@@ -547,6 +1200,16 @@ impl Compiler {
         self.parser.current.token_type == expected
     }
 
+    /// Return `true` if the token after the current one has the given token type, without
+    /// consuming anything
+    fn check_next(&self, expected: TokenType) -> bool {
+        let next = match &self.tokens {
+            Some(tokens) => tokens[self.position.min(tokens.len() - 1)],
+            None => self.scanner.clone().scan_token(),
+        };
+        next.token_type == expected
+    }
+
     /// Consume the current token and return `true` if it hash the given token type, otherwise
     /// return `false`
     fn my_match(&mut self, expected: TokenType) -> bool {
@@ -564,11 +1227,33 @@ impl Compiler {
         self.emit_byte(OpCode::Print);
     }
 
+    /// `import "path";` runs the module once, which defines its globals
+    fn import_statement(&mut self) {
+        self.consume(TokenType::STRING, "Expect module path after 'import'.");
+        let lexeme = self.parser.previous.lexeme(&self.source);
+        let path = Value::string(lexeme[1..lexeme.len() - 1].to_string());
+        let constant = self.make_constant(path);
+        // Emitted before the `;`, so a runtime error points at the module path
+        self.emit_variable(OpCode::Import, constant);
+        self.consume(TokenType::Semicolon, "Expect ';' after module path.");
+        // The value returned by the module, or `nil` if it has run before
+        self.emit_byte(OpCode::Pop);
+    }
+
     /// A expression followed by a semicolon
     fn expression_statement(&mut self) {
         self.expression();
-        self.consume(TokenType::Semicolon, "Expect ';' after expression.");
-        self.emit_byte(OpCode::Pop);
+        let top_level = self.repl
+            && self.state.function_type == FunctionType::Script
+            && self.state.scope_depth == 0;
+        if !(top_level && self.check(TokenType::Eof)) {
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+        }
+        self.emit_byte(if top_level {
+            OpCode::Print
+        } else {
+            OpCode::Pop
+        });
     }
 
     /// To "create" a scope, we just need to increment the current depth
@@ -588,26 +1273,49 @@ impl Compiler {
                 } else {
                     OpCode::Pop
                 });
-                self.state.locals.pop().unwrap();
+                let local = self.state.locals.pop().unwrap();
+                self.check_unused(&local);
             } else {
                 break;
             }
         }
     }
 
-    /// Emit jump instruction and placeholder(2 bytes) and return the offset of the emitted
-    /// instruction
-    fn emit_jump<T>(&mut self, instruction: T) -> usize
-    where
-        T: Into<u8>,
-    {
+    /// Warn about a local going out of scope without being read. The hidden locals and the ones
+    /// named like `_unused` are left alone
+    fn check_unused(&mut self, local: &Local) {
+        let name = local.name.lexeme(&self.source);
+        if local.is_read || local.is_captured || local.name.text.is_some() || name.starts_with('_')
+        {
+            return;
+        }
+        let message = if local.is_parameter {
+            format!("Unused parameter '{name}'.")
+        } else if local.is_assigned {
+            format!("Local variable '{name}' is assigned but never read.")
+        } else {
+            format!("Unused local variable '{name}'.")
+        };
+        self.warning_at(local.name, &message);
+    }
+
+    /// Emit jump instruction and placeholder(2 bytes, or 4 bytes with wide jumps) and return the
+    /// offset of the emitted instruction
+    #[allow(clippy::legacy_numeric_constants)]
+    fn emit_jump(&mut self, instruction: OpCode) -> usize {
+        // The jump target isn't known yet, so the width is decided for the whole script
+        let (instruction, width) = match instruction {
+            OpCode::Jump if self.wide_jumps => (OpCode::JumpLong, 4),
+            OpCode::JumpIfFalse if self.wide_jumps => (OpCode::JumpIfFalseLong, 4),
+            instruction => (instruction, 2),
+        };
         self.emit_byte(instruction);
         // placeholder for jump offset
-        // use 2 bytes for the jump offset operand
-        self.emit_byte(std::u8::MAX);
-        self.emit_byte(std::u8::MAX);
+        for _ in 0..width {
+            self.emit_byte(std::u8::MAX);
+        }
 
-        self.current_chunk().code.len() - 2
+        self.current_chunk().code.len() - width
     }
 
     /// Replace the operand at the given location with the calculated jump offset
@@ -615,12 +1323,28 @@ impl Compiler {
     /// This function should be called before we emit the next instruction that we want the jump to
     /// land on
     fn patch_jump(&mut self, offset: usize) {
-        let jump = self.current_chunk().code.len() - offset - 2;
-        if jump > std::u16::MAX as usize {
-            self.error("Too much code to jump over.");
+        // The code before the jump target can't be taken back anymore
+        self.state.last_target = None;
+        let wide = matches!(
+            OpCode::try_from(self.current_chunk().code[offset - 1]),
+            Ok(OpCode::JumpLong | OpCode::JumpIfFalseLong)
+        );
+        if wide {
+            let jump = self.current_chunk().code.len() - offset - 4;
+            let Ok(jump) = u32::try_from(jump) else {
+                self.error("Too much code to jump over.");
+                return;
+            };
+            self.current_chunk().code[offset..offset + 4].copy_from_slice(&jump.to_be_bytes());
+        } else {
+            let jump = self.current_chunk().code.len() - offset - 2;
+            let Ok(jump) = u16::try_from(jump) else {
+                // `compile` starts over with wide jumps
+                self.jump_too_long = true;
+                return;
+            };
+            self.current_chunk().code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
         }
-        self.current_chunk().code[offset] = ((jump >> 8) as u8) & std::u8::MAX;
-        self.current_chunk().code[offset + 1] = jump as u8 & std::u8::MAX;
     }
 
     fn if_statement(&mut self) {
@@ -651,21 +1375,102 @@ impl Compiler {
 
         let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
         self.emit_byte(OpCode::Pop); // pop the condition expression bool
+        self.begin_loop(loop_start, None);
         self.statement();
 
         self.emit_loop(loop_start);
 
         self.patch_jump(exit_jump); // jump to the next statement after the while body
         self.emit_byte(OpCode::Pop); // pop the condition expression bool, another path
+        self.end_loop();
+    }
+
+    fn begin_loop(&mut self, start: usize, loop_variable: Option<(usize, usize)>) {
+        self.state.loops.push(LoopState {
+            start,
+            scope_depth: self.state.scope_depth,
+            break_jumps: vec![],
+            loop_variable,
+        });
+    }
+
+    /// Patch all the `break` jumps to land here
+    fn end_loop(&mut self) {
+        let current_loop = self.state.loops.pop().unwrap();
+        for jump in current_loop.break_jumps {
+            self.patch_jump(jump);
+        }
+    }
+
+    /// Emit the code discarding the locals declared inside the innermost loop body without
+    /// removing them from the compiler, because the code after `break` or `continue` is still in
+    /// their scope
+    fn discard_loop_locals(&mut self) {
+        let depth = self.state.loops.last().unwrap().scope_depth;
+        let discarded: Vec<_> = self
+            .state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| local.is_captured)
+            .collect();
+        for is_captured in discarded {
+            self.emit_byte(if is_captured {
+                OpCode::ClosedUpvalue
+            } else {
+                OpCode::Pop
+            });
+        }
+    }
+
+    fn break_statement(&mut self) {
+        if self.state.loops.is_empty() {
+            self.error("Can't use 'break' outside of a loop.");
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.");
+        if self.state.loops.is_empty() {
+            return;
+        }
+        self.discard_loop_locals();
+        let jump = self.emit_jump(OpCode::Jump);
+        self.state.loops.last_mut().unwrap().break_jumps.push(jump);
+    }
+
+    fn continue_statement(&mut self) {
+        if self.state.loops.is_empty() {
+            self.error("Can't use 'continue' outside of a loop.");
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.");
+        let Some(current_loop) = self.state.loops.last() else {
+            return;
+        };
+        let start = current_loop.start;
+        if let Some((slot, inner_slot)) = current_loop.loop_variable {
+            self.emit_variable(OpCode::GetLocal, inner_slot);
+            self.emit_variable(OpCode::SetLocal, slot);
+            self.emit_byte(OpCode::Pop);
+        }
+        self.discard_loop_locals();
+        self.emit_loop(start);
     }
 
     fn for_statement(&mut self) {
         self.begin_scope();
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
-        if self.my_match(TokenType::Semicolon) {
-            // no intializer
-        } else if self.my_match(TokenType::Var) {
+        let is_var = self.my_match(TokenType::Var);
+        // `for (x in xs)` or `for (var x in xs)`
+        if self.check(TokenType::Identifier) && self.check_next(TokenType::In) {
+            self.for_in_statement();
+            return;
+        }
+        // The slot of the variable declared in the initializer clause
+        let mut loop_variable = None;
+        if is_var {
             self.var_declaration();
+            loop_variable = Some(self.state.locals.len() - 1);
+        } else if self.my_match(TokenType::Semicolon) {
+            // no intializer
         } else {
             self.expression_statement();
         }
@@ -698,12 +1503,79 @@ impl Compiler {
             self.patch_jump(bodyjump);
         }
 
+        self.begin_loop(loop_start, None);
+        // Each iteration gets its own copy of the loop variable, so closures created in the body
+        // capture the value of that iteration instead of sharing one variable
+        if let Some(slot) = loop_variable {
+            self.begin_scope();
+            let name = self.state.locals[slot].name;
+            self.emit_variable(OpCode::GetLocal, slot);
+            self.add_local(name);
+            self.mark_initialized();
+            let inner_slot = self.state.locals.len() - 1;
+            self.state.loops.last_mut().unwrap().loop_variable = Some((slot, inner_slot));
+        }
+
         self.statement(); // loop body
+
+        if let Some(slot) = loop_variable {
+            // Write the (maybe modified) copy back before running the increment clause
+            let inner_slot = self.state.locals.len() - 1;
+            // Reading the copy is reading the loop variable, and the copy itself is hidden
+            let copy = &mut self.state.locals[inner_slot];
+            let (copy_read, copy_assigned) = (copy.is_read || copy.is_captured, copy.is_assigned);
+            copy.is_read = true;
+            let variable = &mut self.state.locals[slot];
+            variable.is_read |= copy_read;
+            variable.is_assigned |= copy_assigned;
+            self.emit_variable(OpCode::GetLocal, inner_slot);
+            self.emit_variable(OpCode::SetLocal, slot);
+            self.emit_byte(OpCode::Pop);
+            self.end_scope();
+        }
         self.emit_loop(loop_start);
         if let Some(v) = exit_jump {
             self.patch_jump(v);
             self.emit_byte(OpCode::Pop); // Pop condition
         }
+        self.end_loop();
+        self.end_scope();
+    }
+
+    /// `for (x in xs) body`, over the elements of a list, the characters of a string or the
+    /// numbers of a range. The scope of the `for` has begun already
+    fn for_in_statement(&mut self) {
+        self.consume(TokenType::Identifier, "Expect variable name.");
+        let name = self.parser.previous;
+        self.consume(TokenType::In, "Expect 'in' after loop variable.");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+        // The iterable and where the loop is in it live in hidden locals, which can't be named
+        self.add_local(Token::synthetic("for iterable"));
+        self.mark_initialized();
+        let slot = self.state.locals.len() - 1;
+        self.emit_constant(Value::Int(0));
+        self.add_local(Token::synthetic("for position"));
+        self.mark_initialized();
+
+        let loop_start = self.current_chunk().code.len();
+        self.emit_byte(OpCode::IterNext);
+        self.emit_bytes((slot >> 8) as u8, slot as u8);
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.begin_loop(loop_start, None);
+        // Each element is a new variable, so closures created in the body capture their own
+        self.begin_scope();
+        self.add_local(name);
+        self.mark_initialized();
+        self.statement();
+        self.end_scope();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        // The `false` and the `nil` in place of the element
+        self.emit_bytes(OpCode::Pop, OpCode::Pop);
+        self.end_loop();
         self.end_scope();
     }
 
@@ -716,6 +1588,11 @@ impl Compiler {
             // `emit_return` will implicitly return nil
             self.emit_return();
         } else {
+            if self.state.function_type == FunctionType::Initializer {
+                self.error("Can't return a value from an initializer.");
+            } else if self.state.function_type == FunctionType::Setter {
+                self.error("Can't return a value from a setter.");
+            }
             self.expression();
             self.consume(TokenType::Semicolon, "Expect ';' after return value.");
             self.emit_byte(OpCode::Return);
@@ -726,22 +1603,37 @@ impl Compiler {
     /// check for the end of the token stream
     fn block(&mut self) {
         // block        -> "{" declarations* "}"
+        let (mut returned, mut warned) = (false, false);
         while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            if returned && !warned {
+                self.warning_at(self.parser.current, "Unreachable code after 'return'.");
+                warned = true;
+            }
+            returned |= self.check(TokenType::Return);
             self.declaration()
         }
         self.consume(TokenType::RightBrace, "Expect '}' after block.");
     }
 
     fn statement(&mut self) {
+        self.nested(Self::statement_nested);
+    }
+
+    fn statement_nested(&mut self) {
         // statement    -> exprStmt
         //              |  printStmt
         //              |  ifStmt
         //              |  whileStmt
         //              |  forStmt
         //              |  returnStmt
+        //              |  breakStmt
+        //              |  continueStmt
+        //              |  importStmt
         //              |  block ;
         if self.my_match(TokenType::Print) {
             self.print_statement();
+        } else if self.my_match(TokenType::Import) {
+            self.import_statement();
         } else if self.my_match(TokenType::If) {
             self.if_statement();
         } else if self.my_match(TokenType::While) {
@@ -750,6 +1642,10 @@ impl Compiler {
             self.for_statement();
         } else if self.my_match(TokenType::Return) {
             self.return_statement();
+        } else if self.my_match(TokenType::Break) {
+            self.break_statement();
+        } else if self.my_match(TokenType::Continue) {
+            self.continue_statement();
         } else if self.my_match(TokenType::LeftBrace) {
             self.begin_scope();
             self.block();
@@ -759,22 +1655,33 @@ impl Compiler {
         }
     }
     /// Try to add the value to constants, return 0 if we got too many constants
-    fn make_constant(&mut self, value: Value) -> u8 {
-        let Ok(constant_idx) = self.current_chunk().add_constant(value).try_into() else {
+    /// Add the value to the constants of the chunk, unless it's there already, and return its
+    /// index. The instructions with a wide variant take up to 65536 constants
+    fn make_constant(&mut self, value: Value) -> usize {
+        let key = ConstantKey::new(&value);
+        if let Some(idx) = key.as_ref().and_then(|key| self.state.constants.get(key)) {
+            return *idx;
+        }
+        if self.current_chunk().constants.values.len() > u16::MAX as usize {
             self.error("Too many constants in one chunk.");
             // todo: or return a Result<T, E>?
             return 0;
-        };
+        }
+        let constant_idx = self.current_chunk().add_constant(value);
+        if let Some(key) = key {
+            self.state.constants.insert(key, constant_idx);
+        }
         constant_idx
     }
 
-    fn identifier_constant(&mut self, name: Token) -> u8 {
-        self.make_constant(Value::String(name.lexeme))
+    fn identifier_constant(&mut self, name: Token) -> usize {
+        let name = name.lexeme(&self.source).to_string();
+        self.make_constant(Value::string(name))
     }
 
     /// Consume the next token, which must be an identifier. Add its lexeme to the chunks's
     /// constants table as a string, and then returns the constant table index where it was added
-    fn parse_variable(&mut self, error_msg: &str) -> u8 {
+    fn parse_variable(&mut self, error_msg: &str) -> usize {
         self.consume(TokenType::Identifier, error_msg);
         self.declare_variable();
         // Exit the function  and return a dummy index if we're in a local scope
@@ -782,18 +1689,21 @@ impl Compiler {
         if self.state.scope_depth > 0 {
             return 0;
         }
-        let previous_token = std::mem::take(&mut self.parser.previous);
+        let previous_token = self.parser.previous;
         self.identifier_constant(previous_token)
     }
 
     /// Add the local variable to the compilers's list of variables
     fn add_local(&mut self, token: Token) {
-        if self.state.locals.len() == std::u8::MAX as usize {
+        // The wide instructions address the slots by two bytes
+        if self.state.locals.len() > u16::MAX as usize {
             self.error("Too many local variables in function.");
             return;
         }
         // -1 is a special sentinel value - this local variable is in "unitialized" state
         self.state.locals.push(Local::new(token, -1, false));
+        let function = &mut self.state.function;
+        function.max_locals = function.max_locals.max(self.state.locals.len());
     }
 
     fn declare_variable(&mut self) {
@@ -802,7 +1712,7 @@ impl Compiler {
             return;
         }
         // Prevent redeclaring a variable with the same name as previous declaration
-        let name = std::mem::take(&mut self.parser.previous);
+        let name = self.parser.previous;
         let mut same_name_in_same_scope = false;
         for token in self.state.locals.iter().rev() {
             // It's only an error to have 2 variables with the same name in the same local scope,
@@ -810,18 +1720,44 @@ impl Compiler {
             if token.depth < self.state.scope_depth {
                 break;
             }
-            if token.name.lexeme == name.lexeme {
+            if token.name.lexeme(&self.source) == name.lexeme(&self.source) {
                 same_name_in_same_scope = true;
                 break;
             }
         }
         if same_name_in_same_scope {
             self.error("Already a variable with this name in this scope.");
+        } else if self.lint.is_some() && self.shadows_local(name) {
+            let message = format!(
+                "Local variable '{}' shadows a variable in an outer scope.",
+                name.lexeme(&self.source)
+            );
+            self.warning_at(name, &message);
         }
 
         self.add_local(name);
     }
 
+    /// Whether a local of an outer scope, in this function or the ones around it, has the name
+    fn shadows_local(&self, name: Token) -> bool {
+        let name = name.lexeme(&self.source);
+        if name.starts_with('_') {
+            return false;
+        }
+        let mut state = Some(&self.state);
+        while let Some(current) = state {
+            let shadowed = current
+                .locals
+                .iter()
+                .any(|local| local.name.text.is_none() && local.name.lexeme(&self.source) == name);
+            if shadowed {
+                return true;
+            }
+            state = current.enclosing.as_deref();
+        }
+        false
+    }
+
     fn mark_initialized(&mut self) {
         // when we declare a function in the top-level, the function is bound to a global variable.
         // There is no local variable to mark initialized
@@ -835,12 +1771,12 @@ impl Compiler {
 
     /// Emit the bytecode for storing the variable's value in the global variable hashtable
     /// Emit the bytecode to store a local variable if we're in a local scope(just return)
-    fn define_variable(&mut self, global: u8) {
+    fn define_variable(&mut self, global: usize) {
         if self.state.scope_depth > 0 {
             self.mark_initialized();
             return;
         }
-        self.emit_bytes(OpCode::DefineGlobal, global);
+        self.emit_variable(OpCode::DefineGlobal, global);
     }
 
     fn var_declaration(&mut self) {
@@ -866,14 +1802,61 @@ impl Compiler {
     }
 
     fn function(&mut self, func_name: String, func_type: FunctionType) {
-        let old_state = std::mem::take(&mut self.state);
-        self.state.function_type = func_type;
+        self.nested(|compiler| compiler.function_nested(func_name, func_type));
+    }
+
+    fn function_nested(&mut self, func_name: String, func_type: FunctionType) {
+        let old_state = std::mem::replace(&mut self.state, CompilerState::new(func_type));
         self.state.function.name = func_name;
         self.state.enclosing = Some(Box::new(old_state));
         // now we have a new state to operate on
 
         self.begin_scope();
 
+        // A getter has no parameter list at all
+        if func_type != FunctionType::Getter {
+            self.parameters();
+        }
+        if func_type == FunctionType::Setter && self.state.function.arity != 1 {
+            self.error("A setter must take exactly one parameter.");
+        }
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        // Note: after self.end_compiler(), the current CompilerState will revert
+        // there is no way to get upvalues. So I first clone the upvalues
+        // todo! can we find a better way?
+        let upvalues = self.state.function.upvalues.clone();
+        let function = self.end_compiler();
+        let val = self.make_constant(Value::Func(Rc::new(function)));
+        self.emit_variable(OpCode::Closure, val);
+
+        // A local upvalue may capture any of the 65536 slots, so the index takes two bytes
+        for upvalue in upvalues {
+            self.emit_byte(u8::from(upvalue.is_local));
+            self.emit_bytes((upvalue.index >> 8) as u8, upvalue.index as u8);
+        }
+    }
+
+    /// The default value of the last parameter, e.g. `greeting = "hi"`. It's computed at the
+    /// start of the function when the argument is left out, so it may use the parameters before
+    fn default_value(&mut self) {
+        // The parameters are in the slots after slot zero
+        let slot = self.state.function.arity as u8;
+        self.emit_bytes(OpCode::ArgMissing, slot);
+        let given_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.expression();
+        self.emit_bytes(OpCode::SetLocal, slot);
+        self.emit_byte(OpCode::Pop);
+        let end_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(given_jump);
+        self.emit_byte(OpCode::Pop);
+        self.patch_jump(end_jump);
+    }
+
+    /// The parenthesized parameter list, which declares the parameters as locals
+    fn parameters(&mut self) {
         self.consume(TokenType::LeftParen, "Expect '(' after function name.");
         if !self.check(TokenType::RightParen) {
             loop {
@@ -881,8 +1864,25 @@ impl Compiler {
                 if self.state.function.arity > 255 {
                     self.error_at_current("Can't have more than 255 parameters.");
                 }
+                // e.g. `...rest`, which collects the arguments after the other parameters
+                let is_rest = self.my_match(TokenType::DotDotDot);
                 let constant = self.parse_variable("Expect parameter name.");
                 self.define_variable(constant);
+                if let Some(local) = self.state.locals.last_mut() {
+                    local.is_parameter = true;
+                }
+                if is_rest {
+                    self.state.function.is_variadic = true;
+                    if self.check(TokenType::Comma) {
+                        self.error_at_current("The rest parameter must be the last one.");
+                    }
+                } else if self.my_match(TokenType::Equal) {
+                    self.default_value();
+                } else if self.state.function.min_arity + 1 < self.state.function.arity {
+                    self.error("A parameter without a default value can't follow one with it.");
+                } else {
+                    self.state.function.min_arity = self.state.function.arity;
+                }
 
                 if !self.my_match(TokenType::Comma) {
                     break;
@@ -890,25 +1890,36 @@ impl Compiler {
             }
         }
         self.consume(TokenType::RightParen, "Expect ')' after parameters.");
-        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
-        self.block();
-
-        // Note: after self.end_compiler(), the current CompilerState will revert
-        // there is no way to get upvalues. So I first clone the upvalues
-        // todo! can we find a better way?
-        let upvalues = self.state.function.upvalues.clone();
-        let function = self.end_compiler();
-        let val = self.make_constant(Value::Func(Rc::new(function)));
-        self.emit_bytes(OpCode::Closure, val);
+    }
 
-        for i in 0..upvalues.len() {
-            self.emit_byte(if upvalues[i].is_local { 1 } else { 0 });
-            self.emit_byte(upvalues[i].index as u8);
+    /// An anonymous function in expression position, e.g. `var f = fun(a, b) { return a + b; };`
+    /// `yield value` suspends the coroutine with the value, and evaluates to the value passed to
+    /// the `resume` continuing it. A function with a `yield` makes a coroutine when called
+    fn yield_(&mut self, _can_assign: bool) {
+        match self.state.function_type {
+            FunctionType::Script => self.error("Can't yield from top-level code."),
+            FunctionType::Initializer => self.error("Can't yield from an initializer."),
+            _ => {}
         }
+        self.state.function.is_generator = true;
+        // `yield;` yields nil
+        if matches!(
+            self.parser.current.token_type,
+            TokenType::Semicolon | TokenType::RightParen
+        ) {
+            self.emit_byte(OpCode::Nil);
+        } else {
+            self.parse_precedence(Precedence::Assignment);
+        }
+        self.emit_byte(OpCode::Yield);
+    }
+
+    fn lambda(&mut self, _can_assign: bool) {
+        self.function("lambda".to_string(), FunctionType::Function);
     }
 
     fn func_declaration(&mut self) {
-        let func_name = self.parser.current.lexeme.clone();
+        let func_name = self.parser.current.lexeme(&self.source).to_string();
         let global = self.parse_variable("Expect func name");
 
         self.mark_initialized();
@@ -916,11 +1927,105 @@ impl Compiler {
         self.define_variable(global);
     }
 
+    fn class_declaration(&mut self) {
+        self.consume(TokenType::Identifier, "Expect class name.");
+        let class_name = self.parser.previous;
+        let name_constant = self.identifier_constant(class_name);
+        self.declare_variable();
+
+        self.emit_variable(OpCode::Class, name_constant);
+        self.define_variable(name_constant);
+        self.classes.push(ClassCompiler::default());
+
+        if self.my_match(TokenType::Less) {
+            // e.g. class Child < Parent {}
+            self.consume(TokenType::Identifier, "Expect superclass name.");
+            self.variable(false);
+            if self.parser.previous.lexeme(&self.source) == class_name.lexeme(&self.source) {
+                self.error("A class can't inherit from itself.");
+            }
+
+            // Store the superclass in a local variable named `super`, every method of this class
+            // can capture it as an upvalue. The new scope makes each class get its own `super`
+            self.begin_scope();
+            self.add_local(Token::synthetic("super"));
+            self.define_variable(0);
+
+            self.named_variable(class_name, false);
+            self.emit_byte(OpCode::Inherit);
+            self.classes.last_mut().unwrap().has_superclass = true;
+        }
+
+        // Load the class back on top of the stack, so `OP_METHOD` can find it
+        self.named_variable(class_name, false);
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.");
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            if self.my_match(TokenType::Static) {
+                self.static_member();
+            } else {
+                self.method();
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.");
+        self.emit_byte(OpCode::Pop);
+
+        if self.classes.pop().unwrap().has_superclass {
+            self.end_scope();
+        }
+    }
+
+    fn method(&mut self) {
+        self.consume(TokenType::Identifier, "Expect method name.");
+        let mut name = self.parser.previous;
+        // `set` is only special before another name, so a method can still be called `set`
+        let is_setter = name.lexeme(&self.source) == "set" && self.check(TokenType::Identifier);
+        if is_setter {
+            self.advance();
+            name = self.parser.previous;
+        }
+        let name_constant = self.identifier_constant(name);
+
+        let name = name.lexeme(&self.source).to_string();
+        let (func_type, op) = if is_setter {
+            (FunctionType::Setter, OpCode::Setter)
+        } else if self.check(TokenType::LeftBrace) {
+            // e.g. `area { return 3.14 * this.r * this.r; }`
+            (FunctionType::Getter, OpCode::Getter)
+        } else if name == "init" {
+            (FunctionType::Initializer, OpCode::Method)
+        } else {
+            (FunctionType::Method, OpCode::Method)
+        };
+        self.function(name, func_type);
+        self.emit_variable(op, name_constant);
+    }
+
+    /// `static name(params) { ... }` or `static name = value;`, which live on the class itself
+    fn static_member(&mut self) {
+        self.consume(TokenType::Identifier, "Expect static member name.");
+        let name = self.parser.previous;
+        let name_constant = self.identifier_constant(name);
+
+        self.classes.last_mut().unwrap().in_static = true;
+        if self.my_match(TokenType::Equal) {
+            self.expression();
+            self.consume(TokenType::Semicolon, "Expect ';' after class-level field.");
+        } else {
+            let name = name.lexeme(&self.source).to_string();
+            self.function(name, FunctionType::Function);
+        }
+        self.classes.last_mut().unwrap().in_static = false;
+        self.emit_variable(OpCode::Static, name_constant);
+    }
+
     fn declaration(&mut self) {
-        // declaration  -> varDecl
+        // declaration  -> classDecl
+        //              |  varDecl
         //              |  funDecl
         //              |  statement ;
-        if self.my_match(TokenType::Var) {
+        if self.my_match(TokenType::Class) {
+            self.class_declaration();
+        } else if self.my_match(TokenType::Var) {
             self.var_declaration();
         } else if self.my_match(TokenType::Fun) {
             self.func_declaration();
@@ -937,12 +2042,12 @@ impl Compiler {
         let mut get_op = OpCode::GetLocal;
         let mut set_op = OpCode::SetLocal;
 
-        let mut arg = 0_u8;
+        let arg;
         // Note: the if let order matters, which will decide the priority
-        if let Ok(idx) = self.state.resolve_local(&token) {
-            arg = idx as u8;
-        } else if let Some(idx) = self.state.resolve_upvalue(&token) {
-            arg = idx as u8;
+        if let Ok(idx) = self.state.resolve_local(&token, &self.source) {
+            arg = idx;
+        } else if let Some(idx) = self.state.resolve_upvalue(&token, &self.source) {
+            arg = idx;
             get_op = OpCode::GetUpvalue;
             set_op = OpCode::SetUpvalue;
         } else {
@@ -951,19 +2056,78 @@ impl Compiler {
             set_op = OpCode::SetGlobal;
         }
 
+        let local = (get_op == OpCode::GetLocal).then_some(arg);
         if can_assign && self.my_match(TokenType::Equal) {
             // This is an assignment (setter)
             // e.g. var foo = "bar";
             self.expression();
-            self.emit_bytes(set_op, arg);
+            self.emit_variable(set_op, arg);
+            if let Some(slot) = local {
+                self.state.locals[slot].is_assigned = true;
+            }
         } else {
+            if let Some(slot) = local {
+                self.state.locals[slot].is_read = true;
+            }
             // For access (getter)
-            self.emit_bytes(get_op, arg);
+            self.emit_target(Target::Variable {
+                get_op,
+                set_op,
+                arg,
+            });
         }
     }
 
+    fn super_(&mut self, _can_assign: bool) {
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class."),
+            Some(class) if class.in_static => self.error("Can't use 'super' in a static member."),
+            Some(class) if !class.has_superclass => {
+                self.error("Can't use 'super' in a class with no superclass.")
+            }
+            _ => {}
+        }
+        self.consume(TokenType::Dot, "Expect '.' after 'super'.");
+        self.consume(TokenType::Identifier, "Expect superclass method name.");
+        let name = self.identifier_constant(self.parser.previous);
+
+        // The receiver is needed to bind the method
+        self.named_variable(Token::synthetic("this"), false);
+        if self.my_match(TokenType::LeftParen) {
+            let arguments = self.argument_list();
+            self.named_variable(Token::synthetic("super"), false);
+            match arguments {
+                Elements::Each(arg_cnt) => {
+                    self.emit_variable(OpCode::SuperInvoke, name);
+                    self.emit_byte(arg_cnt);
+                }
+                Elements::Packed => self.emit_variable(OpCode::SuperInvokeSpread, name),
+            }
+        } else {
+            self.named_variable(Token::synthetic("super"), false);
+            self.emit_variable(OpCode::GetSuper, name);
+        }
+    }
+
+    fn this(&mut self, _can_assign: bool) {
+        match self.classes.last() {
+            None => {
+                self.error("Can't use 'this' outside of a class.");
+                return;
+            }
+            Some(class) if class.in_static => {
+                self.error("Can't use 'this' in a static member.");
+                return;
+            }
+            _ => {}
+        }
+        // `this` is a read-only local variable
+        self.variable(false);
+        self.state.last_target = None;
+    }
+
     fn variable(&mut self, can_assign: bool) {
-        let previous_token = std::mem::take(&mut self.parser.previous);
+        let previous_token = self.parser.previous;
         self.named_variable(previous_token, can_assign);
     }
 
@@ -981,8 +2145,11 @@ impl Compiler {
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::If
+                | TokenType::Import
                 | TokenType::While
                 | TokenType::Print
+                | TokenType::Break
+                | TokenType::Continue
                 | TokenType::Return => {
                     return;
                 }
@@ -992,17 +2159,64 @@ impl Compiler {
         }
     }
 
-    pub fn compile(mut self, source: &str) -> Result<Function, InterpretResult> {
-        self.scanner.init_scanner(source);
+    /// Compile the whole source code, or return all the compile errors in the order they are found
+    pub fn compile(self, source: &str) -> Result<Function, Vec<Diagnostic>> {
+        self.compile_with_warnings(source)
+            .map(|(function, _)| function)
+    }
+
+    /// Compile a syntax tree made by [`crate::ast::parse`]. The tree is compiled by replaying its
+    /// tokens through the compiler, so it gives the same bytecode as the source code it comes from
+    pub fn compile_ast(mut self, ast: &Ast) -> Result<Function, Vec<Diagnostic>> {
+        self.tokens = Some(ast.tokens().into());
+        self.compile(ast.source())
+    }
+
+    /// Same as [`Compiler::compile`], also returning the warnings when the code compiles. With
+    /// [`Warnings::Deny`] the warnings are the compile errors instead
+    pub fn compile_with_warnings(
+        mut self,
+        source: &str,
+    ) -> Result<(Function, Vec<Diagnostic>), Vec<Diagnostic>> {
+        let (function_type, repl) = (self.state.function_type, self.repl);
+        self.source = Rc::from(source);
+        self.scanner.init_scanner(Rc::clone(&self.source));
         self.advance();
         while !self.my_match(TokenType::Eof) {
             self.declaration();
         }
 
         if self.parser.had_error {
-            Err(InterpretResult::CompileError)
+            Err(self.diagnostics)
+        } else if self.jump_too_long && !self.wide_jumps {
+            // Huge jumps are rare, so rather than moving the code around to widen one jump, we
+            // compile everything again with the wide ones
+            let mut compiler = Compiler::new(function_type);
+            compiler.repl = repl;
+            compiler.optimize = self.optimize;
+            compiler.disassemble = self.disassemble;
+            compiler.file = self.file;
+            compiler.warnings = self.warnings;
+            compiler.lint = self.lint;
+            compiler.tokens = self.tokens;
+            compiler.wide_jumps = true;
+            compiler.compile_with_warnings(source)
         } else {
-            Ok(self.end_compiler())
+            let function = self.end_compiler();
+            let mut warnings = self.warning_diagnostics;
+            if let Some(globals) = self.lint.filter(|_| self.warnings != Warnings::Off) {
+                let undefined = lint::undefined_globals(&function, |name| globals.contains(name));
+                warnings.extend(undefined);
+            }
+            warnings.sort_by_key(|warning| (warning.line, warning.column));
+            if self.warnings == Warnings::Deny && !warnings.is_empty() {
+                for warning in &mut warnings {
+                    warning.severity = Severity::Error;
+                }
+                Err(warnings)
+            } else {
+                Ok((function, warnings))
+            }
         }
     }
 }
@@ -0,0 +1,193 @@
+//! The runner for the test suite of Lox from Crafting Interpreters. Each test is a Lox file whose
+//! comments tell what it should print and which errors it should report:
+//!
+//! ```text
+//! print 1 + 2; // expect: 3
+//! print -"a"; // expect runtime error: Operand must be a number.
+//! print "a" +; // Error at ';': Expect expression.
+//! ```
+//!
+//! The compile errors may also give their line, e.g. `// [line 3] Error at end: Expect '}'.`, or
+//! be for one implementation only, `// [c line 3]` or `// [java line 3]`. rustlox follows clox, so
+//! the `java` ones are ignored
+use crate::output::SharedBuffer;
+use crate::{LoxError, WriterReporter, VM};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The directories of the suite which aren't for clox: the chapters which only have a scanner or
+/// a parser, and the benchmarks
+const SKIPPED_DIRS: [&str; 3] = ["scanning", "expressions", "benchmark"];
+
+/// What a test expects, from the comments in it
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectations {
+    /// The lines printed, in order
+    pub output: Vec<String>,
+    /// The compile errors as clox prints them, e.g. `[line 3] Error at ';': Expect expression.`
+    pub compile_errors: Vec<String>,
+    /// The message of the runtime error, and the line it happens at
+    pub runtime_error: Option<(String, usize)>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Self::default();
+        for (idx, line) in source.lines().enumerate() {
+            let line_number = idx + 1;
+            if let Some((_, output)) = line.split_once("// expect: ") {
+                expectations.output.push(output.to_string());
+            } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+                expectations.runtime_error = Some((message.to_string(), line_number));
+            } else if let Some((_, comment)) = line.split_once("// ") {
+                if let Some(error) = compile_error(comment, line_number) {
+                    expectations.compile_errors.push(error);
+                }
+            }
+        }
+        expectations
+    }
+
+    /// The exit code of `rustlox` running the test
+    pub fn exit_code(&self) -> i32 {
+        if !self.compile_errors.is_empty() {
+            65
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+}
+
+/// The compile error expected by a comment, e.g. `Error at ';': Expect expression.`, which is
+/// at the line of the comment unless it says otherwise
+fn compile_error(comment: &str, line_number: usize) -> Option<String> {
+    let (line_number, error) = match comment.strip_prefix('[') {
+        Some(rest) => {
+            let (location, error) = rest.split_once("] ")?;
+            let line = match location.split_once(' ') {
+                Some(("line", line)) => line,
+                Some(("c", line)) => line.strip_prefix("line ")?,
+                _ => return None,
+            };
+            (line.parse().ok()?, error)
+        }
+        None => (line_number, comment),
+    };
+    error
+        .starts_with("Error")
+        .then(|| format!("[line {line_number}] {error}"))
+}
+
+/// Run a test in a new VM, and return how it differs from what it expects. It passes if there is
+/// no difference
+pub fn run_test(source: &str) -> Vec<String> {
+    let expected = Expectations::parse(source);
+    let out = SharedBuffer::new();
+    let mut vm = VM::with_reporter(Box::new(WriterReporter::new(Box::new(io::sink()))));
+    vm.set_stdout(Box::new(out.clone()));
+
+    let mut actual = Expectations::default();
+    match vm.interpret(source) {
+        Err(LoxError::CompileError(diagnostics)) => {
+            actual.compile_errors = diagnostics
+                .iter()
+                .map(|d| format!("[line {}] Error{}: {}", d.line, d.location, d.message))
+                .collect();
+        }
+        Err(LoxError::RuntimeError {
+            message,
+            stack_trace,
+        }) => {
+            // The innermost frame comes first, e.g. `[line 3:7] in script`
+            let line = stack_trace
+                .first()
+                .and_then(|frame| frame.strip_prefix("[line "))
+                .and_then(|frame| frame.split([':', ']']).next())
+                .and_then(|line| line.parse().ok())
+                .unwrap_or_default();
+            actual.runtime_error = Some((message, line));
+        }
+        _ => {}
+    }
+    actual.output = out.contents().lines().map(str::to_string).collect();
+    differences(&expected, &actual)
+}
+
+fn differences(expected: &Expectations, actual: &Expectations) -> Vec<String> {
+    let mut failures = vec![];
+    for idx in 0..expected.output.len().max(actual.output.len()) {
+        match (expected.output.get(idx), actual.output.get(idx)) {
+            (Some(want), Some(got)) if want != got => {
+                failures.push(format!("Expected output '{want}' but got '{got}'."));
+            }
+            (Some(want), None) => failures.push(format!("Missing expected output '{want}'.")),
+            (None, Some(got)) => {
+                failures.push(format!("Got output '{got}' when none was expected."))
+            }
+            _ => {}
+        }
+    }
+    for error in &expected.compile_errors {
+        if !actual.compile_errors.contains(error) {
+            failures.push(format!("Missing expected error: {error}"));
+        }
+    }
+    for error in &actual.compile_errors {
+        if !expected.compile_errors.contains(error) {
+            failures.push(format!("Unexpected error: {error}"));
+        }
+    }
+    match (&expected.runtime_error, &actual.runtime_error) {
+        (Some(want), Some(got)) if want != got => failures.push(format!(
+            "Expected runtime error '{}' on line {} but got '{}' on line {}.",
+            want.0, want.1, got.0, got.1
+        )),
+        (Some((message, _)), None) => {
+            failures.push(format!("Expected runtime error '{message}' and got none."));
+        }
+        (None, Some((message, line))) => {
+            failures.push(format!(
+                "Unexpected runtime error '{message}' on line {line}."
+            ));
+        }
+        _ => {}
+    }
+    if expected.exit_code() != actual.exit_code() {
+        failures.push(format!(
+            "Expected exit code {} but got {}.",
+            expected.exit_code(),
+            actual.exit_code()
+        ));
+    }
+    failures
+}
+
+/// The tests in the directory and its subdirectories, in order, without the ones which aren't for
+/// clox
+pub fn test_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    collect_test_files(dir, &mut files)?;
+    Ok(files)
+}
+
+fn collect_test_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            let skipped = path
+                .file_name()
+                .is_some_and(|name| SKIPPED_DIRS.iter().any(|dir| name == *dir));
+            if !skipped {
+                collect_test_files(&path, files)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
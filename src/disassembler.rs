@@ -1,97 +1,391 @@
 use crate::chunk::{Chunk, OpCode};
-use crate::value::Value;
+use crate::value::{Function, Value};
+use std::fmt::{self, Write};
+use std::io;
+
+/// A decoded instruction, for the tools which need more than the text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub op: OpCode,
+    /// The bytes after the opcode
+    pub operands: Vec<u8>,
+    /// How the disassembler shows it, e.g. `OP_CONSTANT      0000 '1'`
+    pub text: String,
+}
+
+/// Decode all of the instructions in the chunk. The bytes which aren't opcodes are left out
+///
+/// ```
+/// use rustlox::chunk::OpCode;
+/// use rustlox::disassembler::instructions;
+/// use rustlox::VM;
+///
+/// let script = VM::check("print 1;").unwrap();
+/// let ops: Vec<_> = instructions(&script.function.chunk).iter().map(|i| i.op).collect();
+/// assert_eq!(ops, [OpCode::Constant, OpCode::Print, OpCode::Nil, OpCode::Return]);
+/// ```
+pub fn instructions(chunk: &Chunk) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let mut text = String::new();
+        let next =
+            write_operation(&mut text, chunk, offset).expect("Write to a String never fails");
+        if let Ok(op) = OpCode::try_from(chunk.code[offset]) {
+            instructions.push(Instruction {
+                offset,
+                line: chunk.lines[offset],
+                column: chunk.columns[offset],
+                op,
+                operands: chunk.code[offset + 1..next].to_vec(),
+                text: text.trim_end().to_string(),
+            });
+        }
+        offset = next;
+    }
+    instructions
+}
 
 /// Disassemble all of the instructions in the entire chunk
 pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    println!("== {name} ==");
-    let mut idx = 0;
-    while idx < chunk.code.len() {
-        idx = disassemble_instruction(chunk, idx);
-    }
+    let mut out = String::new();
+    write_chunk(&mut out, chunk, name).expect("Write to a String never fails");
+    print!("{out}");
 }
 
 /// Disassemble a single instruction and return the offset of
 /// the next instruction, as the instructions can have different sizes
 pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-    print!("{offset:04} ");
+    let mut out = String::new();
+    let next = write_instruction(&mut out, chunk, offset).expect("Write to a String never fails");
+    print!("{out}");
+    next
+}
+
+/// Same as [`disassemble_chunk`], but write the output to `out`
+pub fn write_chunk<W: Write>(out: &mut W, chunk: &Chunk, name: &str) -> fmt::Result {
+    writeln!(out, "== {name} ==")?;
+    let mut idx = 0;
+    while idx < chunk.code.len() {
+        idx = write_instruction(out, chunk, idx)?;
+    }
+    Ok(())
+}
+
+/// Same as [`write_chunk`], but write to an `io::Write`, e.g. a file
+pub fn write_chunk_to<W: io::Write>(out: &mut W, chunk: &Chunk, name: &str) -> io::Result<()> {
+    let mut text = String::new();
+    write_chunk(&mut text, chunk, name).expect("Write to a String never fails");
+    out.write_all(text.as_bytes())
+}
+
+/// Disassemble the function and all the functions defined in it, e.g. the whole script
+pub fn write_program<W: Write>(out: &mut W, function: &Function) -> fmt::Result {
+    let name = if function.name.is_empty() {
+        "<script>"
+    } else {
+        &function.name
+    };
+    write_chunk(out, &function.chunk, name)?;
+    for constant in &function.chunk.constants.values {
+        if let Value::Func(inner) = constant {
+            writeln!(out)?;
+            write_program(out, inner)?;
+        }
+    }
+    Ok(())
+}
+
+/// Same as [`write_program`], but write to an `io::Write`, e.g. a file
+pub fn write_program_to<W: io::Write>(out: &mut W, function: &Function) -> io::Result<()> {
+    let mut text = String::new();
+    write_program(&mut text, function).expect("Write to a String never fails");
+    out.write_all(text.as_bytes())
+}
+
+/// Same as [`disassemble_instruction`], but write the output to `out`
+pub fn write_instruction<W: Write>(
+    out: &mut W,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    write!(out, "{offset:04} ")?;
     if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
         // Show a | for any instruction that comes from the same source line as the preceding one.
-        print!("   | ");
+        write!(out, "   | ")?;
     } else {
-        print!("{:4} ", chunk.lines[offset]);
+        write!(out, "{:4} ", chunk.lines[offset])?;
     }
-    match chunk.code[offset].into() {
-        OpCode::Return => simple_instruction("OP_RETURN", offset),
-        OpCode::Constant => constant_instruction("OP_CONSTANT", chunk, offset),
-        OpCode::Negate => simple_instruction("OP_NEGATE", offset),
-        OpCode::Add => simple_instruction("OP_ADD", offset),
-        OpCode::Substract => simple_instruction("OP_SUBSTRACT", offset),
-        OpCode::Multiply => simple_instruction("OP_MULTIPLY", offset),
-        OpCode::Divide => simple_instruction("OP_DIVIDE", offset),
-        OpCode::Nil => simple_instruction("OP_NIL", offset),
-        OpCode::True => simple_instruction("OP_TRUE", offset),
-        OpCode::False => simple_instruction("OP_FALE", offset),
-        OpCode::Not => simple_instruction("OP_NOT", offset),
-        OpCode::Equal => simple_instruction("OP_EQUAL", offset),
-        OpCode::Greater => simple_instruction("OP_GREATER", offset),
-        OpCode::Less => simple_instruction("OP_LESS", offset),
-        OpCode::Print => simple_instruction("OP_PRINT", offset),
-        OpCode::Pop => simple_instruction("OP_POP", offset),
-        OpCode::DefineGlobal => constant_instruction("OP_DEFINE_GLOBAL", chunk, offset),
-        OpCode::GetGlobal => constant_instruction("OP_GET_GLOBAL", chunk, offset),
-        OpCode::SetGlobal => constant_instruction("OP_SET_GLOBAL", chunk, offset),
-        OpCode::GetLocal => byte_instruction("OP_GET_LOCAL", chunk, offset),
-        OpCode::SetLocal => byte_instruction("OP_SET_LOCAL", chunk, offset),
-        OpCode::Jump => jump_instruction("OP_JUMP", 1, chunk, offset),
-        OpCode::JumpIfFalse => jump_instruction("OP_JUMP_IF_ELSE", 1, chunk, offset),
-        OpCode::Loop => jump_instruction("OP_LOOP", -1, chunk, offset),
-        OpCode::Call => byte_instruction("OP_CALL", chunk, offset),
-        OpCode::Closure => {
-            let constant_idx = chunk.code[offset + 1];
-            print!("{:-16} {:04} ", "OP_CLOSURE", constant_idx);
-            let Value::Func(func) = &chunk.constants.values[constant_idx as usize] else {panic!("Impossible")};
-            println!("'{func}'");
-
-            for (idx, v) in func.upvalues.iter().enumerate() {
-                println!(
-                    "{:04}    |                       {} {}",
-                    offset + idx + 1,
-                    if v.is_local { "local" } else { "upvalue" },
-                    v.index
-                );
-            }
-
-            // offset
-            offset + func.upvalues.len() * 2 + 2
+    write_operation(out, chunk, offset)
+}
+
+/// Write the instruction without the offset and the line
+fn write_operation<W: Write>(
+    out: &mut W,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let op = match OpCode::try_from(chunk.code[offset]) {
+        Ok(op) => op,
+        Err(byte) => {
+            writeln!(out, "Unknown opcode {byte}")?;
+            return Ok(offset + 1);
+        }
+    };
+    match op {
+        OpCode::Return => simple_instruction(out, "OP_RETURN", offset),
+        OpCode::Yield => simple_instruction(out, "OP_YIELD", offset),
+        OpCode::Constant => constant_instruction(out, "OP_CONSTANT", chunk, offset),
+        OpCode::Negate => simple_instruction(out, "OP_NEGATE", offset),
+        OpCode::Add => simple_instruction(out, "OP_ADD", offset),
+        OpCode::Substract => simple_instruction(out, "OP_SUBSTRACT", offset),
+        OpCode::Multiply => simple_instruction(out, "OP_MULTIPLY", offset),
+        OpCode::Divide => simple_instruction(out, "OP_DIVIDE", offset),
+        OpCode::Modulo => simple_instruction(out, "OP_MODULO", offset),
+        OpCode::Power => simple_instruction(out, "OP_POWER", offset),
+        OpCode::BitAnd => simple_instruction(out, "OP_BIT_AND", offset),
+        OpCode::BitOr => simple_instruction(out, "OP_BIT_OR", offset),
+        OpCode::BitXor => simple_instruction(out, "OP_BIT_XOR", offset),
+        OpCode::BitNot => simple_instruction(out, "OP_BIT_NOT", offset),
+        OpCode::ShiftLeft => simple_instruction(out, "OP_SHIFT_LEFT", offset),
+        OpCode::ShiftRight => simple_instruction(out, "OP_SHIFT_RIGHT", offset),
+        OpCode::Nil => simple_instruction(out, "OP_NIL", offset),
+        OpCode::True => simple_instruction(out, "OP_TRUE", offset),
+        OpCode::False => simple_instruction(out, "OP_FALE", offset),
+        OpCode::Not => simple_instruction(out, "OP_NOT", offset),
+        OpCode::Equal => simple_instruction(out, "OP_EQUAL", offset),
+        OpCode::NotEqual => simple_instruction(out, "OP_NOT_EQUAL", offset),
+        OpCode::Increment => simple_instruction(out, "OP_INCREMENT", offset),
+        OpCode::Greater => simple_instruction(out, "OP_GREATER", offset),
+        OpCode::Less => simple_instruction(out, "OP_LESS", offset),
+        OpCode::Print => simple_instruction(out, "OP_PRINT", offset),
+        OpCode::Pop => simple_instruction(out, "OP_POP", offset),
+        OpCode::DefineGlobal => constant_instruction(out, "OP_DEFINE_GLOBAL", chunk, offset),
+        OpCode::GetGlobal => constant_instruction(out, "OP_GET_GLOBAL", chunk, offset),
+        OpCode::SetGlobal => constant_instruction(out, "OP_SET_GLOBAL", chunk, offset),
+        OpCode::GetLocal => byte_instruction(out, "OP_GET_LOCAL", chunk, offset),
+        OpCode::ArgMissing => byte_instruction(out, "OP_ARG_MISSING", chunk, offset),
+        OpCode::BuildList => byte_instruction(out, "OP_BUILD_LIST", chunk, offset),
+        OpCode::AppendList => simple_instruction(out, "OP_APPEND_LIST", offset),
+        OpCode::ExtendList => simple_instruction(out, "OP_EXTEND_LIST", offset),
+        OpCode::CallSpread => simple_instruction(out, "OP_CALL_SPREAD", offset),
+        OpCode::InvokeSpread => constant_instruction(out, "OP_INVOKE_SPREAD", chunk, offset),
+        OpCode::SuperInvokeSpread => {
+            constant_instruction(out, "OP_SUPER_INVOKE_SPREAD", chunk, offset)
+        }
+        OpCode::GetIndex => simple_instruction(out, "OP_GET_INDEX", offset),
+        OpCode::SetIndex => simple_instruction(out, "OP_SET_INDEX", offset),
+        OpCode::IsNil => simple_instruction(out, "OP_IS_NIL", offset),
+        OpCode::Slice => simple_instruction(out, "OP_SLICE", offset),
+        OpCode::Range => byte_instruction(out, "OP_RANGE", chunk, offset),
+        OpCode::Dup => byte_instruction(out, "OP_DUP", chunk, offset),
+        OpCode::Bury => byte_instruction(out, "OP_BURY", chunk, offset),
+        OpCode::IterNext => short_instruction(out, "OP_ITER_NEXT", chunk, offset),
+        OpCode::SetLocal => byte_instruction(out, "OP_SET_LOCAL", chunk, offset),
+        OpCode::Jump => jump_instruction(out, "OP_JUMP", 1, chunk, offset),
+        OpCode::JumpIfFalse => jump_instruction(out, "OP_JUMP_IF_ELSE", 1, chunk, offset),
+        OpCode::Loop => jump_instruction(out, "OP_LOOP", -1, chunk, offset),
+        OpCode::GetLocalLong => short_instruction(out, "OP_GET_LOCAL_LONG", chunk, offset),
+        OpCode::ConstantLong => constant_long_instruction(out, "OP_CONSTANT_LONG", chunk, offset),
+        OpCode::DefineGlobalLong => {
+            constant_long_instruction(out, "OP_DEFINE_GLOBAL_LONG", chunk, offset)
+        }
+        OpCode::GetGlobalLong => {
+            constant_long_instruction(out, "OP_GET_GLOBAL_LONG", chunk, offset)
+        }
+        OpCode::SetGlobalLong => {
+            constant_long_instruction(out, "OP_SET_GLOBAL_LONG", chunk, offset)
+        }
+        OpCode::SetLocalLong => short_instruction(out, "OP_SET_LOCAL_LONG", chunk, offset),
+        OpCode::JumpLong => long_jump_instruction(out, "OP_JUMP_LONG", 1, chunk, offset),
+        OpCode::JumpIfFalseLong => {
+            long_jump_instruction(out, "OP_JUMP_IF_ELSE_LONG", 1, chunk, offset)
+        }
+        OpCode::LoopLong => long_jump_instruction(out, "OP_LOOP_LONG", -1, chunk, offset),
+        OpCode::Call => byte_instruction(out, "OP_CALL", chunk, offset),
+        OpCode::Closure => closure_instruction(out, "OP_CLOSURE", chunk, offset),
+        OpCode::ClosureLong => closure_instruction(out, "OP_CLOSURE_LONG", chunk, offset),
+        OpCode::GetUpvalue => byte_instruction(out, "OP_GET_UPVALUE", chunk, offset),
+        OpCode::SetUpvalue => byte_instruction(out, "OP_SET_UPVALUE", chunk, offset),
+        OpCode::ClosedUpvalue => simple_instruction(out, "OP_CLOSED_UPVALUE", offset),
+        OpCode::Class => constant_instruction(out, "OP_CLASS", chunk, offset),
+        OpCode::GetProperty => constant_instruction(out, "OP_GET_PROPERTY", chunk, offset),
+        OpCode::SetProperty => constant_instruction(out, "OP_SET_PROPERTY", chunk, offset),
+        OpCode::Method => constant_instruction(out, "OP_METHOD", chunk, offset),
+        OpCode::Static => constant_instruction(out, "OP_STATIC", chunk, offset),
+        OpCode::Getter => constant_instruction(out, "OP_GETTER", chunk, offset),
+        OpCode::Setter => constant_instruction(out, "OP_SETTER", chunk, offset),
+        OpCode::Inherit => simple_instruction(out, "OP_INHERIT", offset),
+        OpCode::GetSuper => constant_instruction(out, "OP_GET_SUPER", chunk, offset),
+        OpCode::Invoke => invoke_instruction(out, "OP_INVOKE", chunk, offset),
+        OpCode::Import => constant_instruction(out, "OP_IMPORT", chunk, offset),
+        OpCode::SuperInvoke => invoke_instruction(out, "OP_SUPER_INVOKE", chunk, offset),
+        OpCode::ClassLong => constant_long_instruction(out, "OP_CLASS_LONG", chunk, offset),
+        OpCode::GetPropertyLong => {
+            constant_long_instruction(out, "OP_GET_PROPERTY_LONG", chunk, offset)
+        }
+        OpCode::SetPropertyLong => {
+            constant_long_instruction(out, "OP_SET_PROPERTY_LONG", chunk, offset)
+        }
+        OpCode::MethodLong => constant_long_instruction(out, "OP_METHOD_LONG", chunk, offset),
+        OpCode::StaticLong => constant_long_instruction(out, "OP_STATIC_LONG", chunk, offset),
+        OpCode::GetterLong => constant_long_instruction(out, "OP_GETTER_LONG", chunk, offset),
+        OpCode::SetterLong => constant_long_instruction(out, "OP_SETTER_LONG", chunk, offset),
+        OpCode::GetSuperLong => constant_long_instruction(out, "OP_GET_SUPER_LONG", chunk, offset),
+        OpCode::ImportLong => constant_long_instruction(out, "OP_IMPORT_LONG", chunk, offset),
+        OpCode::InvokeSpreadLong => {
+            constant_long_instruction(out, "OP_INVOKE_SPREAD_LONG", chunk, offset)
+        }
+        OpCode::SuperInvokeSpreadLong => {
+            constant_long_instruction(out, "OP_SUPER_INVOKE_SPREAD_LONG", chunk, offset)
+        }
+        OpCode::InvokeLong => invoke_long_instruction(out, "OP_INVOKE_LONG", chunk, offset),
+        OpCode::SuperInvokeLong => {
+            invoke_long_instruction(out, "OP_SUPER_INVOKE_LONG", chunk, offset)
         }
-        OpCode::GetUpvalue => byte_instruction("OP_GET_UPVALUE", chunk, offset),
-        OpCode::SetUpvalue => byte_instruction("OP_SET_UPVALUE", chunk, offset),
-        OpCode::ClosedUpvalue => simple_instruction("OP_CLOSED_UPVALUE", offset),
     }
 }
 
-fn simple_instruction(name: &str, offset: usize) -> usize {
-    println!("{name}");
-    offset + 1
+/// The function of a closure, followed by whether each upvalue is local and its index
+fn closure_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let (constant_idx, len) = if chunk.code[offset] == OpCode::Closure as u8 {
+        (chunk.code[offset + 1] as usize, 2)
+    } else {
+        let bytes = [chunk.code[offset + 1], chunk.code[offset + 2]];
+        (u16::from_be_bytes(bytes) as usize, 3)
+    };
+    write!(out, "{name:-16} {constant_idx:04} ")?;
+    let Value::Func(func) = &chunk.constants.values[constant_idx] else {
+        panic!("Impossible")
+    };
+    writeln!(out, "'{func}'")?;
+
+    // Each upvalue takes three bytes: whether it's local and a two bytes index
+    for (idx, v) in func.upvalues.iter().enumerate() {
+        writeln!(
+            out,
+            "{:04}    |                       {} {}",
+            offset + len + idx * 3,
+            if v.is_local { "local" } else { "upvalue" },
+            v.index
+        )?;
+    }
+
+    Ok(offset + len + func.upvalues.len() * 3)
+}
+
+fn simple_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    writeln!(out, "{name}")?;
+    Ok(offset + 1)
+}
+
+fn constant_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let constant_idx = chunk.code[offset + 1];
+    write!(out, "{name:-16} {constant_idx:04} ")?;
+    write_constant(out, &chunk.constants.values[constant_idx as usize])?;
+
+    Ok(offset + 2)
+}
+
+/// The wide variant of [`constant_instruction`], with a two bytes index
+fn constant_long_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let constant_idx = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    write!(out, "{name:-16} {constant_idx:04} ")?;
+    write_constant(out, &chunk.constants.values[constant_idx as usize])?;
+
+    Ok(offset + 3)
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+/// An invoke instruction has two operands: the method name and the number of arguments
+fn invoke_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
     let constant_idx = chunk.code[offset + 1];
-    print!("{name:-16} {constant_idx:04} ");
-    println!("'{:?}'", chunk.constants.values[constant_idx as usize]);
+    let arg_cnt = chunk.code[offset + 2];
+    write!(out, "{name:-16} ({arg_cnt} args) {constant_idx:04} ")?;
+    write_constant(out, &chunk.constants.values[constant_idx as usize])?;
+
+    Ok(offset + 3)
+}
+
+/// The wide variant of [`invoke_instruction`], with a two bytes index
+fn invoke_long_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let constant_idx = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    let arg_cnt = chunk.code[offset + 3];
+    write!(out, "{name:-16} ({arg_cnt} args) {constant_idx:04} ")?;
+    write_constant(out, &chunk.constants.values[constant_idx as usize])?;
 
-    offset + 2
+    Ok(offset + 4)
+}
+
+/// A constant the way `print` shows it, like clox does, e.g. `1` and `1.5` for the numbers
+fn write_constant<W: Write>(out: &mut W, constant: &Value) -> fmt::Result {
+    writeln!(out, "'{constant}'")
 }
 
 /// The compiler compiles local variables to direct slot access, so we just show the slot number
-fn byte_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
+fn byte_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
     let slot = chunk.code[offset + 1];
-    println!("{name:-16} {slot:04} ");
+    writeln!(out, "{name:-16} {slot:04} ")?;
 
-    offset + 2
+    Ok(offset + 2)
 }
 
-fn jump_instruction(name: &str, sign: i32, chunk: &Chunk, offset: usize) -> usize {
+/// The wide local instructions take a two bytes slot number
+fn short_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let slot = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    writeln!(out, "{name:-16} {slot:04} ")?;
+
+    Ok(offset + 3)
+}
+
+fn jump_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    sign: i32,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
     // Compute the jump offset
     let mut jump = (chunk.code[offset + 1] as usize) << 8;
     jump |= chunk.code[offset + 2] as usize;
@@ -101,7 +395,28 @@ fn jump_instruction(name: &str, sign: i32, chunk: &Chunk, offset: usize) -> usiz
         offset + 3 - jump
     };
 
-    println!("{name:-16} {offset:04} -> {jump_target}");
+    writeln!(out, "{name:-16} {offset:04} -> {jump_target}")?;
+
+    Ok(offset + 3)
+}
+
+/// Same as [`jump_instruction`], but the offset takes four bytes
+fn long_jump_instruction<W: Write>(
+    out: &mut W,
+    name: &str,
+    sign: i32,
+    chunk: &Chunk,
+    offset: usize,
+) -> Result<usize, fmt::Error> {
+    let bytes = &chunk.code[offset + 1..offset + 5];
+    let jump = u32::from_be_bytes(bytes.try_into().expect("Four bytes")) as usize;
+    let jump_target = if sign == 1 {
+        offset + 5 + jump
+    } else {
+        offset + 5 - jump
+    };
+
+    writeln!(out, "{name:-16} {offset:04} -> {jump_target}")?;
 
-    offset + 3
+    Ok(offset + 5)
 }
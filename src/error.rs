@@ -0,0 +1,69 @@
+use crate::reporter::Diagnostic;
+use std::time::Duration;
+
+/// An error from running Lox code through the embedding API
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoxError {
+    /// The code doesn't compile. All the errors found are kept in the order they occur
+    CompileError(Vec<Diagnostic>),
+    /// The code aborted, e.g. because of a type error. The stack trace starts from the innermost
+    /// call frame
+    RuntimeError {
+        message: String,
+        stack_trace: Vec<String>,
+    },
+    /// The script called `exit(code)`, which stops it without an error
+    Exit(i32),
+    /// The script used up one of the resources the host limits it to, see `VM::set_limits`
+    LimitExceeded(Limit),
+}
+
+/// One of the limits of `rustlox::vm::Limits`, with its value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Instructions(u64),
+    Time(Duration),
+    Frames(usize),
+    Allocations(usize),
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Instructions(max) => write!(f, "{max} instructions"),
+            Self::Time(max) => write!(f, "{max:?} of running time"),
+            Self::Frames(max) => write!(f, "{max} nested calls"),
+            Self::Allocations(max) => write!(f, "{max} allocated objects"),
+        }
+    }
+}
+
+impl std::fmt::Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CompileError(diagnostics) => {
+                for (idx, diagnostic) in diagnostics.iter().enumerate() {
+                    if idx > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{diagnostic}")?;
+                }
+                Ok(())
+            }
+            Self::RuntimeError {
+                message,
+                stack_trace,
+            } => {
+                write!(f, "{message}")?;
+                for line in stack_trace {
+                    write!(f, "\n{line}")?;
+                }
+                Ok(())
+            }
+            Self::Exit(code) => write!(f, "The script exited with code {code}."),
+            Self::LimitExceeded(limit) => write!(f, "The script exceeded the limit of {limit}."),
+        }
+    }
+}
+
+impl std::error::Error for LoxError {}
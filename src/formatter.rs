@@ -0,0 +1,250 @@
+//! The formatter, which prints Lox source code with consistent indentation, spacing and brace
+//! placement. It works on the tokens, comments included, so it also formats code which doesn't
+//! compile, as long as it scans
+use crate::reporter::Diagnostic;
+use crate::scanner::{Scanner, Token, TokenType};
+
+/// The indentation of each level of braces
+const INDENT: &str = "  ";
+
+/// Format the source code:
+///
+/// - one statement per line, indented by the braces around it
+/// - `{` at the end of the line which opens it, and `}` on its own line, except `} else`
+/// - a space around the binary operators and after the commas, but none inside the brackets
+/// - the comments kept where they are, and at most one blank line kept between two lines
+///
+/// Returns the first scanner error, e.g. an unterminated string, instead
+pub fn format(source: &str) -> Result<String, Box<Diagnostic>> {
+    let mut scanner = Scanner::new().with_comments();
+    scanner.init_scanner(source);
+    let mut tokens = vec![];
+    loop {
+        let token = scanner.scan_token();
+        match token.token_type {
+            TokenType::Eof => break,
+            TokenType::Error => {
+                return Err(Box::new(Diagnostic {
+                    line: token.span.line,
+                    column: token.column,
+                    span: token.span,
+                    message: token.text.unwrap_or_default().to_string(),
+                    source_line: source
+                        .lines()
+                        .nth(token.span.line.saturating_sub(1))
+                        .unwrap_or_default()
+                        .to_string(),
+                    ..Default::default()
+                }))
+            }
+            _ => tokens.push(token),
+        }
+    }
+
+    let mut formatter = Formatter {
+        source,
+        out: String::new(),
+        brackets: vec![],
+        newline: false,
+        previous: None,
+        previous_is_operand: false,
+        previous_is_prefix: false,
+        last_line: 0,
+    };
+    for (idx, token) in tokens.iter().enumerate() {
+        let next = tokens[idx + 1..]
+            .iter()
+            .find(|token| token.token_type != TokenType::Comment)
+            .map(|token| token.token_type);
+        if token.token_type == TokenType::Comment {
+            formatter.comment(*token);
+        } else {
+            formatter.token(*token, next);
+        }
+    }
+    let mut out = formatter.out.trim_end().to_string();
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    out: String,
+    /// The brackets, parentheses and braces opened so far and not closed yet
+    brackets: Vec<TokenType>,
+    /// The next token goes on a new line
+    newline: bool,
+    previous: Option<TokenType>,
+    /// Whether the previous token ends an operand, so a `-` after it subtracts and a `(` calls
+    previous_is_operand: bool,
+    /// Whether the previous token is a prefix operator, which the operand follows without a space
+    previous_is_prefix: bool,
+    /// The line where the last token ends in the source code
+    last_line: usize,
+}
+
+impl Formatter<'_> {
+    fn token(&mut self, token: Token, next: Option<TokenType>) {
+        let token_type = token.token_type;
+        if token_type == TokenType::RightBrace {
+            self.close(TokenType::LeftBrace);
+            // A `}` always starts a line, unless the block is empty
+            self.newline |= self.previous != Some(TokenType::LeftBrace);
+        }
+        if self.newline {
+            self.new_line(token, token_type != TokenType::RightBrace);
+        } else if self.space_before(token_type) {
+            self.out.push(' ');
+        }
+        self.write(token);
+
+        let is_postfix = matches!(token_type, TokenType::PlusPlus | TokenType::MinusMinus)
+            && self.previous_is_operand;
+        let is_prefix = !self.previous_is_operand
+            && matches!(
+                token_type,
+                TokenType::Minus
+                    | TokenType::Bang
+                    | TokenType::Tilde
+                    | TokenType::PlusPlus
+                    | TokenType::MinusMinus
+                    | TokenType::DotDotDot
+            );
+        self.previous_is_operand = is_postfix
+            || matches!(
+                token_type,
+                TokenType::Identifier
+                    | TokenType::Number
+                    | TokenType::STRING
+                    | TokenType::True
+                    | TokenType::False
+                    | TokenType::Nil
+                    | TokenType::This
+                    | TokenType::Super
+                    | TokenType::RightParen
+                    | TokenType::RightBracket
+            );
+        self.previous_is_prefix = is_prefix;
+        self.previous = Some(token_type);
+
+        match token_type {
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => {
+                self.brackets.push(token_type);
+            }
+            TokenType::RightParen => self.close(TokenType::LeftParen),
+            TokenType::RightBracket => self.close(TokenType::LeftBracket),
+            _ => {}
+        }
+        self.newline = match token_type {
+            TokenType::LeftBrace => next != Some(TokenType::RightBrace),
+            TokenType::RightBrace => !matches!(
+                next,
+                Some(
+                    TokenType::Else
+                        | TokenType::Semicolon
+                        | TokenType::Comma
+                        | TokenType::Dot
+                        | TokenType::RightParen
+                        | TokenType::RightBracket
+                )
+            ),
+            // The `;` in the parentheses of a `for` separate the clauses
+            TokenType::Semicolon => {
+                matches!(self.brackets.last(), None | Some(TokenType::LeftBrace))
+            }
+            _ => false,
+        };
+    }
+
+    /// A comment at the end of a line stays there, the others get their own lines
+    fn comment(&mut self, token: Token) {
+        let is_line_comment = token.lexeme(self.source).starts_with("//");
+        let at_start = self.out.is_empty();
+        if !at_start && token.span.line == self.last_line {
+            self.out.push(' ');
+            self.write(token);
+            self.newline |= is_line_comment;
+        } else {
+            if !at_start {
+                self.new_line(token, true);
+            }
+            self.write(token);
+            self.newline = true;
+        }
+    }
+
+    fn space_before(&self, token_type: TokenType) -> bool {
+        let Some(previous) = self.previous else {
+            return false;
+        };
+        if self.previous_is_prefix
+            || matches!(
+                previous,
+                TokenType::LeftParen
+                    | TokenType::LeftBracket
+                    | TokenType::Dot
+                    | TokenType::QuestionDot
+                    | TokenType::DotDot
+                    | TokenType::DotDotEqual
+                    | TokenType::Colon
+            )
+        {
+            return false;
+        }
+        match token_type {
+            TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::Comma
+            | TokenType::Semicolon
+            | TokenType::Dot
+            | TokenType::QuestionDot
+            | TokenType::DotDot
+            | TokenType::DotDotEqual
+            | TokenType::Colon => false,
+            // An empty block
+            TokenType::RightBrace => previous != TokenType::LeftBrace,
+            // The postfix ones
+            TokenType::PlusPlus | TokenType::MinusMinus => !self.previous_is_operand,
+            // A call, or a lambda's parameters
+            TokenType::LeftParen => !self.previous_is_operand && previous != TokenType::Fun,
+            // Indexing, rather than a list
+            TokenType::LeftBracket => !self.previous_is_operand,
+            _ => true,
+        }
+    }
+
+    /// Start a new line, indented by the braces around it. One blank line is kept before the
+    /// token if the source code has some, but not at the start or the end of a block
+    fn new_line(&mut self, token: Token, keep_blank_line: bool) {
+        self.out.push('\n');
+        if keep_blank_line
+            && token.span.line > self.last_line + 1
+            && self.previous != Some(TokenType::LeftBrace)
+        {
+            self.out.push('\n');
+        }
+        let depth = self
+            .brackets
+            .iter()
+            .filter(|&&bracket| bracket == TokenType::LeftBrace)
+            .count();
+        self.out.push_str(&INDENT.repeat(depth));
+        self.newline = false;
+    }
+
+    fn write(&mut self, token: Token) {
+        let lexeme = token.lexeme(self.source).trim_end();
+        self.out.push_str(lexeme);
+        self.last_line = token.span.line + lexeme.matches('\n').count();
+    }
+
+    /// Close the innermost bracket if it's the one opened by `open`. Unbalanced ones are left as
+    /// they are, the compiler reports them
+    fn close(&mut self, open: TokenType) {
+        if self.brackets.last() == Some(&open) {
+            self.brackets.pop();
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! The entry points for fuzzing the scanner, the compiler and the VM with arbitrary input, see the
+//! `fuzz` directory for the `cargo fuzz` targets
+use crate::scanner::Span;
+use crate::vm::{Capability, Limits};
+use crate::{WriterReporter, VM};
+use std::io;
+use std::time::Duration;
+
+/// The script whose constants the fuzzed bytecode runs with: a number, strings, a function with
+/// an upvalue and a class
+const CONSTANTS: &str = r#"
+var a = 1;
+fun f(x) { return fun() { return x + a; }; }
+class C { m() { return "m"; } }
+print f(2)().m;
+"#;
+
+fn fuzzing_vm() -> VM {
+    let mut vm = VM::with_reporter(Box::new(WriterReporter::new(Box::new(io::sink()))));
+    vm.set_stdout(Box::new(io::sink()));
+    for capability in Capability::ALL {
+        vm.deny(capability);
+    }
+    vm.set_limits(Limits {
+        max_instructions: Some(100_000),
+        max_time: Some(Duration::from_secs(1)),
+        max_allocations: Some(10_000),
+        ..Default::default()
+    });
+    vm
+}
+
+/// Compile and run the bytes as Lox code, discarding the output and the errors. Whatever the
+/// input, this never panics: a panic is a bug found by the fuzzer. The limits keep the scripts
+/// from running forever, and the VM has no capabilities, e.g. to `exit` the process
+///
+/// ```
+/// rustlox::fuzz::fuzz_interpret(b"while (true) print clock();");
+/// rustlox::fuzz::fuzz_interpret(b"\xff print (((;");
+/// ```
+pub fn fuzz_interpret(bytes: &[u8]) {
+    let source = String::from_utf8_lossy(bytes);
+    let _ = fuzzing_vm().interpret(&source);
+}
+
+/// Run the bytes as the code of a script, as a corrupt or crafted `.loxc` file could have it.
+/// The constants are the ones of a small script, so the code finds some of each kind. Like
+/// [`fuzz_interpret`], this never panics, the malformed code is a runtime error instead
+///
+/// ```
+/// use rustlox::chunk::OpCode;
+///
+/// rustlox::fuzz::fuzz_bytecode(&[OpCode::Pop as u8, OpCode::Pop as u8]);
+/// rustlox::fuzz::fuzz_bytecode(&[0xff, 0xff, 0xff]);
+/// ```
+pub fn fuzz_bytecode(bytes: &[u8]) {
+    let mut script = VM::check(CONSTANTS).expect("The script compiles");
+    let chunk = &mut script.function.chunk;
+    chunk.code = bytes.to_vec();
+    chunk.lines = vec![1; bytes.len()];
+    chunk.columns = vec![1; bytes.len()];
+    chunk.spans = vec![Span::default(); bytes.len()];
+    let _ = fuzzing_vm().run_function(script.function);
+}
@@ -0,0 +1,291 @@
+//! The cycle collector of the objects, which `Rc` shares and frees. It's not an arena with
+//! mark-and-sweep like the one of clox: it only frees the reference cycles `Rc` leaks, and finds
+//! their roots by trial deletion instead of scanning the VM
+use crate::shared::{Rc, RefCell, Weak};
+use crate::value::{BoundMethod, Class, Closure, Coroutine, Instance, ObjUpvalue, Value};
+use std::collections::HashMap;
+
+/// Collect again once the number of tracked objects reaches this, at the least
+const MIN_NEXT_GC: usize = 1024;
+/// How much the heap may grow after a collection before the next one
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+/// An object which may be part of a reference cycle, i.e. it refers to other objects
+#[derive(Clone, Debug)]
+pub enum Object {
+    Closure(Rc<Closure>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    BoundMethod(Rc<BoundMethod>),
+    Upvalue(Rc<ObjUpvalue>),
+    Coroutine(Rc<Coroutine>),
+}
+
+impl Object {
+    /// The object behind a value, if any
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Closure(closure) => Some(Self::Closure(Rc::clone(closure))),
+            Value::Class(class) => Some(Self::Class(Rc::clone(class))),
+            Value::Instance(instance) => Some(Self::Instance(Rc::clone(instance))),
+            Value::List(list) => Some(Self::List(Rc::clone(list))),
+            Value::BoundMethod(bound) => Some(Self::BoundMethod(Rc::clone(bound))),
+            Value::Coroutine(coroutine) => Some(Self::Coroutine(Rc::clone(coroutine))),
+            _ => None,
+        }
+    }
+
+    /// The identity of the object
+    fn address(&self) -> *const () {
+        match self {
+            Self::Closure(rc) => Rc::as_ptr(rc) as *const (),
+            Self::Class(rc) => Rc::as_ptr(rc) as *const (),
+            Self::Instance(rc) => Rc::as_ptr(rc) as *const (),
+            Self::List(rc) => Rc::as_ptr(rc) as *const (),
+            Self::BoundMethod(rc) => Rc::as_ptr(rc) as *const (),
+            Self::Upvalue(rc) => Rc::as_ptr(rc) as *const (),
+            Self::Coroutine(rc) => Rc::as_ptr(rc) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Self::Closure(rc) => Rc::strong_count(rc),
+            Self::Class(rc) => Rc::strong_count(rc),
+            Self::Instance(rc) => Rc::strong_count(rc),
+            Self::List(rc) => Rc::strong_count(rc),
+            Self::BoundMethod(rc) => Rc::strong_count(rc),
+            Self::Upvalue(rc) => Rc::strong_count(rc),
+            Self::Coroutine(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    /// The objects this one refers to
+    fn children(&self) -> Vec<Object> {
+        let mut children = vec![];
+        match self {
+            Self::Closure(closure) => {
+                children.extend(closure.upvalues.iter().cloned().map(Object::Upvalue));
+            }
+            Self::Class(class) => {
+                children.extend(
+                    class
+                        .methods
+                        .borrow()
+                        .values()
+                        .cloned()
+                        .map(Object::Closure),
+                );
+                children.extend(
+                    class
+                        .statics
+                        .borrow()
+                        .values()
+                        .filter_map(Object::from_value),
+                );
+                for accessors in [&class.getters, &class.setters] {
+                    children.extend(accessors.borrow().values().cloned().map(Object::Closure));
+                }
+            }
+            Self::Instance(instance) => {
+                let instance = instance.borrow();
+                children.push(Object::Class(Rc::clone(&instance.class)));
+                children.extend(instance.fields.values().filter_map(Object::from_value));
+            }
+            Self::List(list) => {
+                children.extend(list.borrow().iter().filter_map(Object::from_value));
+            }
+            Self::BoundMethod(bound) => {
+                children.extend(Object::from_value(&bound.receiver));
+                children.push(Object::Closure(Rc::clone(&bound.method)));
+            }
+            Self::Upvalue(upvalue) => {
+                children.extend(
+                    upvalue
+                        .closed
+                        .borrow()
+                        .as_ref()
+                        .and_then(Object::from_value),
+                );
+            }
+            // A running coroutine has moved its frames and values to the VM, which are roots
+            Self::Coroutine(coroutine) => {
+                let state = coroutine.state.borrow();
+                children.extend(state.stack.iter().filter_map(Object::from_value));
+                children.extend(
+                    state
+                        .frames
+                        .iter()
+                        .map(|frame| Object::Closure(Rc::clone(frame.closure()))),
+                );
+                children.extend(state.upvalues.iter().cloned().map(Object::Upvalue));
+            }
+        }
+        children
+    }
+
+    /// Drop the references held by a garbage object, which breaks the cycles it is part of. The
+    /// immutable objects don't need this, as every cycle goes through a mutable one
+    fn clear(&self) {
+        match self {
+            Self::Class(class) => {
+                class.methods.borrow_mut().clear();
+                class.statics.borrow_mut().clear();
+                class.getters.borrow_mut().clear();
+                class.setters.borrow_mut().clear();
+            }
+            Self::Instance(instance) => instance.borrow_mut().fields.clear(),
+            Self::List(list) => list.borrow_mut().clear(),
+            Self::Upvalue(upvalue) => *upvalue.closed.borrow_mut() = None,
+            Self::Coroutine(coroutine) => {
+                let mut state = coroutine.state.borrow_mut();
+                state.stack.clear();
+                state.frames.clear();
+                state.upvalues.clear();
+            }
+            Self::Closure(_) | Self::BoundMethod(_) => {}
+        }
+    }
+
+    fn downgrade(&self) -> WeakObject {
+        match self {
+            Self::Closure(rc) => WeakObject::Closure(Rc::downgrade(rc)),
+            Self::Class(rc) => WeakObject::Class(Rc::downgrade(rc)),
+            Self::Instance(rc) => WeakObject::Instance(Rc::downgrade(rc)),
+            Self::List(rc) => WeakObject::List(Rc::downgrade(rc)),
+            Self::BoundMethod(rc) => WeakObject::BoundMethod(Rc::downgrade(rc)),
+            Self::Upvalue(rc) => WeakObject::Upvalue(Rc::downgrade(rc)),
+            Self::Coroutine(rc) => WeakObject::Coroutine(Rc::downgrade(rc)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum WeakObject {
+    Closure(Weak<Closure>),
+    Class(Weak<Class>),
+    Instance(Weak<RefCell<Instance>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    BoundMethod(Weak<BoundMethod>),
+    Upvalue(Weak<ObjUpvalue>),
+    Coroutine(Weak<Coroutine>),
+}
+
+impl WeakObject {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Self::Closure(weak) => weak.upgrade().map(Object::Closure),
+            Self::Class(weak) => weak.upgrade().map(Object::Class),
+            Self::Instance(weak) => weak.upgrade().map(Object::Instance),
+            Self::List(weak) => weak.upgrade().map(Object::List),
+            Self::BoundMethod(weak) => weak.upgrade().map(Object::BoundMethod),
+            Self::Upvalue(weak) => weak.upgrade().map(Object::Upvalue),
+            Self::Coroutine(weak) => weak.upgrade().map(Object::Coroutine),
+        }
+    }
+}
+
+/// The objects are shared by `Rc`, which frees an object as soon as nobody refers to it, but
+/// leaks reference cycles, e.g. an instance storing itself in a field. The heap keeps track of
+/// every object which may be part of a cycle, through weak references, and frees the unreachable
+/// cycles by clearing the references of their objects, which lets `Rc` drop them.
+///
+/// The roots are the objects referred to from outside the heap: the VM stack, the call frames, the
+/// globals, the open upvalues, and also the values held by the host or by native functions. We
+/// find them by trial deletion, without scanning the VM: such an object has more strong references
+/// than the other tracked objects account for. The objects the roots reach are alive
+///
+/// Unlike the arena of clox, the heap doesn't own the objects: `Rc` still does, and the values
+/// keep pointing to the objects directly. That way the values the host or the natives keep don't
+/// need to be registered as roots, and the values are usable without the VM, e.g. by serde
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<WeakObject>,
+    /// Collect when the number of tracked objects reaches it
+    next_gc: usize,
+    /// Collect on every allocation, to shake out the bugs of the collector
+    pub stress: bool,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            objects: vec![],
+            next_gc: MIN_NEXT_GC,
+            stress: false,
+        }
+    }
+}
+
+impl Heap {
+    /// Start tracking a newly created object. Return whether it's time to collect the garbage
+    pub fn track(&mut self, object: &Object) -> bool {
+        self.objects.push(object.downgrade());
+        self.stress || self.objects.len() >= self.next_gc
+    }
+
+    /// Free the unreachable cycles, and return the number of objects freed by doing so
+    pub fn collect(&mut self) -> usize {
+        // Forget the objects which have already been freed by `Rc`
+        let objects: Vec<_> = self
+            .objects
+            .iter()
+            .filter_map(WeakObject::upgrade)
+            .collect();
+        let index: HashMap<_, _> = objects
+            .iter()
+            .enumerate()
+            .map(|(idx, object)| (object.address(), idx))
+            .collect();
+        let children: Vec<Vec<usize>> = objects
+            .iter()
+            .map(|object| {
+                object
+                    .children()
+                    .iter()
+                    .filter_map(|child| index.get(&child.address()).copied())
+                    .collect()
+            })
+            .collect();
+
+        // Count the references from the heap itself, the rest come from the roots
+        let mut internal = vec![0; objects.len()];
+        for idx in children.iter().flatten() {
+            internal[*idx] += 1;
+        }
+        // Mark: everything reachable from a root is alive. `objects` holds one extra reference
+        let mut gray: Vec<_> = (0..objects.len())
+            .filter(|&idx| objects[idx].strong_count() - 1 > internal[idx])
+            .collect();
+        let mut marked = vec![false; objects.len()];
+        for &idx in &gray {
+            marked[idx] = true;
+        }
+        while let Some(idx) = gray.pop() {
+            for &child in &children[idx] {
+                if !marked[child] {
+                    marked[child] = true;
+                    gray.push(child);
+                }
+            }
+        }
+
+        // Sweep: break the cycles of the unmarked objects, `Rc` frees them when `objects` drops
+        let mut freed = 0;
+        for (object, marked) in objects.iter().zip(&marked) {
+            if !marked {
+                object.clear();
+                freed += 1;
+            }
+        }
+        self.objects = objects
+            .iter()
+            .zip(&marked)
+            .filter(|(_, marked)| **marked)
+            .map(|(object, _)| object.downgrade())
+            .collect();
+        self.next_gc = (self.objects.len() * GC_HEAP_GROW_FACTOR).max(MIN_NEXT_GC);
+        freed
+    }
+}
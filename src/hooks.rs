@@ -0,0 +1,67 @@
+//! The callbacks of a host watching the code the VM runs, e.g. a custom tracer, a profiler or a
+//! watchdog. They cost nothing but a check of an `Option` when none are installed
+use crate::shared::{MaybeSend, MaybeSync, Rc, RefCell};
+use crate::value::{Function, NativeError, Value};
+
+/// The callbacks installed by [`crate::VM::set_hooks`]. Every one does nothing by default, so a
+/// host implements only the ones it needs
+///
+/// ```
+/// use rustlox::hooks::VmHooks;
+/// use rustlox::value::{Function, NativeError};
+/// use rustlox::VM;
+///
+/// /// Stop any script running more than a million instructions
+/// struct Watchdog(u32);
+///
+/// impl VmHooks for Watchdog {
+///     fn on_instruction(&mut self, _function: &Function, _offset: usize) -> Result<(), NativeError> {
+///         self.0 += 1;
+///         if self.0 > 1_000_000 {
+///             return Err(NativeError::new("Too many instructions."));
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let mut vm = VM::new();
+/// vm.set_hooks(Some(Box::new(Watchdog(0))));
+/// assert!(vm.interpret("while (true) {}").is_err());
+/// ```
+pub trait VmHooks: MaybeSend {
+    /// Called before each instruction runs, with the function running it and the offset of the
+    /// instruction in its chunk. An error stops the script with its message as a runtime error
+    fn on_instruction(&mut self, _function: &Function, _offset: usize) -> Result<(), NativeError> {
+        Ok(())
+    }
+
+    /// Called when a Lox function or a native function is called, with its name and arguments.
+    /// Calling a class calls its initializer, if it has one
+    fn on_call(&mut self, _name: &str, _args: &[Value]) {}
+
+    /// Called when a function called by [`VmHooks::on_call`] returns, with its result
+    fn on_return(&mut self, _name: &str, _result: &Value) {}
+
+    /// Called when a runtime error aborts the script, with the message and the stack trace the
+    /// reporter gets
+    fn on_error(&mut self, _message: &str, _stack_trace: &[String]) {}
+}
+
+/// Share the hooks with the host, which reads what they collected afterwards
+impl<H: VmHooks + MaybeSync> VmHooks for Rc<RefCell<H>> {
+    fn on_instruction(&mut self, function: &Function, offset: usize) -> Result<(), NativeError> {
+        self.borrow_mut().on_instruction(function, offset)
+    }
+
+    fn on_call(&mut self, name: &str, args: &[Value]) {
+        self.borrow_mut().on_call(name, args);
+    }
+
+    fn on_return(&mut self, name: &str, result: &Value) {
+        self.borrow_mut().on_return(name, result);
+    }
+
+    fn on_error(&mut self, message: &str, stack_trace: &[String]) {
+        self.borrow_mut().on_error(message, stack_trace);
+    }
+}
@@ -0,0 +1,79 @@
+//! A bytecode virtual machine for the Lox programming language.
+//!
+//! Embed it in a Rust program by creating a [`VM`] and feeding it source code. The globals live as
+//! long as the VM, so the code run by later calls can see the variables defined by earlier ones
+//!
+//! ```
+//! use rustlox::{Value, VM};
+//!
+//! let mut vm = VM::new();
+//! vm.set_global("answer", Value::Number(42.0));
+//! vm.interpret("var doubled = answer * 2;")?;
+//! assert!(matches!(vm.get_global("doubled"), Some(Value::Number(n)) if n == 84.0));
+//! # Ok::<(), rustlox::LoxError>(())
+//! ```
+//!
+//! After loading a script, the host can call the functions it defines without compiling them again
+//!
+//! ```
+//! use rustlox::{Value, VM};
+//!
+//! let mut vm = VM::new();
+//! vm.interpret("fun double(x) { return x * 2; }")?;
+//! assert!(matches!(vm.call_function("double", &[Value::Number(21.0)]), Ok(Value::Number(n)) if n == 42.0));
+//! # Ok::<(), rustlox::LoxError>(())
+//! ```
+//!
+//! The numbers, strings and booleans convert to values with `into()`, and back with `try_into()`,
+//! which fails with a [`value::NativeError`] a native function can return as it is
+//!
+//! ```
+//! use rustlox::VM;
+//!
+//! let mut vm = VM::new();
+//! vm.interpret(r#"fun greet(name) { return "Hello, " + name; }"#)?;
+//! let greeting: String = vm.call_function("greet", &["Lox".into()])?.try_into()?;
+//! assert_eq!(greeting, "Hello, Lox");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Compile errors and runtime errors are printed to stderr by default. Use
+//! [`VM::with_reporter`] to handle them in another way, e.g. keep them with a
+//! [`CollectingReporter`]
+
+pub mod ast;
+pub mod bytecode;
+pub mod chunk;
+pub mod compiler;
+pub mod conformance;
+pub mod disassembler;
+pub mod error;
+pub mod formatter;
+pub mod fuzz;
+pub mod gc;
+pub mod hooks;
+pub mod lint;
+pub mod methods;
+pub mod natives;
+pub mod optimizer;
+pub mod output;
+pub mod profiler;
+pub mod reporter;
+pub mod scanner;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod shared;
+mod timers;
+pub mod value;
+pub mod verifier;
+pub mod vm;
+
+pub use compiler::CompiledScript;
+pub use error::LoxError;
+pub use hooks::VmHooks;
+pub use reporter::{
+    CollectingReporter, Diagnostic, JsonReporter, Reporter, RuntimeErrorReport, Severity,
+    StderrReporter, WriterReporter,
+};
+pub use value::Value;
+pub use vm::{StepResult, VM};
@@ -0,0 +1,98 @@
+//! The lints which walk the compiled code of the whole program, for the likely mistakes the
+//! compiler can't see while it compiles one declaration, e.g. a global which is never defined
+use crate::chunk::OpCode;
+use crate::reporter::{did_you_mean, Diagnostic, Severity};
+use crate::scanner::Span;
+use crate::value::{Function, Value};
+use std::collections::HashSet;
+
+/// A `GetGlobal` instruction, with where it comes from
+struct GlobalRead<'a> {
+    name: &'a str,
+    function: &'a Function,
+    offset: usize,
+}
+
+/// Warn about the globals the program reads but never defines, which are mostly typos. The ones
+/// `is_defined` says are defined outside the program, e.g. the natives, are fine. An imported
+/// module may define any global, so a program with `import` isn't checked
+pub fn undefined_globals(
+    function: &Function,
+    is_defined: impl Fn(&str) -> bool,
+) -> Vec<Diagnostic> {
+    let (mut defined, mut reads) = (HashSet::new(), vec![]);
+    if !collect_globals(function, &mut defined, &mut reads) {
+        return vec![];
+    }
+    let mut reported = HashSet::new();
+    reads
+        .into_iter()
+        .filter(|read| !defined.contains(read.name) && !is_defined(read.name))
+        // One warning for each name is enough
+        .filter(|read| reported.insert(read.name))
+        .map(|read| {
+            let message = format!("Global variable '{}' is never defined.", read.name);
+            let diagnostic = Diagnostic::at_instruction(read.function, read.offset, &message);
+            let notes = did_you_mean(read.name, defined.iter().copied());
+            Diagnostic {
+                severity: Severity::Warning,
+                notes: notes.into_iter().collect(),
+                lexeme: read.name.to_string(),
+                location: format!(" at '{}'", read.name),
+                span: Span {
+                    len: read.name.len(),
+                    ..diagnostic.span
+                },
+                ..diagnostic
+            }
+        })
+        .collect()
+}
+
+/// Collect the globals defined and read by the function and the functions defined in it.
+/// Returns false if it imports a module
+fn collect_globals<'a>(
+    function: &'a Function,
+    defined: &mut HashSet<&'a str>,
+    reads: &mut Vec<GlobalRead<'a>>,
+) -> bool {
+    let chunk = &function.chunk;
+    // A constant which isn't a name, which the compiler never writes, is skipped
+    let name = |idx: usize| match chunk.constants.values.get(idx) {
+        Some(Value::String(name)) => Some(name.as_str()),
+        _ => None,
+    };
+    let byte = |offset: usize| chunk.code[offset + 1] as usize;
+    let short =
+        |offset: usize| u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        match OpCode::try_from(chunk.code[offset]) {
+            Ok(OpCode::DefineGlobal) => defined.extend(name(byte(offset))),
+            Ok(OpCode::DefineGlobalLong) => defined.extend(name(short(offset) as usize)),
+            Ok(OpCode::GetGlobal) => reads.extend(name(byte(offset)).map(|name| GlobalRead {
+                name,
+                function,
+                offset,
+            })),
+            Ok(OpCode::GetGlobalLong) => {
+                reads.extend(name(short(offset) as usize).map(|name| GlobalRead {
+                    name,
+                    function,
+                    offset,
+                }))
+            }
+            Ok(OpCode::Import | OpCode::ImportLong) => return false,
+            _ => {}
+        }
+        offset += chunk.instruction_len(offset);
+    }
+    chunk
+        .constants
+        .values
+        .iter()
+        .all(|constant| match constant {
+            Value::Func(inner) => collect_globals(inner, defined, reads),
+            _ => true,
+        })
+}
@@ -1,51 +1,579 @@
-mod chunk;
-mod compiler;
-mod disassembler;
-mod scanner;
-mod value;
-mod vm;
-
+use rustlox::ast;
+use rustlox::compiler::Warnings;
+use rustlox::conformance;
+use rustlox::disassembler::write_program_to;
+use rustlox::formatter;
+use rustlox::shared::Rc;
+use rustlox::vm::Capability;
+use rustlox::{bytecode, JsonReporter, LoxError, WriterReporter, VM};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::{fs, io, io::Read, io::Write, process};
-use vm::{InterpretResult, VM};
 
 fn repl(vm: &mut VM) {
+    let running = handle_ctrl_c(vm);
+    // Keep reading lines until the input is complete, e.g. a function typed over several lines
+    let mut input = String::new();
     loop {
-        print!("> ");
+        print!("{}", if input.is_empty() { "> " } else { "... " });
         io::stdout().flush().expect("Write to stdout failed");
-        let mut line = String::new();
-        if let Ok(size) = io::stdin().read_line(&mut line) {
-            if size == 0 {
-                break;
-            }
+        match io::stdin().read_line(&mut input) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if VM::is_incomplete(&input) {
+            continue;
+        }
+        // The errors are already printed by the VM, which discards the aborted calls. The globals
+        // defined so far are kept, so just move on to the next input
+        running.store(true, Ordering::Relaxed);
+        let result = vm.interpret_repl(&input);
+        running.store(false, Ordering::Relaxed);
+        if let Err(LoxError::Exit(code)) = result {
+            process::exit(code);
         }
-        vm.interpret(&line);
+        input.clear();
     }
 }
 
-fn run_file(filename: &str, vm: &mut VM) {
+/// Make Ctrl-C interrupt the script while the returned flag is set, which is a runtime error, and
+/// exit otherwise, e.g. at the REPL prompt
+fn handle_ctrl_c(vm: &VM) -> Arc<AtomicBool> {
+    let running = Arc::new(AtomicBool::new(false));
+    let (interrupt, flag) = (vm.interrupt_handle(), Arc::clone(&running));
+    let handled = ctrlc::set_handler(move || {
+        if flag.load(Ordering::Relaxed) {
+            interrupt.interrupt();
+        } else {
+            process::exit(130);
+        }
+    });
+    if let Err(e) = handled {
+        eprintln!("Could not handle Ctrl-C: {e}");
+    }
+    running
+}
+
+/// Read the whole file, or exit if it can't be read
+fn read_file(filename: &str) -> Vec<u8> {
     let Ok(mut file) = fs::File::open(filename) else {
         eprintln!("Could not open the file {filename} or not enough memory to read");
         process::exit(74);
     };
-    let mut content = String::new();
-    if file.read_to_string(&mut content).is_err() {
+    let mut content = vec![];
+    if file.read_to_end(&mut content).is_err() {
+        eprintln!("Could not read file {filename}");
+        process::exit(74);
+    }
+    content
+}
+
+/// Read the source code of the file, or exit if it's not text
+fn read_source(filename: &str) -> String {
+    into_source(filename, read_file(filename))
+}
+
+fn into_source(filename: &str, content: Vec<u8>) -> String {
+    String::from_utf8(content).unwrap_or_else(|_| {
         eprintln!("Could not read file {filename}");
         process::exit(74);
+    })
+}
+
+fn exit_on_error(result: Result<(), LoxError>) {
+    match result {
+        Err(LoxError::CompileError(_)) => process::exit(65),
+        Err(LoxError::RuntimeError { .. } | LoxError::LimitExceeded(_)) => process::exit(70),
+        Err(LoxError::Exit(code)) => process::exit(code),
+        Ok(()) => (),
+    }
+}
+
+/// Run a Lox file, which is either source code or compiled by `compile`
+fn run_file(filename: &str, vm: &mut VM) -> Result<(), LoxError> {
+    let content = read_file(filename);
+    let result = if bytecode::is_bytecode(&content) {
+        match bytecode::deserialize(&content) {
+            Ok(mut function) => {
+                function.file = Some(Rc::from(filename));
+                vm.run_function(function)
+            }
+            Err(e) => {
+                eprintln!("Could not load {filename}: {e}");
+                process::exit(65);
+            }
+        }
+    } else {
+        vm.interpret_file(filename, &into_source(filename, content))
+    };
+    // Then the timers the script set, like Node.js does
+    result.and_then(|()| vm.run_event_loop())
+}
+
+/// The scripts run from the command line are trusted with every capability, unless the options
+/// say otherwise
+fn deny_capabilities(vm: &mut VM, options: &Options) {
+    for capability in Capability::ALL {
+        let allowed = if options.denied.contains(&capability) {
+            false
+        } else {
+            !options.sandbox || options.allowed.contains(&capability)
+        };
+        if !allowed {
+            vm.deny(capability);
+        }
+    }
+}
+
+/// Print the profile table to stderr, and write the JSON if asked to
+fn report_profile(vm: &VM, options: &Options) {
+    let Some(profile) = vm.profile() else {
+        return;
+    };
+    if options.profile {
+        eprint!("{}", profile.report());
+    }
+    if let Some(path) = &options.profile_json {
+        if fs::write(path, profile.to_json()).is_err() {
+            eprintln!("Could not write the file {path}");
+            process::exit(74);
+        }
+    }
+}
+
+/// Compile a Lox file into a `.loxc` file which runs without the source
+fn compile_file(filename: &str, output: &str, vm: &mut VM) {
+    let source = read_source(filename);
+    let Ok(function) = vm.compile(&source) else {
+        process::exit(65);
+    };
+    if fs::write(output, bytecode::serialize(&function)).is_err() {
+        eprintln!("Could not write the file {output}");
+        process::exit(74);
+    }
+}
+
+/// Compile a Lox file without running it. The VM reports the errors and the warnings if any
+fn check_file(filename: &str, vm: &mut VM) {
+    if vm.compile(&read_source(filename)).is_err() {
+        process::exit(65);
+    }
+}
+
+/// Format a Lox file in place, or with `check` only tell whether it's formatted already
+fn format_file(filename: &str, check: bool) {
+    let source = read_source(filename);
+    let formatted = formatter::format(&source).unwrap_or_else(|diagnostic| {
+        eprintln!("{diagnostic}");
+        process::exit(65);
+    });
+    if formatted == source {
+        return;
+    }
+    if check {
+        println!("{filename} is not formatted");
+        process::exit(1);
+    }
+    if fs::write(filename, formatted).is_err() {
+        eprintln!("Could not write the file {filename}");
+        process::exit(74);
+    }
+}
+
+/// Print the syntax tree of a Lox file, or the compile errors
+fn print_ast(filename: &str) {
+    match ast::parse(&read_source(filename)) {
+        Ok(tree) => print!("{tree}"),
+        Err(diagnostics) => {
+            for diagnostic in diagnostics {
+                eprintln!("{diagnostic}");
+            }
+            process::exit(65);
+        }
+    }
+}
+
+/// The `.lox` files in the directory and its subdirectories, in order
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            lox_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Run every Lox file in the directory in a new VM, and count the ones which run without errors.
+/// The output of the scripts is discarded, and the errors are printed after the failing files
+fn run_tests(dir: &str, options: &Options) {
+    let mut files = vec![];
+    if let Err(e) = lox_files(Path::new(dir), &mut files) {
+        eprintln!("Could not read the directory {dir}: {e}");
+        process::exit(74);
+    }
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let filename = file.to_string_lossy();
+        let source = read_source(&filename);
+        let mut vm = VM::with_reporter(Box::new(WriterReporter::new(Box::new(io::sink()))));
+        vm.set_stdout(Box::new(io::sink()));
+        vm.set_optimize(options.optimize);
+        vm.set_warnings(options.warnings);
+        vm.set_lint(options.lint);
+        deny_capabilities(&mut vm, options);
+        vm.set_module_path(options.module_path.clone());
+        match vm.interpret_file(&filename, &source) {
+            Ok(()) | Err(LoxError::Exit(0)) => {
+                println!("PASS {}", file.display());
+                passed += 1;
+            }
+            Err(e) => {
+                println!("FAIL {}", file.display());
+                for line in e.to_string().lines() {
+                    println!("    {line}");
+                }
+                failed += 1;
+            }
+        }
+    }
+    println!("\n{passed} passed, {failed} failed");
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Run the Lox test suite of Crafting Interpreters, or any tests written like it, which check
+/// the output and the errors against the `// expect: ...` comments in the files
+fn run_test_suite(dir: &str) {
+    let files = conformance::test_files(Path::new(dir)).unwrap_or_else(|e| {
+        eprintln!("Could not read the directory {dir}: {e}");
+        process::exit(74);
+    });
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let failures = conformance::run_test(&read_source(&file.to_string_lossy()));
+        if failures.is_empty() {
+            passed += 1;
+            continue;
+        }
+        println!("FAIL {}", file.display());
+        for failure in failures {
+            println!("    {failure}");
+        }
+        failed += 1;
     }
-    match vm.interpret(&content) {
-        InterpretResult::CompileError => process::exit(65),
-        InterpretResult::RuntimeError => process::exit(70),
-        InterpretResult::Ok => (),
+    println!("\n{passed} passed, {failed} failed");
+    if failed > 0 {
+        process::exit(1);
     }
 }
 
+/// Print the bytecode of every function in a Lox file, or a `.loxc` file
+fn disassemble_file(filename: &str, vm: &mut VM) {
+    let content = read_file(filename);
+    let function = if bytecode::is_bytecode(&content) {
+        bytecode::deserialize(&content).unwrap_or_else(|e| {
+            eprintln!("Could not load {filename}: {e}");
+            process::exit(65);
+        })
+    } else {
+        let Ok(function) = vm.compile(&into_source(filename, content)) else {
+            process::exit(65);
+        };
+        function
+    };
+    if write_program_to(&mut io::stdout(), &function).is_err() {
+        eprintln!("Could not write the output");
+        process::exit(74);
+    }
+}
+
+const USAGE: &str = "\
+Usage: rustlox [options] [command] [script arguments]
+
+Commands:
+    repl                    Start the REPL (the default)
+    run <file>              Run a Lox file or a compiled .loxc file, also `rustlox <file>`
+    check <file>            Compile a Lox file and report the errors without running it, also
+                            `rustlox --check <file>`
+    disasm <file>           Print the bytecode of all the functions in a file
+    compile <file> [-o out] Compile a Lox file to bytecode, `<file>c` by default
+    test <dir>              Run every .lox file in a directory and its subdirectories, and report
+                            the ones which fail, e.g. by `assert(condition, message)`
+    fmt <file> [--check]    Format a Lox file in place, or with --check exit with 1 if it isn't
+                            formatted
+    ast <file>              Print the syntax tree of a Lox file
+    test-suite <dir>        Run the Lox test suite of Crafting Interpreters in a directory, which
+                            checks the output and the errors against the `// expect:` comments
+
+Options:
+    -O, --optimize          Run the peephole optimizer over the bytecode
+    --disassemble           Print the bytecode of every function when it's compiled
+    --trace-execution       Print every instruction and the stack while running
+    --trace-file <path>     Write the trace to a file instead
+    --profile               Print the calls and the executed opcodes when the script exits
+    --profile-json <path>   Write them to a file as JSON
+    --module-path <dir>     Also look for the imported modules in the directory, may be repeated
+    --sandbox               Deny all the capabilities but the ones given by --allow
+    --allow <capability>    Allow one of fs, env, time, process and net, may be repeated
+    --deny <capability>     Deny one of them, may be repeated
+    --warnings=<level>      deny, warn (the default) or off, for the warnings about e.g. the
+                            unused variables. Denied warnings are compile errors
+    --lint                  Also warn about the locals shadowing other locals, and the globals
+                            which are never defined
+    --error-format=<format> human (the default) or json, which prints the errors and the warnings
+                            as JSON objects, one per line
+    --max-trace-frames <n>  Show at most n frames in the stack trace of a runtime error
+    --no-color              Don't color the errors and the warnings, which are only colored for
+                            a terminal anyway
+    --deterministic <seed>  Run the same way every time: the clock is virtual, `sleep` doesn't
+                            wait and `random` starts from the seed, a whole number
+    -h, --help              Show this message
+
+The arguments after the file to run go to the script, which reads them as a list with `args()`
+
+Environment:
+    RUSTLOX_TRACE           1 to trace to stdout, or the file to trace to
+    NO_COLOR                Set to anything but empty to not color the errors, like --no-color
+    LOX_PATH                The directories to look for the imported modules in after the
+                            --module-path ones, separated like PATH";
+
+/// The commands which take a file, anything else is a file to run
+const COMMANDS: [&str; 9] = [
+    "repl",
+    "run",
+    "check",
+    "disasm",
+    "compile",
+    "test",
+    "fmt",
+    "ast",
+    "test-suite",
+];
+
+enum Command {
+    Help,
+    Repl,
+    Run(String),
+    Check(String),
+    Disasm(String),
+    Compile { file: String, output: String },
+    Test(String),
+    TestSuite(String),
+    Fmt { file: String, check: bool },
+    Ast(String),
+}
+
+#[derive(Default)]
+struct Options {
+    optimize: bool,
+    disassemble: bool,
+    trace_execution: bool,
+    /// Write the trace to this file instead of stdout
+    trace_file: Option<String>,
+    /// Print the profile table when the script exits
+    profile: bool,
+    /// Write the profile as JSON to this file when the script exits
+    profile_json: Option<String>,
+    /// The arguments after the file to run, for the script
+    script_args: Vec<String>,
+    /// The directories given by `--module-path`, then the ones in `LOX_PATH`
+    module_path: Vec<PathBuf>,
+    /// Deny all the capabilities but the allowed ones, instead of allowing all but the denied ones
+    sandbox: bool,
+    allowed: Vec<Capability>,
+    denied: Vec<Capability>,
+    warnings: Warnings,
+    /// Warn about the shadowed locals and the undefined globals too
+    lint: bool,
+    /// Report the errors and the warnings as JSON lines instead of text
+    json_errors: bool,
+    /// The most frames shown in the stack trace of a runtime error
+    max_trace_frames: Option<usize>,
+    /// Print the errors without colors, even to a terminal
+    no_color: bool,
+    /// Run with the virtual clock, and the random numbers from the seed
+    seed: Option<u64>,
+}
+
+/// Parse the arguments without the program name. The options may come anywhere before the file
+/// to run, the arguments after it are the script's own
+fn parse_args(args: &[String]) -> Result<(Command, Options), String> {
+    let mut options = Options::default();
+    let mut positional = vec![];
+    let mut output = None;
+    let mut check = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok((Command::Help, options)),
+            "-O" | "--optimize" => options.optimize = true,
+            "--disassemble" => options.disassemble = true,
+            "--trace-execution" => options.trace_execution = true,
+            "--trace-file" => match args.next() {
+                Some(path) => options.trace_file = Some(path.clone()),
+                None => return Err("Missing the path after --trace-file.".to_string()),
+            },
+            "--profile" => options.profile = true,
+            "--profile-json" => match args.next() {
+                Some(path) => options.profile_json = Some(path.clone()),
+                None => return Err("Missing the path after --profile-json.".to_string()),
+            },
+            "--module-path" => match args.next() {
+                Some(dir) => options.module_path.push(PathBuf::from(dir)),
+                None => return Err("Missing the directory after --module-path.".to_string()),
+            },
+            "--sandbox" => options.sandbox = true,
+            "--allow" | "--deny" => {
+                let Some(name) = args.next() else {
+                    return Err(format!("Missing the capability after {arg}."));
+                };
+                let capability = name.parse()?;
+                if arg == "--allow" {
+                    options.allowed.push(capability);
+                } else {
+                    options.denied.push(capability);
+                }
+            }
+            "--max-trace-frames" => match args.next().map(|n| n.parse()) {
+                Some(Ok(max)) => options.max_trace_frames = Some(max),
+                Some(Err(_)) | None => {
+                    return Err("Expected a number after --max-trace-frames.".to_string())
+                }
+            },
+            "--no-color" => options.no_color = true,
+            "--deterministic" => match args.next().map(|seed| seed.parse()) {
+                Some(Ok(seed)) => options.seed = Some(seed),
+                Some(Err(_)) | None => {
+                    return Err("Expected a seed after --deterministic.".to_string())
+                }
+            },
+            "--lint" => options.lint = true,
+            flag if flag.starts_with("--error-format=") => {
+                options.json_errors = match &flag["--error-format=".len()..] {
+                    "json" => true,
+                    "human" => false,
+                    format => return Err(format!("Unknown error format '{format}'.")),
+                };
+            }
+            flag if flag.starts_with("--warnings=") => {
+                options.warnings = flag["--warnings=".len()..].parse()?;
+            }
+            "--check" => check = true,
+            "-o" => match args.next() {
+                Some(path) => output = Some(path.clone()),
+                None => return Err("Missing the path after -o.".to_string()),
+            },
+            flag if flag.starts_with('-') => return Err(format!("Unknown option {flag}.")),
+            _ => {
+                positional.push(arg.clone());
+                let runs = match positional.as_slice() {
+                    [command, _] => command == "run",
+                    [file] => !COMMANDS.contains(&file.as_str()),
+                    _ => false,
+                };
+                if runs {
+                    options.script_args = args.by_ref().cloned().collect();
+                }
+            }
+        }
+    }
+
+    let command = match positional.as_slice() {
+        [] => Command::Repl,
+        [command] if command == "repl" => Command::Repl,
+        [command, file] if command == "run" => Command::Run(file.clone()),
+        [command, file] if command == "check" => Command::Check(file.clone()),
+        [command, file] if command == "disasm" => Command::Disasm(file.clone()),
+        [command, dir] if command == "test" => Command::Test(dir.clone()),
+        [command, file] if command == "ast" => Command::Ast(file.clone()),
+        [command, dir] if command == "test-suite" => Command::TestSuite(dir.clone()),
+        [command, file] if command == "fmt" => Command::Fmt {
+            file: file.clone(),
+            check,
+        },
+        [command, file] if command == "compile" => Command::Compile {
+            output: output.take().unwrap_or_else(|| format!("{file}c")),
+            file: file.clone(),
+        },
+        [file] => Command::Run(file.clone()),
+        _ => return Err("Unexpected arguments.".to_string()),
+    };
+    let command = match command {
+        Command::Run(file) | Command::Check(file) if check => Command::Check(file),
+        command @ Command::Fmt { .. } => command,
+        _ if check => return Err("--check needs a file.".to_string()),
+        command => command,
+    };
+    if output.is_some() {
+        return Err("-o only works with compile.".to_string());
+    }
+    Ok((command, options))
+}
+
 fn main() {
-    let args: Vec<_> = std::env::args().collect();
-    let mut virtual_machine = VM::new();
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let (command, mut options) = parse_args(&args).unwrap_or_else(|e| {
+        eprintln!("{e}\n\n{USAGE}");
+        process::exit(64);
+    });
 
-    match &args[1..] {
-        [] => repl(&mut virtual_machine),
-        [file] => run_file(file, &mut virtual_machine),
-        _ => eprintln!("Usage: clox [path]"),
+    let mut virtual_machine = if options.json_errors {
+        VM::with_reporter(Box::new(JsonReporter::new(Box::new(io::stderr()))))
+    } else if options.no_color {
+        VM::with_reporter(Box::new(WriterReporter::new(Box::new(io::stderr()))))
+    } else {
+        // Colored when stderr is a terminal, unless `NO_COLOR` is set
+        VM::new()
+    };
+    deny_capabilities(&mut virtual_machine, &options);
+    virtual_machine.set_optimize(options.optimize);
+    virtual_machine.set_disassemble(options.disassemble);
+    virtual_machine.set_warnings(options.warnings);
+    virtual_machine.set_lint(options.lint);
+    virtual_machine.set_max_trace_frames(options.max_trace_frames);
+    virtual_machine.set_deterministic(options.seed);
+    virtual_machine.set_args(std::mem::take(&mut options.script_args));
+    if let Some(paths) = std::env::var_os("LOX_PATH") {
+        let dirs = std::env::split_paths(&paths).filter(|dir| !dir.as_os_str().is_empty());
+        options.module_path.extend(dirs);
+    }
+    virtual_machine.set_module_path(options.module_path.clone());
+    // `RUSTLOX_TRACE=1` traces to stdout, any other value is the file to trace to
+    let env_trace = std::env::var("RUSTLOX_TRACE").unwrap_or_default();
+    virtual_machine.set_profiling(options.profile || options.profile_json.is_some());
+    virtual_machine.set_trace_execution(options.trace_execution || env_trace == "1");
+    let env_trace_file = (!["", "0", "1"].contains(&env_trace.as_str())).then_some(env_trace);
+    if let Some(path) = options.trace_file.clone().or(env_trace_file) {
+        let Ok(file) = fs::File::create(&path) else {
+            eprintln!("Could not create the trace file {path}");
+            process::exit(74);
+        };
+        virtual_machine.set_trace_output(Box::new(io::BufWriter::new(file)));
+    }
+    match command {
+        Command::Help => println!("{USAGE}"),
+        Command::Repl => repl(&mut virtual_machine),
+        Command::Run(file) => {
+            handle_ctrl_c(&virtual_machine).store(true, Ordering::Relaxed);
+            let result = run_file(&file, &mut virtual_machine);
+            // The profile is also useful when the script fails
+            report_profile(&virtual_machine, &options);
+            exit_on_error(result);
+        }
+        Command::Check(file) => check_file(&file, &mut virtual_machine),
+        Command::Disasm(file) => disassemble_file(&file, &mut virtual_machine),
+        Command::Compile { file, output } => compile_file(&file, &output, &mut virtual_machine),
+        Command::Test(dir) => run_tests(&dir, &options),
+        Command::TestSuite(dir) => run_test_suite(&dir),
+        Command::Fmt { file, check } => format_file(&file, check),
+        Command::Ast(file) => print_ast(&file),
     }
 }
@@ -0,0 +1,267 @@
+//! The methods of the built-in types every VM starts with, e.g. `"a,b".split(",")` and
+//! `(42).toString()`. Each one gets the receiver as its first argument
+use crate::shared::{Rc, RefCell};
+use crate::value::{NativeError, NativeFunction, Value};
+use crate::vm::VM;
+
+/// Define the methods of the strings, numbers, booleans, `nil`, lists and ranges on `vm`
+pub(crate) fn register(vm: &mut VM) {
+    for type_name in ["string", "number", "boolean", "nil", "list", "range"] {
+        vm.register_method(type_name, "toString", 0, to_string);
+    }
+
+    vm.register_method("string", "length", 0, |_vm, args| {
+        Ok(Value::Int(receiver(args)?.chars().count() as i64))
+    });
+    vm.register_method("string", "upper", 0, |_vm, args| {
+        Ok(receiver(args)?.to_uppercase().into())
+    });
+    vm.register_method("string", "lower", 0, |_vm, args| {
+        Ok(receiver(args)?.to_lowercase().into())
+    });
+    vm.register_method("string", "trim", 0, |_vm, args| {
+        Ok(receiver(args)?.trim().into())
+    });
+    vm.register_method("string", "contains", 1, |_vm, args| {
+        Ok(receiver(args)?.contains(string(args, 1)?).into())
+    });
+    vm.register_method("string", "startsWith", 1, |_vm, args| {
+        Ok(receiver(args)?.starts_with(string(args, 1)?).into())
+    });
+    vm.register_method("string", "endsWith", 1, |_vm, args| {
+        Ok(receiver(args)?.ends_with(string(args, 1)?).into())
+    });
+    vm.register_method("string", "indexOf", 1, index_of);
+    vm.register_method("string", "split", 1, split);
+    vm.register_method("string", "replace", 2, |_vm, args| {
+        Ok(receiver(args)?
+            .replace(string(args, 1)?, string(args, 2)?)
+            .into())
+    });
+    vm.register_method("string", "repeat", 1, repeat);
+
+    vm.register_method("number", "toFixed", 1, to_fixed);
+
+    vm.register_method("list", "length", 0, |_vm, args| {
+        Ok(Value::Int(list(args)?.borrow().len() as i64))
+    });
+    vm.register_method("list", "push", 1, |_vm, args| {
+        list(args)?.borrow_mut().push(args[1].clone());
+        Ok(Value::Nil)
+    });
+    vm.register_method("list", "pop", 0, |_vm, args| {
+        list(args)?
+            .borrow_mut()
+            .pop()
+            .ok_or_else(|| NativeError::new("Can't pop from an empty list."))
+    });
+    vm.register_method("list", "map", 1, map);
+    vm.register_method("list", "filter", 1, filter);
+    vm.define_method(
+        "list",
+        NativeFunction::new("reduce", 2, reduce).min_arity(1),
+    );
+    vm.define_method("list", NativeFunction::new("sort", 1, sort).min_arity(0));
+    vm.register_method("list", "reverse", 0, |_vm, args| {
+        list(args)?.borrow_mut().reverse();
+        Ok(args[0].clone())
+    });
+    vm.register_method("list", "contains", 1, |_vm, args| {
+        Ok(list(args)?.borrow().contains(&args[1]).into())
+    });
+    vm.register_method("list", "indexOf", 1, |_vm, args| {
+        let idx = list(args)?
+            .borrow()
+            .iter()
+            .position(|element| *element == args[1]);
+        Ok(Value::Int(idx.map_or(-1, |idx| idx as i64)))
+    });
+}
+
+/// The receiver, which is a string for the methods of the strings
+fn receiver(args: &[Value]) -> Result<&str, NativeError> {
+    string(args, 0)
+}
+
+/// The argument at `idx`, which must be a string
+fn string(args: &[Value], idx: usize) -> Result<&str, NativeError> {
+    (&args[idx]).try_into()
+}
+
+/// The receiver of the methods of the lists
+fn list(args: &[Value]) -> Result<&Rc<RefCell<Vec<Value>>>, NativeError> {
+    match &args[0] {
+        Value::List(list) => Ok(list),
+        value => Err(NativeError::new(format!(
+            "Expect a list, got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+/// The elements of the list receiver, copied so a callback may change the list meanwhile
+fn elements(args: &[Value]) -> Result<Vec<Value>, NativeError> {
+    Ok(list(args)?.borrow().clone())
+}
+
+/// The argument at `idx`, which must be an integer which isn't negative, e.g. a count
+fn count(args: &[Value], idx: usize) -> Result<usize, NativeError> {
+    let n = f64::try_from(&args[idx])?;
+    if n.fract() != 0.0 || n < 0.0 {
+        return Err(NativeError::new(format!(
+            "Expect a non-negative integer, got {}.",
+            args[idx]
+        )));
+    }
+    Ok(n as usize)
+}
+
+/// The receiver the way `print` shows it
+fn to_string(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    match &args[0] {
+        Value::String(_) => Ok(args[0].clone()),
+        value => Ok(Value::string(vm.stringify(value)?)),
+    }
+}
+
+/// The index of the first character of the first occurrence of the argument, or `-1`. The
+/// indexes count the characters like `length()` does, not the bytes
+fn index_of(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let s = receiver(args)?;
+    let idx = s
+        .find(string(args, 1)?)
+        .map_or(-1, |byte_idx| s[..byte_idx].chars().count() as i64);
+    Ok(Value::Int(idx))
+}
+
+/// The list of the parts between the separators, e.g. `"a,b".split(",")` is `["a", "b"]`. An
+/// empty separator splits the string into its characters
+fn split(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let s = receiver(args)?;
+    let separator = string(args, 1)?;
+    let parts = if separator.is_empty() {
+        s.chars().map(|ch| ch.to_string().into()).collect()
+    } else {
+        s.split(separator).map(Value::from).collect()
+    };
+    Ok(Value::list(parts))
+}
+
+fn repeat(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let (s, times) = (receiver(args)?, count(args, 1)?);
+    // `str::repeat` panics when the length overflows
+    if s.len()
+        .checked_mul(times)
+        .is_none_or(|len| len > isize::MAX as usize)
+    {
+        return Err(NativeError::new("The repeated string is too long."));
+    }
+    Ok(s.repeat(times).into())
+}
+
+/// The number with exactly the digits after the point, e.g. `(3.14159).toFixed(2)` is `"3.14"`
+fn to_fixed(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let n = f64::try_from(&args[0])?;
+    let digits = count(args, 1)?;
+    if digits > 100 {
+        return Err(NativeError::new("Expect at most 100 digits."));
+    }
+    Ok(format!("{n:.digits$}").into())
+}
+
+/// A new list of the results of the function for every element, e.g. `[1, 2].map(str)`
+fn map(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let results = elements(args)?
+        .iter()
+        .map(|element| vm.call_value(&args[1], std::slice::from_ref(element)))
+        .collect::<Result<_, _>>()?;
+    Ok(Value::list(results))
+}
+
+/// A new list of the elements for which the function returns a truthy value
+fn filter(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let mut kept = vec![];
+    for element in elements(args)? {
+        let keep = vm.call_value(&args[1], std::slice::from_ref(&element))?;
+        if !matches!(keep, Value::Nil | Value::Bool(false)) {
+            kept.push(element);
+        }
+    }
+    Ok(Value::list(kept))
+}
+
+/// Combine the elements from the left by the function of the result so far and the element,
+/// e.g. `list.reduce(fun (sum, n) { return sum + n; }, 0)`. Without the initial value, the
+/// first element is the start
+fn reduce(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let mut elements = elements(args)?.into_iter();
+    let Some(mut result) = args.get(2).cloned().or_else(|| elements.next()) else {
+        return Err(NativeError::new(
+            "Can't reduce an empty list without an initial value.",
+        ));
+    };
+    for element in elements {
+        result = vm.call_value(&args[1], &[result, element])?;
+    }
+    Ok(result)
+}
+
+/// Sort the list in place and return it. The numbers and the strings sort in their order, and
+/// anything else needs the comparator, a function of two elements which returns a negative number
+/// if the first comes first, a positive one if it comes last, or zero. The sort is stable
+fn sort(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let list = list(args)?;
+    let elements = elements(args)?;
+    let sorted = match args.get(1) {
+        Some(comparator) => merge_sort(elements, &mut |a, b| {
+            let order = vm.call_value(comparator, &[a.clone(), b.clone()])?;
+            match order.as_number() {
+                Some(order) => Ok(order < 0.0),
+                None => Err(NativeError::new(format!(
+                    "The comparator must return a number, got {}.",
+                    order.type_name()
+                ))),
+            }
+        })?,
+        None => merge_sort(elements, &mut |a, b| match (a, b) {
+            (Value::Int(a), Value::Int(b)) => Ok(a < b),
+            (Value::String(a), Value::String(b)) => Ok(a < b),
+            _ => match (a.as_number(), b.as_number()) {
+                (Some(a), Some(b)) => Ok(a < b),
+                _ => Err(NativeError::new(format!(
+                    "Can't sort {} and {} without a comparator.",
+                    a.type_name(),
+                    b.type_name()
+                ))),
+            },
+        })?,
+    };
+    *list.borrow_mut() = sorted;
+    Ok(args[0].clone())
+}
+
+/// Sort by `less`, which may fail or be inconsistent, unlike the comparison `slice::sort_by`
+/// needs
+fn merge_sort(
+    mut elements: Vec<Value>,
+    less: &mut impl FnMut(&Value, &Value) -> Result<bool, NativeError>,
+) -> Result<Vec<Value>, NativeError> {
+    if elements.len() <= 1 {
+        return Ok(elements);
+    }
+    let right = elements.split_off(elements.len() / 2);
+    let (left, right) = (merge_sort(elements, less)?, merge_sort(right, less)?);
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Only a smaller element on the right goes first, which keeps the sort stable
+        let next = if less(b, a)? {
+            right.next()
+        } else {
+            left.next()
+        };
+        merged.extend(next);
+    }
+    merged.extend(left.chain(right));
+    Ok(merged)
+}
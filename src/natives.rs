@@ -0,0 +1,571 @@
+//! The native functions and constants every VM starts with
+use crate::disassembler::write_chunk;
+use crate::shared::Rc;
+use crate::value::{Coroutine, CoroutineStatus, NativeError, Value, MAX_SAFE_INTEGER};
+use crate::vm::{Capability, VM};
+use std::f64::consts;
+use std::fmt::Write;
+use std::time::Duration;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Define the natives as globals of `vm`
+pub(crate) fn register(vm: &mut VM) {
+    vm.register_native("clock", 0, clock);
+    vm.register_native("timeMillis", 0, time_millis);
+    vm.register_native("sleep", 1, sleep);
+    vm.register_native("formatTime", 2, format_time);
+    vm.register_native("setTimeout", 2, |vm, args| set_timer(vm, args, false));
+    vm.register_native("setInterval", 2, |vm, args| set_timer(vm, args, true));
+    vm.register_native("clearTimer", 1, clear_timer);
+    vm.register_native("random", 0, random);
+    vm.register_variadic_native("format", 1, format);
+    vm.register_variadic_native("printf", 1, printf);
+    vm.register_native("dis", 1, dis);
+    vm.register_native("isNan", 1, is_nan);
+    vm.register_native("isFinite", 1, is_finite);
+    vm.register_native("isInteger", 1, is_integer);
+    vm.register_native("str", 1, str);
+    vm.register_native("toString", 1, str);
+    vm.register_native("num", 1, num);
+    vm.register_native("len", 1, len);
+    vm.register_native("append", 2, append);
+    vm.register_native("assert", 2, assert);
+    vm.register_native("error", 1, error);
+    vm.register_native("exit", 1, exit);
+    vm.register_native("readLine", 0, read_line);
+    vm.register_native("getEnv", 1, get_env);
+    vm.register_native("setEnv", 2, set_env);
+    vm.register_native("args", 0, args);
+    vm.register_native("resume", 2, resume);
+    vm.register_native("isDone", 1, is_done);
+    register_math(vm);
+}
+
+/// The argument at `idx`, which must be a string
+fn string(args: &[Value], idx: usize) -> Result<&str, NativeError> {
+    (&args[idx]).try_into()
+}
+
+/// The argument at `idx`, which must be a coroutine
+fn coroutine(args: &[Value], idx: usize) -> Result<Rc<Coroutine>, NativeError> {
+    match &args[idx] {
+        Value::Coroutine(coroutine) => Ok(Rc::clone(coroutine)),
+        value => Err(NativeError::new(format!(
+            "Expect a coroutine, got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+/// The argument at `idx`, which must be a number
+fn number(args: &[Value], idx: usize) -> Result<f64, NativeError> {
+    f64::try_from(&args[idx])
+}
+
+type Unary = fn(f64) -> f64;
+type Binary = fn(f64, f64) -> f64;
+
+/// The math functions take and return numbers, like the ones of `f64` they call
+fn register_math(vm: &mut VM) {
+    let unary: [(&str, Unary); 9] = [
+        ("sqrt", f64::sqrt),
+        ("abs", f64::abs),
+        ("floor", f64::floor),
+        ("ceil", f64::ceil),
+        // Halfway cases round away from zero, e.g. `round(-2.5)` is `-3`
+        ("round", f64::round),
+        ("sin", f64::sin),
+        ("cos", f64::cos),
+        // The natural logarithm
+        ("log", f64::ln),
+        ("exp", f64::exp),
+    ];
+    for (name, function) in unary {
+        vm.register_native(name, 1, move |_vm, args| {
+            Ok(Value::Number(function(number(args, 0)?)))
+        });
+    }
+    let binary: [(&str, Binary); 3] = [("min", f64::min), ("max", f64::max), ("pow", f64::powf)];
+    for (name, function) in binary {
+        vm.register_native(name, 2, move |_vm, args| {
+            Ok(Value::Number(function(number(args, 0)?, number(args, 1)?)))
+        });
+    }
+    vm.set_global("PI", Value::Number(consts::PI));
+    vm.set_global("E", Value::Number(consts::E));
+    vm.set_global("MAX_SAFE_INTEGER", Value::Number(MAX_SAFE_INTEGER));
+}
+
+/// The time since the Unix epoch
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) fn since_the_epoch() -> Result<Duration, NativeError> {
+    // see: https://stackoverflow.com/questions/26593387/how-can-i-get-the-current-time-in-milliseconds
+    // The system clock may be set before 1970, which is an error of the script rather than a panic
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| NativeError::new("The system clock is set before the Unix epoch."))
+}
+
+/// A browser has no system clock for the standard library, `SystemTime::now` panics
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn since_the_epoch() -> Result<Duration, NativeError> {
+    Err(NativeError::new(
+        "The clock isn't available in WebAssembly.",
+    ))
+}
+
+fn clock(vm: &mut VM, _args: &[Value]) -> Result<Value, NativeError> {
+    vm.require(Capability::Time, "clock")?;
+    Ok(Value::Number(vm.now()?.as_secs_f64()))
+}
+
+/// A random number in `[0, 1)`, the same ones on every run of a deterministic VM
+fn random(vm: &mut VM, _args: &[Value]) -> Result<Value, NativeError> {
+    Ok(Value::Number(vm.random()))
+}
+
+/// Abort the script with the message if the condition is falsey, i.e. `nil` or `false`
+fn assert(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    match &args[0] {
+        Value::Nil | Value::Bool(false) => {
+            Err(NativeError::new(format!("Assertion failed: {}", args[1])))
+        }
+        _ => Ok(Value::Nil),
+    }
+}
+
+/// Abort the script with a runtime error, e.g. `error("Expect a positive number.")`
+fn error(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    Err(NativeError::new(args[0].to_string()))
+}
+
+/// Stop the script, which makes the interpreter exit with the code
+fn exit(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    vm.require(Capability::Process, "exit")?;
+    let code = number(args, 0)?;
+    if code.fract() != 0.0 || code < i32::MIN as f64 || code > i32::MAX as f64 {
+        return Err(NativeError::new(format!(
+            "Expect an integer exit code, got {}.",
+            args[0]
+        )));
+    }
+    Err(vm.exit(code as i32))
+}
+
+/// The milliseconds since the Unix epoch, as a whole number
+fn time_millis(vm: &mut VM, _args: &[Value]) -> Result<Value, NativeError> {
+    vm.require(Capability::Time, "timeMillis")?;
+    Ok(Value::Int(vm.now()?.as_millis() as i64))
+}
+
+/// Pause the script for the seconds, which may have a fraction
+fn sleep(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    vm.require(Capability::Time, "sleep")?;
+    let seconds = number(args, 0)?;
+    let duration = Duration::try_from_secs_f64(seconds).map_err(|_| {
+        NativeError::new(format!(
+            "Expect a non-negative number of seconds, got {}.",
+            args[0]
+        ))
+    })?;
+    vm.sleep(duration)?;
+    Ok(Value::Nil)
+}
+
+/// Call the function after the milliseconds, or every time they pass for an interval, once the
+/// script is done and the event loop runs, e.g. `setTimeout(fun () { print "later"; }, 500)`.
+/// Return the id of the timer, for `clearTimer`
+fn set_timer(vm: &mut VM, args: &[Value], repeat: bool) -> Result<Value, NativeError> {
+    vm.require(
+        Capability::Time,
+        if repeat { "setInterval" } else { "setTimeout" },
+    )?;
+    if !matches!(
+        args[0],
+        Value::Closure(_) | Value::NativeFunc(_) | Value::BoundMethod(_) | Value::Class(_)
+    ) {
+        return Err(NativeError::new(format!(
+            "Expect a function, got {}.",
+            args[0].type_name()
+        )));
+    }
+    let millis = number(args, 1)?;
+    let delay = Duration::try_from_secs_f64(millis / 1000.0).map_err(|_| {
+        NativeError::new(format!(
+            "Expect a non-negative number of milliseconds, got {}.",
+            args[1]
+        ))
+    })?;
+    let id = vm.set_timer(args[0].clone(), delay, repeat)?;
+    Ok(Value::Int(id as i64))
+}
+
+/// Cancel a timer by the id `setTimeout` or `setInterval` returned, and return whether it was
+/// pending. A timer may cancel itself
+fn clear_timer(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let id = number(args, 0)?;
+    Ok(Value::Bool(
+        id.fract() == 0.0 && id >= 1.0 && vm.clear_timer(id as u64),
+    ))
+}
+
+/// Format the seconds since the Unix epoch as a UTC date and time, e.g.
+/// `formatTime(clock(), "%Y-%m-%d %H:%M:%S")`. The format understands `%Y` (the year), `%m`,
+/// `%d`, `%H`, `%M`, `%S` (two digits each), `%L` (three digits of milliseconds) and `%%`
+fn format_time(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let epoch = number(args, 0)?;
+    let Value::String(format) = &args[1] else {
+        return Err(NativeError::new(format!(
+            "Expect a format string, got {}.",
+            args[1].type_name()
+        )));
+    };
+    if !epoch.is_finite() {
+        return Err(NativeError::new(format!(
+            "Expect a finite time, got {}.",
+            args[0]
+        )));
+    }
+    let millis = (epoch * 1000.0).floor() as i64;
+    let (days, millis_of_day) = (millis.div_euclid(86_400_000), millis.rem_euclid(86_400_000));
+    let (year, month, day) = civil_from_days(days);
+    let seconds_of_day = millis_of_day / 1000;
+
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('Y') => write!(out, "{year}"),
+            Some('m') => write!(out, "{month:02}"),
+            Some('d') => write!(out, "{day:02}"),
+            Some('H') => write!(out, "{:02}", seconds_of_day / 3600),
+            Some('M') => write!(out, "{:02}", seconds_of_day / 60 % 60),
+            Some('S') => write!(out, "{:02}", seconds_of_day % 60),
+            Some('L') => write!(out, "{:03}", millis_of_day % 1000),
+            Some('%') => write!(out, "%"),
+            Some(other) => {
+                return Err(NativeError::new(format!("Unknown time format '%{other}'.")))
+            }
+            None => return Err(NativeError::new("Expect a letter after '%'.")),
+        };
+    }
+    Ok(Value::string(out))
+}
+
+/// The year, month and day of the days since the Unix epoch, in the proleptic Gregorian
+/// calendar. See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Return the disassembly of a function's chunk as a string, or `nil` if the argument is not a
+/// function
+fn dis(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let function = match &args[0] {
+        Value::Closure(closure) => &closure.function,
+        Value::Func(function) => function,
+        _ => return Ok(Value::Nil),
+    };
+    let mut out = String::new();
+    write_chunk(&mut out, &function.chunk, &function.to_string())
+        .expect("Write to a String never fails");
+    Ok(Value::string(out))
+}
+
+/// Whether the argument is a number which is not a number, e.g. `0 / 0`
+fn is_nan(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    Ok(Value::Bool(number(args, 0)?.is_nan()))
+}
+
+/// Whether the argument is a number other than `inf`, `-inf` and `nan`
+fn is_finite(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    Ok(Value::Bool(number(args, 0)?.is_finite()))
+}
+
+/// Whether the argument is a whole number, an integer or a float, e.g. an index of a list. Past
+/// `MAX_SAFE_INTEGER` the floats are all whole but skip some integers, e.g. `2.0 ** 53 + 1` is
+/// `2.0 ** 53`
+fn is_integer(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let n = number(args, 0)?;
+    Ok(Value::Bool(n.is_finite() && n.fract() == 0.0))
+}
+
+/// Convert any value to a string, the way `print` shows it, e.g. `"count: " + str(3)`. It's also
+/// `toString(value)`
+fn str(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    match &args[0] {
+        Value::String(_) => Ok(args[0].clone()),
+        value => Ok(Value::string(vm.stringify(value)?)),
+    }
+}
+
+/// Fill the placeholders of the format string with the other arguments, e.g.
+/// `format("{} is {:.2}", "pi", PI)` is `"pi is 3.14"`. `{}` takes the next argument as `print`
+/// shows it, and `{:spec}` pads it and rounds it, see [`Spec`]. `{{` and `}}` are braces
+fn format(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let text = format_arguments(vm, string(args, 0)?, &args[1..])?;
+    Ok(Value::string(text))
+}
+
+/// Same as `format`, but print the result, without adding a newline
+fn printf(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let text = format_arguments(vm, string(args, 0)?, &args[1..])?;
+    vm.write_output(&text)?;
+    Ok(Value::Nil)
+}
+
+fn format_arguments(vm: &mut VM, format: &str, args: &[Value]) -> Result<String, NativeError> {
+    let mut out = String::new();
+    let mut next = 0;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' if chars.peek() == Some(&c) => {
+                chars.next();
+                out.push(c);
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(NativeError::new("Unterminated '{' in the format.")),
+                    }
+                }
+                let spec = match placeholder.strip_prefix(':') {
+                    Some(spec) => Spec::parse(spec)?,
+                    None if placeholder.is_empty() => Spec::default(),
+                    None => {
+                        return Err(NativeError::new(format!(
+                            "Invalid placeholder '{{{placeholder}}}', expect '{{}}' or '{{:spec}}'."
+                        )))
+                    }
+                };
+                let Some(value) = args.get(next) else {
+                    return Err(NativeError::new(format!(
+                        "Expect an argument for every placeholder of the format, got {}.",
+                        args.len()
+                    )));
+                };
+                let text = match (value.as_number(), spec.precision) {
+                    (Some(n), Some(precision)) => format!("{n:.precision$}"),
+                    (_, Some(precision)) => vm.stringify(value)?.chars().take(precision).collect(),
+                    (_, None) => vm.stringify(value)?,
+                };
+                spec.write(&mut out, &text, value.as_number().is_some());
+                next += 1;
+            }
+            '}' => return Err(NativeError::new("Unmatched '}' in the format, write '}}'.")),
+            c => out.push(c),
+        }
+    }
+    if next < args.len() {
+        return Err(NativeError::new(format!(
+            "Expect {next} arguments for the placeholders of the format, got {}.",
+            args.len()
+        )));
+    }
+    Ok(out)
+}
+
+/// How a placeholder shows its value: `[[fill]align][0][width][.precision]`, like in Rust. The
+/// value is padded to `width` characters with `fill`, a space by default, on the left for `>`,
+/// on the right for `<` and on both sides for `^`. The numbers are aligned right by default and
+/// the rest left. `0` pads a number with zeros after its sign. The precision is the number of
+/// decimals of a number, or the most characters of anything else, e.g. `{:>8.3}`
+#[derive(Default)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Result<Self, NativeError> {
+        let invalid = || NativeError::new(format!("Invalid format spec '{spec}'."));
+        let mut result = Spec::default();
+        let mut rest = spec;
+        let mut chars = spec.chars();
+        match (chars.next(), chars.next()) {
+            (Some(fill), Some(align @ ('<' | '^' | '>'))) => {
+                (result.fill, result.align) = (Some(fill), Some(align));
+                rest = chars.as_str();
+            }
+            (Some(align @ ('<' | '^' | '>')), _) => {
+                result.align = Some(align);
+                rest = &rest[1..];
+            }
+            _ => {}
+        }
+        if let Some(after) = rest.strip_prefix('0') {
+            result.zero = true;
+            rest = after;
+        }
+        let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let end = digits(rest);
+        if end > 0 {
+            result.width = rest[..end].parse().map_err(|_| invalid())?;
+        }
+        rest = &rest[end..];
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = digits(after);
+            if end == 0 {
+                return Err(invalid());
+            }
+            result.precision = Some(after[..end].parse().map_err(|_| invalid())?);
+            rest = &after[end..];
+        }
+        if rest.is_empty() {
+            Ok(result)
+        } else {
+            Err(invalid())
+        }
+    }
+
+    /// Pad the text of a value, already rounded or cut to the precision
+    fn write(&self, out: &mut String, text: &str, is_number: bool) {
+        let padding = self.width.saturating_sub(text.chars().count());
+        if self.zero && is_number {
+            // After the sign, e.g. `-007`
+            let (sign, digits) = text.split_at(usize::from(text.starts_with('-')));
+            out.push_str(sign);
+            out.extend(std::iter::repeat_n('0', padding));
+            out.push_str(digits);
+            return;
+        }
+        let fill = self.fill.unwrap_or(' ');
+        let (before, after) = match self.align {
+            Some('>') => (padding, 0),
+            Some('^') => (padding / 2, padding - padding / 2),
+            Some(_) => (0, padding),
+            None if is_number => (padding, 0),
+            None => (0, padding),
+        };
+        out.extend(std::iter::repeat_n(fill, before));
+        out.push_str(text);
+        out.extend(std::iter::repeat_n(fill, after));
+    }
+}
+
+/// The number of elements of a list, or of characters of a string
+fn len(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    match &args[0] {
+        Value::List(list) => Ok(Value::Int(list.borrow().len() as i64)),
+        Value::String(s) => Ok(Value::Int(s.chars().count() as i64)),
+        value => Err(NativeError::new(format!(
+            "Expect a list or a string, got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Add the value to the end of the list, e.g. `append(xs, 1)`
+fn append(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let Value::List(list) = &args[0] else {
+        return Err(NativeError::new(format!(
+            "Expect a list, got {}.",
+            args[0].type_name()
+        )));
+    };
+    list.borrow_mut().push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+/// Convert a string to a number, e.g. `num("1.5")` or `num("42")`. Surrounding whitespace is
+/// ignored
+fn num(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    match &args[0] {
+        Value::Int(_) | Value::Number(_) => Ok(args[0].clone()),
+        // An integer unless it has a fraction, like a literal
+        Value::String(s) => match s.trim().parse() {
+            Ok(n) => Ok(Value::Int(n)),
+            Err(_) => s
+                .trim()
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| NativeError::new(format!("Can't convert '{s}' to a number."))),
+        },
+        value => Err(NativeError::new(format!(
+            "Expect a string, got {}.",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Read a line from stdin, without the line break, or `nil` at the end of the input
+fn read_line(vm: &mut VM, _args: &[Value]) -> Result<Value, NativeError> {
+    vm.require(Capability::Process, "readLine")?;
+    match vm.read_line() {
+        Ok(line) => Ok(line.into()),
+        Err(e) => Err(NativeError::new(format!("Could not read a line: {e}."))),
+    }
+}
+
+/// The arguments of the script, a new list on every call so the script may change it
+fn args(vm: &mut VM, _args: &[Value]) -> Result<Value, NativeError> {
+    let args = vm
+        .args()
+        .iter()
+        .map(|arg| Value::string(arg.clone()))
+        .collect();
+    Ok(Value::list(args))
+}
+
+/// The value of an environment variable, or `nil` if it's not set or not Unicode
+fn get_env(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    vm.require(Capability::Env, "getEnv")?;
+    let name = string(args, 0)?;
+    Ok(std::env::var(name).map_or(Value::Nil, Value::string))
+}
+
+/// Set an environment variable of the process, which the programs it starts inherit. Changing
+/// the environment while another thread reads it is undefined behavior, so with the `threadsafe`
+/// feature, where the VMs run on several threads, it's a runtime error
+fn set_env(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    vm.require(Capability::Env, "setEnv")?;
+    if cfg!(feature = "threadsafe") {
+        return Err(NativeError::new(
+            "'setEnv' isn't available with the threadsafe feature.",
+        ));
+    }
+    let (name, value) = (string(args, 0)?, string(args, 1)?);
+    if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+        return Err(NativeError::new(format!(
+            "Invalid environment variable '{name}'."
+        )));
+    }
+    std::env::set_var(name, value);
+    Ok(Value::Nil)
+}
+
+/// Run the coroutine until its next `yield`, where the value it yields is the result, or until
+/// it returns, where the return value is. The `yield` it continues from evaluates to `value`,
+/// which the first `resume` ignores
+fn resume(vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    let coroutine = coroutine(args, 0)?;
+    vm.resume(&coroutine, args[1].clone())
+}
+
+/// Whether the function of the coroutine has returned, so it can't be resumed anymore
+fn is_done(_vm: &mut VM, args: &[Value]) -> Result<Value, NativeError> {
+    Ok(Value::Bool(
+        coroutine(args, 0)?.status() == CoroutineStatus::Done,
+    ))
+}
@@ -0,0 +1,251 @@
+//! The peephole optimizer, which rewrites a finished chunk into a shorter one doing the same
+use crate::chunk::{Chunk, OpCode};
+use crate::scanner::Span;
+use crate::value::Value;
+
+/// A decoded instruction. A jump refers to the index of the instruction it lands on instead of
+/// an offset, so the instructions can be added and removed without breaking the jumps
+#[derive(Debug)]
+struct Instruction {
+    /// The jumps always use the narrow opcodes here, the width is picked when encoding again
+    op: OpCode,
+    /// The operands of the other instructions, as they are
+    operands: Vec<u8>,
+    /// The index of the instruction a jump lands on, which is one past the end for the end of
+    /// the chunk
+    target: Option<usize>,
+    span: Span,
+    column: usize,
+}
+
+/// Rewrite the chunk with:
+///
+/// - `Equal; Not` fused into `NotEqual`, and `Constant 1; Add` into `Increment`
+/// - the jumps landing on an unconditional jump going straight to its destination
+/// - the unreachable code after `Return` and the unconditional jumps removed
+pub fn optimize(chunk: &mut Chunk) {
+    let mut instructions = decode(chunk);
+    thread_jumps(&mut instructions);
+    // Removing the unreachable jumps may make more code unreachable
+    loop {
+        let len = instructions.len();
+        instructions = rewrite(instructions, chunk);
+        if instructions.len() == len {
+            break;
+        }
+    }
+    encode(&instructions, chunk);
+}
+
+fn decode(chunk: &Chunk) -> Vec<Instruction> {
+    let mut offsets = vec![];
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let len = chunk.instruction_len(offset);
+        let operands = &chunk.code[offset + 1..offset + len];
+        let op = OpCode::try_from(chunk.code[offset]).expect("The compiler only writes opcodes");
+        let (op, target) = match op {
+            op @ (OpCode::Jump | OpCode::JumpIfFalse) => {
+                let jump = u16::from_be_bytes([operands[0], operands[1]]) as usize;
+                (op, Some(offset + len + jump))
+            }
+            OpCode::Loop => {
+                let jump = u16::from_be_bytes([operands[0], operands[1]]) as usize;
+                (OpCode::Loop, Some(offset + len - jump))
+            }
+            op @ (OpCode::JumpLong | OpCode::JumpIfFalseLong | OpCode::LoopLong) => {
+                let bytes = operands.try_into().expect("Four bytes");
+                let jump = u32::from_be_bytes(bytes) as usize;
+                match op {
+                    OpCode::JumpLong => (OpCode::Jump, Some(offset + len + jump)),
+                    OpCode::JumpIfFalseLong => (OpCode::JumpIfFalse, Some(offset + len + jump)),
+                    _ => (OpCode::Loop, Some(offset + len - jump)),
+                }
+            }
+            op => (op, None),
+        };
+        instructions.push(Instruction {
+            op,
+            operands: if target.is_some() {
+                vec![]
+            } else {
+                operands.to_vec()
+            },
+            target,
+            span: chunk.spans[offset],
+            column: chunk.columns[offset],
+        });
+        offsets.push(offset);
+        offset += len;
+    }
+    offsets.push(offset);
+
+    // Turn the target offsets into instruction indexes
+    for instruction in &mut instructions {
+        if let Some(target) = instruction.target.as_mut() {
+            *target = offsets
+                .binary_search(target)
+                .expect("A jump lands on an instruction");
+        }
+    }
+    instructions
+}
+
+/// A jump landing on an unconditional jump can go where that one goes. `Jump` only goes forward,
+/// so following them always ends
+fn thread_jumps(instructions: &mut [Instruction]) {
+    for idx in 0..instructions.len() {
+        if !matches!(instructions[idx].op, OpCode::Jump | OpCode::JumpIfFalse) {
+            continue;
+        }
+        let mut target = instructions[idx].target.expect("A jump has a target");
+        while let Some(Instruction {
+            op: OpCode::Jump,
+            target: Some(next),
+            ..
+        }) = instructions.get(target)
+        {
+            target = *next;
+        }
+        instructions[idx].target = Some(target);
+    }
+}
+
+/// Fuse the instruction pairs and drop the unreachable instructions. An instruction some jump
+/// lands on is never merged into the one before it, nor removed
+fn rewrite(instructions: Vec<Instruction>, chunk: &Chunk) -> Vec<Instruction> {
+    let mut is_target = vec![false; instructions.len() + 1];
+    for target in instructions
+        .iter()
+        .filter_map(|instruction| instruction.target)
+    {
+        is_target[target] = true;
+    }
+
+    // Where each of the old instructions is now, to fix the jumps afterwards
+    let mut new_index = vec![0; instructions.len() + 1];
+    let mut result: Vec<Instruction> = vec![];
+    let mut reachable = true;
+    let mut instructions = instructions.into_iter().enumerate().peekable();
+    while let Some((idx, mut instruction)) = instructions.next() {
+        new_index[idx] = result.len();
+        reachable |= is_target[idx];
+        if !reachable {
+            continue;
+        }
+        let next = instructions
+            .peek()
+            .filter(|(next_idx, _)| !is_target[*next_idx])
+            .map(|(next_idx, next)| (*next_idx, next.op));
+        match (instruction.op, next) {
+            (OpCode::Equal, Some((next_idx, OpCode::Not))) => {
+                instruction.op = OpCode::NotEqual;
+                instructions.next();
+                new_index[next_idx] = result.len();
+            }
+            (OpCode::Constant, Some((next_idx, OpCode::Add)))
+                if is_one(chunk, instruction.operands[0]) =>
+            {
+                // A runtime error of the addition points at the `+`
+                let (_, add) = instructions.next().expect("Peeked");
+                instruction = Instruction {
+                    op: OpCode::Increment,
+                    operands: vec![],
+                    ..add
+                };
+                new_index[next_idx] = result.len();
+            }
+            _ => {}
+        }
+        reachable = !matches!(instruction.op, OpCode::Return | OpCode::Jump | OpCode::Loop);
+        result.push(instruction);
+    }
+    new_index[is_target.len() - 1] = result.len();
+
+    for instruction in &mut result {
+        if let Some(target) = instruction.target.as_mut() {
+            *target = new_index[*target];
+        }
+    }
+    result
+}
+
+fn is_one(chunk: &Chunk, constant_idx: u8) -> bool {
+    // Not the float `1.0`, since adding it makes a float
+    matches!(chunk.constants.values[constant_idx as usize], Value::Int(1))
+}
+
+/// Write the instructions back. The jumps get two bytes offsets where they fit, and the wide
+/// instructions otherwise
+fn encode(instructions: &[Instruction], chunk: &mut Chunk) {
+    // Widening a jump moves the code after it, which may push another jump out of range, so
+    // repeat until nothing changes. The jumps only ever get wider, so this ends
+    let mut wide = vec![false; instructions.len()];
+    let offsets = loop {
+        let offsets = layout(instructions, &wide);
+        let mut changed = false;
+        for (idx, instruction) in instructions.iter().enumerate() {
+            if let Some(target) = instruction.target {
+                if !wide[idx] && distance(&offsets, idx, target) > u16::MAX as usize {
+                    wide[idx] = true;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break offsets;
+        }
+    };
+
+    chunk.code.clear();
+    chunk.lines.clear();
+    chunk.columns.clear();
+    chunk.spans.clear();
+    for (idx, instruction) in instructions.iter().enumerate() {
+        let mut bytes = vec![];
+        match (instruction.target, wide[idx]) {
+            (Some(target), false) => {
+                bytes.push(instruction.op.into());
+                let jump = distance(&offsets, idx, target) as u16;
+                bytes.extend(jump.to_be_bytes());
+            }
+            (Some(target), true) => {
+                let op = match instruction.op {
+                    OpCode::Jump => OpCode::JumpLong,
+                    OpCode::JumpIfFalse => OpCode::JumpIfFalseLong,
+                    _ => OpCode::LoopLong,
+                };
+                bytes.push(op.into());
+                let jump = distance(&offsets, idx, target) as u32;
+                bytes.extend(jump.to_be_bytes());
+            }
+            (None, _) => {
+                bytes.push(instruction.op.into());
+                bytes.extend(&instruction.operands);
+            }
+        }
+        for byte in bytes {
+            chunk.write(byte, instruction.span, instruction.column);
+        }
+    }
+}
+
+/// The offset of every instruction, and of the end of the chunk
+fn layout(instructions: &[Instruction], wide: &[bool]) -> Vec<usize> {
+    let mut offsets = vec![0];
+    for (instruction, wide) in instructions.iter().zip(wide) {
+        let len = match (instruction.target, wide) {
+            (Some(_), false) => 3,
+            (Some(_), true) => 5,
+            (None, _) => 1 + instruction.operands.len(),
+        };
+        offsets.push(offsets.last().unwrap() + len);
+    }
+    offsets
+}
+
+/// How far the jump at `idx` goes, which is counted from the end of the jump instruction
+fn distance(offsets: &[usize], idx: usize, target: usize) -> usize {
+    offsets[idx + 1].abs_diff(offsets[target])
+}
@@ -0,0 +1,45 @@
+use crate::shared::{Rc, RefCell};
+use std::io::{self, Write};
+
+/// An in-memory sink which can be handed to the VM and read back afterwards, because all the
+/// clones share the same buffer
+///
+/// ```
+/// use rustlox::output::SharedBuffer;
+/// use rustlox::VM;
+///
+/// let out = SharedBuffer::new();
+/// let mut vm = VM::new();
+/// vm.set_stdout(Box::new(out.clone()));
+/// vm.interpret("print 1 + 2;")?;
+/// assert_eq!(out.contents(), "3\n");
+/// # Ok::<(), rustlox::LoxError>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far, with invalid UTF-8 replaced
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    /// Discard everything written so far
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
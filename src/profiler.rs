@@ -0,0 +1,150 @@
+//! Counters for finding the hot loops of a Lox script and the hot opcodes of the VM, see
+//! [`crate::VM::set_profiling`]
+use crate::chunk::OpCode;
+use crate::value::Function;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// The calls of one function
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    /// The time spent in the calls which returned, not counting the functions they called, so the
+    /// recursive calls aren't counted twice
+    pub time: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// Indexed by the opcode
+    opcodes: [u64; 256],
+    functions: HashMap<String, FunctionProfile>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            opcodes: [0; 256],
+            functions: HashMap::new(),
+        }
+    }
+}
+
+impl Profile {
+    pub(crate) fn count_opcode(&mut self, op: OpCode) {
+        self.opcodes[op as usize] += 1;
+    }
+
+    pub(crate) fn count_call(&mut self, name: String) {
+        self.functions.entry(name).or_default().calls += 1;
+    }
+
+    pub(crate) fn add_time(&mut self, name: String, time: Duration) {
+        self.functions.entry(name).or_default().time += time;
+    }
+
+    /// The opcodes executed and how many times, the most frequent first
+    pub fn opcodes(&self) -> Vec<(OpCode, u64)> {
+        let mut opcodes: Vec<_> = (0..=u8::MAX)
+            .filter(|&op| self.opcodes[op as usize] > 0)
+            .filter_map(|op| Some((OpCode::try_from(op).ok()?, self.opcodes[op as usize])))
+            .collect();
+        opcodes.sort_by_key(|(_, count)| Reverse(*count));
+        opcodes
+    }
+
+    /// The functions called, the most time consuming first
+    pub fn functions(&self) -> Vec<(&str, &FunctionProfile)> {
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|(name, profile)| (name.as_str(), profile))
+            .collect();
+        functions.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(b.1.calls.cmp(&a.1.calls)));
+        functions
+    }
+
+    /// The profile as two tables, of the functions and of the opcodes
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<32} {:>12} {:>14} {:>14}",
+            "Function", "Calls", "Self (ms)", "Average (us)"
+        );
+        for (name, profile) in self.functions() {
+            let time = profile.time.as_secs_f64();
+            let average = time / profile.calls.max(1) as f64;
+            let _ = writeln!(
+                out,
+                "{:<32} {:>12} {:>14.3} {:>14.3}",
+                name,
+                profile.calls,
+                time * 1e3,
+                average * 1e6
+            );
+        }
+        let _ = writeln!(out, "\n{:<32} {:>12}", "Opcode", "Count");
+        for (op, count) in self.opcodes() {
+            let _ = writeln!(out, "{:<32} {:>12}", format!("{op:?}"), count);
+        }
+        out
+    }
+
+    /// The profile as JSON, for other tools to read
+    pub fn to_json(&self) -> String {
+        let functions: Vec<_> = self
+            .functions()
+            .iter()
+            .map(|(name, profile)| {
+                format!(
+                    r#"{{"name":{},"calls":{},"self_time_ms":{}}}"#,
+                    json_string(name),
+                    profile.calls,
+                    profile.time.as_secs_f64() * 1e3
+                )
+            })
+            .collect();
+        let opcodes: Vec<_> = self
+            .opcodes()
+            .iter()
+            .map(|(op, count)| format!(r#"{{"opcode":"{op:?}","count":{count}}}"#))
+            .collect();
+        format!(
+            r#"{{"functions":[{}],"opcodes":[{}]}}"#,
+            functions.join(","),
+            opcodes.join(",")
+        )
+    }
+}
+
+/// The string as a JSON string literal, quoted and escaped
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The name which tells the functions apart in a profile, e.g. `fib (line 1)`. Many functions may
+/// have the same name, e.g. the lambdas and the methods of different classes
+pub(crate) fn function_key(function: &Function) -> String {
+    if function.name.is_empty() {
+        return "<script>".to_string();
+    }
+    match function.chunk.lines.first() {
+        Some(line) => format!("{} (line {line})", function.name),
+        None => function.name.clone(),
+    }
+}
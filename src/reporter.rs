@@ -0,0 +1,459 @@
+use crate::profiler::json_string;
+use crate::scanner::Span;
+use crate::shared::{MaybeSend, MaybeSync, Output, Rc, RefCell};
+use crate::value::Function;
+use std::io::{self, IsTerminal, Write};
+
+/// The ANSI escape codes of the colored diagnostics
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether a diagnostic stops the code from compiling
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Severity {
+    #[default]
+    Error,
+    /// The code compiles, but probably doesn't do what it's meant to, e.g. a variable is never read
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Error => write!(f, "Error"),
+            Self::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+/// A compile error or a warning reported by the compiler, or where a runtime error occurs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The file of the source code, if it's from a file
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    /// The bytes of the offending token in the source code
+    pub span: Span,
+    /// The text of the offending token. Empty for scanner errors and errors at the end
+    pub lexeme: String,
+    /// Where the error occurs, e.g. ` at end` or ` at 'foo'`. Empty for scanner errors
+    pub location: String,
+    pub message: String,
+    /// The whole line of the source code where the error occurs
+    pub source_line: String,
+    /// The hints shown after the diagnostic, e.g. `did you mean 'counter'?`
+    pub notes: Vec<String>,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+impl Diagnostic {
+    /// A diagnostic about the instruction at `offset` in the chunk of the function, spanning the
+    /// token it's compiled from
+    pub fn at_instruction(function: &Function, offset: usize, message: &str) -> Self {
+        let (line, column) = function.chunk.location(offset);
+        Self {
+            file: function.file.as_deref().map(str::to_string),
+            line,
+            column,
+            span: function.chunk.span(offset),
+            message: message.to_string(),
+            source_line: function.source_line(offset).unwrap_or_default().to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The diagnostic as it's printed, e.g.
+    ///
+    /// ```text
+    /// [main.lox:3:7] Error at 'conter': Undefined variable 'conter'.
+    ///     print conter;
+    ///           ^^^^^^
+    ///     = note: did you mean 'counter'?
+    /// ```
+    ///
+    /// With `color`, the severity, the location and the carets are colored by ANSI escape codes
+    pub fn render(&self, color: bool) -> String {
+        let (severity_color, blue, bold, reset) = match (color, self.severity) {
+            (false, _) => ("", "", "", ""),
+            (true, Severity::Error) => (RED, BLUE, BOLD, RESET),
+            (true, Severity::Warning) => (YELLOW, BLUE, BOLD, RESET),
+        };
+        let mut text = format!(
+            "{blue}[{}]{reset} {severity_color}{}{reset}{bold}{}: {}{reset}",
+            position(self.file.as_deref(), self.line, self.column),
+            self.severity,
+            self.location,
+            self.message
+        );
+        if !self.source_line.is_empty() {
+            let snippet = snippet(&self.source_line, self.column, self.span.len);
+            text.push('\n');
+            text.push_str(&paint_carets(&snippet, severity_color, reset));
+        }
+        for note in &self.notes {
+            text.push('\n');
+            text.push_str(&render_note(note, color));
+        }
+        text
+    }
+
+    /// The diagnostic as a JSON object, see [`JsonReporter`]
+    pub fn to_json(&self) -> String {
+        self.json_object(None)
+    }
+
+    fn json_object(&self, stack_trace: Option<&[String]>) -> String {
+        let file = self.file.as_deref().map_or("null".to_string(), json_string);
+        let mut json = format!(
+            r#"{{"severity":"{}","message":{},"file":{},"line":{},"column":{},"span":{{"start":{},"end":{}}}"#,
+            self.severity.to_string().to_lowercase(),
+            json_string(&self.message),
+            file,
+            self.line,
+            self.column,
+            self.span.start,
+            self.span.end()
+        );
+        if !self.notes.is_empty() {
+            let notes: Vec<_> = self.notes.iter().map(|note| json_string(note)).collect();
+            json.push_str(&format!(r#","notes":[{}]"#, notes.join(",")));
+        }
+        if let Some(stack_trace) = stack_trace {
+            let frames: Vec<_> = stack_trace.iter().map(|frame| json_string(frame)).collect();
+            json.push_str(&format!(r#","stack_trace":[{}]"#, frames.join(",")));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Where a diagnostic or a stack frame is, e.g. `path/to/file.lox:12:5`, or `line 12:5` for the
+/// code which isn't from a file, e.g. typed in the REPL
+pub(crate) fn position(file: Option<&str>, line: usize, column: usize) -> String {
+    match file {
+        Some(file) => format!("{file}:{line}:{column}"),
+        None => format!("line {line}:{column}"),
+    }
+}
+
+/// A note under a diagnostic, e.g. `    = note: did you mean 'counter'?`
+fn render_note(note: &str, color: bool) -> String {
+    if color {
+        format!("    {CYAN}= note{RESET}: {note}")
+    } else {
+        format!("    = note: {note}")
+    }
+}
+
+/// Color the lines of carets under the source code, which are the lines of only spaces, tabs and
+/// carets
+fn paint_carets(text: &str, color: &str, reset: &str) -> String {
+    let lines: Vec<_> = text
+        .lines()
+        .map(|line| {
+            let carets = line.trim_start();
+            if !color.is_empty() && carets.starts_with('^') && carets.chars().all(|c| c == '^') {
+                let indent = &line[..line.len() - carets.len()];
+                format!("{indent}{color}{carets}{reset}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// A runtime error as it's printed: the message, the stack trace and the notes. With `color`,
+/// the message, the locations of the frames and the carets are colored
+pub fn render_runtime_error(
+    diagnostic: &Diagnostic,
+    stack_trace: &[String],
+    color: bool,
+) -> String {
+    let (red, blue, reset) = if color {
+        (RED, BLUE, RESET)
+    } else {
+        ("", "", "")
+    };
+    let mut text = format!("{red}{}{reset}", diagnostic.message);
+    for entry in stack_trace {
+        text.push('\n');
+        // The location of the frame is in brackets at the start, e.g. `[line 3:5] in f()`
+        match entry.split_once(']') {
+            Some((position, rest)) if color && position.starts_with('[') => {
+                text.push_str(&format!("{blue}{position}]{reset}"));
+                text.push_str(&paint_carets(rest, red, reset));
+            }
+            _ => text.push_str(entry),
+        }
+    }
+    for note in &diagnostic.notes {
+        text.push('\n');
+        text.push_str(&render_note(note, color));
+    }
+    text
+}
+
+/// Whether to color the diagnostics printed to stderr: only for a terminal, and not when the
+/// `NO_COLOR` environment variable is set or `TERM` is `dumb`
+pub fn stderr_supports_color() -> bool {
+    io::stderr().is_terminal()
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+}
+
+/// The hint for a misspelled name, e.g. `did you mean 'counter'?` for `conter`, naming the
+/// closest of the `names` if it's only a few edits away
+pub(crate) fn did_you_mean<'a>(
+    name: &str,
+    names: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    // A third of the name may be wrong, so short names only get a single edit
+    let max_distance = (name.chars().count() / 3).max(1);
+    names
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        // The names come from hash maps, so break the ties by the name to be deterministic
+        .min()
+        .map(|(_, candidate)| format!("did you mean '{candidate}'?"))
+}
+
+/// The edits turning one string into the other: inserting, deleting or substituting a character,
+/// or swapping two adjacent ones, which is the most common typo
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<_>, Vec<_>) = (a.chars().collect(), b.chars().collect());
+    // `distances[i][j]` is the distance between the first `i` characters of `a` and the first `j`
+    // of `b`
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = substitution
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+/// Show a line of the source code with carets under the `len` bytes from the column, or one
+/// caret when it's empty, e.g.
+///
+/// ```text
+///     print a + "b";
+///               ^
+/// ```
+pub fn snippet(source_line: &str, column: usize, len: usize) -> String {
+    // Keep the tabs so the carets line up with the code
+    let padding: String = source_line
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    // A token may go on past the line, e.g. a string with a newline in it
+    let mut bytes = 0;
+    let width = source_line
+        .chars()
+        .skip(column.saturating_sub(1))
+        .take_while(|c| {
+            bytes += c.len_utf8();
+            bytes <= len
+        })
+        .count();
+    format!(
+        "    {source_line}\n    {padding}{}",
+        "^".repeat(width.max(1))
+    )
+}
+
+/// The single place where the compiler and the VM send their errors to, so embedders can decide
+/// what to do with them instead of scraping stderr
+pub trait Reporter: MaybeSend {
+    /// Called once for every compile error and warning
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic);
+
+    /// Called when a runtime error aborts the script. The stack trace starts from the innermost
+    /// call frame, e.g. `[foo.lox:3:5] in foo(1)`, or `[line 3:5] in foo(1)` without a file
+    fn report_runtime_error(&mut self, message: &str, stack_trace: &[String]);
+
+    /// Same as [`Reporter::report_runtime_error`], which it calls by default, with where the error
+    /// occurs in the innermost call frame for the reporters which need it structured
+    fn report_runtime_diagnostic(&mut self, diagnostic: &Diagnostic, stack_trace: &[String]) {
+        self.report_runtime_error(&diagnostic.message, stack_trace);
+    }
+}
+
+/// The default reporter, which prints everything to stderr like clox does. The errors are colored
+/// when stderr is a terminal, see [`stderr_supports_color`]
+#[derive(Debug, Default)]
+pub struct StderrReporter;
+
+impl Reporter for StderrReporter {
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        eprintln!("{}", diagnostic.render(stderr_supports_color()));
+    }
+
+    fn report_runtime_error(&mut self, message: &str, stack_trace: &[String]) {
+        let diagnostic = Diagnostic {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        self.report_runtime_diagnostic(&diagnostic, stack_trace);
+    }
+
+    fn report_runtime_diagnostic(&mut self, diagnostic: &Diagnostic, stack_trace: &[String]) {
+        let color = stderr_supports_color();
+        eprintln!("{}", render_runtime_error(diagnostic, stack_trace, color));
+    }
+}
+
+/// Print the errors to any writer, in the same format as [`StderrReporter`] but without colors
+/// unless [`WriterReporter::with_color`] says so. Errors while writing are ignored, as there is
+/// nowhere else to report them
+pub struct WriterReporter {
+    out: Box<Output>,
+    color: bool,
+}
+
+impl WriterReporter {
+    pub fn new(out: Box<Output>) -> Self {
+        Self { out, color: false }
+    }
+
+    /// Color the errors by ANSI escape codes, e.g. for a terminal
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+}
+
+impl Reporter for WriterReporter {
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        let _ = writeln!(self.out, "{}", diagnostic.render(self.color));
+    }
+
+    fn report_runtime_error(&mut self, message: &str, stack_trace: &[String]) {
+        let diagnostic = Diagnostic {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        self.report_runtime_diagnostic(&diagnostic, stack_trace);
+    }
+
+    fn report_runtime_diagnostic(&mut self, diagnostic: &Diagnostic, stack_trace: &[String]) {
+        let text = render_runtime_error(diagnostic, stack_trace, self.color);
+        let _ = writeln!(self.out, "{text}");
+    }
+}
+
+/// Print every error and warning as a JSON object on its own line, for the editors and the other
+/// tools, e.g.
+///
+/// ```text
+/// {"severity":"error","message":"Expect expression.","file":"a.lox","line":1,"column":7,"span":{"start":6,"end":7}}
+/// ```
+///
+/// A runtime error also has the `"stack_trace"`, an array of the frames as
+/// [`Reporter::report_runtime_error`] gets them, and a diagnostic with hints has the `"notes"`
+pub struct JsonReporter {
+    out: Box<Output>,
+}
+
+impl JsonReporter {
+    pub fn new(out: Box<Output>) -> Self {
+        Self { out }
+    }
+}
+
+impl Reporter for JsonReporter {
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        let _ = writeln!(self.out, "{}", diagnostic.to_json());
+    }
+
+    fn report_runtime_error(&mut self, message: &str, stack_trace: &[String]) {
+        let diagnostic = Diagnostic {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        self.report_runtime_diagnostic(&diagnostic, stack_trace);
+    }
+
+    fn report_runtime_diagnostic(&mut self, diagnostic: &Diagnostic, stack_trace: &[String]) {
+        let _ = writeln!(self.out, "{}", diagnostic.json_object(Some(stack_trace)));
+    }
+}
+
+/// A runtime error together with its stack trace
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeErrorReport {
+    pub message: String,
+    pub stack_trace: Vec<String>,
+    /// The hints about the error, see [`Diagnostic::notes`]
+    pub notes: Vec<String>,
+}
+
+/// Keep all the errors in memory. Wrap it in the `Rc<RefCell<..>>` of [`crate::shared`] to
+/// inspect the errors after handing it to a VM
+#[derive(Debug, Default)]
+pub struct CollectingReporter {
+    pub diagnostics: Vec<Diagnostic>,
+    pub runtime_errors: Vec<RuntimeErrorReport>,
+}
+
+impl Reporter for CollectingReporter {
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.diagnostics.push(diagnostic.clone());
+    }
+
+    fn report_runtime_error(&mut self, message: &str, stack_trace: &[String]) {
+        self.runtime_errors.push(RuntimeErrorReport {
+            message: message.to_string(),
+            stack_trace: stack_trace.to_vec(),
+            notes: vec![],
+        });
+    }
+
+    fn report_runtime_diagnostic(&mut self, diagnostic: &Diagnostic, stack_trace: &[String]) {
+        self.runtime_errors.push(RuntimeErrorReport {
+            message: diagnostic.message.clone(),
+            stack_trace: stack_trace.to_vec(),
+            notes: diagnostic.notes.clone(),
+        });
+    }
+}
+
+impl<R: Reporter + MaybeSync> Reporter for Rc<RefCell<R>> {
+    fn report_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.borrow_mut().report_diagnostic(diagnostic);
+    }
+
+    fn report_runtime_error(&mut self, message: &str, stack_trace: &[String]) {
+        self.borrow_mut().report_runtime_error(message, stack_trace);
+    }
+
+    fn report_runtime_diagnostic(&mut self, diagnostic: &Diagnostic, stack_trace: &[String]) {
+        self.borrow_mut()
+            .report_runtime_diagnostic(diagnostic, stack_trace);
+    }
+}
@@ -1,18 +1,41 @@
-#[derive(Hash, Eq, Clone, Debug, PartialEq, Default)]
+use crate::shared::Rc;
+
+#[derive(Hash, Eq, Clone, Copy, Debug, PartialEq, Default)]
 pub enum TokenType {
     // Single-character tokens
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    /// `:`, between the bounds of a slice
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
     Slash,
-    Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
     // One or two character tokens
+    Star,
+    StarStar,
+    /// `...`, the rest parameter and the spread of a list
+    DotDotDot,
+    /// `..` and `..=`, the ranges
+    DotDot,
+    DotDotEqual,
+    /// `??` and `?.`, which deal with nil
+    QuestionQuestion,
+    QuestionDot,
+    /// `++` and `--`, which add or subtract one and store the result back
+    PlusPlus,
+    MinusMinus,
     Bang,
     BangEqual,
     Equal,
@@ -21,91 +44,192 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
     Identifier,
+    #[allow(clippy::upper_case_acronyms)]
     STRING,
     Number,
     // keywords
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
     Return,
+    Static,
     Super,
     This,
     True,
     Var,
     While,
+    Yield,
+    /// A `//` or `/* */` comment, only scanned with [`Scanner::with_comments`]
+    Comment,
     #[default]
     Eof,
     Error,
 }
 
-#[derive(Debug, Default)]
+/// Where a token is in the source code
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// The offset of the first byte
+    pub start: usize,
+    /// The length in bytes
+    pub len: usize,
+    /// The line where the token starts
+    pub line: usize,
+}
+
+impl Span {
+    pub fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+/// A token refers to its text in the source code instead of owning a copy of it, see
+/// [`Token::lexeme`]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
-    pub line: usize,
+    pub span: Span,
+    /// The column where the token starts, counted in characters from 1
+    pub column: usize,
+    /// The text of the tokens which aren't in the source code: the message of an error token, or
+    /// the name of the implicit `this` and `super` variables
+    pub text: Option<&'static str>,
 }
 
-#[derive(Debug)]
+impl Token {
+    /// A token which doesn't appear in the source code, e.g. `this` and `super`
+    pub fn synthetic(text: &'static str) -> Self {
+        Self {
+            token_type: TokenType::Identifier,
+            text: Some(text),
+            ..Default::default()
+        }
+    }
+
+    /// The text of the token, `source` is the code it was scanned from
+    pub fn lexeme<'a>(&self, source: &'a str) -> &'a str {
+        match self.text {
+            Some(text) => text,
+            None => &source[self.span.start..self.span.end()],
+        }
+    }
+}
+
+/// The scanner works on the bytes of the source code. Everything but the strings and the comments
+/// is ASCII, so a lexeme never starts or ends in the middle of a character
+#[derive(Clone, Debug)]
 pub struct Scanner {
-    source: Vec<char>,
+    source: Rc<str>,
     /// Marks the beginning of the current lexeme being scanned
     start: usize,
-    /// Points to the current character being lookat at
+    /// Points to the current byte being lookat at
     current: usize,
     line: usize,
+    /// Where the current line begins, to compute the columns
+    line_start: usize,
+    /// The position of the current lexeme, which may span lines, e.g. a multi-line string
+    start_line: usize,
+    start_column: usize,
+    /// Return the comments as tokens instead of skipping them
+    comments: bool,
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scanner {
     pub fn new() -> Self {
         Self {
-            source: vec![],
+            source: Rc::from(""),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            comments: false,
         }
     }
-    pub fn init_scanner(&mut self, source: &str) {
-        self.source = source.chars().collect();
+
+    /// Scan the comments as [`TokenType::Comment`] tokens, e.g. for the formatter to keep them
+    pub fn with_comments(mut self) -> Self {
+        self.comments = true;
+        self
+    }
+
+    /// Start scanning `source`, which may be shared with the compiler to avoid copying it
+    pub fn init_scanner(&mut self, source: impl Into<Rc<str>>) {
+        self.source = source.into();
     }
 
     fn make_token(&self, token_type: TokenType) -> Token {
         Token {
-            lexeme: self.source[self.start..self.current].iter().collect(),
-            line: self.line,
             token_type,
+            span: self.span(),
+            column: self.start_column,
+            text: None,
         }
     }
 
-    fn error_token(&self, msg: &str) -> Token {
+    fn error_token(&self, msg: &'static str) -> Token {
         Token {
             token_type: TokenType::Error,
-            lexeme: msg.to_string(),
-            line: self.line,
+            span: self.span(),
+            column: self.start_column,
+            text: Some(msg),
         }
     }
 
+    fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            len: self.current - self.start,
+            line: self.start_line,
+        }
+    }
+
+    /// The column of the current byte, in characters
+    fn column(&self) -> usize {
+        self.source[self.line_start..self.current].chars().count() + 1
+    }
+
+    /// Called after consuming a `\n`
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
     fn is_at_end(&self) -> bool {
         self.current == self.source.len()
     }
 
-    fn advance(&mut self) -> char {
+    fn advance(&mut self) -> u8 {
         self.current += 1;
-        self.source[self.current - 1]
+        self.source.as_bytes()[self.current - 1]
     }
 
-    fn my_match(&mut self, expected: char) -> bool {
+    fn my_match(&mut self, expected: u8) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source[self.current] != expected {
+        if self.source.as_bytes()[self.current] != expected {
             return false;
         }
         self.current += 1;
@@ -113,53 +237,87 @@ impl Scanner {
         true
     }
 
-    fn peek(&self) -> char {
-        // todo: or change self.peek() to return Option<char>
+    fn peek(&self) -> u8 {
+        // todo: or change self.peek() to return Option<u8>
         if self.is_at_end() {
-            return '\0';
+            return b'\0';
         }
-        self.source[self.current]
+        self.source.as_bytes()[self.current]
     }
 
-    fn peek_next(&self) -> Option<char> {
-        // todo: is this correct?
-        if self.current + 1 >= self.source.len() {
-            None
-        } else {
-            Some(self.source[self.current + 1])
-        }
+    fn peek_next(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.current + 1).copied()
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skip whitespace and comments. Return an error token if a block comment is never closed, or
+    /// the comment if they are kept
+    fn skip_whitespace(&mut self) -> Option<Token> {
         loop {
             match self.peek() {
-                '\n' => {
-                    self.line += 1;
+                b'\n' => {
                     self.advance();
+                    self.new_line();
                 }
-                '/' => {
-                    if let Some('/') = self.peek_next() {
+                b'/' => match self.peek_next() {
+                    Some(b'/') => {
+                        self.start = self.current;
+                        self.start_line = self.line;
+                        self.start_column = self.column();
                         // A comment goes until the end of the line
-                        while self.peek() != '\n' && !self.is_at_end() {
+                        while self.peek() != b'\n' && !self.is_at_end() {
                             self.advance();
                         }
+                        if self.comments {
+                            return Some(self.make_token(TokenType::Comment));
+                        }
                     }
-                    return;
-                }
-                ' ' | '\r' | '\t' => {
+                    Some(b'*') => {
+                        if let Some(error) = self.block_comment() {
+                            return Some(error);
+                        }
+                        if self.comments {
+                            return Some(self.make_token(TokenType::Comment));
+                        }
+                    }
+                    // A single `/` is the division operator
+                    _ => return None,
+                },
+                b' ' | b'\r' | b'\t' => {
                     self.advance();
                 }
-                _ => return,
+                _ => return None,
             }
         }
     }
 
+    /// Skip a `/* ... */` comment, which may contain other block comments
+    fn block_comment(&mut self) -> Option<Token> {
+        // Report the error where the comment starts
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column();
+        self.advance();
+        self.advance();
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                return Some(self.error_token("Unterminated block comment."));
+            }
+            match self.advance() {
+                b'/' if self.my_match(b'*') => depth += 1,
+                b'*' if self.my_match(b'/') => depth -= 1,
+                b'\n' => self.new_line(),
+                _ => {}
+            }
+        }
+        None
+    }
+
     fn make_string(&mut self) -> Token {
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+        while self.peek() != b'"' && !self.is_at_end() {
+            if self.advance() == b'\n' {
+                self.new_line();
             }
-            self.advance();
         }
         if self.is_at_end() {
             return self.error_token("Unterminated string.");
@@ -175,7 +333,7 @@ impl Scanner {
             self.advance();
         }
 
-        if let ('.', Some(ch2)) = (self.peek(), self.peek_next()) {
+        if let (b'.', Some(ch2)) = (self.peek(), self.peek_next()) {
             if ch2.is_ascii_digit() {
                 // Consume the "."
                 self.advance();
@@ -195,10 +353,7 @@ impl Scanner {
         token_type: TokenType,
     ) -> TokenType {
         if self.current - self.start == start + length
-            && self.source[self.start + start..self.current]
-                .iter()
-                .collect::<String>()
-                == rest
+            && &self.source.as_bytes()[self.start + start..self.current] == rest.as_bytes()
         {
             token_type
         } else {
@@ -208,35 +363,50 @@ impl Scanner {
 
     /// By using the Trie data structure to decide if an identifier is a keyword
     fn identifier_type(&self) -> TokenType {
-        match self.source[self.start] {
-            'a' => self.check_keyword(1, 2, "nd", TokenType::And),
-            'c' => self.check_keyword(1, 4, "lass", TokenType::Class),
-            'e' => self.check_keyword(1, 3, "lse", TokenType::Else),
-            'i' => self.check_keyword(1, 1, "f", TokenType::If),
-            'f' if self.current - self.start > 1 => match self.source[self.start + 1] {
-                'a' => self.check_keyword(2, 3, "lse", TokenType::False),
-                'o' => self.check_keyword(2, 1, "r", TokenType::For),
-                'u' => self.check_keyword(2, 1, "n", TokenType::Fun),
+        match self.source.as_bytes()[self.start] {
+            b'a' => self.check_keyword(1, 2, "nd", TokenType::And),
+            b'b' => self.check_keyword(1, 4, "reak", TokenType::Break),
+            b'c' if self.current - self.start > 1 => match self.source.as_bytes()[self.start + 1] {
+                b'l' => self.check_keyword(2, 3, "ass", TokenType::Class),
+                b'o' => self.check_keyword(2, 6, "ntinue", TokenType::Continue),
                 _ => TokenType::Identifier,
             },
-            'n' => self.check_keyword(1, 2, "il", TokenType::Nil),
-            'o' => self.check_keyword(1, 1, "r", TokenType::Or),
-            'p' => self.check_keyword(1, 4, "rint", TokenType::Print),
-            'r' => self.check_keyword(1, 5, "eturn", TokenType::Return),
-            's' => self.check_keyword(1, 4, "uper", TokenType::Super),
-            't' if self.current - self.start > 1 => match self.source[self.start + 1] {
-                'h' => self.check_keyword(2, 2, "is", TokenType::This),
-                'r' => self.check_keyword(2, 2, "ue", TokenType::True),
+            b'e' => self.check_keyword(1, 3, "lse", TokenType::Else),
+            b'i' if self.current - self.start > 1 => match self.source.as_bytes()[self.start + 1] {
+                b'f' => self.check_keyword(2, 0, "", TokenType::If),
+                b'm' => self.check_keyword(2, 4, "port", TokenType::Import),
+                b'n' => self.check_keyword(2, 0, "", TokenType::In),
                 _ => TokenType::Identifier,
             },
-            'v' => self.check_keyword(1, 2, "ar", TokenType::Var),
-            'w' => self.check_keyword(1, 4, "hile", TokenType::While),
+            b'f' if self.current - self.start > 1 => match self.source.as_bytes()[self.start + 1] {
+                b'a' => self.check_keyword(2, 3, "lse", TokenType::False),
+                b'o' => self.check_keyword(2, 1, "r", TokenType::For),
+                b'u' => self.check_keyword(2, 1, "n", TokenType::Fun),
+                _ => TokenType::Identifier,
+            },
+            b'n' => self.check_keyword(1, 2, "il", TokenType::Nil),
+            b'o' => self.check_keyword(1, 1, "r", TokenType::Or),
+            b'p' => self.check_keyword(1, 4, "rint", TokenType::Print),
+            b'r' => self.check_keyword(1, 5, "eturn", TokenType::Return),
+            b's' if self.current - self.start > 1 => match self.source.as_bytes()[self.start + 1] {
+                b't' => self.check_keyword(2, 4, "atic", TokenType::Static),
+                b'u' => self.check_keyword(2, 3, "per", TokenType::Super),
+                _ => TokenType::Identifier,
+            },
+            b't' if self.current - self.start > 1 => match self.source.as_bytes()[self.start + 1] {
+                b'h' => self.check_keyword(2, 2, "is", TokenType::This),
+                b'r' => self.check_keyword(2, 2, "ue", TokenType::True),
+                _ => TokenType::Identifier,
+            },
+            b'v' => self.check_keyword(1, 2, "ar", TokenType::Var),
+            b'w' => self.check_keyword(1, 4, "hile", TokenType::While),
+            b'y' => self.check_keyword(1, 4, "ield", TokenType::Yield),
             _ => TokenType::Identifier,
         }
     }
 
     fn make_identifier(&mut self) -> Token {
-        while self.peek() == '_'
+        while self.peek() == b'_'
             || self.peek().is_ascii_alphabetic()
             || self.peek().is_ascii_digit()
         {
@@ -247,37 +417,181 @@ impl Scanner {
 
     /// Returns the next token in the source code
     pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+        if let Some(error) = self.skip_whitespace() {
+            return error;
+        }
         self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column();
 
         if self.is_at_end() {
             return self.make_token(TokenType::Eof);
         }
 
         match self.advance() {
-            '(' => self.make_token(TokenType::LeftParen),
-            ')' => self.make_token(TokenType::RightParen),
-            '{' => self.make_token(TokenType::LeftBrace),
-            '}' => self.make_token(TokenType::RightBrace),
-            ';' => self.make_token(TokenType::Semicolon),
-            ',' => self.make_token(TokenType::Comma),
-            '.' => self.make_token(TokenType::Dot),
-            '-' => self.make_token(TokenType::Minus),
-            '+' => self.make_token(TokenType::Plus),
-            '/' => self.make_token(TokenType::Slash),
-            '*' => self.make_token(TokenType::Star),
-            '!' if self.my_match('=') => self.make_token(TokenType::BangEqual),
-            '!' => self.make_token(TokenType::Bang),
-            '=' if self.my_match('=') => self.make_token(TokenType::EqualEqual),
-            '=' => self.make_token(TokenType::Equal),
-            '<' if self.my_match('=') => self.make_token(TokenType::LessEqual),
-            '<' => self.make_token(TokenType::Less),
-            '>' if self.my_match('=') => self.make_token(TokenType::GreaterEqual),
-            '>' => self.make_token(TokenType::Greater),
+            b'(' => self.make_token(TokenType::LeftParen),
+            b')' => self.make_token(TokenType::RightParen),
+            b'{' => self.make_token(TokenType::LeftBrace),
+            b'}' => self.make_token(TokenType::RightBrace),
+            b'[' => self.make_token(TokenType::LeftBracket),
+            b']' => self.make_token(TokenType::RightBracket),
+            b';' => self.make_token(TokenType::Semicolon),
+            b',' => self.make_token(TokenType::Comma),
+            b':' => self.make_token(TokenType::Colon),
+            b'.' if self.peek() == b'.' && self.peek_next() == Some(b'.') => {
+                self.advance();
+                self.advance();
+                self.make_token(TokenType::DotDotDot)
+            }
+            b'.' if self.my_match(b'.') => {
+                if self.my_match(b'=') {
+                    self.make_token(TokenType::DotDotEqual)
+                } else {
+                    self.make_token(TokenType::DotDot)
+                }
+            }
+            b'.' => self.make_token(TokenType::Dot),
+            b'-' if self.my_match(b'-') => self.make_token(TokenType::MinusMinus),
+            b'-' => self.make_token(TokenType::Minus),
+            b'+' if self.my_match(b'+') => self.make_token(TokenType::PlusPlus),
+            b'+' => self.make_token(TokenType::Plus),
+            b'/' => self.make_token(TokenType::Slash),
+            b'%' => self.make_token(TokenType::Percent),
+            b'&' => self.make_token(TokenType::Ampersand),
+            b'|' => self.make_token(TokenType::Pipe),
+            b'^' => self.make_token(TokenType::Caret),
+            b'~' => self.make_token(TokenType::Tilde),
+            b'?' if self.my_match(b'?') => self.make_token(TokenType::QuestionQuestion),
+            b'?' if self.my_match(b'.') => self.make_token(TokenType::QuestionDot),
+            b'*' if self.my_match(b'*') => self.make_token(TokenType::StarStar),
+            b'*' => self.make_token(TokenType::Star),
+            b'!' if self.my_match(b'=') => self.make_token(TokenType::BangEqual),
+            b'!' => self.make_token(TokenType::Bang),
+            b'=' if self.my_match(b'=') => self.make_token(TokenType::EqualEqual),
+            b'=' => self.make_token(TokenType::Equal),
+            b'<' if self.my_match(b'=') => self.make_token(TokenType::LessEqual),
+            b'<' if self.my_match(b'<') => self.make_token(TokenType::LessLess),
+            b'<' => self.make_token(TokenType::Less),
+            b'>' if self.my_match(b'=') => self.make_token(TokenType::GreaterEqual),
+            b'>' if self.my_match(b'>') => self.make_token(TokenType::GreaterGreater),
+            b'>' => self.make_token(TokenType::Greater),
             ch if ch.is_ascii_digit() => self.make_number(),
-            ch if ch.is_ascii_alphabetic() || ch == '_' => self.make_identifier(),
-            '"' => self.make_string(),
-            _ => self.error_token("Unexpcted character."),
+            ch if ch.is_ascii_alphabetic() || ch == b'_' => self.make_identifier(),
+            b'"' => self.make_string(),
+            _ => {
+                // Skip the rest of a multi-byte character, to stay on the character boundaries
+                while self.peek() & 0b1100_0000 == 0b1000_0000 {
+                    self.advance();
+                }
+                self.error_token("Unexpcted character.")
+            }
+        }
+    }
+}
+
+/// What a piece of the source code is, for highlighting it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Including `true`, `false`, `nil`, `this` and `super`
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Operator,
+    /// The brackets, `,`, `;`, `.` and `:`
+    Punctuation,
+    Comment,
+    Whitespace,
+    /// Something which doesn't scan, e.g. an unterminated string
+    Error,
+}
+
+impl From<TokenType> for TokenKind {
+    fn from(token_type: TokenType) -> Self {
+        match token_type {
+            TokenType::And
+            | TokenType::Break
+            | TokenType::Class
+            | TokenType::Continue
+            | TokenType::Else
+            | TokenType::False
+            | TokenType::Fun
+            | TokenType::For
+            | TokenType::If
+            | TokenType::Import
+            | TokenType::In
+            | TokenType::Nil
+            | TokenType::Or
+            | TokenType::Print
+            | TokenType::Return
+            | TokenType::Static
+            | TokenType::Super
+            | TokenType::This
+            | TokenType::True
+            | TokenType::Var
+            | TokenType::While
+            | TokenType::Yield => Self::Keyword,
+            TokenType::Identifier => Self::Identifier,
+            TokenType::Number => Self::Number,
+            TokenType::STRING => Self::String,
+            TokenType::LeftParen
+            | TokenType::RightParen
+            | TokenType::LeftBrace
+            | TokenType::RightBrace
+            | TokenType::LeftBracket
+            | TokenType::RightBracket
+            | TokenType::Comma
+            | TokenType::Colon
+            | TokenType::Dot
+            | TokenType::Semicolon => Self::Punctuation,
+            TokenType::Comment => Self::Comment,
+            TokenType::Error | TokenType::Eof => Self::Error,
+            _ => Self::Operator,
+        }
+    }
+}
+
+/// Split the whole source code into the pieces to highlight, e.g. for an editor. Unlike the
+/// compiler, it keeps the comments and the whitespace between the tokens, so the spans cover all
+/// of the source code, and it goes on after the errors
+///
+/// ```
+/// use rustlox::scanner::{tokenize, TokenKind};
+///
+/// let kinds: Vec<_> = tokenize("print x; // hi").into_iter().map(|(kind, _)| kind).collect();
+/// assert_eq!(
+///     kinds,
+///     [
+///         TokenKind::Keyword,
+///         TokenKind::Whitespace,
+///         TokenKind::Identifier,
+///         TokenKind::Punctuation,
+///         TokenKind::Whitespace,
+///         TokenKind::Comment,
+///     ]
+/// );
+/// ```
+pub fn tokenize(source: &str) -> Vec<(TokenKind, Span)> {
+    let mut scanner = Scanner::new().with_comments();
+    scanner.init_scanner(source);
+    let (mut pieces, mut end, mut line) = (vec![], 0, 1);
+    loop {
+        let token = scanner.scan_token();
+        if token.span.start > end {
+            pieces.push((
+                TokenKind::Whitespace,
+                Span {
+                    start: end,
+                    len: token.span.start - end,
+                    line,
+                },
+            ));
+        }
+        if token.token_type == TokenType::Eof {
+            return pieces;
         }
+        pieces.push((token.token_type.into(), token.span));
+        end = token.span.end();
+        line = token.span.line + source[token.span.start..end].matches('\n').count();
     }
 }
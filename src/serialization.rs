@@ -0,0 +1,130 @@
+//! Convert the values to and from any format serde supports, e.g. JSON, with the `serde` feature
+//!
+//! `nil`, the booleans, the integers, the floats, the strings and the lists map to the same things
+//! in the data model. An instance is a map of its fields, and a map becomes an instance of a class
+//! named `Object`. The functions and the classes can't be serialized
+//!
+//! ```
+//! use rustlox::{Value, VM};
+//!
+//! let mut vm = VM::new();
+//! vm.interpret("class Point {} var p = Point(); p.x = 1; p.y = 0.5;")?;
+//! let json = serde_json::to_string(&vm.get_global("p").unwrap())?;
+//! assert_eq!(json, r#"{"x":1,"y":0.5}"#);
+//!
+//! let config: Value = serde_json::from_str(r#"{"name": "lox", "tags": ["a", "b"]}"#)?;
+//! vm.set_global("config", config);
+//! vm.interpret(r#"var name = config.name; var tag = config.tags[1];"#)?;
+//! assert_eq!(vm.get_global("name"), Some("lox".into()));
+//! assert_eq!(vm.get_global("tag"), Some("b".into()));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::shared::{Rc, RefCell};
+use crate::value::{Class, Instance, Value};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::fmt;
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Nil => serializer.serialize_unit(),
+            Self::Bool(b) => serializer.serialize_bool(*b),
+            Self::Int(n) => serializer.serialize_i64(*n),
+            Self::Number(n) => serializer.serialize_f64(*n),
+            Self::String(s) => serializer.serialize_str(s),
+            Self::List(list) => {
+                let list = list.borrow();
+                let mut seq = serializer.serialize_seq(Some(list.len()))?;
+                for element in list.iter() {
+                    seq.serialize_element(element)?;
+                }
+                seq.end()
+            }
+            Self::Instance(instance) => {
+                let instance = instance.borrow();
+                // Sorted, so the same instance always serializes the same
+                let mut fields: Vec<_> = instance.fields.iter().collect();
+                fields.sort_by_key(|(name, _)| name.as_str());
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (name, value) in fields {
+                    map.serialize_entry(name, value)?;
+                }
+                map.end()
+            }
+            value => Err(ser::Error::custom(format!(
+                "Can't serialize a {}.",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nil, a boolean, a number, a string, a list or a map")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::Int(n))
+    }
+
+    /// A float when it's too large for an integer
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+        Ok(i64::try_from(n).map_or(Value::Number(n as f64), Value::Int))
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<Value, E> {
+        Ok(Value::Number(n))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        Ok(s.into())
+    }
+
+    fn visit_string<E: de::Error>(self, s: String) -> Result<Value, E> {
+        Ok(s.into())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut elements = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Value::list(elements))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut instance = Instance::new(Rc::new(Class::new("Object".to_string())));
+        while let Some((name, value)) = map.next_entry::<String, Value>()? {
+            instance.fields.insert(name, value);
+        }
+        Ok(Value::Instance(Rc::new(RefCell::new(instance))))
+    }
+}
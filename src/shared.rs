@@ -0,0 +1,157 @@
+//! The shared ownership and the interior mutability of the objects, and the trait objects the VM
+//! keeps. They are the ones of `std::rc` and `std::cell` by default. With the `threadsafe`
+//! feature they are thread-safe instead, so a [`crate::VM`] is `Send` and can be moved to a worker
+//! thread, at the cost of atomic reference counts and locks
+//!
+//! ```
+//! # #[cfg(feature = "threadsafe")]
+//! # {
+//! use rustlox::VM;
+//!
+//! let mut vm = VM::new();
+//! vm.interpret("var answer = 42;")?;
+//! let worker = std::thread::spawn(move || vm.get_global("answer"));
+//! assert!(worker.join().unwrap().is_some());
+//! # }
+//! # Ok::<(), rustlox::LoxError>(())
+//! ```
+use std::io::{BufRead, Write};
+
+#[cfg(not(feature = "threadsafe"))]
+pub use std::cell::{Cell, RefCell};
+#[cfg(not(feature = "threadsafe"))]
+pub use std::rc::{Rc, Weak};
+
+#[cfg(feature = "threadsafe")]
+pub use std::sync::{Arc as Rc, Weak};
+#[cfg(feature = "threadsafe")]
+pub use sync::{Cell, RefCell};
+
+/// `Send` with the `threadsafe` feature, nothing without it, for the traits whose trait objects
+/// the VM keeps, e.g. [`crate::Reporter`]
+#[cfg(not(feature = "threadsafe"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "threadsafe"))]
+impl<T: ?Sized> MaybeSend for T {}
+#[cfg(feature = "threadsafe")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "threadsafe")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// `Send` and `Sync` with the `threadsafe` feature, nothing without it, for what the values share,
+/// e.g. the closures of the native functions
+#[cfg(not(feature = "threadsafe"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "threadsafe"))]
+impl<T: ?Sized> MaybeSync for T {}
+#[cfg(feature = "threadsafe")]
+pub trait MaybeSync: Send + Sync {}
+#[cfg(feature = "threadsafe")]
+impl<T: Send + Sync + ?Sized> MaybeSync for T {}
+
+/// Where the VM writes, e.g. the output of `print`
+#[cfg(not(feature = "threadsafe"))]
+pub type Output = dyn Write;
+#[cfg(feature = "threadsafe")]
+pub type Output = dyn Write + Send;
+
+/// Where the VM reads from, e.g. the input of `readLine`
+#[cfg(not(feature = "threadsafe"))]
+pub type Input = dyn BufRead;
+#[cfg(feature = "threadsafe")]
+pub type Input = dyn BufRead + Send;
+
+/// The data of the host wrapped in a [`crate::value::UserData`]
+#[cfg(not(feature = "threadsafe"))]
+pub type AnyData = dyn std::any::Any;
+#[cfg(feature = "threadsafe")]
+pub type AnyData = dyn std::any::Any + Send + Sync;
+
+/// The `RefCell` and the `Cell` of the `threadsafe` feature, on top of the locks of `std::sync`
+#[cfg(feature = "threadsafe")]
+mod sync {
+    use std::fmt;
+    use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    /// A `RefCell` which is `Sync`. A value may be shared by the VMs of several threads, so
+    /// borrowing it while another thread has it mutably borrowed waits for that thread instead of
+    /// panicking
+    #[derive(Default)]
+    pub struct RefCell<T: ?Sized>(RwLock<T>);
+
+    impl<T> RefCell<T> {
+        pub fn new(value: T) -> Self {
+            Self(RwLock::new(value))
+        }
+
+        pub fn replace(&self, value: T) -> T {
+            std::mem::replace(&mut *self.borrow_mut(), value)
+        }
+
+        pub fn take(&self) -> T
+        where
+            T: Default,
+        {
+            self.replace(T::default())
+        }
+    }
+
+    impl<T: ?Sized> RefCell<T> {
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            // A panic while it was borrowed doesn't matter, as it does for a `RefCell`
+            self.0
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+    }
+
+    impl<T: Clone> Clone for RefCell<T> {
+        fn clone(&self) -> Self {
+            Self::new(self.borrow().clone())
+        }
+    }
+
+    impl<T: fmt::Debug + ?Sized> fmt::Debug for RefCell<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("RefCell")
+                .field("value", &&*self.borrow())
+                .finish()
+        }
+    }
+
+    /// A `Cell` which is `Sync`
+    #[derive(Default)]
+    pub struct Cell<T>(Mutex<T>);
+
+    impl<T: Copy> Cell<T> {
+        pub fn new(value: T) -> Self {
+            Self(Mutex::new(value))
+        }
+
+        pub fn get(&self) -> T {
+            *self
+                .0
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }
+
+        pub fn set(&self, value: T) {
+            *self
+                .0
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) = value;
+        }
+    }
+
+    impl<T: Copy + fmt::Debug> fmt::Debug for Cell<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Cell").field("value", &self.get()).finish()
+        }
+    }
+}
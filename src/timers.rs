@@ -0,0 +1,74 @@
+//! The timers set by `setTimeout` and `setInterval`, which the event loop of the VM runs when
+//! they're due, see [`crate::VM::run_event_loop`]
+use crate::value::Value;
+use std::time::Duration;
+
+/// The shortest period of an interval, so an interval of zero doesn't keep the loop busy
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+struct Timer {
+    id: u64,
+    /// The time since the Unix epoch it's due at, on the clock of the VM
+    due: Duration,
+    /// The period of an interval, which is set again after each run
+    interval: Option<Duration>,
+    callback: Value,
+}
+
+/// The pending timers, in the order they were set
+#[derive(Default)]
+pub(crate) struct Timers {
+    pending: Vec<Timer>,
+    /// The id of the last timer set. The ids start from 1, so a script may use 0 for no timer
+    last_id: u64,
+}
+
+impl Timers {
+    /// Set a timer calling `callback` at `due`, and every `interval` afterwards if given. Return
+    /// its id
+    pub fn add(&mut self, callback: Value, due: Duration, interval: Option<Duration>) -> u64 {
+        self.last_id += 1;
+        self.pending.push(Timer {
+            id: self.last_id,
+            due,
+            interval: interval.map(|interval| interval.max(MIN_INTERVAL)),
+            callback,
+        });
+        self.last_id
+    }
+
+    /// Cancel the timer, and return whether it was pending
+    pub fn clear(&mut self, id: u64) -> bool {
+        let before = self.pending.len();
+        self.pending.retain(|timer| timer.id != id);
+        self.pending.len() < before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// When the next timer is due
+    pub fn next_due(&self) -> Option<Duration> {
+        self.next().map(|idx| self.pending[idx].due)
+    }
+
+    /// Take the callback of the next timer, which is due at `now`. An interval is set again for
+    /// `now` plus its period, and a timeout is done
+    pub fn take_next(&mut self, now: Duration) -> Option<Value> {
+        let idx = self.next()?;
+        let timer = &mut self.pending[idx];
+        match timer.interval {
+            Some(interval) => {
+                timer.due = now + interval;
+                Some(timer.callback.clone())
+            }
+            None => Some(self.pending.remove(idx).callback),
+        }
+    }
+
+    /// The index of the earliest timer, the first one set among those due at the same time
+    fn next(&self) -> Option<usize> {
+        (0..self.pending.len()).min_by_key(|&idx| (self.pending[idx].due, self.pending[idx].id))
+    }
+}
@@ -1,14 +1,66 @@
 use crate::chunk::Chunk;
 use crate::compiler::Upvalue;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::error::LoxError;
+use crate::shared::{AnyData, Cell, MaybeSync, Rc, RefCell};
+use crate::vm::{CallFrame, VM};
+use std::any::Any;
+use std::collections::HashMap;
 #[derive(Default, Clone, Debug)]
 pub struct Function {
     pub name: String,
     /// The number of parameters the function expects
     pub arity: usize,
+    /// The parameters without a default value, so a call passes from `min_arity` up to `arity`
+    /// arguments
+    pub min_arity: usize,
     pub chunk: Chunk,
     pub upvalues: Vec<Upvalue>,
+    /// The most locals in scope at once, including slot zero, so the VM knows the stack it needs
+    pub max_locals: usize,
+    /// The source code the function is compiled from, to show the code in runtime errors
+    pub source: Rc<str>,
+    /// The file of the source code, if it's from a file. The imports are relative to it
+    pub file: Option<Rc<str>>,
+    /// It has a `yield`, so calling it creates a coroutine running its body
+    pub is_generator: bool,
+    /// The last parameter is a rest parameter, e.g. `...args`, which gets a list of the
+    /// arguments after the other parameters
+    pub is_variadic: bool,
+}
+
+impl Function {
+    /// The source code of the token the instruction at `offset` is compiled from, e.g. the name of
+    /// a variable. It's `None` without the source code, e.g. for a script loaded from a `.loxc` file
+    ///
+    /// The instruction pointer of a call frame is already past the instruction it runs, so that
+    /// one is at `ip - 1`
+    ///
+    /// ```
+    /// use rustlox::VM;
+    ///
+    /// let script = VM::check("var a = 1;\nprint a + 2;").unwrap().function;
+    /// // `Constant 1; DefineGlobal a; GetGlobal a; ...`
+    /// assert_eq!(script.source_text(4), Some("a"));
+    /// assert_eq!(script.source_line(4), Some("print a + 2;"));
+    /// ```
+    pub fn source_text(&self, offset: usize) -> Option<&str> {
+        let span = self.chunk.spans.get(offset)?;
+        self.source
+            .get(span.start..span.end())
+            .filter(|_| !self.source.is_empty())
+    }
+
+    /// The whole line of source code where the token of the instruction at `offset` starts
+    pub fn source_line(&self, offset: usize) -> Option<&str> {
+        if self.source.is_empty() {
+            return None;
+        }
+        let start = self.chunk.spans.get(offset)?.start;
+        let (before, after) = (self.source.get(..start)?, self.source.get(start..)?);
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = start + after.find('\n').unwrap_or(after.len());
+        Some(self.source[line_start..line_end].trim_end_matches('\r'))
+    }
 }
 
 impl std::fmt::Display for Function {
@@ -18,18 +70,24 @@ impl std::fmt::Display for Function {
 }
 
 /// The runtime representation for upvalues
-#[derive(Clone, Debug)]
+///
+/// While the captured variable is still on the stack, the upvalue is "open" and reads/writes go
+/// to the stack slot directly, so the enclosing function and the closures see the same variable.
+/// Once the variable goes out of scope, the VM "closes" the upvalue by moving the value into it
+#[derive(Debug)]
 pub struct ObjUpvalue {
-    /// Points to the closed-over variable in the stack by the index
-    pub location: usize,
-    pub obj: RefCell<Value>,
+    /// Points to the closed-over variable in the stack by the index. A suspended coroutine takes
+    /// the stack slots of its variables along, and moves them when it resumes, see [`Coroutine`]
+    pub location: Cell<usize>,
+    /// `Some` after the upvalue is closed
+    pub closed: RefCell<Option<Value>>,
 }
 
 impl ObjUpvalue {
-    pub fn new(location: usize, obj: Value) -> Self {
+    pub fn new(location: usize) -> Self {
         Self {
-            location,
-            obj: RefCell::new(obj),
+            location: Cell::new(location),
+            closed: RefCell::new(None),
         }
     }
 }
@@ -49,103 +107,677 @@ impl Closure {
     }
 }
 
+/// The runtime representation for classes
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    /// Methods are added one by one after the class is created, by `OP_METHOD`
+    pub methods: RefCell<HashMap<String, Rc<Closure>>>,
+    /// The static methods and the class-level fields, e.g. `Math.square` and `Math.pi`
+    pub statics: RefCell<HashMap<String, Value>>,
+    /// The methods run by reading and assigning the properties, e.g. `circle.area`
+    pub getters: RefCell<HashMap<String, Rc<Closure>>>,
+    pub setters: RefCell<HashMap<String, Rc<Closure>>>,
+}
+
+impl Class {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(HashMap::new()),
+            statics: RefCell::new(HashMap::new()),
+            getters: RefCell::new(HashMap::new()),
+            setters: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+/// An instance of a class, which is created by calling the class
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    /// Each instance can freely add fields at runtime
+    pub fields: HashMap<String, Value>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+/// A method which remembers the instance it was accessed from, e.g. `var m = foo.bar;`
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+impl BoundMethod {
+    pub fn new(receiver: Value, method: Rc<Closure>) -> Self {
+        Self { receiver, method }
+    }
+}
+
+/// Where a coroutine is in running its function
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    /// Created by calling the function, which hasn't started yet
+    New,
+    /// Waiting at a `yield`
+    Suspended,
+    /// Resumed, and running or resuming another coroutine
+    Running,
+    /// The function returned, or aborted with an error
+    Done,
+}
+
+/// A call of a function with a `yield`, which runs until the `yield` each time it's resumed.
+/// While it's suspended, it keeps its call frames and its part of the stack. The upvalues still
+/// pointing to its stack slots are closed meanwhile, and open again when it resumes
+#[derive(Debug)]
+pub struct Coroutine {
+    pub name: String,
+    pub(crate) state: RefCell<CoroutineState>,
+}
+
+#[derive(Debug)]
+pub(crate) struct CoroutineState {
+    pub status: CoroutineStatus,
+    /// The call frames, whose slots count from the start of `stack`
+    pub frames: Vec<CallFrame>,
+    pub stack: Vec<Value>,
+    /// The upvalues of the variables in `stack`, whose locations count from its start too
+    pub upvalues: Vec<Rc<ObjUpvalue>>,
+}
+
+impl Coroutine {
+    /// A coroutine which will call the function in `frame`. `stack` holds the callee and the
+    /// arguments
+    pub(crate) fn new(name: String, frame: CallFrame, stack: Vec<Value>) -> Self {
+        Self {
+            name,
+            state: RefCell::new(CoroutineState {
+                status: CoroutineStatus::New,
+                frames: vec![frame],
+                stack,
+                upvalues: vec![],
+            }),
+        }
+    }
+
+    pub fn status(&self) -> CoroutineStatus {
+        self.state.borrow().status
+    }
+}
+
+/// The integers from `start` up to `end`, e.g. `1..10` or `1..=10`. Iterating over it or slicing
+/// with it doesn't materialize the numbers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    /// Whether `end` is one of the numbers, i.e. `..=`
+    pub inclusive: bool,
+}
+
+impl Range {
+    /// The end which isn't one of the numbers, like `..` has
+    pub fn exclusive_end(&self) -> f64 {
+        if self.inclusive {
+            self.end + 1.0
+        } else {
+            self.end
+        }
+    }
+
+    /// The number of integers in the range, which is zero when it ends before it starts
+    pub fn len(&self) -> usize {
+        (self.exclusive_end() - self.start).max(0.0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl std::fmt::Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = if self.inclusive { "..=" } else { ".." };
+        write!(
+            f,
+            "{}{op}{}",
+            format_number(self.start),
+            format_number(self.end)
+        )
+    }
+}
+
+/// A Rust object handed to the scripts, e.g. a file handle or a database connection. The scripts
+/// can only pass it around and compare it, the natives get it back with [`Value::downcast_ref`]
+pub struct UserData {
+    /// The name of its type, which the error messages show
+    pub type_name: &'static str,
+    data: Box<AnyData>,
+}
+
+impl UserData {
+    pub fn new<T: Any + MaybeSync>(data: T) -> Self {
+        Self {
+            type_name: std::any::type_name::<T>(),
+            data: Box::new(data),
+        }
+    }
+}
+
+impl std::fmt::Debug for UserData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<userdata {}>", self.type_name)
+    }
+}
+
+/// The error returned by a native function, which becomes a runtime error of the script
+#[derive(Clone, Debug, PartialEq)]
+pub struct NativeError {
+    pub message: String,
+}
+
+impl NativeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for NativeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for NativeError {}
+
+/// The error of a callback run by [`VM::call_value`], so a native passes it on by `?`
+impl From<LoxError> for NativeError {
+    fn from(error: LoxError) -> Self {
+        match error {
+            LoxError::RuntimeError { message, .. } => Self::new(message),
+            error => Self::new(error.to_string()),
+        }
+    }
+}
+
+/// The signature of native functions. They get the VM, so they can e.g. read the globals. With
+/// the `threadsafe` feature they are `Send` and `Sync` too
+#[cfg(not(feature = "threadsafe"))]
+pub type NativeFn = dyn Fn(&mut VM, &[Value]) -> Result<Value, NativeError>;
+#[cfg(feature = "threadsafe")]
+pub type NativeFn = dyn Fn(&mut VM, &[Value]) -> Result<Value, NativeError> + Send + Sync;
+
+/// A function implemented in Rust. It's a closure, so it can capture the state of the host
 #[derive(Clone)]
-pub struct NativeFunction(pub fn(&[Value]) -> Value);
+pub struct NativeFunction {
+    pub name: String,
+    /// The number of arguments, which is checked by the VM before calling the function
+    pub arity: usize,
+    /// The least number of arguments, when the last ones are optional, see
+    /// [`NativeFunction::min_arity`]
+    pub min_arity: usize,
+    /// Whether it also takes any number of arguments past `arity`, see [`NativeFunction::variadic`]
+    pub variadic: bool,
+    pub function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&mut VM, &[Value]) -> Result<Value, NativeError> + MaybeSync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            arity,
+            min_arity: arity,
+            variadic: false,
+            function: Rc::new(function),
+        }
+    }
+
+    /// Take any number of arguments past `arity`, which becomes the least it takes, e.g. for
+    /// `format(string, ...)`
+    pub fn variadic(mut self) -> Self {
+        self.variadic = true;
+        self
+    }
+
+    /// Take `min_arity` arguments or more, up to `arity`, e.g. for `list.sort(comparator)` whose
+    /// comparator is optional. The function gets only the arguments of the call
+    pub fn min_arity(mut self, min_arity: usize) -> Self {
+        self.min_arity = min_arity.min(self.arity);
+        self
+    }
+}
 
 impl std::fmt::Debug for NativeFunction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<native fn>")
+        write!(f, "<native fn {}>", self.name)
     }
 }
 
 /// Let the compiler tell when it's compiling top-level code vs. the body of a function
-#[derive(PartialEq, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
 pub enum FunctionType {
     Function,
+    Method,
+    /// The `init` method of a class, which always returns the instance
+    Initializer,
+    /// A method without parameters which runs when the property is read, e.g. `area { ... }`
+    Getter,
+    /// A method which runs when the property is assigned, e.g. `set area(value) { ... }`. It
+    /// always returns the assigned value
+    Setter,
     #[default]
     Script,
 }
 
-#[derive(Clone, Debug)]
+/// The payload of [`Value::String`]. Strings are shared instead of copied, so loading a string
+/// constant or reading a variable holding a string only bumps a reference count
+pub type LoxString = Rc<String>;
+
+/// The payload of [`Value::NativeFunc`], which is boxed by the `compact-values` feature
+#[cfg(feature = "compact-values")]
+pub type NativeRef = Rc<NativeFunction>;
+#[cfg(not(feature = "compact-values"))]
+pub type NativeRef = NativeFunction;
+
+/// Take the text out of a string value, which only copies it if the string is still shared
+pub fn into_string(s: LoxString) -> String {
+    Rc::try_unwrap(s).unwrap_or_else(|s| s.to_string())
+}
+
+#[derive(Clone, Debug, Default)]
 pub enum Value {
     Bool(bool),
+    #[default]
     Nil,
+    /// A whole number, which the integer literals and the integer arithmetic make. An operation
+    /// whose result doesn't fit makes a float instead
+    Int(i64),
     Number(f64),
     /// A pointer to a String in the heap
-    String(String),
+    String(LoxString),
     Func(Rc<Function>),
-    NativeFunc(NativeFunction),
+    NativeFunc(NativeRef),
     Closure(Rc<Closure>),
+    Class(Rc<Class>),
+    /// Instances are mutable, and all the references to an instance see the same object
+    Instance(Rc<RefCell<Instance>>),
+    /// A growable list, which is mutable and shared like an instance
+    List(Rc<RefCell<Vec<Value>>>),
+    BoundMethod(Rc<BoundMethod>),
+    /// An opaque Rust object, see [`Value::user_data`]
+    UserData(Rc<UserData>),
+    Coroutine(Rc<Coroutine>),
+    /// Immutable, so it compares by its bounds like a number does
+    Range(Rc<Range>),
+}
+
+impl Value {
+    /// Make a string value, whichever representation the strings use
+    pub fn string(s: String) -> Self {
+        Self::String(Rc::new(s))
+    }
+
+    /// Make a list value of the elements
+    pub fn list(elements: Vec<Value>) -> Self {
+        Self::List(Rc::new(RefCell::new(elements)))
+    }
+
+    /// The method `name` of the class of an instance, e.g. the `plus` overloading `+`
+    pub(crate) fn method(&self, name: &str) -> Option<Rc<Closure>> {
+        match self {
+            Self::Instance(instance) => instance.borrow().class.methods.borrow().get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    /// The number of an integer or a float, e.g. an operand of the arithmetic mixing them
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Int(n) => Some(*n as f64),
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The name of the type of the value, for the error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Bool(_) => "boolean",
+            Self::Nil => "nil",
+            Self::Int(_) | Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::Func(_) | Self::Closure(_) | Self::BoundMethod(_) => "function",
+            Self::NativeFunc(_) => "native function",
+            Self::Class(_) => "class",
+            Self::Instance(_) => "instance",
+            Self::List(_) => "list",
+            Self::UserData(_) => "userdata",
+            Self::Coroutine(_) => "coroutine",
+            Self::Range(_) => "range",
+        }
+    }
+
+    /// Wrap a Rust object, so the host can hand it to the scripts and get it back in the natives
+    ///
+    /// ```
+    /// use rustlox::value::NativeError;
+    /// use rustlox::{Value, VM};
+    /// use std::sync::atomic::{AtomicU32, Ordering};
+    ///
+    /// struct Counter(AtomicU32);
+    ///
+    /// let mut vm = VM::new();
+    /// vm.set_global("counter", Value::user_data(Counter(AtomicU32::new(0))));
+    /// vm.register_native("increment", 1, |_vm, args| {
+    ///     let counter: &Counter = args[0].downcast_ref()?;
+    ///     counter.0.fetch_add(1, Ordering::Relaxed);
+    ///     Ok(Value::Nil)
+    /// });
+    /// vm.interpret("increment(counter); increment(counter);")?;
+    /// let counter = vm.get_global("counter").unwrap();
+    /// assert_eq!(counter.downcast_ref::<Counter>()?.0.load(Ordering::Relaxed), 2);
+    /// assert!(vm.interpret("increment(1);").is_err());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn user_data<T: Any + MaybeSync>(data: T) -> Self {
+        Self::UserData(Rc::new(UserData::new(data)))
+    }
+
+    /// The Rust object wrapped by [`Value::user_data`], if it's a `T`
+    pub fn downcast_ref<T: Any>(&self) -> Result<&T, NativeError> {
+        let expected = std::any::type_name::<T>();
+        match self {
+            Self::UserData(user_data) => user_data.data.downcast_ref().ok_or_else(|| {
+                NativeError::new(format!(
+                    "Expect a {expected}, got a {}.",
+                    user_data.type_name
+                ))
+            }),
+            value => Err(NativeError::new(format!(
+                "Expect a {expected}, got {}.",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Self::Number(n)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Self::Int(n)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Self::string(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Self::string(s)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(elements: Vec<Value>) -> Self {
+        Self::list(elements)
+    }
+}
+
+/// `None` is `nil`
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Nil, Into::into)
+    }
+}
+
+/// The error of converting a value of another type, which a native function can return as it is
+fn expected(type_name: &str, value: &Value) -> NativeError {
+    NativeError::new(format!("Expect a {type_name}, got {}.", value.type_name()))
+}
+
+/// Take the number out of a value, e.g. an argument of a native function
+///
+/// ```
+/// use rustlox::Value;
+///
+/// assert_eq!(f64::try_from(&Value::Number(1.5)), Ok(1.5));
+/// assert_eq!(f64::try_from(&Value::Int(2)), Ok(2.0));
+/// let error = f64::try_from(&Value::Nil).unwrap_err();
+/// assert_eq!(error.message, "Expect a number, got nil.");
+/// ```
+impl TryFrom<&Value> for f64 {
+    type Error = NativeError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        value.as_number().ok_or_else(|| expected("number", value))
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = NativeError;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Self::try_from(&value)
+    }
+}
+
+/// Only the booleans convert, `nil` isn't `false` here
+impl TryFrom<&Value> for bool {
+    type Error = NativeError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(expected("boolean", value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = NativeError;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Self::try_from(&value)
+    }
+}
+
+impl<'a> TryFrom<&'a Value> for &'a str {
+    type Error = NativeError;
+    fn try_from(value: &'a Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            _ => Err(expected("string", value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = NativeError;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(into_string(s)),
+            _ => Err(expected("string", &value)),
+        }
+    }
+}
+
+/// A copy of the elements of a list, which the script may still change
+impl TryFrom<&Value> for Vec<Value> {
+    type Error = NativeError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::List(list) => Ok(list.borrow().clone()),
+            _ => Err(expected("list", value)),
+        }
+    }
+}
+
+/// The largest integer up to which the floats hold every integer, so the arithmetic of the whole
+/// floats is exact, `2 ** 53 - 1` like JavaScript's `Number.MAX_SAFE_INTEGER`
+pub const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+/// The text of a number, as `print` and the string conversions show it. Whole numbers have no
+/// `.0`, and the digits are the shortest ones which read back as the same number, e.g. `0.1 + 0.2`
+/// is `0.30000000000000004` since `0.3` is another number, and `2.0 ** 62` is
+/// `4611686018427388000`. Like JavaScript, the very large and very small numbers use an exponent.
+/// The integers print all of their digits
+///
+/// ```
+/// use rustlox::value::format_number;
+///
+/// assert_eq!(format_number(3.0), "3");
+/// assert_eq!(format_number(2f64.powi(62)), "4611686018427388000");
+/// assert_eq!(format_number(-0.5), "-0.5");
+/// assert_eq!(format_number(1e21), "1e+21");
+/// assert_eq!(format_number(1.5e-7), "1.5e-7");
+/// assert_eq!(format_number(f64::NAN), "nan");
+/// assert_eq!(format_number(f64::NEG_INFINITY), "-inf");
+/// ```
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return "nan".to_string();
+    }
+    let abs = n.abs();
+    if abs.is_infinite() || abs == 0.0 || (1e-6..1e21).contains(&abs) {
+        return n.to_string();
+    }
+    let text = format!("{n:e}");
+    match text.split_once('e') {
+        Some((mantissa, exponent)) if !exponent.starts_with('-') => {
+            format!("{mantissa}e+{exponent}")
+        }
+        _ => text,
+    }
 }
 
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Number(v) => write!(f, "{v}"),
+            Self::Int(v) => write!(f, "{v}"),
+            Self::Number(v) => write!(f, "{}", format_number(*v)),
             Self::Bool(v) => write!(f, "{v}"),
             Self::Nil => write!(f, "nil"),
             Self::String(s) => write!(f, "{s}"),
-            Self::Func(func) => write!(
-                f,
-                "<fn {}>",
-                if func.name.is_empty() {
-                    "<script>"
-                } else {
-                    &func.name
-                }
-            ),
+            Self::Func(func) => write_function(f, func),
+            // Like clox, which the test suite expects
             Self::NativeFunc(..) => write!(f, "<native fn>"),
-            Self::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Self::Closure(closure) => write_function(f, &closure.function),
+            Self::Class(class) => write!(f, "{}", class.name),
+            Self::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Self::List(list) => write_list(f, list, &mut vec![]),
+            Self::BoundMethod(bound) => write_function(f, &bound.method.function),
+            Self::UserData(user_data) => write!(f, "<userdata {}>", user_data.type_name),
+            Self::Coroutine(coroutine) => write!(f, "<coroutine {}>", coroutine.name),
+            Self::Range(range) => write!(f, "{range}"),
         }
     }
 }
 
-impl std::ops::Neg for Value {
-    type Output = Self;
-    fn neg(self) -> Self::Output {
-        match self {
-            Self::Number(v) => Self::Number(-v),
-            _ => panic!("Impossible"),
-        }
+/// Write a Lox function the same way whether it's wrapped in a closure or bound to a receiver. The
+/// top-level code is a function without a name
+fn write_function(f: &mut std::fmt::Formatter<'_>, function: &Function) -> std::fmt::Result {
+    if function.name.is_empty() {
+        write!(f, "<script>")
+    } else {
+        write!(f, "<fn {}>", function.name)
     }
 }
 
-impl std::ops::Add for Value {
-    type Output = Self;
-    fn add(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a + b),
-            _ => panic!("Impossible"),
-        }
+/// Write the elements in brackets, with the strings in quotes so e.g. `["a, b"]` isn't mistaken
+/// for two elements. A list inside itself is written as `[...]` instead of forever
+fn write_list(
+    f: &mut std::fmt::Formatter<'_>,
+    list: &Rc<RefCell<Vec<Value>>>,
+    outer: &mut Vec<*const RefCell<Vec<Value>>>,
+) -> std::fmt::Result {
+    if outer.contains(&Rc::as_ptr(list)) {
+        return write!(f, "[...]");
     }
-}
-impl std::ops::Sub for Value {
-    type Output = Self;
-    fn sub(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a - b),
-            _ => panic!("Impossible"),
+    outer.push(Rc::as_ptr(list));
+    write!(f, "[")?;
+    for (idx, element) in list.borrow().iter().enumerate() {
+        if idx > 0 {
+            write!(f, ", ")?;
+        }
+        match element {
+            Value::String(s) => write!(f, "{s:?}")?,
+            Value::List(inner) => write_list(f, inner, outer)?,
+            element => write!(f, "{element}")?,
         }
     }
+    outer.pop();
+    write!(f, "]")
 }
 
-impl std::ops::Div for Value {
-    type Output = Self;
-    fn div(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a / b),
-            _ => panic!("Impossible"),
+/// The `==` of Lox. Values of different types are never equal, and `nil` only equals `nil`.
+/// Numbers, booleans, strings and ranges compare by value, with `nan` not equal to itself. An
+/// integer equals the float of the same number, exactly, so `1 == 1.0` but `2 ** 53 + 1` doesn't
+/// equal the float `2 ** 53` it rounds to. The
+/// objects compare by identity: a class, an instance, a list, a coroutine or a userdata only
+/// equals itself, and so does a function. Two bound methods are equal when they bind the same
+/// method to the same receiver, so `obj.method == obj.method` holds
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Nil, Self::Nil) => true,
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::Int(a), Self::Number(b)) | (Self::Number(b), Self::Int(a)) => int_equals(*a, *b),
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Func(a), Self::Func(b)) => Rc::ptr_eq(a, b),
+            (Self::NativeFunc(a), Self::NativeFunc(b)) => Rc::ptr_eq(&a.function, &b.function),
+            (Self::Closure(a), Self::Closure(b)) => Rc::ptr_eq(a, b),
+            (Self::Class(a), Self::Class(b)) => Rc::ptr_eq(a, b),
+            (Self::Instance(a), Self::Instance(b)) => Rc::ptr_eq(a, b),
+            (Self::List(a), Self::List(b)) => Rc::ptr_eq(a, b),
+            (Self::UserData(a), Self::UserData(b)) => Rc::ptr_eq(a, b),
+            (Self::Coroutine(a), Self::Coroutine(b)) => Rc::ptr_eq(a, b),
+            (Self::Range(a), Self::Range(b)) => a == b,
+            (Self::BoundMethod(a), Self::BoundMethod(b)) => {
+                Rc::ptr_eq(&a.method, &b.method) && a.receiver == b.receiver
+            }
+            _ => false,
         }
     }
 }
 
-impl std::ops::Mul for Value {
-    type Output = Self;
-    fn mul(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a * b),
-            _ => panic!("Impossible"),
-        }
+/// Whether the float is exactly the integer, which the integers past `2 ** 53` may round to
+fn int_equals(a: i64, b: f64) -> bool {
+    const TWO_POW_63: f64 = 9_223_372_036_854_775_808.0;
+    b.fract() == 0.0 && (-TWO_POW_63..TWO_POW_63).contains(&b) && b as i64 == a
+}
+
+/// The value of a whole float, as an integer when it fits one, e.g. a number of a range
+pub(crate) fn whole(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER {
+        Value::Int(n as i64)
+    } else {
+        Value::Number(n)
     }
 }
 
@@ -163,3 +795,7 @@ impl ValueArray {
         self.values.push(val);
     }
 }
+
+// The tag and one pointer or `f64`
+#[cfg(feature = "compact-values")]
+const _: () = assert!(std::mem::size_of::<Value>() == 16);
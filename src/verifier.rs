@@ -0,0 +1,417 @@
+//! The verifier, which checks that a script loaded from a `.loxc` file is bytecode the compiler
+//! could have written before it runs. The VM doesn't panic on malformed bytecode either, but it
+//! only notices it when it gets there, after running the code before it
+//!
+//! The checks, for the script and every function in its constants:
+//!
+//! - every byte of the code belongs to an instruction, whose opcode is known and whose operands
+//!   fit in the code
+//! - the jumps land at the start of an instruction, and no path runs past the end of the code
+//! - the constants, the upvalues and the local slots the instructions refer to exist, and the
+//!   names are strings
+//! - each instruction finds the values it pops on the stack, without reaching the slot of the
+//!   function itself, and every path to an instruction leaves the stack as deep
+use crate::chunk::OpCode;
+use crate::value::{Function, Value};
+use std::fmt;
+
+/// The most local slots a function may have, the wide instructions address them with two bytes
+const MAX_LOCALS: usize = u16::MAX as usize + 1;
+
+/// Why the bytecode of a function can't run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyError {
+    /// The name of the function, empty for the script
+    pub function: String,
+    /// The offset of the instruction in the chunk of the function
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let function = if self.function.is_empty() {
+            "<script>"
+        } else {
+            &self.function
+        };
+        write!(f, "{} (at {} in {function})", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Verify the script, and the functions it defines
+///
+/// ```
+/// use rustlox::chunk::OpCode;
+/// use rustlox::verifier::verify;
+/// use rustlox::VM;
+///
+/// let mut script = VM::check("print 1;").unwrap().function;
+/// assert!(verify(&script).is_ok());
+/// // `Constant 0; Print; Nil; Return`, with the `nil` returned by the script popped instead
+/// script.chunk.code[3] = OpCode::Pop.into();
+/// assert_eq!(verify(&script).unwrap_err().message, "Pops more values than the stack has.");
+/// ```
+pub fn verify(script: &Function) -> Result<(), VerifyError> {
+    if !script.upvalues.is_empty() {
+        return Err(error(script, 0, "The script can't capture upvalues."));
+    }
+    verify_function(script)
+}
+
+fn error(function: &Function, offset: usize, message: &str) -> VerifyError {
+    VerifyError {
+        function: function.name.clone(),
+        offset,
+        message: message.to_string(),
+    }
+}
+
+fn verify_function(function: &Function) -> Result<(), VerifyError> {
+    let chunk = &function.chunk;
+    if function.min_arity > function.arity
+        || function.arity > u8::MAX as usize
+        || (function.is_variadic && function.arity == 0)
+    {
+        return Err(error(function, 0, "Invalid number of parameters."));
+    }
+    if function.max_locals > MAX_LOCALS {
+        return Err(error(function, 0, "Too many local variables."));
+    }
+    if chunk.lines.len() != chunk.code.len()
+        || chunk.columns.len() != chunk.code.len()
+        || chunk.spans.len() != chunk.code.len()
+    {
+        return Err(error(function, 0, "The lines don't match the code."));
+    }
+    if chunk.code.is_empty() {
+        return Err(error(function, 0, "Falls off the end of the code."));
+    }
+
+    // Where each instruction starts
+    let mut starts = vec![false; chunk.code.len()];
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let Ok(op) = OpCode::try_from(chunk.code[offset]) else {
+            let message = format!("Unknown opcode {}.", chunk.code[offset]);
+            return Err(error(function, offset, &message));
+        };
+        if offset + chunk.instruction_len(offset) > chunk.code.len() {
+            return Err(error(
+                function,
+                offset,
+                "The operands run past the end of the code.",
+            ));
+        }
+        verify_operands(function, op, offset)?;
+        starts[offset] = true;
+        offset += chunk.instruction_len(offset);
+    }
+    verify_stack(function, &starts)?;
+
+    for constant in &chunk.constants.values {
+        if let Value::Func(function) = constant {
+            verify_function(function)?;
+        }
+    }
+    Ok(())
+}
+
+/// The instructions which read a constant, with its index as the first operand
+fn constant_kind(op: OpCode) -> Option<ConstantKind> {
+    match op.narrow_constant() {
+        OpCode::Constant => Some(ConstantKind::Any),
+        OpCode::Closure => Some(ConstantKind::Function),
+        OpCode::DefineGlobal
+        | OpCode::GetGlobal
+        | OpCode::SetGlobal
+        | OpCode::Class
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::Method
+        | OpCode::Static
+        | OpCode::Getter
+        | OpCode::Setter
+        | OpCode::Import
+        | OpCode::GetSuper
+        | OpCode::Invoke
+        | OpCode::InvokeSpread
+        | OpCode::SuperInvoke
+        | OpCode::SuperInvokeSpread => Some(ConstantKind::Name),
+        _ => None,
+    }
+}
+
+enum ConstantKind {
+    Any,
+    Name,
+    Function,
+}
+
+/// Check the operands which don't depend on the stack
+fn verify_operands(function: &Function, op: OpCode, offset: usize) -> Result<(), VerifyError> {
+    let chunk = &function.chunk;
+    let operand = |idx: usize| chunk.code[offset + idx];
+    if let Some(kind) = constant_kind(op) {
+        let idx = if op.has_wide_constant() {
+            u16::from_be_bytes([operand(1), operand(2)]) as usize
+        } else {
+            operand(1) as usize
+        };
+        let Some(constant) = chunk.constants.values.get(idx) else {
+            return Err(error(function, offset, &format!("No constant {idx}.")));
+        };
+        match (kind, constant) {
+            (ConstantKind::Any, _)
+            | (ConstantKind::Name, Value::String(_))
+            | (ConstantKind::Function, Value::Func(_)) => {}
+            (ConstantKind::Name, _) => {
+                return Err(error(
+                    function,
+                    offset,
+                    &format!("Constant {idx} isn't a name."),
+                ));
+            }
+            (ConstantKind::Function, _) => {
+                let message = format!("Constant {idx} isn't a function.");
+                return Err(error(function, offset, &message));
+            }
+        }
+    }
+    match op {
+        OpCode::GetUpvalue | OpCode::SetUpvalue
+            if operand(1) as usize >= function.upvalues.len() =>
+        {
+            Err(error(function, offset, "No such upvalue."))
+        }
+        OpCode::Closure | OpCode::ClosureLong => {
+            // The local ones are checked against the stack
+            let upvalues = offset + upvalues_start(op)..offset + chunk.instruction_len(offset);
+            for captured in upvalues.step_by(3) {
+                let (is_local, index) = closure_upvalue(&chunk.code, captured);
+                if is_local > 1 || (is_local == 0 && index >= function.upvalues.len()) {
+                    return Err(error(function, offset, "No such upvalue."));
+                }
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Where the upvalues of a closure start, after its function
+fn upvalues_start(op: OpCode) -> usize {
+    if op.has_wide_constant() {
+        3
+    } else {
+        2
+    }
+}
+
+/// The upvalue a closure captures at `offset`: whether it's a local of the enclosing function,
+/// and its index
+fn closure_upvalue(code: &[u8], offset: usize) -> (u8, usize) {
+    let index = u16::from_be_bytes([code[offset + 1], code[offset + 2]]);
+    (code[offset], index as usize)
+}
+
+/// The offsets of the instructions which may run after the one at `offset`, which don't have to
+/// be in the code
+fn successors(code: &[u8], op: OpCode, offset: usize, len: usize) -> Vec<Option<usize>> {
+    let next = offset + len;
+    let short = || u16::from_be_bytes([code[offset + 1], code[offset + 2]]) as usize;
+    let long = || {
+        let bytes = [
+            code[offset + 1],
+            code[offset + 2],
+            code[offset + 3],
+            code[offset + 4],
+        ];
+        u32::from_be_bytes(bytes) as usize
+    };
+    match op {
+        OpCode::Return => vec![],
+        OpCode::Jump => vec![Some(next + short())],
+        OpCode::JumpLong => vec![Some(next + long())],
+        OpCode::JumpIfFalse => vec![Some(next), Some(next + short())],
+        OpCode::JumpIfFalseLong => vec![Some(next), Some(next + long())],
+        OpCode::Loop => vec![next.checked_sub(short())],
+        OpCode::LoopLong => vec![next.checked_sub(long())],
+        _ => vec![Some(next)],
+    }
+}
+
+/// The number of values the instruction pops, and the number it pushes after that. The ones
+/// which only read or move the values below the top pop and push them again
+fn stack_effect(code: &[u8], op: OpCode, offset: usize) -> (usize, usize) {
+    let operand = code.get(offset + 1).copied().unwrap_or_default() as usize;
+    // The argument count follows the name, which takes two bytes in the wide variants
+    let arg_cnt = || code[offset + upvalues_start(op)] as usize;
+    match op.narrow_constant() {
+        OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::GetLocal
+        | OpCode::GetLocalLong
+        | OpCode::GetUpvalue
+        | OpCode::Closure
+        | OpCode::Class
+        | OpCode::ArgMissing
+        | OpCode::Import => (0, 1),
+        OpCode::Negate
+        | OpCode::Not
+        | OpCode::BitNot
+        | OpCode::Increment
+        | OpCode::SetGlobal
+        | OpCode::SetLocal
+        | OpCode::SetLocalLong
+        | OpCode::SetUpvalue
+        | OpCode::JumpIfFalse
+        | OpCode::JumpIfFalseLong
+        | OpCode::GetProperty
+        | OpCode::Yield => (1, 1),
+        OpCode::IsNil => (1, 2),
+        OpCode::Jump | OpCode::JumpLong | OpCode::Loop | OpCode::LoopLong => (0, 0),
+        OpCode::IterNext => (0, 2),
+        OpCode::Add
+        | OpCode::Substract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Modulo
+        | OpCode::Power
+        | OpCode::BitAnd
+        | OpCode::BitOr
+        | OpCode::BitXor
+        | OpCode::ShiftLeft
+        | OpCode::ShiftRight
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::Less
+        | OpCode::Range
+        | OpCode::GetIndex
+        | OpCode::AppendList
+        | OpCode::ExtendList
+        | OpCode::SetProperty
+        | OpCode::Method
+        | OpCode::Getter
+        | OpCode::Setter
+        | OpCode::Static
+        | OpCode::Inherit
+        | OpCode::GetSuper
+        | OpCode::CallSpread
+        | OpCode::InvokeSpread => (2, 1),
+        OpCode::SetIndex | OpCode::Slice | OpCode::SuperInvokeSpread => (3, 1),
+        OpCode::Print
+        | OpCode::Pop
+        | OpCode::DefineGlobal
+        | OpCode::ClosedUpvalue
+        | OpCode::Return => (1, 0),
+        OpCode::Dup => (operand + 1, operand + 2),
+        OpCode::Bury => (operand + 1, operand + 1),
+        OpCode::BuildList => (operand, 1),
+        OpCode::Call => (operand + 1, 1),
+        OpCode::Invoke => (arg_cnt() + 1, 1),
+        OpCode::SuperInvoke => (arg_cnt() + 2, 1),
+        OpCode::ConstantLong
+        | OpCode::DefineGlobalLong
+        | OpCode::GetGlobalLong
+        | OpCode::SetGlobalLong
+        | OpCode::ClosureLong
+        | OpCode::ClassLong
+        | OpCode::GetPropertyLong
+        | OpCode::SetPropertyLong
+        | OpCode::MethodLong
+        | OpCode::GetSuperLong
+        | OpCode::SuperInvokeLong
+        | OpCode::InvokeLong
+        | OpCode::ImportLong
+        | OpCode::StaticLong
+        | OpCode::GetterLong
+        | OpCode::SetterLong
+        | OpCode::InvokeSpreadLong
+        | OpCode::SuperInvokeSpreadLong => {
+            unreachable!("The wide variants are matched as the narrow ones")
+        }
+    }
+}
+
+/// The local slot the instruction reads or writes, and the ones the closure captures, which must
+/// be below the top of the stack
+fn local_slots(code: &[u8], op: OpCode, offset: usize, len: usize) -> Vec<usize> {
+    let byte = || code[offset + 1] as usize;
+    let short = || u16::from_be_bytes([code[offset + 1], code[offset + 2]]) as usize;
+    match op {
+        OpCode::GetLocal | OpCode::SetLocal => vec![byte()],
+        OpCode::GetLocalLong | OpCode::SetLocalLong => vec![short()],
+        // The position of the loop is in the slot after the iterable
+        OpCode::IterNext => vec![short() + 1],
+        OpCode::Closure | OpCode::ClosureLong => (offset + upvalues_start(op)..offset + len)
+            .step_by(3)
+            .map(|captured| closure_upvalue(code, captured))
+            .filter(|(is_local, _)| *is_local == 1)
+            .map(|(_, index)| index)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Follow every path through the code from the start, with the depth of the stack. The slot of
+/// the function and its parameters are on the stack when it starts
+fn verify_stack(function: &Function, starts: &[bool]) -> Result<(), VerifyError> {
+    let code = &function.chunk.code;
+    let mut depths = vec![None; code.len()];
+    let mut pending = vec![(0, 1 + function.arity, 0)];
+    while let Some((offset, depth, from)) = pending.pop() {
+        if offset >= code.len() {
+            return Err(error(function, from, "Runs past the end of the code."));
+        }
+        if !starts[offset] {
+            return Err(error(
+                function,
+                from,
+                "Jumps into the middle of an instruction.",
+            ));
+        }
+        match depths[offset] {
+            Some(known) if known == depth => continue,
+            Some(_) => {
+                let message = "The paths to the instruction leave different values on the stack.";
+                return Err(error(function, offset, message));
+            }
+            None => depths[offset] = Some(depth),
+        }
+
+        let op = OpCode::try_from(code[offset]).expect("Checked by `verify_function`");
+        let len = function.chunk.instruction_len(offset);
+        if local_slots(code, op, offset, len)
+            .into_iter()
+            .any(|slot| slot >= depth)
+        {
+            return Err(error(function, offset, "No such local variable."));
+        }
+        let (pops, pushes) = stack_effect(code, op, offset);
+        if pops >= depth {
+            return Err(error(
+                function,
+                offset,
+                "Pops more values than the stack has.",
+            ));
+        }
+        for next in successors(code, op, offset, len) {
+            let Some(next) = next else {
+                return Err(error(
+                    function,
+                    offset,
+                    "Jumps before the start of the code.",
+                ));
+            };
+            pending.push((next, depth - pops + pushes, offset));
+        }
+    }
+    Ok(())
+}
@@ -1,16 +1,82 @@
 use crate::chunk::OpCode;
-use crate::compiler::Compiler;
-use crate::disassembler::disassemble_instruction;
-use crate::value::{Closure, FunctionType, NativeFunction, ObjUpvalue, Value};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::time::{SystemTime, UNIX_EPOCH};
-
-pub enum InterpretResult {
+use crate::compiler::{CompiledScript, Compiler, Warnings};
+use crate::disassembler::write_instruction;
+use crate::error::{Limit, LoxError};
+use crate::gc::{Heap, Object};
+use crate::hooks::VmHooks;
+use crate::methods;
+use crate::natives;
+use crate::profiler::{self, Profile};
+use crate::reporter::{
+    did_you_mean, position, snippet, Diagnostic, Reporter, StderrReporter, WriterReporter,
+};
+use crate::shared::{Input, MaybeSync, Output, Rc, RefCell};
+use crate::timers::Timers;
+use crate::value::{
+    format_number, into_string, whole, BoundMethod, Class, Closure, Coroutine, CoroutineStatus,
+    Function, FunctionType, Instance, LoxString, NativeError, NativeFunction, NativeRef,
+    ObjUpvalue, Range, Value,
+};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The default maximum depth of nested calls, see [`VM::set_max_frames`]
+const FRAMES_MAX: usize = 64;
+/// The stack slots every call frame reserves for the temporaries, on top of its locals
+const FRAME_SLOTS: usize = u8::MAX as usize + 1;
+/// The most natives calling back into Lox inside each other, e.g. by a recursion through
+/// `list.map`, so the nested runs don't overflow the Rust stack
+const MAX_NATIVE_DEPTH: usize = 40;
+/// The frames of a run of the same call, e.g. of a deep recursion, a stack trace shows before
+/// collapsing the rest of them
+const REPEATED_FRAMES: usize = 3;
+/// The longest an argument is shown in a stack trace, in characters
+const TRACE_ARGUMENT_LEN: usize = 24;
+
+/// The instant to measure the profiler and the time limit from. `Instant::now` panics on
+/// `wasm32-unknown-unknown`, so nothing is measured there
+fn now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+/// The error of the clock read by the event loop, e.g. an interrupt while it waits
+fn timer_error(error: NativeError) -> LoxError {
+    LoxError::RuntimeError {
+        message: error.message,
+        stack_trace: vec![],
+    }
+}
+
+/// Whether the VM should keep running. The details of a runtime error are kept in
+/// `VM::last_error`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum InterpretResult {
     Ok,
-    CompileError,
     RuntimeError,
+    /// The script started by [`VM::start`] ran all the steps it was given
+    Paused,
+}
+
+/// Where [`VM::run_steps`] stopped
+#[derive(Debug)]
+pub enum StepResult {
+    /// The script ran to the end, or there was no script to run
+    Done,
+    /// The script ran all the steps, and continues from there on the next `run_steps`
+    Yielded,
+    /// A runtime error aborted the script
+    Error(LoxError),
 }
 
 #[derive(Debug)]
@@ -19,426 +85,3436 @@ pub struct CallFrame {
     ip: usize,
     /// The starts position of this CallFrame in the VM's stack
     slots: usize,
+    /// The number of arguments the caller passed, the parameters after them get their default
+    /// values
+    arg_cnt: usize,
+    /// When the call started, only while profiling
+    started: Option<Instant>,
+    /// The time spent in the functions called by this one, which the profiler doesn't count as
+    /// the time of this one
+    callee_time: Duration,
 }
 
 impl CallFrame {
     pub fn new(closure: Rc<Closure>, ip: usize, slots: usize) -> Self {
-        Self { closure, ip, slots }
+        Self {
+            closure,
+            ip,
+            slots,
+            arg_cnt: 0,
+            started: None,
+            callee_time: Duration::ZERO,
+        }
+    }
+
+    pub(crate) fn closure(&self) -> &Rc<Closure> {
+        &self.closure
+    }
+}
+
+/// What `/` and `%` do when the divisor is zero, see [`VM::set_division_by_zero`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DivisionByZero {
+    /// Follow IEEE 754: `1 / 0` is `inf`, `-1 / 0` is `-inf`, and `0 / 0` and `1 % 0` are `nan`
+    #[default]
+    Ieee,
+    /// Abort the script with a runtime error
+    Error,
+}
+
+/// The resources one run of the VM may use, e.g. by a script from an untrusted source. Exceeding
+/// one aborts the run with [`LoxError::LimitExceeded`]. A run is an `interpret` or a
+/// `call_function` of the host, including the code the natives run meanwhile
+///
+/// ```
+/// use rustlox::error::Limit;
+/// use rustlox::vm::Limits;
+/// use rustlox::{LoxError, VM};
+///
+/// let mut vm = VM::new();
+/// vm.set_limits(Limits {
+///     max_instructions: Some(10_000),
+///     ..Limits::default()
+/// });
+/// assert!(vm.interpret("var a = 1 + 2;").is_ok());
+/// assert_eq!(
+///     vm.interpret("while (true) {}"),
+///     Err(LoxError::LimitExceeded(Limit::Instructions(10_000)))
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The instructions executed
+    pub max_instructions: Option<u64>,
+    /// The wall time, which is checked every thousand or so instructions, so a native function
+    /// blocking for longer isn't stopped. A browser has no clock to measure it with, so it isn't
+    /// limited in WebAssembly
+    pub max_time: Option<Duration>,
+    /// The depth of nested calls, which replaces [`VM::set_max_frames`]
+    pub max_frames: Option<usize>,
+    /// The objects created: the closures, the classes, the instances, the bound methods and the
+    /// captured variables. The strings aren't counted
+    pub max_allocations: Option<usize>,
+}
+
+/// Stops the script running in a VM from another thread, e.g. on Ctrl-C, see
+/// [`VM::interrupt_handle`]
+///
+/// ```
+/// use rustlox::VM;
+/// use std::{thread, time::Duration};
+///
+/// let mut vm = VM::new();
+/// let handle = vm.interrupt_handle();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_millis(10));
+///     handle.interrupt();
+/// });
+/// assert!(vm.interpret("while (true) {}").is_err());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Abort the script with the runtime error "Interrupted." soon, or the next one if the VM
+    /// isn't running any
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether there was an interrupt, which is handled by this call
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// How many instructions run between the checks of the limits, at most
+const CHECK_INTERVAL: u32 = 1024;
+
+/// What malformed bytecode finds below the bottom of the stack
+const NIL: Value = Value::Nil;
+
+/// What the natives may reach outside of the VM. A new VM allows all of them, and a host running a
+/// script from an untrusted source denies them, so it doesn't get e.g. the secrets in the
+/// environment variables
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Read the files, by `import`ing a module from the disk
+    Fs,
+    /// Read and change the environment variables, by `getEnv` and `setEnv`
+    Env,
+    /// Read the clock and wait, by `clock`, `timeMillis` and `sleep`
+    Time,
+    /// Deal with the process running the VM, by `exit` and `readLine`
+    Process,
+    /// Reach the network. No native does, it's for the ones of the host, see [`VM::require`]
+    Net,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [Self::Fs, Self::Env, Self::Time, Self::Process, Self::Net];
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Fs => "fs",
+            Self::Env => "env",
+            Self::Time => "time",
+            Self::Process => "process",
+            Self::Net => "net",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The names shown by `Display`, e.g. `"env".parse()`
+impl std::str::FromStr for Capability {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|capability| capability.to_string() == s)
+            .ok_or_else(|| format!("Unknown capability '{s}'."))
     }
 }
 
-fn clock(_args: &[Value]) -> Value {
-    // see: https://stackoverflow.com/questions/26593387/how-can-i-get-the-current-time-in-milliseconds
-    let since_the_epoch = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    Value::Number(since_the_epoch.as_secs_f64())
+/// A module in the cache of the imported modules
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Module {
+    /// A Lox file, by its canonical path
+    File(PathBuf),
+    /// The natives registered by the host under the name, see [`VM::register_module`]
+    Native(String),
 }
 
 pub struct VM {
     pub frames: Vec<CallFrame>,
 
-    pub stack: Vec<Value>,
+    /// A fixed-capacity buffer, the live values are in `stack[..stack_top]`
+    pub stack: Box<[Value]>,
+
+    /// Points just past the top element of the stack, i.e. where the next value goes
+    stack_top: usize,
 
     globals: HashMap<String, Value>,
 
     /// All open upvalues that point to variables still on the stack
     open_upvalues: Vec<Rc<ObjUpvalue>>,
+
+    /// Where the compile errors and runtime errors go
+    reporter: Box<dyn Reporter>,
+
+    /// Where `print` writes to
+    stdout: Box<Output>,
+
+    /// The error which aborted the last run, if any
+    last_error: Option<LoxError>,
+
+    /// Tracks the objects to free the reference cycles among them
+    heap: Heap,
+
+    /// The maximum depth of nested calls, deeper calls are a stack overflow
+    max_frames: usize,
+
+    /// The most frames a stack trace shows, if limited, see [`VM::set_max_trace_frames`]
+    max_trace_frames: Option<usize>,
+
+    /// Run the peephole optimizer over the compiled code
+    optimize: bool,
+
+    /// Print the bytecode of every compiled function
+    disassemble: bool,
+
+    /// What the compiler does with the warnings, e.g. about the unused variables
+    warnings: Warnings,
+
+    /// Run the lints over the compiled code too
+    lint: bool,
+
+    /// The counters of the executed code, while profiling
+    profile: Option<Profile>,
+
+    /// Where to write the stack and the instruction before executing each instruction, if
+    /// anywhere
+    trace: Option<Box<Output>>,
+
+    /// What dividing by zero does
+    division_by_zero: DivisionByZero,
+
+    /// The code passed to `exit`, which stops the script
+    exit_code: Option<i32>,
+
+    /// The time since the Unix epoch on the virtual clock of a deterministic VM, see
+    /// [`VM::set_deterministic`]
+    virtual_clock: Option<Duration>,
+
+    /// The state of the generator of the random numbers
+    random_state: u64,
+
+    /// Where `readLine` reads from
+    stdin: Box<Input>,
+
+    /// The arguments of the script, see [`VM::set_args`]
+    args: Vec<String>,
+
+    /// What the natives are allowed to do
+    capabilities: HashSet<Capability>,
+
+    /// Where to look for the imported modules which aren't next to the importing file
+    module_path: Vec<PathBuf>,
+
+    /// The modules imported so far, which don't run again
+    modules: HashSet<Module>,
+
+    /// The resources a run may use
+    limits: Limits,
+
+    /// The instructions which may start before checking the limits again
+    countdown: u32,
+
+    /// The instructions the countdown started from
+    batch: u32,
+
+    /// Whether the running code read past its code, its constants or the stack, see
+    /// [`VM::corrupt_bytecode`]
+    corrupt: bool,
+
+    /// The instructions started by this run, up to the last check
+    executed: u64,
+
+    /// The natives running, inside each other if more than one
+    native_depth: usize,
+
+    /// The callbacks set by `setTimeout` and `setInterval`, see [`VM::run_event_loop`]
+    timers: Timers,
+
+    /// The value of `executed` to pause the script started by [`VM::start`] at, while there is one
+    pause_at: Option<u64>,
+
+    /// The objects created by this run
+    allocations: usize,
+
+    /// When this run started, if its time is limited
+    run_started: Option<Instant>,
+
+    /// Set from another thread to stop the script
+    interrupt: InterruptHandle,
+
+    /// The coroutines being resumed, the innermost last, with the number of frames below the
+    /// frames of each
+    coroutines: Vec<(Rc<Coroutine>, usize)>,
+
+    /// The modules registered by the host, which are imported by their name before looking for a
+    /// file
+    native_modules: HashMap<String, Vec<NativeRef>>,
+
+    /// The methods of the built-in types by the name of the type, see [`VM::register_method`]
+    builtin_methods: HashMap<&'static str, HashMap<String, NativeFunction>>,
+
+    /// The callbacks of the host, if any, see [`VM::set_hooks`]
+    hooks: Option<Box<dyn VmHooks>>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
+    /// Create a VM which prints its errors to stderr
+    ///
+    /// **The VM allows every [`Capability`]**, so a script may read files, the environment and
+    /// the clock, and exit the process. Deny them before running a script you don't trust:
+    ///
+    /// ```
+    /// use rustlox::vm::Capability;
+    /// use rustlox::VM;
+    ///
+    /// let mut vm = VM::new();
+    /// for capability in Capability::ALL {
+    ///     vm.deny(capability);
+    /// }
+    /// assert!(vm.interpret("exit(1);").is_err());
+    /// ```
     pub fn new() -> Self {
+        Self::with_reporter(Box::new(StderrReporter))
+    }
+
+    /// Create a VM which sends all of its errors to `reporter`
+    pub fn with_reporter(reporter: Box<dyn Reporter>) -> Self {
         let mut vm = Self {
             frames: vec![],
-            stack: vec![],
+            // The value stack is allocated once, with room for the locals of every frame
+            stack: vec![Value::Nil; FRAMES_MAX * FRAME_SLOTS].into_boxed_slice(),
+            stack_top: 0,
             globals: HashMap::new(),
             open_upvalues: vec![],
+            reporter,
+            stdout: Box::new(io::stdout()),
+            last_error: None,
+            heap: Heap::default(),
+            max_frames: FRAMES_MAX,
+            max_trace_frames: None,
+            optimize: false,
+            disassemble: false,
+            warnings: Warnings::default(),
+            lint: false,
+            trace: None,
+            profile: None,
+            division_by_zero: DivisionByZero::default(),
+            exit_code: None,
+            virtual_clock: None,
+            random_state: RandomState::new().build_hasher().finish(),
+            stdin: Box::new(io::BufReader::new(io::stdin())),
+            args: vec![],
+            // The natives keep working as they did before the capabilities, denying is up to the host
+            capabilities: Capability::ALL.into_iter().collect(),
+            module_path: vec![],
+            modules: HashSet::new(),
+            native_modules: HashMap::new(),
+            builtin_methods: HashMap::new(),
+            hooks: None,
+            limits: Limits::default(),
+            interrupt: InterruptHandle::default(),
+            coroutines: vec![],
+            countdown: 0,
+            corrupt: false,
+            batch: 0,
+            executed: 0,
+            native_depth: 0,
+            timers: Timers::default(),
+            pause_at: None,
+            allocations: 0,
+            run_started: None,
         };
-        vm.define_native("clock", NativeFunction(clock));
+        natives::register(&mut vm);
+        methods::register(&mut vm);
         vm
     }
 
-    pub fn current_frame(&mut self) -> &mut CallFrame {
-        self.frames.last_mut().unwrap()
+    /// Send the output of `print` statements to `out` instead of stdout
+    pub fn set_stdout(&mut self, out: Box<Output>) {
+        self.stdout = out;
     }
 
-    pub fn current_closure(&mut self) -> &Closure {
-        &self.current_frame().closure
+    /// Make `readLine` read from `input` instead of stdin
+    pub fn set_stdin(&mut self, input: Box<Input>) {
+        self.stdin = input;
     }
 
-    /// Runs the chunk and then responds with a value
-    pub fn interpret(&mut self, source: &str) -> InterpretResult {
-        let compiler = Compiler::new(FunctionType::Script);
-        let Ok(func) = compiler.compile(source) else {return InterpretResult::CompileError};
-        self.frames
-            .push(CallFrame::new(Rc::new(Closure::new(Rc::new(func))), 0, 0));
-        self.run()
+    /// Read a line for `readLine`, without the line break. `None` at the end of the input
+    pub(crate) fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.stdin.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
     }
 
-    /// Read the current byte pointed by `frame.ip` as an instruction and then advances the `self.ip`
-    fn read_byte(&mut self) -> u8 {
-        let frame = self.current_frame();
-        frame.ip += 1;
-        frame.closure.function.chunk.code[frame.ip - 1]
+    /// Stop the script when the native calling this returns, for the `exit` native. The run
+    /// returns [`LoxError::Exit`]
+    pub(crate) fn exit(&mut self, code: i32) -> NativeError {
+        self.exit_code = Some(code);
+        NativeError::new(format!("Exit with code {code}."))
     }
 
-    /// Read a two bytes operand
-    fn read_short(&mut self) -> u16 {
-        let frame = self.current_frame();
-        frame.ip += 2;
-        let last_two = frame.closure.function.chunk.code[frame.ip - 2] as u16;
-        let last_one = frame.closure.function.chunk.code[frame.ip - 1] as u16;
+    /// Make the runs reproducible, e.g. for replays and differential tests: with a seed, the natives
+    /// read a virtual clock, which starts at the Unix epoch and moves a millisecond on every read,
+    /// `sleep` moves it instead of waiting, and `random` starts from the seed. `None`, the
+    /// default, goes back to the system clock and random seeds. The limit on the running time
+    /// still uses the system clock
+    ///
+    /// ```
+    /// use rustlox::output::SharedBuffer;
+    /// use rustlox::vm::Capability;
+    /// use rustlox::VM;
+    ///
+    /// let run = || {
+    ///     let out = SharedBuffer::new();
+    ///     let mut vm = VM::new();
+    ///     vm.allow(Capability::Time);
+    ///     vm.set_deterministic(Some(42));
+    ///     vm.set_stdout(Box::new(out.clone()));
+    ///     vm.interpret("print random(); sleep(60); print clock();")?;
+    ///     Ok::<_, rustlox::LoxError>(out.contents())
+    /// };
+    /// assert_eq!(run()?, run()?);
+    /// # Ok::<(), rustlox::LoxError>(())
+    /// ```
+    pub fn set_deterministic(&mut self, seed: Option<u64>) {
+        match seed {
+            Some(seed) => {
+                self.virtual_clock = Some(Duration::ZERO);
+                self.random_state = seed;
+            }
+            None => {
+                self.virtual_clock = None;
+                self.random_state = RandomState::new().build_hasher().finish();
+            }
+        }
+    }
 
-        (last_two << 8) | last_one
+    /// The time since the Unix epoch, for the natives which read the clock, including the ones of
+    /// the host, so they follow [`VM::set_deterministic`]
+    pub fn now(&mut self) -> Result<Duration, NativeError> {
+        match self.virtual_clock.as_mut() {
+            Some(clock) => {
+                // Moving on every read, a script waiting for the clock doesn't wait forever
+                *clock += Duration::from_millis(1);
+                Ok(*clock)
+            }
+            None => natives::since_the_epoch(),
+        }
     }
 
-    /// For a two bytes byte code: `[Opcode, the index of value]`, return the corresponding value
-    fn read_constant(&mut self) -> Value {
-        let frame = self.current_frame();
-        let constant_idx = frame.closure.function.chunk.code[frame.ip];
-        frame.ip += 1;
-        frame.closure.function.chunk.constants.values[constant_idx as usize].clone()
+    /// A random number in `[0, 1)`, for the `random` native and the ones of the host. The numbers
+    /// aren't good enough for cryptography
+    pub fn random(&mut self) -> f64 {
+        // SplitMix64, see https://prng.di.unimi.it/splitmix64.c
+        self.random_state = self.random_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // The 53 high bits fill the mantissa
+        (z >> 11) as f64 / (1u64 << 53) as f64
     }
 
-    fn binary_operator(&mut self, op: char) -> InterpretResult {
-        if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-            match (a, b) {
-                (Value::Number(a), Value::Number(b)) => {
-                    let val = match op {
-                        '+' => Value::Number(a + b),
-                        '-' => Value::Number(a - b),
-                        '*' => Value::Number(a * b),
-                        '/' => Value::Number(a / b),
-                        '>' => Value::Bool(a > b),
-                        '<' => Value::Bool(a < b),
-                        _ => panic!("Impossible"),
-                    };
-                    self.stack.push(val);
-                    InterpretResult::Ok
+    /// Write `text` where `print` writes, for the `printf` native. It's flushed, so a prompt
+    /// without a newline shows up before reading the answer
+    pub(crate) fn write_output(&mut self, text: &str) -> Result<(), NativeError> {
+        self.stdout
+            .write_all(text.as_bytes())
+            .and_then(|()| self.stdout.flush())
+            .map_err(|_| NativeError::new("Could not write the output."))
+    }
+
+    /// Pause the script, for the `sleep` native. It sleeps in short steps, so an interrupt ends
+    /// it early with an error
+    pub(crate) fn sleep(&mut self, duration: Duration) -> Result<(), NativeError> {
+        const STEP: Duration = Duration::from_millis(50);
+        if let Some(clock) = self.virtual_clock.as_mut() {
+            *clock = clock.saturating_add(duration);
+            return Ok(());
+        }
+        // A browser can't block its thread
+        if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            return Err(NativeError::new("'sleep' isn't available in WebAssembly."));
+        }
+        let until = Instant::now() + duration;
+        loop {
+            if self.interrupt.take() {
+                return Err(NativeError::new("Interrupted."));
+            }
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            std::thread::sleep(left.min(STEP));
+        }
+    }
+
+    /// A handle which stops the script running in this VM, from any thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Define a module implemented in Rust, so `import "name";` defines its natives as globals.
+    /// It's imported before any file of the same name, e.g.
+    ///
+    /// ```
+    /// use rustlox::value::NativeFunction;
+    /// use rustlox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.register_module(
+    ///     "greetings",
+    ///     [NativeFunction::new("hello", 0, |_vm, _args| Ok(Value::string("hello".to_string())))],
+    /// );
+    /// assert!(vm.interpret(r#"import "greetings"; var h = hello();"#).is_ok());
+    /// assert_eq!(vm.get_global("h"), Some(Value::string("hello".to_string())));
+    /// ```
+    pub fn register_module(
+        &mut self,
+        name: &str,
+        natives: impl IntoIterator<Item = NativeFunction>,
+    ) {
+        #[allow(clippy::useless_conversion)] // not useless with the `compact-values` feature
+        let natives = natives.into_iter().map(Into::into).collect();
+        self.native_modules.insert(name.to_string(), natives);
+        // Registering it again makes the next import define the new natives
+        self.modules.remove(&Module::Native(name.to_string()));
+    }
+
+    /// Look for the imported modules in these directories, in order, when they aren't next to the
+    /// importing file. It's like `LOX_PATH` for the command line
+    pub fn set_module_path(&mut self, dirs: Vec<PathBuf>) {
+        self.module_path = dirs;
+    }
+
+    /// The file of the module imported by `import "name";`, or the files tried if it's nowhere.
+    /// A name without an extension gets `.lox`
+    fn resolve_module(&self, name: &str) -> Result<PathBuf, Vec<PathBuf>> {
+        let file = if Path::new(name).extension().is_some() {
+            PathBuf::from(name)
+        } else {
+            PathBuf::from(format!("{name}.lox"))
+        };
+        // The innermost code which knows its file, e.g. the module being imported
+        let importer_dir = self
+            .frames
+            .iter()
+            .rev()
+            .find_map(|frame| frame.closure.function.file.as_deref())
+            .and_then(|file| Path::new(file).parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let candidates: Vec<_> = std::iter::once(importer_dir)
+            .chain(self.module_path.iter().cloned())
+            .map(|dir| dir.join(&file))
+            .collect();
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(candidates),
+        }
+    }
+
+    /// The files of the scripts and modules still running, outermost first, if `path` is one of
+    /// them. The module frames are exactly the modules which are still loading
+    fn import_cycle(&self, path: &Path) -> Option<Vec<String>> {
+        let loading: Vec<_> = self
+            .frames
+            .iter()
+            .filter(|frame| frame.closure.function.name.is_empty())
+            .filter_map(|frame| frame.closure.function.file.as_deref())
+            .collect();
+        let start = loading
+            .iter()
+            .position(|file| canonical(Path::new(file)) == path)?;
+        Some(
+            loading[start..]
+                .iter()
+                .map(|file| file.to_string())
+                .collect(),
+        )
+    }
+
+    /// Start running the module as a call without arguments, unless it has run before. Either
+    /// way, a value ends up on top of the stack for the `Pop` after `Import`
+    fn import(&mut self, name: &str) -> bool {
+        if let Some(natives) = self.native_modules.get(name) {
+            if self.modules.insert(Module::Native(name.to_string())) {
+                for native in natives.clone() {
+                    let name = native.name.clone();
+                    self.set_global(&name, Value::NativeFunc(native));
                 }
-                (Value::String(a), Value::String(b)) => {
-                    self.stack.push(Value::String(format!("{a}{b}")));
-                    InterpretResult::Ok
+            }
+            self.push(Value::Nil);
+            return true;
+        }
+        if let Err(error) = self.require(Capability::Fs, "import") {
+            self.runtime_error(&error.message);
+            return false;
+        }
+        let file = match self.resolve_module(name) {
+            Ok(file) => file,
+            Err(searched) => {
+                let searched: Vec<_> = searched
+                    .iter()
+                    .map(|path| format!("    {}", path.display()))
+                    .collect();
+                self.runtime_error(&format!(
+                    "Module '{name}' not found, searched:\n{}",
+                    searched.join("\n")
+                ));
+                return false;
+            }
+        };
+        let path = canonical(&file);
+        if let Some(mut chain) = self.import_cycle(&path) {
+            chain.push(file.display().to_string());
+            self.runtime_error(&format!("Circular import: {}.", chain.join(" -> ")));
+            return false;
+        }
+        if self.modules.contains(&Module::File(path.clone())) {
+            self.push(Value::Nil);
+            return true;
+        }
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                self.runtime_error(&format!("Could not read module '{}': {e}.", path.display()));
+                return false;
+            }
+        };
+        let compiler = Compiler::new(FunctionType::Script).file(&file.to_string_lossy());
+        let Ok(function) = self.compile_with(compiler, &source) else {
+            self.runtime_error(&format!("Could not compile module '{name}'."));
+            return false;
+        };
+        if !self.has_room_for_frame(&function) {
+            self.stack_overflow();
+            return false;
+        }
+        // A runtime error in the module takes it out of the cache again, see `VM::unwind`
+        self.modules.insert(Module::File(path));
+        let closure = Rc::new(Closure::new(Rc::new(function)));
+        self.track(Object::Closure(Rc::clone(&closure)));
+        self.push(Value::Closure(Rc::clone(&closure)));
+        self.push_frame(closure, self.stack_top - 1);
+        true
+    }
+
+    /// Let the natives needing the capability run again, e.g. `getEnv` with [`Capability::Env`]
+    pub fn allow(&mut self, capability: Capability) {
+        self.capabilities.insert(capability);
+    }
+
+    /// Make the natives needing the capability fail with a runtime error. A new VM allows all of
+    /// them
+    ///
+    /// ```
+    /// use rustlox::vm::Capability;
+    /// use rustlox::VM;
+    ///
+    /// let mut vm = VM::new();
+    /// assert!(vm.interpret("getEnv(\"HOME\");").is_ok());
+    /// vm.deny(Capability::Env);
+    /// assert!(vm.interpret("getEnv(\"HOME\");").is_err());
+    /// ```
+    pub fn deny(&mut self, capability: Capability) {
+        self.capabilities.remove(&capability);
+    }
+
+    pub fn is_allowed(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Fail unless the VM allows the capability, for a native function to return, e.g.
+    ///
+    /// ```
+    /// use rustlox::vm::Capability;
+    /// use rustlox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.register_native("fetch", 1, |vm, _args| {
+    ///     vm.require(Capability::Net, "fetch")?;
+    ///     Ok(Value::Nil)
+    /// });
+    /// vm.deny(Capability::Net);
+    /// assert!(vm.interpret(r#"fetch("https://example.com");"#).is_err());
+    /// ```
+    pub fn require(&self, capability: Capability, native: &str) -> Result<(), NativeError> {
+        if self.is_allowed(capability) {
+            Ok(())
+        } else {
+            Err(NativeError::new(format!(
+                "'{native}' needs the {capability} capability, which the VM denies."
+            )))
+        }
+    }
+
+    /// Hand the script its arguments, which it reads as a list of strings with the `args` native
+    ///
+    /// ```
+    /// use rustlox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.set_args(vec!["input.txt".to_string()]);
+    /// vm.interpret("var count = len(args()); var first = args()[0];")?;
+    /// assert_eq!(vm.get_global("count"), Some(Value::Int(1)));
+    /// assert!(matches!(vm.get_global("first"), Some(Value::String(s)) if s.as_str() == "input.txt"));
+    /// # Ok::<(), rustlox::LoxError>(())
+    /// ```
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// The arguments of the script
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Print the compile errors and runtime errors to `out` instead of stderr. It replaces the
+    /// reporter of the VM
+    pub fn set_stderr(&mut self, out: Box<Output>) {
+        self.reporter = Box::new(WriterReporter::new(out));
+    }
+
+    /// Free the objects which are only kept alive by reference cycles, and return how many objects
+    /// are freed. The VM also does this on its own as more objects are created
+    pub fn collect_garbage(&mut self) -> usize {
+        self.heap.collect()
+    }
+
+    /// Collect the garbage every time an object is created. It's slow, but finds the objects
+    /// freed too early (if any) right away
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.stress = stress;
+    }
+
+    /// Run the peephole optimizer over the code compiled from now on, see [`crate::optimizer`]
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Print the bytecode of the functions compiled from now on to stdout
+    pub fn set_disassemble(&mut self, disassemble: bool) {
+        self.disassemble = disassemble;
+    }
+
+    /// Deny, report or ignore the warnings of the code compiled from now on. They are reported
+    /// like the compile errors, with [`crate::Severity::Warning`]
+    pub fn set_warnings(&mut self, warnings: Warnings) {
+        self.warnings = warnings;
+    }
+
+    /// Also warn about the locals shadowing other locals, and the globals read by the code but
+    /// defined neither by it nor by the VM, see [`crate::lint`]
+    pub fn set_lint(&mut self, lint: bool) {
+        self.lint = lint;
+    }
+
+    /// Print every instruction to stdout before executing it, along with the call depth, the
+    /// stack and the upvalues of the running function
+    pub fn set_trace_execution(&mut self, trace_execution: bool) {
+        self.trace = trace_execution.then(|| Box::new(io::stdout()) as Box<Output>);
+    }
+
+    /// Same as [`VM::set_trace_execution`], but write the trace to `out`, e.g. a file
+    pub fn set_trace_output(&mut self, out: Box<Output>) {
+        self.trace = Some(out);
+    }
+
+    /// Install the callbacks of the host, which see every instruction, call, return and runtime
+    /// error from now on, or remove them with `None`
+    pub fn set_hooks(&mut self, hooks: Option<Box<dyn VmHooks>>) {
+        self.hooks = hooks;
+    }
+
+    /// Count the executed opcodes and the calls of every function, and time the calls. Turning it
+    /// off drops the counters
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profile = profiling.then(Profile::default);
+    }
+
+    /// The counters collected since profiling was turned on
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Push the frame of a call, which is also where the profiler counts the calls
+    fn push_frame(&mut self, closure: Rc<Closure>, slots: usize) {
+        let mut frame = CallFrame::new(closure, 0, slots);
+        if let Some(profile) = self.profile.as_mut() {
+            profile.count_call(profiler::function_key(&frame.closure.function));
+            frame.started = now();
+        }
+        self.frames.push(frame);
+    }
+
+    /// Start tracking a new object, and collect the garbage if it's time to
+    fn track(&mut self, object: Object) {
+        self.allocations += 1;
+        if self
+            .limits
+            .max_allocations
+            .is_some_and(|max| self.allocations > max)
+        {
+            // Check the limits before the next instruction
+            self.batch -= self.countdown;
+            self.countdown = 0;
+        }
+        if self.heap.track(&object) {
+            self.heap.collect();
+        }
+    }
+
+    /// Choose what `/` and `%` do when the divisor is zero. By default they follow IEEE 754, and
+    /// the scripts may check the results with the `isNan` and `isFinite` natives
+    ///
+    /// ```
+    /// use rustlox::vm::DivisionByZero;
+    /// use rustlox::VM;
+    ///
+    /// let mut vm = VM::new();
+    /// assert!(vm.interpret("var a = isFinite(1 / 0);").is_ok());
+    /// vm.set_division_by_zero(DivisionByZero::Error);
+    /// assert!(vm.interpret("var b = 1 % 0;").is_err());
+    /// ```
+    pub fn set_division_by_zero(&mut self, policy: DivisionByZero) {
+        self.division_by_zero = policy;
+    }
+
+    /// Allow `max_frames` nested calls (64 by default) before reporting a stack overflow. Deep
+    /// recursion needs more. The value stack is allocated again with 256 slots per frame, and
+    /// doesn't grow while running
+    pub fn set_max_frames(&mut self, max_frames: usize) {
+        self.max_frames = max_frames.max(1);
+        let capacity = (self.max_frames * FRAME_SLOTS).max(self.stack_top);
+        let mut stack = vec![Value::Nil; capacity].into_boxed_slice();
+        for (slot, value) in stack.iter_mut().zip(&mut self.stack[..self.stack_top]) {
+            *slot = std::mem::take(value);
+        }
+        self.stack = stack;
+    }
+
+    /// Show at most `max` frames, the innermost ones, in the stack traces of the runtime errors.
+    /// `None`, the default, shows them all. Either way a run of the same call, e.g. of a deep
+    /// recursion, is collapsed after its first three frames
+    ///
+    /// ```
+    /// use rustlox::{LoxError, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.set_max_trace_frames(Some(2));
+    /// let code = "fun a() { b(); } fun b() { c(); } fun c() { nil(); } a();";
+    /// match vm.interpret(code) {
+    ///     Err(LoxError::RuntimeError { stack_trace, .. }) => {
+    ///         assert_eq!(stack_trace.len(), 3);
+    ///         assert!(stack_trace[1].starts_with("[line 1:30] in b()"));
+    ///         assert_eq!(stack_trace[2], "... 2 more frames");
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn set_max_trace_frames(&mut self, max: Option<usize>) {
+        self.max_trace_frames = max;
+    }
+
+    /// Limit the resources of the runs from now on, see [`Limits`]
+    pub fn set_limits(&mut self, limits: Limits) {
+        if let Some(max_frames) = limits.max_frames {
+            self.set_max_frames(max_frames);
+        }
+        self.limits = limits;
+    }
+
+    /// Reset the counters of the limits, when the host starts running code. The code run by a
+    /// native meanwhile is part of the same run
+    fn start_run(&mut self) {
+        if !self.frames.is_empty() {
+            return;
+        }
+        (self.countdown, self.batch, self.executed, self.allocations) = (0, 0, 0, 0);
+        // An interrupt which came while nothing was running isn't meant for this run
+        self.interrupt.take();
+        self.run_started = self.limits.max_time.and_then(|_| now());
+    }
+
+    /// Whether the run is within its limits and not interrupted, checked when the countdown runs
+    /// out. Also start the next countdown, which stops exactly at the instruction limit
+    fn check_limits(&mut self) -> bool {
+        if self.corrupt {
+            self.invalid_bytecode();
+            return false;
+        }
+        if self.interrupt.take() {
+            self.runtime_error("Interrupted.");
+            return false;
+        }
+        self.executed += u64::from(self.batch);
+        let exceeded = if let Some(max) = self
+            .limits
+            .max_instructions
+            .filter(|max| self.executed >= *max)
+        {
+            Some(Limit::Instructions(max))
+        } else if let Some(max) = self.limits.max_time.filter(|max| {
+            self.run_started
+                .is_some_and(|started| started.elapsed() > *max)
+        }) {
+            Some(Limit::Time(max))
+        } else {
+            self.limits
+                .max_allocations
+                .filter(|max| self.allocations > *max)
+                .map(Limit::Allocations)
+        };
+        if let Some(limit) = exceeded {
+            self.limit_exceeded(limit);
+            return false;
+        }
+        let mut left = self
+            .limits
+            .max_instructions
+            .map_or(u64::MAX, |max| max - self.executed);
+        if let Some(at) = self.pause_at {
+            // Past the pause, the code run by a native checks every instruction until the script
+            // gets back to pause
+            left = left.min(at.saturating_sub(self.executed).max(1));
+        }
+        self.batch = left.min(u64::from(CHECK_INTERVAL)) as u32;
+        self.countdown = self.batch;
+        true
+    }
+
+    /// Abort the run like a runtime error, which the host tells apart by the error it gets
+    fn limit_exceeded(&mut self, limit: Limit) {
+        self.runtime_error(&format!("Exceeded the limit of {limit}."));
+        self.last_error = Some(LoxError::LimitExceeded(limit));
+    }
+
+    /// Report that one more call frame doesn't fit
+    fn stack_overflow(&mut self) {
+        match self.limits.max_frames {
+            Some(max) => self.limit_exceeded(Limit::Frames(max)),
+            None => self.runtime_error("Stack overflow."),
+        }
+    }
+
+    /// Whether one more call frame running `function` fits, with all of its slots, so `push`
+    /// doesn't run out of the stack
+    fn has_room_for_frame(&self, function: &Function) -> bool {
+        self.frames.len() < self.max_frames && self.has_room(function.max_locals + FRAME_SLOTS)
+    }
+
+    /// Whether `slots` more values fit on the stack
+    fn has_room(&self, slots: usize) -> bool {
+        self.stack_top + slots <= self.stack.len()
+    }
+
+    /// The frame of the running function, if there is one
+    pub fn current_frame(&mut self) -> Option<&mut CallFrame> {
+        self.frames.last_mut()
+    }
+
+    pub fn current_closure(&mut self) -> Option<&Closure> {
+        self.current_frame().map(|frame| &*frame.closure)
+    }
+
+    /// Compile `source` and run it. The errors are also sent to the reporter, so by default they
+    /// are printed to stderr
+    pub fn interpret(&mut self, source: &str) -> Result<(), LoxError> {
+        self.run_source(Compiler::new(FunctionType::Script), source)
+    }
+
+    /// Same as [`VM::interpret`], for the source code read from the file at `path`. Its imports
+    /// are relative to the directory of the file
+    pub fn interpret_file(&mut self, path: &str, source: &str) -> Result<(), LoxError> {
+        self.run_source(Compiler::new(FunctionType::Script).file(path), source)
+    }
+
+    /// Same as [`VM::interpret`], but the expression statements at the top level print their
+    /// values, like `print` does, so typing `1 + 2` in a REPL shows `3`
+    pub fn interpret_repl(&mut self, source: &str) -> Result<(), LoxError> {
+        self.run_source(Compiler::new(FunctionType::Script).repl_mode(), source)
+    }
+
+    /// Whether the code typed in a REPL stops before it's complete, e.g. in the middle of a
+    /// function or a string, so the REPL should read more lines before running it. That's when
+    /// the first compile error is at the end of the code, or is an unterminated string or comment
+    ///
+    /// ```
+    /// use rustlox::VM;
+    ///
+    /// assert!(VM::is_incomplete("fun f() {"));
+    /// assert!(!VM::is_incomplete("1 + 2"));
+    /// // The error is there, however much more is typed
+    /// assert!(!VM::is_incomplete("print (;"));
+    /// ```
+    pub fn is_incomplete(source: &str) -> bool {
+        let Err(diagnostics) = Compiler::new(FunctionType::Script)
+            .repl_mode()
+            .compile(source)
+        else {
+            return false;
+        };
+        diagnostics.first().is_some_and(|error| {
+            (error.location == " at end" && error.message.starts_with("Expect"))
+                || error.message.starts_with("Unterminated")
+        })
+    }
+
+    /// Compile `source` without running it, e.g. to save it by [`crate::bytecode::serialize`].
+    /// The errors are sent to the reporter like [`VM::interpret`] does
+    pub fn compile(&mut self, source: &str) -> Result<Function, LoxError> {
+        self.compile_with(Compiler::new(FunctionType::Script), source)
+    }
+
+    /// Compile `source` without a VM, e.g. to validate it in an editor. The errors and the warnings
+    /// are returned instead of reported, and the script can still be run by [`VM::run_function`]
+    ///
+    /// ```
+    /// use rustlox::VM;
+    ///
+    /// assert!(VM::check("print 1 + 2;").is_ok());
+    /// let diagnostics = VM::check("print (1;").unwrap_err();
+    /// assert_eq!(diagnostics[0].message, "Expect ')' after expression.");
+    /// ```
+    pub fn check(source: &str) -> Result<CompiledScript, Vec<Diagnostic>> {
+        Compiler::new(FunctionType::Script)
+            .compile_with_warnings(source)
+            .map(|(function, warnings)| CompiledScript { function, warnings })
+    }
+
+    fn compile_with(&mut self, mut compiler: Compiler, source: &str) -> Result<Function, LoxError> {
+        if self.optimize {
+            compiler = compiler.optimized();
+        }
+        if self.disassemble {
+            compiler = compiler.disassemble();
+        }
+        if self.lint {
+            compiler = compiler.lint(self.globals.keys().cloned());
+        }
+        match compiler
+            .warnings(self.warnings)
+            .compile_with_warnings(source)
+        {
+            Ok((function, warnings)) => {
+                for warning in &warnings {
+                    self.reporter.report_diagnostic(warning);
                 }
-                _ => {
-                    self.runtime_error("Operands must be numbers.");
-                    InterpretResult::RuntimeError
+                Ok(function)
+            }
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    self.reporter.report_diagnostic(diagnostic);
                 }
+                Err(LoxError::CompileError(diagnostics))
             }
+        }
+    }
+
+    fn run_source(&mut self, compiler: Compiler, source: &str) -> Result<(), LoxError> {
+        let func = self.compile_with(compiler, source)?;
+        self.run_function(func)
+    }
+
+    /// Run a script compiled before, e.g. by [`VM::compile`] or loaded from a `.loxc` file by
+    /// [`crate::bytecode::deserialize`]
+    pub fn run_function(&mut self, func: Function) -> Result<(), LoxError> {
+        // A native function may run more code while the VM is running
+        if !self.has_room_for_frame(&func) {
+            self.stack_overflow();
+            return Err(self
+                .last_error
+                .take()
+                .expect("A runtime error was reported"));
+        }
+        self.start_run();
+        let closure = Rc::new(Closure::new(Rc::new(func)));
+        self.track(Object::Closure(Rc::clone(&closure)));
+        // The top-level script occupies slot zero like any other function
+        let (depth, stack_base) = (self.frames.len(), self.stack_top);
+        self.push_frame(Rc::clone(&closure), self.stack_top);
+        self.push(Value::Closure(closure));
+        if self.run(depth) == InterpretResult::Ok {
+            // Discard the return value of the script
+            self.pop();
+            Ok(())
         } else {
-            InterpretResult::RuntimeError
+            Err(self.unwind(depth, stack_base))
         }
     }
 
-    fn reset_stack(&mut self) {
-        self.stack.clear();
+    /// Compile `source` and get it ready to run in steps by [`VM::run_steps`], e.g. so a game runs
+    /// a bit of the script in each frame instead of blocking until it finishes. It fails if a
+    /// script is running already
+    ///
+    /// ```
+    /// use rustlox::{StepResult, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.start("var total = 0; for (var i = 0; i < 1000; i = i + 1) total = total + i;")?;
+    /// let mut frames = 1;
+    /// while let StepResult::Yielded = vm.run_steps(100) {
+    ///     frames += 1;
+    /// }
+    /// assert!(frames > 10);
+    /// assert!(vm.get_global("total").is_some());
+    /// # Ok::<(), rustlox::LoxError>(())
+    /// ```
+    pub fn start(&mut self, source: &str) -> Result<(), LoxError> {
+        if !self.frames.is_empty() {
+            return Err(LoxError::RuntimeError {
+                message: "A script is already running.".to_string(),
+                stack_trace: vec![],
+            });
+        }
+        let func = self.compile_with(Compiler::new(FunctionType::Script), source)?;
+        if !self.has_room_for_frame(&func) {
+            self.stack_overflow();
+            return Err(self
+                .last_error
+                .take()
+                .expect("A runtime error was reported"));
+        }
+        self.start_run();
+        let closure = Rc::new(Closure::new(Rc::new(func)));
+        self.track(Object::Closure(Rc::clone(&closure)));
+        self.push_frame(Rc::clone(&closure), self.stack_top);
+        self.push(Value::Closure(closure));
+        // Nothing pauses the code the host runs before the first step
+        self.pause_at = Some(u64::MAX);
+        Ok(())
     }
 
-    fn runtime_error(&mut self, msg: &str) {
-        // The VM advances past each instruction before executing it
-        eprintln!("{msg}");
-
-        // print stack trace
-        for frame in self.frames.iter().rev() {
-            let instruction = frame.ip - 1;
-            let line = frame.closure.function.chunk.lines[instruction];
-            eprintln!(
-                "[line {}] in {}",
-                line,
-                if frame.closure.function.name.is_empty() {
-                    "<script>"
-                } else {
-                    &frame.closure.function.name
+    /// Run at most `steps` instructions of the script started by [`VM::start`]. The limits apply
+    /// to the script as a whole, across the steps. The code run by a native meanwhile, e.g. a
+    /// getter or a coroutine, runs to its end before the script pauses, so a step may take a few
+    /// more instructions
+    pub fn run_steps(&mut self, steps: u64) -> StepResult {
+        if self.pause_at.is_none() {
+            return StepResult::Done;
+        }
+        // Count the instructions of the current batch, so the next one starts from the pause
+        self.executed += u64::from(self.batch - self.countdown);
+        (self.countdown, self.batch) = (0, 0);
+        self.pause_at = Some(self.executed.saturating_add(steps));
+        match self.run(0) {
+            InterpretResult::Paused => {
+                // Nothing pauses the code the host runs until the next step
+                self.pause_at = Some(u64::MAX);
+                StepResult::Yielded
+            }
+            InterpretResult::Ok => {
+                self.pause_at = None;
+                // Discard the return value of the script
+                self.pop();
+                StepResult::Done
+            }
+            InterpretResult::RuntimeError => {
+                self.pause_at = None;
+                StepResult::Error(self.unwind(0, 0))
+            }
+        }
+    }
+
+    /// Run the callbacks set by `setTimeout` and `setInterval` as they come due, waiting in
+    /// between, until none is left, like Node.js does after the script. A callback may set more
+    /// timers. A runtime error of a callback stops the loop, and the other timers stay pending
+    ///
+    /// ```
+    /// use rustlox::vm::Capability;
+    /// use rustlox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.allow(Capability::Time);
+    /// vm.set_deterministic(Some(0));
+    /// vm.interpret(
+    ///     "var ticks = 0; var id = setInterval(fun () { ticks = ticks + 1; if (ticks == 3) clearTimer(id); }, 100);",
+    /// )?;
+    /// vm.run_event_loop()?;
+    /// assert_eq!(vm.get_global("ticks"), Some(Value::Number(3.0)));
+    /// # Ok::<(), rustlox::LoxError>(())
+    /// ```
+    pub fn run_event_loop(&mut self) -> Result<(), LoxError> {
+        while let Some(due) = self.timers.next_due() {
+            let now = self.now().map_err(timer_error)?;
+            if due > now {
+                self.sleep(due - now).map_err(timer_error)?;
+            }
+            self.run_timer(due.max(now))?;
+        }
+        Ok(())
+    }
+
+    /// Run the callbacks of the timers which are due, without waiting for the others, e.g. once
+    /// per frame of a game. Return whether any timer is still pending
+    pub fn run_due_timers(&mut self) -> Result<bool, LoxError> {
+        let now = self.now().map_err(timer_error)?;
+        while self.timers.next_due().is_some_and(|due| due <= now) {
+            self.run_timer(now)?;
+        }
+        Ok(!self.timers.is_empty())
+    }
+
+    /// Run the callback of the next timer, which is due at `now`
+    fn run_timer(&mut self, now: Duration) -> Result<(), LoxError> {
+        if let Some(callback) = self.timers.take_next(now) {
+            self.call_value(&callback, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Set a timer calling `callback` after `delay`, and every `delay` afterwards for an
+    /// interval, for `setTimeout` and `setInterval`. Return its id
+    pub(crate) fn set_timer(
+        &mut self,
+        callback: Value,
+        delay: Duration,
+        repeat: bool,
+    ) -> Result<u64, NativeError> {
+        let due = self.now()? + delay;
+        Ok(self.timers.add(callback, due, repeat.then_some(delay)))
+    }
+
+    /// Cancel a timer, for `clearTimer`. Return whether it was pending
+    pub(crate) fn clear_timer(&mut self, id: u64) -> bool {
+        self.timers.clear(id)
+    }
+
+    /// Call a global function defined by the code run before, e.g. a `fun handle(x)` loaded by
+    /// [`VM::interpret`], and return its result. Any callable global works, including classes and
+    /// native functions
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Value, LoxError> {
+        let Some(callee) = self.get_global(name) else {
+            return Err(LoxError::RuntimeError {
+                message: format!("Undefined variable '{name}'."),
+                stack_trace: vec![],
+            });
+        };
+        self.call_value(&callee, args)
+    }
+
+    /// Call a function, a class or a native with the arguments and return its result, e.g. a
+    /// callback the script handed to a native of the host. A native calls back into Lox with it
+    /// while the script runs: the error of the callee has then already aborted the script, and the
+    /// native passes it on, e.g. by `?`, rather than going on
+    ///
+    /// ```
+    /// use rustlox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.register_native("twice", 2, |vm, args| {
+    ///     let once = vm.call_value(&args[0], &args[1..])?;
+    ///     Ok(vm.call_value(&args[0], &[once])?)
+    /// });
+    /// vm.interpret("var n = twice(fun (x) { return x * 3; }, 2);")?;
+    /// assert_eq!(vm.get_global("n"), Some(Value::Number(18.0)));
+    /// # Ok::<(), rustlox::LoxError>(())
+    /// ```
+    pub fn call_value(&mut self, callee: &Value, args: &[Value]) -> Result<Value, LoxError> {
+        let error = |message: &str| LoxError::RuntimeError {
+            message: message.to_string(),
+            stack_trace: vec![],
+        };
+        if args.len() > u8::MAX as usize {
+            return Err(error("Can't have more than 255 arguments."));
+        }
+        if self.stack_top + args.len() + 1 > self.stack.len() {
+            return Err(error("Stack overflow."));
+        }
+        // Every native calling back runs the callee on top of its own run, on the Rust stack
+        if self.native_depth >= MAX_NATIVE_DEPTH {
+            return Err(error("Too many natives calling back into Lox."));
+        }
+
+        self.start_run();
+        let (depth, stack_base) = (self.frames.len(), self.stack_top);
+        self.push(callee.clone());
+        for arg in args {
+            self.push(arg.clone());
+        }
+        // Natives and classes without an initializer are done without pushing a frame
+        let ok = self.call_callee(args.len() as u8)
+            && (self.frames.len() == depth || self.run(depth) == InterpretResult::Ok);
+        if ok {
+            Ok(self.pop())
+        } else if self.native_depth > 0 {
+            // The native returns the error, and the run which called it unwinds the frames
+            Err(self
+                .last_error
+                .clone()
+                .expect("A runtime error was reported"))
+        } else {
+            Err(self.unwind(depth, stack_base))
+        }
+    }
+
+    /// Read the current byte pointed by `frame.ip` as an instruction and then advances the `self.ip`
+    fn read_byte(&mut self) -> u8 {
+        let [byte] = self.read_bytes();
+        byte
+    }
+
+    /// Read a two bytes operand
+    fn read_short(&mut self) -> u16 {
+        u16::from_be_bytes(self.read_bytes())
+    }
+
+    /// Read a four bytes operand of the wide instructions
+    fn read_long(&mut self) -> u32 {
+        u32::from_be_bytes(self.read_bytes())
+    }
+
+    /// Read the next `N` bytes of the code, or zeros past its end
+    #[inline(always)]
+    fn read_bytes<const N: usize>(&mut self) -> [u8; N] {
+        let Some(frame) = self.frames.last_mut() else {
+            self.corrupt_bytecode();
+            return [0; N];
+        };
+        frame.ip += N;
+        let bytes = frame
+            .closure
+            .function
+            .chunk
+            .code
+            .get(frame.ip - N..frame.ip)
+            .and_then(|bytes| bytes.try_into().ok());
+        bytes.unwrap_or_else(|| {
+            self.corrupt_bytecode();
+            [0; N]
+        })
+    }
+
+    /// For a two bytes byte code: `[Opcode, the index of value]`, return the corresponding value
+    fn read_constant(&mut self) -> Value {
+        let constant_idx = self.read_byte() as usize;
+        self.constant(constant_idx)
+    }
+
+    /// Read the two bytes index of a wide instruction, and return that constant
+    fn read_constant_long(&mut self) -> Value {
+        let constant_idx = self.read_short() as usize;
+        self.constant(constant_idx)
+    }
+
+    /// The constant at the index in the chunk of the running function
+    fn constant(&mut self, constant_idx: usize) -> Value {
+        let constant = self.frames.last().and_then(|frame| {
+            frame
+                .closure
+                .function
+                .chunk
+                .constants
+                .values
+                .get(constant_idx)
+        });
+        match constant {
+            Some(constant) => constant.clone(),
+            None => {
+                self.corrupt_bytecode();
+                Value::Nil
+            }
+        }
+    }
+
+    /// Read the constant of the instruction, whose index takes two bytes after the wide variants
+    fn read_constant_of(&mut self, instruction: OpCode) -> Value {
+        if instruction.has_wide_constant() {
+            self.read_constant_long()
+        } else {
+            self.read_constant()
+        }
+    }
+
+    /// Read a constant which is a name, e.g. of a global variable. The strings are shared, so
+    /// this doesn't copy the name. It's `None` if the constant isn't a string, which the compiler
+    /// never emits
+    fn read_name(&mut self, instruction: OpCode) -> Option<LoxString> {
+        match self.read_constant_of(instruction) {
+            Value::String(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Report an instruction whose operands aren't the ones the compiler emits for it, e.g. in a
+    /// `.loxc` file which was changed by hand
+    fn invalid_bytecode(&mut self) -> InterpretResult {
+        self.corrupt = false;
+        self.runtime_error("Invalid bytecode.");
+        InterpretResult::RuntimeError
+    }
+
+    /// Note that the code read past its code, its constants or the stack. The instruction goes
+    /// on with zeros or `nil` instead, and the error is reported before the next one starts,
+    /// where the limits are checked, so the dispatch loop doesn't check anything more
+    fn corrupt_bytecode(&mut self) {
+        self.corrupt = true;
+        self.batch -= self.countdown;
+        self.countdown = 0;
+    }
+
+    /// Push a value on top of the stack. The stack never grows, `call` makes sure there is
+    /// enough room for every new call frame
+    fn push(&mut self, value: Value) {
+        match self.stack.get_mut(self.stack_top) {
+            Some(slot) => {
+                *slot = value;
+                self.stack_top += 1;
+            }
+            // More than the locals of the function and the room for the temporaries
+            None => self.corrupt_bytecode(),
+        }
+    }
+
+    /// Pop the top value, the slot is reset so that it doesn't keep the value alive
+    fn pop(&mut self) -> Value {
+        let Some(top) = self.stack_top.checked_sub(1) else {
+            self.corrupt_bytecode();
+            return Value::Nil;
+        };
+        self.stack_top = top;
+        std::mem::take(&mut self.stack[top])
+    }
+
+    /// Return the value `distance` slots down from the top of the stack, `nil` below the bottom.
+    /// The instructions whose operands tell the distance check it themselves
+    fn peek(&self, distance: usize) -> &Value {
+        self.stack_top
+            .checked_sub(1 + distance)
+            .map_or(&NIL, |idx| &self.stack[idx])
+    }
+
+    /// Whether `count` values are on the stack, or else note the bytecode is malformed
+    fn has_values(&mut self, count: usize) -> bool {
+        if count > self.stack_top {
+            self.corrupt_bytecode();
+            return false;
+        }
+        true
+    }
+
+    /// The stack slot of a local variable of the running function, if it's within the stack
+    fn local_slot(&mut self, index: usize) -> Option<usize> {
+        let slot = self
+            .frames
+            .last()
+            .map(|frame| frame.slots + index)
+            .filter(|slot| *slot < self.stack.len());
+        if slot.is_none() {
+            self.corrupt_bytecode();
+        }
+        slot
+    }
+
+    /// An upvalue of the running closure
+    fn upvalue(&mut self, index: usize) -> Option<Rc<ObjUpvalue>> {
+        let upvalue = self
+            .frames
+            .last()
+            .and_then(|frame| frame.closure.upvalues.get(index).cloned());
+        if upvalue.is_none() {
+            self.corrupt_bytecode();
+        }
+        upvalue
+    }
+
+    /// Move the running function forward by `offset` bytes
+    fn jump(&mut self, offset: usize) {
+        match self.frames.last_mut() {
+            Some(frame) => frame.ip += offset,
+            None => self.corrupt_bytecode(),
+        }
+    }
+
+    /// Move the running function back by `offset` bytes, for the loops
+    fn jump_back(&mut self, offset: usize) {
+        let Some(frame) = self.frames.last_mut() else {
+            return self.corrupt_bytecode();
+        };
+        match frame.ip.checked_sub(offset) {
+            Some(ip) => frame.ip = ip,
+            None => self.corrupt_bytecode(),
+        }
+    }
+
+    /// Discard all the values above `stack_top`
+    fn truncate(&mut self, stack_top: usize) {
+        if let Some(slots) = self.stack.get_mut(stack_top..self.stack_top) {
+            slots.fill(Value::Nil);
+        }
+        self.stack_top = stack_top;
+    }
+
+    /// The error of an operator applied to the wrong types of operands, e.g. `"a" - 1`. The
+    /// comparisons don't name the operator, as `>=` and `<=` run the opposite one
+    fn operands_error(&mut self, op: &str, a: &Value, b: &Value) {
+        let expected = if op == "+" {
+            "two numbers or two strings"
+        } else {
+            "numbers"
+        };
+        let operator = match op {
+            ">" | "<" => "the comparison".to_string(),
+            _ => format!("'{op}'"),
+        };
+        self.runtime_error(&format!(
+            "Operands must be {expected}, got {} and {} for {operator}.",
+            a.type_name(),
+            b.type_name()
+        ));
+    }
+
+    fn binary_operator(&mut self, op: &str) -> InterpretResult {
+        // An instance on the left may overload the operator, the method gets the right operand
+        if let Some(method) = operator_method(op).and_then(|name| self.peek(1).method(name)) {
+            return if self.call(method, 1) {
+                InterpretResult::Ok
+            } else {
+                InterpretResult::RuntimeError
+            };
+        }
+        let b = self.pop();
+        let a = self.pop();
+        if let (Value::Int(a), Value::Int(b)) = (&a, &b) {
+            if let Some(val) = int_operator(op, *a, *b) {
+                self.push(val);
+                return InterpretResult::Ok;
+            }
+        }
+        // The integers mixed with the floats, or whose result isn't an integer, are floats
+        if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+            if matches!(op, "/" | "%") && b == 0.0 && self.division_by_zero == DivisionByZero::Error
+            {
+                self.runtime_error(&format!("Division by zero for '{op}'."));
+                return InterpretResult::RuntimeError;
+            }
+            let val = match op {
+                "+" => Value::Number(a + b),
+                "-" => Value::Number(a - b),
+                "*" => Value::Number(a * b),
+                "/" => Value::Number(a / b),
+                // The result has the same sign as the dividend, like C's `fmod`
+                "%" => Value::Number(a % b),
+                "**" => Value::Number(a.powf(b)),
+                ">" => Value::Bool(a > b),
+                "<" => Value::Bool(a < b),
+                _ => unreachable!("Not an arithmetic operator: {op}"),
+            };
+            self.push(val);
+            return InterpretResult::Ok;
+        }
+        match (a, b) {
+            (Value::String(a), Value::String(b)) if op == "+" => {
+                self.push(Value::string(format!("{a}{b}")));
+                InterpretResult::Ok
+            }
+            (a, b) => {
+                self.operands_error(op, &a, &b);
+                InterpretResult::RuntimeError
+            }
+        }
+    }
+
+    /// The fast path of the arithmetic and the comparisons of two numbers, which are most of them:
+    /// the result takes the slot of the left operand, without looking for an operator method or
+    /// moving the values. Return false to leave the other operands, and the operators which may
+    /// fail, to [`VM::binary_operator`]
+    fn number_operator(&mut self, instruction: OpCode) -> bool {
+        let Some(left) = self.stack_top.checked_sub(2) else {
+            return false;
+        };
+        let result = match (&self.stack[left], &self.stack[left + 1]) {
+            // An overflow leaves the promotion to a float to the slow path
+            (Value::Int(a), Value::Int(b)) => match instruction {
+                OpCode::Add => a.checked_add(*b).map(Value::Int),
+                OpCode::Substract => a.checked_sub(*b).map(Value::Int),
+                OpCode::Multiply => a.checked_mul(*b).map(Value::Int),
+                OpCode::Greater => Some(Value::Bool(a > b)),
+                OpCode::Less => Some(Value::Bool(a < b)),
+                _ => None,
+            },
+            (a, b) => match (a.as_number(), b.as_number()) {
+                (Some(a), Some(b)) => match instruction {
+                    OpCode::Add => Some(Value::Number(a + b)),
+                    OpCode::Substract => Some(Value::Number(a - b)),
+                    OpCode::Multiply => Some(Value::Number(a * b)),
+                    OpCode::Greater => Some(Value::Bool(a > b)),
+                    OpCode::Less => Some(Value::Bool(a < b)),
+                    _ => None,
+                },
+                _ => None,
+            },
+        };
+        let Some(result) = result else {
+            return false;
+        };
+        self.stack[left] = result;
+        self.stack_top = left + 1;
+        true
+    }
+
+    fn bitwise_operator(&mut self, op: &str) -> InterpretResult {
+        let b = self.pop();
+        let a = self.pop();
+        let (Some(a), Some(b)) = (integer_of(&a), integer_of(&b)) else {
+            self.operands_error(op, &a, &b);
+            return InterpretResult::RuntimeError;
+        };
+        let val = match op {
+            "&" => a & b,
+            "|" => a | b,
+            "^" => a ^ b,
+            // Only the low 6 bits of the shift count are used, like JavaScript does with 5 bits
+            "<<" => a.wrapping_shl(b as u32),
+            ">>" => a.wrapping_shr(b as u32),
+            _ => unreachable!("Not a bitwise operator: {op}"),
+        };
+        self.push(Value::Int(val));
+        InterpretResult::Ok
+    }
+
+    /// Discard the frames and the values of the code aborted by a runtime error, and return the
+    /// error. Only the code run by the innermost `interpret` or `call_function` is aborted, as a
+    /// native function may run more code while the VM is running. The globals stay as the aborted
+    /// code left them, so e.g. the next line typed in a REPL sees the ones defined before the error
+    fn unwind(&mut self, depth: usize, stack_base: usize) -> LoxError {
+        // The closures which outlive the aborted code must not point to the discarded slots
+        self.close_upvalues(stack_base);
+        self.truncate(stack_base);
+        // The modules aborted halfway aren't imported, so importing them again runs them again
+        for frame in self.frames.drain(depth.min(self.frames.len())..) {
+            let function = &frame.closure.function;
+            if let (true, Some(file)) = (function.name.is_empty(), function.file.as_deref()) {
+                self.modules
+                    .remove(&Module::File(canonical(Path::new(file))));
+            }
+        }
+        self.last_error
+            .take()
+            .expect("A runtime error was reported")
+    }
+
+    fn runtime_error(&mut self, msg: &str) {
+        self.runtime_error_with_notes(msg, vec![]);
+    }
+
+    /// Same as [`VM::runtime_error`], with hints for the reporter, e.g. `did you mean 'counter'?`
+    fn runtime_error_with_notes(&mut self, msg: &str, notes: Vec<String>) {
+        let stack_trace = self.stack_trace();
+        let mut diagnostic = match self.frames.last() {
+            Some(frame) => {
+                Diagnostic::at_instruction(&frame.closure.function, frame.ip.saturating_sub(1), msg)
+            }
+            None => Diagnostic {
+                message: msg.to_string(),
+                ..Default::default()
+            },
+        };
+        diagnostic.notes = notes;
+        self.reporter
+            .report_runtime_diagnostic(&diagnostic, &stack_trace);
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_error(msg, &stack_trace);
+        }
+        self.last_error = Some(LoxError::RuntimeError {
+            message: msg.to_string(),
+            stack_trace,
+        });
+    }
+
+    /// The stack trace of a runtime error, from the innermost frame. Each frame shows where it is
+    /// and the values of the parameters of its function. A run of frames at the same
+    /// instruction of the same function shows its first three frames and a count of the rest, and
+    /// the frames past `max_trace_frames` are only counted
+    fn stack_trace(&self) -> Vec<String> {
+        let limit = self.max_trace_frames.unwrap_or(usize::MAX);
+        let frames: Vec<_> = self.frames.iter().rev().collect();
+        let mut stack_trace = vec![];
+        let mut shown = 0;
+        let mut idx = 0;
+        while idx < frames.len() {
+            if shown == limit {
+                stack_trace.push(format!("... {} more frames", frames.len() - idx));
+                break;
+            }
+            let first = frames[idx];
+            let run = frames[idx..]
+                .iter()
+                .take_while(|frame| {
+                    Rc::ptr_eq(&frame.closure.function, &first.closure.function)
+                        && frame.ip == first.ip
+                })
+                .count();
+            let show = run.min(REPEATED_FRAMES).min(limit - shown);
+            for frame in &frames[idx..idx + show] {
+                stack_trace.push(self.trace_entry(frame));
+            }
+            shown += show;
+            if show == REPEATED_FRAMES && run > show {
+                stack_trace.push(format!("... {} more frames like this", run - show));
+                idx += run;
+            } else {
+                idx += show;
+            }
+        }
+        stack_trace
+    }
+
+    /// The stack trace entry of a frame: where it is, e.g. `[main.lox:3:12] in add(1, "a")`, with
+    /// the line of code under it when there's source code. The parameters are shown as they are
+    /// now, so a parameter assigned in the body shows its new value
+    fn trace_entry(&self, frame: &CallFrame) -> String {
+        // The VM advances past each instruction before executing it
+        let function = &frame.closure.function;
+        let offset = frame.ip.saturating_sub(1);
+        let (line, column) = function.chunk.location(offset);
+        let position = position(function.file.as_deref(), line, column);
+        let mut entry = if function.name.is_empty() {
+            format!("[{position}] in <script>")
+        } else {
+            let arguments: Vec<_> = (1..=function.arity)
+                .map(|slot| trace_argument(self.stack.get(frame.slots + slot).unwrap_or(&NIL)))
+                .collect();
+            format!(
+                "[{position}] in {}({})",
+                function.name,
+                arguments.join(", ")
+            )
+        };
+        if let Some(source_line) = function.source_line(offset) {
+            entry.push('\n');
+            entry.push_str(&snippet(
+                source_line,
+                column,
+                function.chunk.span(offset).len,
+            ));
+        }
+        entry
+    }
+
+    /// Only `Nil` and `false` is falsey, everything else is `true`
+    fn is_falsey(&self, value: &Value) -> bool {
+        matches!(value, Value::Nil | Value::Bool(false))
+    }
+
+    /// Continue the coroutine from where it yielded, or start it, until it yields or returns. The
+    /// `yield` it's suspended at evaluates to `value`. A runtime error in the coroutine is
+    /// reported as it is, and ends it
+    pub(crate) fn resume(
+        &mut self,
+        coroutine: &Rc<Coroutine>,
+        value: Value,
+    ) -> Result<Value, NativeError> {
+        let mut state = coroutine.state.borrow_mut();
+        match state.status {
+            CoroutineStatus::Running => {
+                return Err(NativeError::new("Can't resume a running coroutine."))
+            }
+            CoroutineStatus::Done => {
+                return Err(NativeError::new("Can't resume a finished coroutine."))
+            }
+            CoroutineStatus::New | CoroutineStatus::Suspended => {}
+        }
+        let innermost = state.frames.last().expect("A coroutine has frames");
+        let capacity = self.stack_top
+            + state.stack.len()
+            + innermost.closure.function.max_locals
+            + FRAME_SLOTS;
+        if self.frames.len() + state.frames.len() > self.max_frames || capacity > self.stack.len() {
+            return Err(NativeError::new("Stack overflow."));
+        }
+
+        let (base, depth) = (self.stack_top, self.frames.len());
+        for value in state.stack.drain(..) {
+            self.push(value);
+        }
+        for upvalue in state.upvalues.drain(..) {
+            let location = base + upvalue.location.get();
+            upvalue.location.set(location);
+            self.stack[location] = upvalue.closed.take().expect("Closed while suspended");
+            self.open_upvalues.push(upvalue);
+        }
+        for mut frame in state.frames.drain(..) {
+            frame.slots += base;
+            self.frames.push(frame);
+        }
+        if state.status == CoroutineStatus::Suspended {
+            // The result of the `yield`
+            self.push(value);
+        }
+        state.status = CoroutineStatus::Running;
+        drop(state);
+
+        self.coroutines.push((Rc::clone(coroutine), depth));
+        let result = self.run(depth);
+        self.coroutines.pop();
+        let mut state = coroutine.state.borrow_mut();
+        if result == InterpretResult::RuntimeError {
+            // The error unwinds the frames of the coroutine along with the others
+            state.status = CoroutineStatus::Done;
+            return Err(NativeError::new("The coroutine aborted."));
+        }
+        // Returning rather than yielding ends it
+        if state.status == CoroutineStatus::Running {
+            state.status = CoroutineStatus::Done;
+        }
+        Ok(self.pop())
+    }
+
+    /// Move the frames and the values of the innermost coroutine into it, at a `yield`
+    fn suspend(&mut self) {
+        let (coroutine, depth) = self.coroutines.last().cloned().expect("Checked");
+        let base = self.frames[depth].slots;
+        let mut state = coroutine.state.borrow_mut();
+        // The closures created by the coroutine may still use its variables meanwhile
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let location = upvalue.location.get();
+            if location < base {
+                return true;
+            }
+            *upvalue.closed.borrow_mut() = Some(stack[location].clone());
+            upvalue.location.set(location - base);
+            state.upvalues.push(Rc::clone(upvalue));
+            false
+        });
+        state.stack = self.stack[base..self.stack_top].to_vec();
+        self.truncate(base);
+        state.frames = self.frames.split_off(depth);
+        for frame in &mut state.frames {
+            frame.slots -= base;
+        }
+        state.status = CoroutineStatus::Suspended;
+    }
+
+    /// Report a call with the wrong number of arguments, for the Lox functions and the natives
+    /// alike
+    fn arity_error(&mut self, min_arity: usize, arity: usize, variadic: bool, arg_cnt: usize) {
+        let expected = if variadic {
+            format!("at least {min_arity}")
+        } else if min_arity == arity {
+            arity.to_string()
+        } else {
+            format!("{min_arity} to {arity}")
+        };
+        self.runtime_error(&format!("Expected {expected} arguments but got {arg_cnt}."));
+    }
+
+    /// Create a new CallFrame and push it to `self.frames`
+    /// Turn the arguments on top of the stack into exactly one value per parameter: the
+    /// parameters left out start as nil until their default values are computed, and the extra
+    /// arguments of a variadic function are packed into the list of its rest parameter. Return
+    /// the number of parameters which got an argument
+    fn bind_arguments(&mut self, function: &Function, arg_cnt: usize) -> Option<usize> {
+        // The usual call, which has nothing to do
+        if arg_cnt == function.arity && !function.is_variadic {
+            return Some(arg_cnt);
+        }
+        let (min_arity, arity) = (function.min_arity, function.arity);
+        let fixed = arity - usize::from(function.is_variadic);
+        if arg_cnt < min_arity || (!function.is_variadic && arg_cnt > arity) {
+            self.arity_error(min_arity, arity, function.is_variadic, arg_cnt);
+            return None;
+        }
+        let rest = if function.is_variadic && arg_cnt > fixed {
+            let start = self.stack_top - (arg_cnt - fixed);
+            let rest = self.stack[start..self.stack_top].to_vec();
+            self.truncate(start);
+            rest
+        } else {
+            vec![]
+        };
+        if !self.has_room(arity) {
+            self.stack_overflow();
+            return None;
+        }
+        for _ in arg_cnt.min(fixed)..fixed {
+            self.push(Value::Nil);
+        }
+        if function.is_variadic {
+            let rest = Rc::new(RefCell::new(rest));
+            self.track(Object::List(Rc::clone(&rest)));
+            self.push(Value::List(rest));
+        }
+        Some(arg_cnt.min(fixed))
+    }
+
+    /// Pop the list of the arguments packed by a spread, and push them one by one instead
+    fn unpack_arguments(&mut self) -> Option<u8> {
+        let Value::List(list) = self.pop() else {
+            self.invalid_bytecode();
+            return None;
+        };
+        let arguments = list.borrow();
+        let Ok(arg_cnt) = u8::try_from(arguments.len()) else {
+            drop(arguments);
+            self.runtime_error("Can't have more than 255 arguments.");
+            return None;
+        };
+        if !self.has_room(arguments.len()) {
+            drop(arguments);
+            self.stack_overflow();
+            return None;
+        }
+        for argument in arguments.iter() {
+            self.stack[self.stack_top] = argument.clone();
+            self.stack_top += 1;
+        }
+        Some(arg_cnt)
+    }
+
+    /// Where `index` points in a list of `len` elements, or report why it doesn't
+    fn list_index(&mut self, index: &Value, len: usize) -> Option<usize> {
+        let Some(idx) = index.as_number() else {
+            self.runtime_error(&format!(
+                "List index must be a number, got {}.",
+                index.type_name()
+            ));
+            return None;
+        };
+        if idx.fract() != 0.0 || idx < 0.0 || idx >= len as f64 {
+            self.runtime_error(&format!(
+                "List index {index} is out of bounds for a list of length {len}."
+            ));
+            return None;
+        }
+        Some(idx as usize)
+    }
+
+    /// Where the range of the list or the string `target` starts and ends, or report why it
+    /// doesn't fit in it
+    fn range_bounds(&mut self, target: &Value, range: &Range) -> Option<(usize, usize)> {
+        self.slice_bounds(
+            target,
+            range.start,
+            range.exclusive_end(),
+            &format!("Range {range}"),
+        )
+    }
+
+    /// Check that `target` is a list or a string, and that `start` and `end` are within it in
+    /// order. `shown` is how the error messages show the bounds
+    fn slice_bounds(
+        &mut self,
+        target: &Value,
+        start: f64,
+        end: f64,
+        shown: &str,
+    ) -> Option<(usize, usize)> {
+        let len = match target {
+            Value::List(list) => list.borrow().len(),
+            Value::String(s) => s.chars().count(),
+            _ => {
+                let got = target.type_name();
+                self.runtime_error(&format!("Can only slice lists and strings, got {got}."));
+                return None;
+            }
+        };
+        if start < 0.0 || start > end || end > len as f64 {
+            self.runtime_error(&format!(
+                "{shown} is out of bounds for a {} of length {len}.",
+                target.type_name()
+            ));
+            return None;
+        }
+        Some((start as usize, end as usize))
+    }
+
+    /// A new list or string of the elements or the characters of `target` from `start` up to
+    /// `end`, which are within it
+    fn slice(&mut self, target: &Value, start: usize, end: usize) -> Value {
+        match target {
+            Value::List(list) => {
+                let slice = Rc::new(RefCell::new(list.borrow()[start..end].to_vec()));
+                self.track(Object::List(Rc::clone(&slice)));
+                Value::List(slice)
+            }
+            Value::String(s) => Value::string(s.chars().skip(start).take(end - start).collect()),
+            _ => unreachable!("Checked by `range_bounds`"),
+        }
+    }
+
+    /// Create a new CallFrame and push it to `self.frames`
+    fn call(&mut self, closure: Rc<Closure>, arg_cnt: u8) -> bool {
+        let Some(given) = self.bind_arguments(&closure.function, arg_cnt as usize) else {
+            return false;
+        };
+        let arity = closure.function.arity;
+        if closure.function.is_generator {
+            // The callee and the arguments move to the new coroutine, which replaces them
+            let start = self.stack_top - arity - 1;
+            let stack = self.stack[start..self.stack_top].to_vec();
+            self.truncate(start);
+            let name = closure.function.name.clone();
+            let mut frame = CallFrame::new(closure, 0, 0);
+            frame.arg_cnt = given;
+            let coroutine = Rc::new(Coroutine::new(name, frame, stack));
+            self.track(Object::Coroutine(Rc::clone(&coroutine)));
+            self.push(Value::Coroutine(coroutine));
+            return true;
+        }
+        if !self.has_room_for_frame(&closure.function) {
+            self.stack_overflow();
+            return false;
+        }
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_call(
+                &closure.function.name,
+                &self.stack[self.stack_top - arity..self.stack_top],
+            );
+        }
+        // the starts slots includes the function itself (or the receiver of a method) in slot zero
+        self.push_frame(closure, self.stack_top - arity - 1);
+        if let Some(frame) = self.frames.last_mut() {
+            frame.arg_cnt = given;
+        }
+
+        true
+    }
+
+    /// Call a function implemented in Rust with the arguments on top of the stack, and replace
+    /// the callee and the arguments with its result. A built-in method also gets the receiver,
+    /// which is in the slot of the callee
+    fn call_native(&mut self, native: &NativeFunction, arg_cnt: u8, with_receiver: bool) -> bool {
+        let (min_arity, arity) = (native.min_arity, native.arity);
+        if (arg_cnt as usize) < min_arity || (!native.variadic && arg_cnt as usize > arity) {
+            self.arity_error(min_arity, arity, native.variadic, arg_cnt.into());
+            return false;
+        }
+        let callee_slot = self.stack_top - arg_cnt as usize - 1;
+        let arg_start = callee_slot + usize::from(!with_receiver);
+        // The native function may use the stack, so hand it a copy of the arguments
+        let args = self.stack[arg_start..self.stack_top].to_vec();
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_call(&native.name, &args);
+        }
+        let started = self.profile.is_some().then(now).flatten();
+        self.native_depth += 1;
+        let result = (native.function)(self, &args);
+        self.native_depth -= 1;
+        if let (Some(hooks), Ok(result)) = (self.hooks.as_mut(), &result) {
+            hooks.on_return(&native.name, result);
+        }
+        if let (Some(profile), Some(started)) = (self.profile.as_mut(), started) {
+            let name = format!("{} (native)", native.name);
+            let elapsed = started.elapsed();
+            profile.count_call(name.clone());
+            profile.add_time(name, elapsed);
+            if let Some(caller) = self.frames.last_mut() {
+                caller.callee_time += elapsed;
+            }
+        }
+        match result {
+            Ok(result) => {
+                self.truncate(callee_slot);
+                self.push(result);
+                true
+            }
+            Err(_) if self.exit_code.is_some() => {
+                let code = self.exit_code.take().expect("Checked");
+                self.last_error = Some(LoxError::Exit(code));
+                false
+            }
+            // The code run by the native, e.g. a resumed coroutine, has already reported its
+            // error
+            Err(_) if self.last_error.is_some() => false,
+            Err(error) => {
+                self.runtime_error(&error.message);
+                false
+            }
+        }
+    }
+
+    fn call_callee(&mut self, arg_cnt: u8) -> bool {
+        // todo: can we avoid the cloning overhead?
+        //       how to solve the ownership issue?
+        let callee = self.peek(arg_cnt as usize).clone();
+        match callee {
+            Value::NativeFunc(native) => self.call_native(&native, arg_cnt, false),
+            Value::Closure(closure) => self.call(closure, arg_cnt),
+            Value::Class(class) => {
+                // Replace the class being called with the new instance, s.t. the initializer
+                // finds it in slot zero as `this`
+                let initializer = class.methods.borrow().get("init").cloned();
+                let instance = Rc::new(RefCell::new(Instance::new(class)));
+                self.track(Object::Instance(Rc::clone(&instance)));
+                self.stack[self.stack_top - 1 - arg_cnt as usize] = Value::Instance(instance);
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_cnt),
+                    None if arg_cnt != 0 => {
+                        self.runtime_error(&format!("Expected 0 arguments but got {arg_cnt}."));
+                        false
+                    }
+                    None => true,
+                }
+            }
+            Value::BoundMethod(bound) => {
+                self.stack[self.stack_top - 1 - arg_cnt as usize] = bound.receiver.clone();
+                self.call(Rc::clone(&bound.method), arg_cnt)
+            }
+            _ => {
+                self.runtime_error("Can only call functions and classes.");
+                false
+            }
+        }
+    }
+
+    /// The built-in method `name` of the type of a string, a number, etc.
+    fn builtin_method(&self, receiver: &Value, name: &str) -> Option<NativeFunction> {
+        self.builtin_methods
+            .get(receiver.type_name())?
+            .get(name)
+            .cloned()
+    }
+
+    /// The error of reading a global which isn't defined, with the closest global as a hint
+    fn undefined_variable(&mut self, name: &str) {
+        let notes = did_you_mean(name, self.globals.keys().map(String::as_str));
+        self.runtime_error_with_notes(
+            &format!("Undefined variable '{name}'"),
+            notes.into_iter().collect(),
+        );
+    }
+
+    /// The error of reading a property the instance doesn't have, with the closest of its fields,
+    /// getters and methods as a hint
+    fn undefined_property(&mut self, instance: &RefCell<Instance>, name: &str) {
+        let notes = {
+            let instance = instance.borrow();
+            let class = &instance.class;
+            let (getters, methods) = (class.getters.borrow(), class.methods.borrow());
+            let names = instance
+                .fields
+                .keys()
+                .chain(getters.keys())
+                .chain(methods.keys());
+            did_you_mean(name, names.map(String::as_str))
+        };
+        self.runtime_error_with_notes(
+            &format!("Undefined property '{name}'."),
+            notes.into_iter().collect(),
+        );
+    }
+
+    /// The error of looking up a method `name` the receiver below the arguments doesn't have
+    fn method_error(&mut self, name: &str, arg_cnt: usize) {
+        let type_name = self.peek(arg_cnt).type_name();
+        if self.builtin_methods.contains_key(type_name) {
+            self.runtime_error(&format!("Undefined method '{name}' of {type_name}."));
+        } else {
+            self.runtime_error("Only instances and classes have methods.");
+        }
+    }
+
+    /// Call the method `name` of the receiver below the arguments, the way `GetProperty` and
+    /// `Call` would, but without creating a bound method in between
+    fn invoke(&mut self, name: &str, arg_cnt: u8) -> bool {
+        if let Value::Class(class) = self.peek(arg_cnt as usize) {
+            // Static methods have no receiver, the class in slot zero is never read
+            let Some(value) = class.statics.borrow().get(name).cloned() else {
+                self.runtime_error(&format!("Undefined property '{name}'."));
+                return false;
+            };
+            self.stack[self.stack_top - 1 - arg_cnt as usize] = value;
+            return self.call_callee(arg_cnt);
+        }
+        let receiver = self.peek(arg_cnt as usize);
+        let Value::Instance(instance) = receiver else {
+            let Some(method) = self.builtin_method(receiver, name) else {
+                self.method_error(name, arg_cnt as usize);
+                return false;
+            };
+            // The receiver stays where it is, as the first argument of the method
+            return self.call_native(&method, arg_cnt, true);
+        };
+        let instance = Rc::clone(instance);
+        // A field holding a function shadows the method of the same name
+        let field = instance.borrow().fields.get(name).cloned();
+        if let Some(field) = field {
+            self.stack[self.stack_top - 1 - arg_cnt as usize] = field;
+            return self.call_callee(arg_cnt);
+        }
+        let getter = instance.borrow().class.getters.borrow().get(name).cloned();
+        if let Some(getter) = getter {
+            // Run the getter to the end first, then call what it returns with the arguments
+            let Some(callee) = self.call_method_now(getter, Value::Instance(instance), &[]) else {
+                return false;
+            };
+            self.stack[self.stack_top - 1 - arg_cnt as usize] = callee;
+            return self.call_callee(arg_cnt);
+        }
+        let method = instance.borrow().class.methods.borrow().get(name).cloned();
+        match method {
+            // The receiver is already in the slot zero of the new call frame
+            Some(method) => self.call(method, arg_cnt),
+            None => {
+                self.undefined_property(&instance, name);
+                false
+            }
+        }
+    }
+
+    /// Run a method of the receiver in a nested run, for the places which need its result right
+    /// away, e.g. a getter or an operator method
+    fn call_method_now(
+        &mut self,
+        method: Rc<Closure>,
+        receiver: Value,
+        args: &[Value],
+    ) -> Option<Value> {
+        let depth = self.frames.len();
+        self.push(receiver);
+        for arg in args {
+            self.push(arg.clone());
+        }
+        if self.call(method, args.len() as u8) && self.run(depth) == InterpretResult::Ok {
+            Some(self.pop())
+        } else {
+            None
+        }
+    }
+
+    /// The string `print` shows for the value, which `str`, `toString`, `format` and the
+    /// `toString()` methods of the built-in types show too. An instance whose class has a
+    /// `toString` method shows the string the method returns, also inside a list
+    ///
+    /// ```
+    /// use rustlox::VM;
+    ///
+    /// let mut vm = VM::new();
+    /// vm.interpret("class P { toString() { return \"a P\"; } } var ps = [P(), 1, \"two\"];")?;
+    /// let ps = vm.get_global("ps").unwrap();
+    /// assert_eq!(vm.stringify(&ps)?, r#"[a P, 1, "two"]"#);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn stringify(&mut self, value: &Value) -> Result<String, NativeError> {
+        let mut out = String::new();
+        self.stringify_into(&mut out, value, &mut vec![])?;
+        Ok(out)
+    }
+
+    /// Write the string of the value, like `Display` does but calling the `toString` methods. The
+    /// lists being written are in `outer`, so a list inside itself is written as `[...]`
+    fn stringify_into(
+        &mut self,
+        out: &mut String,
+        value: &Value,
+        outer: &mut Vec<*const RefCell<Vec<Value>>>,
+    ) -> Result<(), NativeError> {
+        match value {
+            Value::List(list) => {
+                if outer.contains(&Rc::as_ptr(list)) {
+                    out.push_str("[...]");
+                    return Ok(());
+                }
+                outer.push(Rc::as_ptr(list));
+                out.push('[');
+                // A `toString` method may change the list meanwhile
+                let elements = list.borrow().clone();
+                for (idx, element) in elements.iter().enumerate() {
+                    if idx > 0 {
+                        out.push_str(", ");
+                    }
+                    match element {
+                        Value::String(s) => out.push_str(&format!("{s:?}")),
+                        element => self.stringify_into(out, element, outer)?,
+                    }
+                }
+                outer.pop();
+                out.push(']');
+            }
+            Value::Instance(_) if value.method("toString").is_some() => {
+                let method = value.method("toString").expect("Checked");
+                match self.call_method_now(method, value.clone(), &[]) {
+                    Some(Value::String(s)) => out.push_str(&s),
+                    Some(result) => {
+                        return Err(NativeError::new(format!(
+                            "'toString' must return a string, got {}.",
+                            result.type_name()
+                        )))
+                    }
+                    None => return Err(NativeError::new("'toString' aborted.")),
                 }
-            );
+            }
+            value => out.push_str(&value.to_string()),
         }
-        self.reset_stack()
+        Ok(())
     }
 
-    /// Only `Nil` and `false` is falsey, everything else is `true`
-    fn is_falsey(&self, value: &Value) -> bool {
-        matches!(value, Value::Nil | Value::Bool(false))
+    /// `fp` is a function pointer
+    /// Define a global function implemented in Rust, which expects exactly `arity` arguments. An
+    /// `Err` returned by the function aborts the script with a runtime error, e.g.
+    ///
+    /// ```
+    /// use rustlox::value::NativeError;
+    /// use rustlox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.register_native("sqrt", 1, |_vm, args| match args[0].as_number() {
+    ///     Some(n) if n >= 0.0 => Ok(Value::Number(n.sqrt())),
+    ///     _ => Err(NativeError::new("Expect a non-negative number.")),
+    /// });
+    /// assert!(vm.interpret("var a = sqrt(4);").is_ok());
+    /// assert!(vm.interpret("var b = sqrt(-4);").is_err());
+    /// ```
+    pub fn register_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&mut VM, &[Value]) -> Result<Value, NativeError> + MaybeSync + 'static,
+    {
+        #[allow(clippy::useless_conversion)] // not useless with the `compact-values` feature
+        let native = NativeFunction::new(name, arity, function).into();
+        self.set_global(name, Value::NativeFunc(native));
     }
 
-    fn values_equal(&self, a: &Value, b: &Value) -> bool {
-        match (a, b) {
-            (Value::Bool(x), Value::Bool(y)) => x == y,
-            (Value::Nil, _) => true,
-            (Value::Number(x), Value::Number(y)) => x == y,
-            (Value::String(s1), Value::String(s2)) => s1 == s2,
-            _ => false,
-        }
+    /// Same as [`VM::register_native`], for a function which takes `min_arity` arguments or more,
+    /// all of them in its slice of arguments
+    ///
+    /// ```
+    /// use rustlox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.register_variadic_native("sum", 0, |_vm, args| {
+    ///     let numbers = args.iter().map(f64::try_from).collect::<Result<Vec<_>, _>>()?;
+    ///     Ok(Value::Number(numbers.iter().sum()))
+    /// });
+    /// vm.interpret("var total = sum(1, 2, 3);")?;
+    /// assert_eq!(vm.get_global("total"), Some(Value::Number(6.0)));
+    /// # Ok::<(), rustlox::LoxError>(())
+    /// ```
+    pub fn register_variadic_native<F>(&mut self, name: &str, min_arity: usize, function: F)
+    where
+        F: Fn(&mut VM, &[Value]) -> Result<Value, NativeError> + MaybeSync + 'static,
+    {
+        #[allow(clippy::useless_conversion)] // not useless with the `compact-values` feature
+        let native = NativeFunction::new(name, min_arity, function)
+            .variadic()
+            .into();
+        self.set_global(name, Value::NativeFunc(native));
     }
 
-    /// Create a new CallFrame and push it to `self.frames`
-    fn call(&mut self, closure: Rc<Closure>, arg_cnt: u8) -> bool {
-        if arg_cnt as usize != closure.function.arity {
-            self.runtime_error(&format!(
-                "Expected {} arguments but got {}.",
-                closure.function.arity, arg_cnt,
-            ));
-            return false;
-        }
-        // the starts slots DOES NOT include the function name in the stack
-        self.frames.push(CallFrame::new(
-            closure,
-            0,
-            self.stack.len() - arg_cnt as usize,
-        ));
+    /// Define a method of a built-in type implemented in Rust, e.g. `"hello".length()`. The type
+    /// is named like in the error messages: `"string"`, `"number"`, `"boolean"`, `"nil"`,
+    /// `"list"` or `"range"`. The function gets the receiver as its first argument, before the `arity`
+    /// arguments of the call
+    ///
+    /// ```
+    /// use rustlox::{Value, VM};
+    ///
+    /// let mut vm = VM::new();
+    /// vm.register_method("number", "double", 0, |_vm, args| {
+    ///     Ok(Value::Number(f64::try_from(&args[0])? * 2.0))
+    /// });
+    /// vm.interpret("var four = (2).double();")?;
+    /// assert_eq!(vm.get_global("four"), Some(Value::Number(4.0)));
+    /// # Ok::<(), rustlox::LoxError>(())
+    /// ```
+    pub fn register_method<F>(
+        &mut self,
+        type_name: &'static str,
+        name: &str,
+        arity: usize,
+        function: F,
+    ) where
+        F: Fn(&mut VM, &[Value]) -> Result<Value, NativeError> + MaybeSync + 'static,
+    {
+        self.define_method(type_name, NativeFunction::new(name, arity, function));
+    }
 
-        true
+    /// Same as [`VM::register_method`], for a method built with e.g. optional arguments
+    pub(crate) fn define_method(&mut self, type_name: &'static str, method: NativeFunction) {
+        self.builtin_methods
+            .entry(type_name)
+            .or_default()
+            .insert(method.name.clone(), method);
     }
 
-    fn call_value(&mut self, arg_cnt: u8) -> bool {
-        // todo: can we avoid the cloning overhead?
-        //       how to solve the ownership issue?
-        let callee = self.stack[self.stack.len() - 1 - arg_cnt as usize].clone();
-        match callee {
-            Value::NativeFunc(fp) => {
-                let arg_start = self.stack.len() - arg_cnt as usize;
-                let result = fp.0(&self.stack[arg_start..]);
-                self.stack.truncate(arg_start - 1);
-                self.stack.push(result);
-                true
-            }
-            Value::Closure(closure) => self.call(closure, arg_cnt),
-            _ => {
-                self.runtime_error("Can only call functions and classes.");
-                false
-            }
-        }
+    /// Define a global variable (or overwrite an existing one), so the host can hand constants,
+    /// configurations, and data to the script before running it
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
     }
 
-    /// `fp` is a function pointer
-    fn define_native(&mut self, name: &str, fp: NativeFunction) {
-        self.globals.insert(name.to_string(), Value::NativeFunc(fp));
+    /// Read a global variable back, e.g. the one defined by a script after `interpret`
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name).cloned()
     }
 
     /// The variable get captured is located in `slot`
     fn capture_upvalue(&mut self, slot: usize) -> Rc<ObjUpvalue> {
         // Searching for an existing upvalue pointing to the `slot`
         for val in &self.open_upvalues {
-            if val.location == slot {
-                return Rc::clone(&val);
+            if val.location.get() == slot {
+                return Rc::clone(val);
             }
         }
-        let upvalue = Rc::new(ObjUpvalue::new(slot, self.stack[slot].clone()));
-        self.open_upvalues.push(upvalue);
-        self.open_upvalues.last().unwrap().clone()
+        let upvalue = Rc::new(ObjUpvalue::new(slot));
+        self.track(Object::Upvalue(Rc::clone(&upvalue)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
     }
 
-    // Move the captured local variable in `slot` to heap
-    // After that, the VM is free to discard the stack `slot`
-    // todo: It seems that I don't need to close upvalues because I have done this in [`capture_upvalue`]?
-    fn close_upvalues(&mut self, slot: usize) {}
+    /// Close every open upvalue pointing to `last` or any slot above it. Later captures of
+    /// these slots will create new upvalues, e.g. one for each iteration of a loop
+    fn close_upvalues(&mut self, last: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            if upvalue.location.get() < last {
+                return true;
+            }
+            // Move the captured variable from the stack to the upvalue itself
+            *upvalue.closed.borrow_mut() = Some(stack[upvalue.location.get()].clone());
+            false
+        });
+    }
+
+    /// Run the instruction hook before the next instruction. Returns false if it stopped the script
+    fn instruction_hook(&mut self) -> bool {
+        let (Some(hooks), Some(frame)) = (self.hooks.as_mut(), self.frames.last()) else {
+            return true;
+        };
+        match hooks.on_instruction(&frame.closure.function, frame.ip) {
+            Ok(()) => true,
+            Err(error) => {
+                self.runtime_error(&error.message);
+                false
+            }
+        }
+    }
+
+    /// Write the call depth, the stack, the upvalues of the running closure and the next
+    /// instruction to the trace
+    fn trace_instruction(&mut self) {
+        let Some(frame) = self.frames.last() else {
+            return;
+        };
+        let mut out = format!("          depth {} ", self.frames.len());
+        for val in &self.stack[..self.stack_top] {
+            out += &format!("[ {val} ]");
+        }
+        out.push('\n');
+        if !frame.closure.upvalues.is_empty() {
+            out += "          upvalues ";
+            for upvalue in &frame.closure.upvalues {
+                match upvalue.closed.borrow().as_ref() {
+                    Some(val) => out += &format!("[ {val} ]"),
+                    // Still on the stack
+                    None => out += &format!("[ {} ]", self.stack[upvalue.location.get()]),
+                }
+            }
+            out.push('\n');
+        }
+        if frame.ip < frame.closure.function.chunk.code.len() {
+            write_instruction(&mut out, &frame.closure.function.chunk, frame.ip)
+                .expect("Write to a String never fails");
+        }
+        // The trace is only for debugging, so failing to write it doesn't stop the script
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = trace.write_all(out.as_bytes());
+        }
+    }
 
-    fn run(&mut self) -> InterpretResult {
+    /// Run until the frames above `depth` return, and leave the return value of the last one on
+    /// the stack
+    fn run(&mut self, depth: usize) -> InterpretResult {
+        // Every instruction runs in a frame, and the last `Return` leaves `depth` of them
+        if self.frames.len() <= depth {
+            return self.invalid_bytecode();
+        }
         loop {
+            // Only the script started by `start` pauses, not the code a native runs. The countdown
+            // of a stepped script runs out exactly at the pause, and the batch isn't counted yet
+            if self.countdown == 0
+                && depth == 0
+                && self
+                    .pause_at
+                    .is_some_and(|at| self.executed + u64::from(self.batch) >= at)
+            {
+                return InterpretResult::Paused;
+            }
             // stack tracing - show the current contents of the stack before we interpret each
             // instruction
-            #[cfg(debug_assertions)]
-            {
-                print!("          ");
-                for val in &self.stack {
-                    print!("[ {val} ]");
-                }
-                println!();
-                disassemble_instruction(
-                    &self.frames.last().unwrap().closure.function.chunk,
-                    self.frames.last().unwrap().ip,
-                );
+            if self.trace.is_some() {
+                self.trace_instruction();
+            }
+            if self.hooks.is_some() && !self.instruction_hook() {
+                return InterpretResult::RuntimeError;
             }
 
-            let instruction: OpCode = self.read_byte().into();
+            let byte = self.read_byte();
+            // The error points at the instruction which would exceed the limit, or which is past
+            // the end of the code
+            if self.countdown == 0 && !self.check_limits() {
+                return InterpretResult::RuntimeError;
+            }
+            self.countdown -= 1;
+            let Ok(instruction) = OpCode::try_from(byte) else {
+                return self.invalid_bytecode();
+            };
+            if let Some(profile) = self.profile.as_mut() {
+                profile.count_opcode(instruction);
+            }
             match instruction {
                 OpCode::Return => {
-                    let result = self.stack.pop().unwrap();
-                    let return_addr = self.current_frame().slots.saturating_sub(1);
-                    self.frames.pop().unwrap();
-                    // It means we have finished executing the top-level code
-                    // , then we exit the VM
-                    if self.frames.is_empty() {
-                        return InterpretResult::Ok;
+                    let result = self.pop();
+                    // The function popped more than it pushed
+                    let Some(slots) = self
+                        .frames
+                        .last()
+                        .map(|frame| frame.slots)
+                        .filter(|slots| !self.corrupt && self.stack_top >= *slots)
+                    else {
+                        return self.invalid_bytecode();
+                    };
+                    // The locals of the returning function are about to be discarded
+                    self.close_upvalues(slots);
+                    let Some(frame) = self.frames.pop() else {
+                        return self.invalid_bytecode();
+                    };
+                    if let (Some(profile), Some(started)) = (self.profile.as_mut(), frame.started) {
+                        let name = profiler::function_key(&frame.closure.function);
+                        let elapsed = started.elapsed();
+                        profile.add_time(name, elapsed.saturating_sub(frame.callee_time));
+                        if let Some(caller) = self.frames.last_mut() {
+                            caller.callee_time += elapsed;
+                        }
                     }
-
-                    self.stack.truncate(return_addr);
-
+                    if let Some(hooks) = self.hooks.as_mut() {
+                        // The scripts, the modules and the bodies of the coroutines aren't called
+                        let function = &frame.closure.function;
+                        if !function.name.is_empty() && !function.is_generator {
+                            hooks.on_return(&function.name, &result);
+                        }
+                    }
+                    self.truncate(slots);
                     // The return value of the callee
-                    self.stack.push(result);
+                    self.push(result);
+                    // It means we have finished executing the top-level code (or the function
+                    // called by the host), then we exit the VM
+                    if self.frames.len() == depth {
+                        return InterpretResult::Ok;
+                    }
+                }
+                OpCode::Yield => {
+                    let value = self.pop();
+                    // Only the frames of the coroutine may be suspended, not a native function
+                    // it called
+                    match self.coroutines.last() {
+                        Some((_, frames_below)) if *frames_below == depth => {
+                            self.suspend();
+                            self.push(value);
+                            return InterpretResult::Ok;
+                        }
+                        _ => {
+                            self.runtime_error("Can't yield across a native function.");
+                            return InterpretResult::RuntimeError;
+                        }
+                    }
                 }
                 OpCode::Constant => {
                     let constant = self.read_constant();
-                    self.stack.push(constant);
+                    self.push(constant);
+                }
+                OpCode::ConstantLong => {
+                    let constant = self.read_constant_long();
+                    self.push(constant);
                 }
                 OpCode::Negate => {
-                    if let Some(v) = self.stack.pop() {
-                        if let Value::Number(v) = v {
-                            self.stack.push(Value::Number(-v));
-                        } else {
-                            self.stack.push(v); // todo: shoule we cancel the previous pop
-                                                // operation?
-                            self.runtime_error("Operand must be a number.");
+                    let negated = match self.peek(0) {
+                        Value::Int(v) => v
+                            .checked_neg()
+                            .map_or(Value::Number(-(*v as f64)), Value::Int),
+                        Value::Number(v) => Value::Number(-v),
+                        value => {
+                            let got = value.type_name();
+                            self.runtime_error(&format!(
+                                "Operand must be a number, got {got} for '-'."
+                            ));
                             return InterpretResult::RuntimeError;
                         }
+                    };
+                    self.pop();
+                    self.push(negated);
+                }
+                OpCode::Add
+                | OpCode::Substract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::Modulo
+                | OpCode::Power
+                | OpCode::Greater
+                | OpCode::Less => {
+                    let op = match instruction {
+                        OpCode::Add => "+",
+                        OpCode::Substract => "-",
+                        OpCode::Multiply => "*",
+                        OpCode::Divide => "/",
+                        OpCode::Modulo => "%",
+                        OpCode::Power => "**",
+                        OpCode::Greater => ">",
+                        _ => "<",
+                    };
+                    if self.number_operator(instruction) {
+                        continue;
+                    }
+                    if let InterpretResult::RuntimeError = self.binary_operator(op) {
+                        return InterpretResult::RuntimeError;
+                    }
+                }
+                OpCode::BitAnd
+                | OpCode::BitOr
+                | OpCode::BitXor
+                | OpCode::ShiftLeft
+                | OpCode::ShiftRight => {
+                    let op = match instruction {
+                        OpCode::BitAnd => "&",
+                        OpCode::BitOr => "|",
+                        OpCode::BitXor => "^",
+                        OpCode::ShiftLeft => "<<",
+                        _ => ">>",
+                    };
+                    if let InterpretResult::RuntimeError = self.bitwise_operator(op) {
+                        return InterpretResult::RuntimeError;
                     }
                 }
-                OpCode::Add => {
-                    self.binary_operator('+');
+                OpCode::BitNot => {
+                    if let Some(v) = integer_of(self.peek(0)) {
+                        let inverted = Value::Int(!v);
+                        self.pop();
+                        self.push(inverted);
+                    } else {
+                        let got = self.peek(0).type_name();
+                        self.runtime_error(&format!(
+                            "Operand must be a number, got {got} for '~'."
+                        ));
+                        return InterpretResult::RuntimeError;
+                    }
                 }
-                OpCode::Substract => {
-                    self.binary_operator('-');
+                OpCode::Nil => self.push(Value::Nil),
+                OpCode::True => self.push(Value::Bool(true)),
+                OpCode::False => self.push(Value::Bool(false)),
+                OpCode::Dup => {
+                    let distance = self.read_byte() as usize;
+                    if self.has_values(distance + 1) {
+                        self.push(self.peek(distance).clone());
+                    }
                 }
-                OpCode::Multiply => {
-                    self.binary_operator('*');
+                OpCode::Bury => {
+                    let depth = self.read_byte() as usize;
+                    if self.has_values(depth + 1) {
+                        self.stack[self.stack_top - 1 - depth..self.stack_top].rotate_right(1);
+                    }
                 }
-                OpCode::Divide => {
-                    self.binary_operator('/');
+                OpCode::IsNil => {
+                    let is_nil = matches!(self.peek(0), Value::Nil);
+                    self.push(Value::Bool(is_nil));
                 }
-                OpCode::Nil => self.stack.push(Value::Nil),
-                OpCode::True => self.stack.push(Value::Bool(true)),
-                OpCode::False => self.stack.push(Value::Bool(false)),
                 OpCode::Not => {
-                    if let Some(operand) = self.stack.pop() {
-                        self.stack.push(Value::Bool(self.is_falsey(&operand)));
-                    }
+                    let operand = self.pop();
+                    self.push(Value::Bool(self.is_falsey(&operand)));
                 }
                 OpCode::Equal => {
-                    if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-                        self.stack.push(Value::Bool(self.values_equal(&a, &b)));
+                    if let Some(method) = self.peek(1).method("equals") {
+                        if !self.call(method, 1) {
+                            return InterpretResult::RuntimeError;
+                        }
+                        continue;
                     }
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::Bool(a == b));
                 }
-                OpCode::Greater => {
-                    self.binary_operator('>');
+                OpCode::NotEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let not_equal = match a.method("equals") {
+                        Some(method) => match self.call_method_now(method, a, &[b]) {
+                            Some(equal) => self.is_falsey(&equal),
+                            None => return InterpretResult::RuntimeError,
+                        },
+                        None => a != b,
+                    };
+                    self.push(Value::Bool(not_equal));
                 }
-                OpCode::Less => {
-                    self.binary_operator('<');
+                OpCode::Increment => {
+                    // A loop counter is incremented in its slot
+                    match self
+                        .stack_top
+                        .checked_sub(1)
+                        .map(|top| &mut self.stack[top])
+                    {
+                        Some(Value::Int(n)) if *n < i64::MAX => {
+                            *n += 1;
+                            continue;
+                        }
+                        Some(Value::Number(n)) => {
+                            *n += 1.0;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    // Same as adding the constant one, including the promotion of the largest
+                    // integer and the error for the other values
+                    self.push(Value::Int(1));
+                    if let InterpretResult::RuntimeError = self.binary_operator("+") {
+                        return InterpretResult::RuntimeError;
+                    }
                 }
                 OpCode::Print => {
                     // When the VM reaches this instruction, it has already executed the code for
                     // the expression, leaving the result value on top of the stack
-                    println!("{}", self.stack.pop().unwrap());
+                    let value = self.pop();
+                    let text = match self.stringify(&value) {
+                        Ok(text) => text,
+                        Err(error) => {
+                            // The error of the code run by `toString` is already reported
+                            if self.last_error.is_none() {
+                                self.runtime_error(&error.message);
+                            }
+                            return InterpretResult::RuntimeError;
+                        }
+                    };
+                    if writeln!(self.stdout, "{text}").is_err() {
+                        self.runtime_error("Could not write the output.");
+                        return InterpretResult::RuntimeError;
+                    }
                 }
                 OpCode::Pop => {
-                    self.stack.pop().unwrap();
+                    self.pop();
+                }
+                OpCode::ArgMissing => {
+                    let slot = self.read_byte() as usize;
+                    // The slots of the parameters start from one
+                    let missing = self.frames.last().is_some_and(|frame| frame.arg_cnt < slot);
+                    self.push(Value::Bool(missing));
                 }
-                OpCode::DefineGlobal => {
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
                     // Get the name of the variable from the constant table
-                    let name = self.read_constant();
-
-                    if let Value::String(s) = name {
-                        let val = self.stack.pop().unwrap();
-                        self.globals.insert(s, val);
-                    }
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    let val = self.pop();
+                    self.globals.insert(into_string(name), val);
                 }
-                OpCode::GetGlobal => {
-                    let name = self.read_constant();
-
-                    if let Value::String(s) = name {
-                        if self.globals.contains_key(&s) {
-                            // todo: copying function object may be inefficient here, should we
-                            // avoid the clone() here?
-                            self.stack.push(self.globals.get(&s).unwrap().clone());
-                        } else {
-                            self.runtime_error(&format!("Undefined variable '{s}'"));
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    // Cloning the value only bumps a reference count, whatever it holds
+                    match self.globals.get(name.as_str()) {
+                        Some(value) => {
+                            let value = value.clone();
+                            self.push(value);
+                        }
+                        None => {
+                            self.undefined_variable(&name);
                             return InterpretResult::RuntimeError;
                         }
                     }
                 }
-                OpCode::SetGlobal => {
-                    let name = self.read_constant();
-
-                    if let Value::String(s) = name {
-                        // todo: avoid copy or look up the hashmap twice?
-                        if let Entry::Occupied(mut e) = self.globals.entry(s.clone()) {
-                            // Assignment is an expression, so it needs to leave that value there
-                            // incase the assignment is nested inside some larger expression
-                            let val = self.stack.last().unwrap().clone();
-                            e.insert(val);
-                        } else {
-                            self.runtime_error(&format!("Undefined variable '{s}'"));
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    // Assignment is an expression, so it needs to leave that value there incase
+                    // the assignment is nested inside some larger expression
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(name.as_str()) {
+                        Some(global) => *global = value,
+                        None => {
+                            self.undefined_variable(&name);
                             return InterpretResult::RuntimeError;
                         }
                     }
                 }
-                OpCode::GetLocal => {
-                    // It takes a single-byte operand for the stack slot where the local lives
-                    let index = self.read_byte();
-                    let slots_offset = self.current_frame().slots;
-
+                OpCode::GetLocal | OpCode::GetLocalLong => {
+                    // It takes an operand for the stack slot where the local lives
+                    let index = match instruction {
+                        OpCode::GetLocal => self.read_byte() as usize,
+                        _ => self.read_short() as usize,
+                    };
                     // Load the value from that index and then push it on top of the stack s.t.
                     // later instruction can find it
-                    self.stack
-                        .push(self.stack[index as usize + slots_offset].clone());
+                    if let Some(slot) = self.local_slot(index) {
+                        self.push(self.stack[slot].clone());
+                    }
                 }
-                OpCode::SetLocal => {
-                    // It taks a single-byte operand for the stack slot where the local lives
-                    let index = self.read_byte();
-                    let slots_offset = self.current_frame().slots;
-                    self.stack[index as usize + slots_offset] = self.stack.last().unwrap().clone();
+                OpCode::SetLocal | OpCode::SetLocalLong => {
+                    let index = match instruction {
+                        OpCode::SetLocal => self.read_byte() as usize,
+                        _ => self.read_short() as usize,
+                    };
+                    if let Some(slot) = self.local_slot(index) {
+                        self.stack[slot] = self.peek(0).clone();
+                    }
                 }
                 OpCode::JumpIfFalse => {
                     let offset = self.read_short();
-                    if let Some(condition) = self.stack.last() {
-                        if self.is_falsey(condition) {
-                            self.frames.last_mut().unwrap().ip += offset as usize;
-                        }
+                    if self.is_falsey(self.peek(0)) {
+                        self.jump(offset as usize);
                     }
                 }
                 OpCode::Jump => {
                     let offset = self.read_short();
-                    self.current_frame().ip += offset as usize;
+                    self.jump(offset as usize);
                 }
                 OpCode::Loop => {
                     let offset = self.read_short();
-                    self.current_frame().ip -= offset as usize;
+                    self.jump_back(offset as usize);
+                }
+                OpCode::JumpIfFalseLong => {
+                    let offset = self.read_long();
+                    if self.is_falsey(self.peek(0)) {
+                        self.jump(offset as usize);
+                    }
+                }
+                OpCode::JumpLong => {
+                    let offset = self.read_long();
+                    self.jump(offset as usize);
+                }
+                OpCode::LoopLong => {
+                    let offset = self.read_long();
+                    self.jump_back(offset as usize);
                 }
                 OpCode::Call => {
                     let arg_cnt = self.read_byte();
+                    if !self.has_values(arg_cnt as usize + 1) {
+                        continue;
+                    }
                     // Do not decide callee here because the ownership issue
-                    if !self.call_value(arg_cnt) {
+                    if !self.call_callee(arg_cnt) {
+                        return InterpretResult::RuntimeError;
+                    }
+                }
+                OpCode::CallSpread => {
+                    let Some(arg_cnt) = self.unpack_arguments() else {
+                        return InterpretResult::RuntimeError;
+                    };
+                    if !self.has_values(arg_cnt as usize + 1) {
+                        continue;
+                    }
+                    if !self.call_callee(arg_cnt) {
+                        return InterpretResult::RuntimeError;
+                    }
+                }
+                OpCode::BuildList => {
+                    let count = self.read_byte() as usize;
+                    if !self.has_values(count) {
+                        continue;
+                    }
+                    let start = self.stack_top - count;
+                    let list = Rc::new(RefCell::new(self.stack[start..self.stack_top].to_vec()));
+                    self.truncate(start);
+                    self.track(Object::List(Rc::clone(&list)));
+                    self.push(Value::List(list));
+                }
+                OpCode::AppendList | OpCode::ExtendList => {
+                    // The stack looks like: [list][element or list to spread]
+                    let value = self.pop();
+                    let Value::List(list) = self.peek(0) else {
+                        return self.invalid_bytecode();
+                    };
+                    match value {
+                        _ if instruction == OpCode::AppendList => list.borrow_mut().push(value),
+                        Value::List(elements) => {
+                            let elements = elements.borrow().clone();
+                            list.borrow_mut().extend(elements);
+                        }
+                        value => {
+                            self.runtime_error(&format!(
+                                "Can only spread a list, got {}.",
+                                value.type_name()
+                            ));
+                            return InterpretResult::RuntimeError;
+                        }
+                    }
+                }
+                OpCode::GetIndex | OpCode::SetIndex => {
+                    // The stack looks like: [list][index], and then [value] for `SetIndex`
+                    let value_cnt = usize::from(instruction == OpCode::SetIndex);
+                    if let (OpCode::GetIndex, Value::Range(range)) = (instruction, self.peek(0)) {
+                        // e.g. `xs[1..3]` or `s[1..3]`
+                        let (target, range) = (self.peek(1).clone(), Rc::clone(range));
+                        let Some((start, end)) = self.range_bounds(&target, &range) else {
+                            return InterpretResult::RuntimeError;
+                        };
+                        let slice = self.slice(&target, start, end);
+                        self.truncate(self.stack_top - 2);
+                        self.push(slice);
+                        continue;
+                    }
+                    let Value::List(list) = self.peek(1 + value_cnt) else {
+                        let got = self.peek(1 + value_cnt).type_name();
+                        self.runtime_error(&format!("Can only index lists, got {got}."));
+                        return InterpretResult::RuntimeError;
+                    };
+                    let list = Rc::clone(list);
+                    let index = self.peek(value_cnt).clone();
+                    let len = list.borrow().len();
+                    let Some(idx) = self.list_index(&index, len) else {
                         return InterpretResult::RuntimeError;
+                    };
+                    let value = if instruction == OpCode::SetIndex {
+                        let value = self.pop();
+                        list.borrow_mut()[idx] = value.clone();
+                        // Assignment is an expression, so it leaves the assigned value
+                        value
+                    } else {
+                        list.borrow()[idx].clone()
+                    };
+                    self.truncate(self.stack_top - 2);
+                    self.push(value);
+                }
+                OpCode::Slice => {
+                    // The stack looks like: [target][start][end]
+                    let target = self.peek(2).clone();
+                    let len = match &target {
+                        Value::List(list) => list.borrow().len(),
+                        Value::String(s) => s.chars().count(),
+                        _ => 0,
+                    };
+                    let mut bounds = [0.0, len as f64];
+                    for (idx, bound) in bounds.iter_mut().enumerate() {
+                        match *self.peek(1 - idx) {
+                            Value::Nil => {}
+                            Value::Int(n) => *bound = n as f64,
+                            Value::Number(n) if n.fract() == 0.0 => *bound = n,
+                            ref value => {
+                                let got = match value {
+                                    Value::Number(n) => format_number(*n),
+                                    value => value.type_name().to_string(),
+                                };
+                                self.runtime_error(&format!(
+                                    "Slice bounds must be integers or nil, got {got}."
+                                ));
+                                return InterpretResult::RuntimeError;
+                            }
+                        }
+                    }
+                    let [start, end] = bounds;
+                    let shown = format!("Slice {}:{}", format_number(start), format_number(end));
+                    let Some((start, end)) = self.slice_bounds(&target, start, end, &shown) else {
+                        return InterpretResult::RuntimeError;
+                    };
+                    let slice = self.slice(&target, start, end);
+                    self.truncate(self.stack_top - 3);
+                    self.push(slice);
+                }
+                OpCode::Range => {
+                    let inclusive = self.read_byte() == 1;
+                    let end = self.pop();
+                    let start = self.pop();
+                    let (Some(start), Some(end)) = (start.as_number(), end.as_number()) else {
+                        self.operands_error("..", &start, &end);
+                        return InterpretResult::RuntimeError;
+                    };
+                    if start.fract() != 0.0 || end.fract() != 0.0 {
+                        let (start, end) = (format_number(start), format_number(end));
+                        self.runtime_error(&format!(
+                            "Range bounds must be integers, got {start} and {end}."
+                        ));
+                        return InterpretResult::RuntimeError;
+                    }
+                    let range = Range {
+                        start,
+                        end,
+                        inclusive,
+                    };
+                    self.push(Value::Range(Rc::new(range)));
+                }
+                OpCode::IterNext => {
+                    // The stack slots look like: [iterable][position], the position is an index
+                    // of a list or a range, or a byte offset of a string
+                    let index = self.read_short() as usize;
+                    let Some(slot) = self.local_slot(index + 1).map(|slot| slot - 1) else {
+                        continue;
+                    };
+                    let Value::Int(position) = self.stack[slot + 1] else {
+                        return self.invalid_bytecode();
+                    };
+                    let position = position as usize;
+                    let next = match &self.stack[slot] {
+                        Value::List(list) => list
+                            .borrow()
+                            .get(position)
+                            .map(|element| (element.clone(), position + 1)),
+                        Value::String(s) => {
+                            let Some(rest) = s.get(position..) else {
+                                return self.invalid_bytecode();
+                            };
+                            rest.chars()
+                                .next()
+                                .map(|ch| (Value::string(ch.to_string()), position + ch.len_utf8()))
+                        }
+                        Value::Range(range) => (position < range.len())
+                            .then(|| (whole(range.start + position as f64), position + 1)),
+                        value => {
+                            let got = value.type_name();
+                            self.runtime_error(&format!(
+                                "Can only iterate over lists, strings and ranges, got {got}."
+                            ));
+                            return InterpretResult::RuntimeError;
+                        }
+                    };
+                    match next {
+                        Some((element, next)) => {
+                            self.stack[slot + 1] = Value::Int(next as i64);
+                            self.push(element);
+                            self.push(Value::Bool(true));
+                        }
+                        None => {
+                            self.push(Value::Nil);
+                            self.push(Value::Bool(false));
+                        }
                     }
                 }
-                OpCode::Closure => {
-                    let Value::Func(func) = self.read_constant() else {panic!("impossible");};
+                OpCode::Closure | OpCode::ClosureLong => {
+                    let Value::Func(func) = self.read_constant_of(instruction) else {
+                        return self.invalid_bytecode();
+                    };
                     let mut closure = Closure::new(func);
 
-                    // todo: push reference in the future
                     for _ in 0..closure.function.upvalues.len() {
                         let is_local = self.read_byte();
-                        let upvalue_idx = self.read_byte();
-                        if is_local == 1 {
-                            let location = self.current_frame().slots + upvalue_idx as usize;
-                            closure.upvalues.push(self.capture_upvalue(location));
+                        let upvalue_idx = self.read_short();
+                        let upvalue = if is_local == 1 {
+                            self.local_slot(upvalue_idx as usize)
+                                .map(|location| self.capture_upvalue(location))
                         } else {
-                            let val =
-                                self.current_frame().closure.upvalues[upvalue_idx as usize].clone();
-                            closure.upvalues.push(val);
-                        }
+                            self.upvalue(upvalue_idx as usize)
+                        };
+                        let Some(upvalue) = upvalue else {
+                            return self.invalid_bytecode();
+                        };
+                        closure.upvalues.push(upvalue);
                     }
                     let rc_closure = Rc::new(closure);
-                    self.stack.push(Value::Closure(rc_closure));
+                    self.track(Object::Closure(Rc::clone(&rc_closure)));
+                    self.push(Value::Closure(rc_closure));
                 }
                 OpCode::SetUpvalue => {
                     let slot = self.read_byte();
-                    let val = self.stack.last().unwrap().clone();
-                    let upvalue = &self.current_frame().closure.upvalues[slot as usize];
-                    upvalue.obj.replace(val);
+                    let val = self.peek(0).clone();
+                    let Some(upvalue) = self.upvalue(slot as usize) else {
+                        continue;
+                    };
+                    let mut closed = upvalue.closed.borrow_mut();
+                    match closed.as_mut() {
+                        Some(closed) => *closed = val,
+                        None => self.stack[upvalue.location.get()] = val,
+                    }
                 }
                 OpCode::GetUpvalue => {
-                    // look up the corresponding upvalue and clone the value in that slot
-                    // todo: performance issue
+                    // look up the corresponding upvalue and clone the value it points to
                     let slot = self.read_byte();
-                    let upvalue = self.current_frame().closure.upvalues[slot as usize].clone();
-                    self.stack.push((*upvalue.obj.borrow_mut()).clone());
+                    let Some(upvalue) = self.upvalue(slot as usize) else {
+                        continue;
+                    };
+                    let val = match upvalue.closed.borrow().as_ref() {
+                        Some(closed) => closed.clone(),
+                        None => self.stack[upvalue.location.get()].clone(),
+                    };
+                    self.push(val);
                 }
                 OpCode::ClosedUpvalue => {
                     // when we execute this instruction, the `Value` to hoisted is on top of the
-                    // stack
-                    // self.close_upvalues(self.stack.len() - 1);
-                    self.stack.pop();
+                    // stack, move it to the heap before discarding the stack slot
+                    self.close_upvalues(self.stack_top.saturating_sub(1));
+                    self.pop();
+                }
+                OpCode::Class | OpCode::ClassLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    let class = Rc::new(Class::new(into_string(name)));
+                    self.track(Object::Class(Rc::clone(&class)));
+                    self.push(Value::Class(class));
+                }
+                OpCode::Method | OpCode::MethodLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    // The stack looks like: [class][method closure]
+                    let Value::Closure(method) = self.pop() else {
+                        return self.invalid_bytecode();
+                    };
+                    let Value::Class(class) = self.peek(0) else {
+                        return self.invalid_bytecode();
+                    };
+                    class.methods.borrow_mut().insert(into_string(name), method);
+                }
+                OpCode::Getter | OpCode::Setter | OpCode::GetterLong | OpCode::SetterLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    // The stack looks like: [class][accessor closure]
+                    let Value::Closure(accessor) = self.pop() else {
+                        return self.invalid_bytecode();
+                    };
+                    let Value::Class(class) = self.peek(0) else {
+                        return self.invalid_bytecode();
+                    };
+                    let accessors = if matches!(instruction, OpCode::Getter | OpCode::GetterLong) {
+                        &class.getters
+                    } else {
+                        &class.setters
+                    };
+                    accessors.borrow_mut().insert(into_string(name), accessor);
+                }
+                OpCode::Static | OpCode::StaticLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    // The stack looks like: [class][static method or field value]
+                    let value = self.pop();
+                    let Value::Class(class) = self.peek(0) else {
+                        return self.invalid_bytecode();
+                    };
+                    class.statics.borrow_mut().insert(into_string(name), value);
+                }
+                OpCode::Inherit => {
+                    // The stack looks like: [superclass][subclass]
+                    let Value::Class(superclass) = self.peek(1) else {
+                        self.runtime_error("Superclass must be a class.");
+                        return InterpretResult::RuntimeError;
+                    };
+                    let Value::Class(subclass) = self.peek(0) else {
+                        return self.invalid_bytecode();
+                    };
+                    // Copy-down inheritance, the methods defined in the subclass body later will
+                    // override these ones
+                    let methods = superclass.methods.borrow().clone();
+                    subclass.methods.borrow_mut().extend(methods);
+                    let statics = superclass.statics.borrow().clone();
+                    subclass.statics.borrow_mut().extend(statics);
+                    let getters = superclass.getters.borrow().clone();
+                    subclass.getters.borrow_mut().extend(getters);
+                    let setters = superclass.setters.borrow().clone();
+                    subclass.setters.borrow_mut().extend(setters);
+                    self.pop();
+                }
+                OpCode::GetSuper | OpCode::GetSuperLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    // The stack looks like: [receiver][superclass]
+                    let Value::Class(superclass) = self.pop() else {
+                        return self.invalid_bytecode();
+                    };
+                    let getter = superclass.getters.borrow().get(name.as_str()).cloned();
+                    if let Some(getter) = getter {
+                        if !self.call(getter, 0) {
+                            return InterpretResult::RuntimeError;
+                        }
+                        continue;
+                    }
+                    let Some(method) = superclass.methods.borrow().get(name.as_str()).cloned()
+                    else {
+                        self.runtime_error(&format!("Undefined property '{name}'."));
+                        return InterpretResult::RuntimeError;
+                    };
+                    let receiver = self.pop();
+                    let bound = Rc::new(BoundMethod::new(receiver, method));
+                    self.track(Object::BoundMethod(Rc::clone(&bound)));
+                    self.push(Value::BoundMethod(bound));
+                }
+                OpCode::Import | OpCode::ImportLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    if !self.import(&name) {
+                        return InterpretResult::RuntimeError;
+                    }
+                }
+                OpCode::Invoke
+                | OpCode::InvokeSpread
+                | OpCode::InvokeLong
+                | OpCode::InvokeSpreadLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    let arg_cnt = match instruction {
+                        OpCode::Invoke | OpCode::InvokeLong => self.read_byte(),
+                        _ => match self.unpack_arguments() {
+                            Some(arg_cnt) => arg_cnt,
+                            None => return InterpretResult::RuntimeError,
+                        },
+                    };
+                    if !self.has_values(arg_cnt as usize + 1) {
+                        continue;
+                    }
+                    if !self.invoke(&name, arg_cnt) {
+                        return InterpretResult::RuntimeError;
+                    }
+                }
+                OpCode::SuperInvoke
+                | OpCode::SuperInvokeSpread
+                | OpCode::SuperInvokeLong
+                | OpCode::SuperInvokeSpreadLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    let arg_cnt = match instruction {
+                        OpCode::SuperInvoke | OpCode::SuperInvokeLong => Some(self.read_byte()),
+                        _ => None,
+                    };
+                    // The stack looks like: [receiver][arguments...][superclass], the receiver
+                    // is already in the slot zero of the new call frame
+                    let Value::Class(superclass) = self.pop() else {
+                        return self.invalid_bytecode();
+                    };
+                    let Some(arg_cnt) = arg_cnt.or_else(|| self.unpack_arguments()) else {
+                        return InterpretResult::RuntimeError;
+                    };
+                    if !self.has_values(arg_cnt as usize + 1) {
+                        continue;
+                    }
+                    let Some(method) = superclass.methods.borrow().get(name.as_str()).cloned()
+                    else {
+                        self.runtime_error(&format!("Undefined property '{name}'."));
+                        return InterpretResult::RuntimeError;
+                    };
+                    if !self.call(method, arg_cnt) {
+                        return InterpretResult::RuntimeError;
+                    }
+                }
+                OpCode::GetProperty | OpCode::GetPropertyLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    if let Value::Class(class) = self.peek(0) {
+                        let Some(value) = class.statics.borrow().get(name.as_str()).cloned() else {
+                            self.runtime_error(&format!("Undefined property '{name}'."));
+                            return InterpretResult::RuntimeError;
+                        };
+                        self.pop();
+                        self.push(value);
+                        continue;
+                    }
+                    let Value::Instance(instance) = self.peek(0) else {
+                        let receiver = self.peek(0).clone();
+                        let Some(method) = self.builtin_method(&receiver, &name) else {
+                            if self.builtin_methods.contains_key(receiver.type_name()) {
+                                self.runtime_error(&format!(
+                                    "Undefined method '{name}' of {}.",
+                                    receiver.type_name()
+                                ));
+                            } else {
+                                self.runtime_error("Only instances and classes have properties.");
+                            }
+                            return InterpretResult::RuntimeError;
+                        };
+                        // Bind the receiver, so the method can be called later
+                        let (min_arity, variadic) = (method.min_arity, method.variadic);
+                        let mut bound =
+                            NativeFunction::new(&name, method.arity, move |vm, args| {
+                                let mut args_with_receiver = vec![receiver.clone()];
+                                args_with_receiver.extend_from_slice(args);
+                                (method.function)(vm, &args_with_receiver)
+                            })
+                            .min_arity(min_arity);
+                        bound.variadic = variadic;
+                        #[allow(clippy::useless_conversion)] // not useless with `compact-values`
+                        let bound: NativeRef = bound.into();
+                        self.pop();
+                        self.push(Value::NativeFunc(bound));
+                        continue;
+                    };
+                    // Fields shadow getters, which shadow methods
+                    let field = instance.borrow().fields.get(name.as_str()).cloned();
+                    let getter = instance
+                        .borrow()
+                        .class
+                        .getters
+                        .borrow()
+                        .get(name.as_str())
+                        .cloned();
+                    if let (None, Some(getter)) = (&field, getter) {
+                        // The instance is already in the slot zero of the getter, and the
+                        // result replaces it
+                        if !self.call(getter, 0) {
+                            return InterpretResult::RuntimeError;
+                        }
+                        continue;
+                    }
+                    let value = if let Some(value) = field {
+                        value
+                    } else {
+                        let method = instance
+                            .borrow()
+                            .class
+                            .methods
+                            .borrow()
+                            .get(name.as_str())
+                            .cloned();
+                        let Some(method) = method else {
+                            let instance = Rc::clone(instance);
+                            self.undefined_property(&instance, &name);
+                            return InterpretResult::RuntimeError;
+                        };
+                        let bound = Rc::new(BoundMethod::new(self.peek(0).clone(), method));
+                        self.track(Object::BoundMethod(Rc::clone(&bound)));
+                        Value::BoundMethod(bound)
+                    };
+                    // Replace the instance with the property value
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::SetProperty | OpCode::SetPropertyLong => {
+                    let Some(name) = self.read_name(instruction) else {
+                        return self.invalid_bytecode();
+                    };
+                    // The stack looks like: [instance or class][value]
+                    let value = self.peek(0).clone();
+                    match self.peek(1) {
+                        Value::Instance(instance) => {
+                            let setter = instance
+                                .borrow()
+                                .class
+                                .setters
+                                .borrow()
+                                .get(name.as_str())
+                                .cloned();
+                            if let Some(setter) = setter {
+                                // The setter returns the value, which is what the assignment
+                                // evaluates to
+                                if !self.call(setter, 1) {
+                                    return InterpretResult::RuntimeError;
+                                }
+                                continue;
+                            }
+                            instance
+                                .borrow_mut()
+                                .fields
+                                .insert(into_string(name), value);
+                        }
+                        Value::Class(class) => {
+                            class.statics.borrow_mut().insert(into_string(name), value);
+                        }
+                        _ => {
+                            self.runtime_error("Only instances and classes have fields.");
+                            return InterpretResult::RuntimeError;
+                        }
+                    }
+
+                    // Assignment is an expression, so leave the assigned value on the stack
+                    let value = self.pop();
+                    self.pop();
+                    self.push(value);
                 }
             }
         }
     }
 }
+
+/// The method which overloads the binary operator for the instances on its left
+fn operator_method(op: &str) -> Option<&'static str> {
+    match op {
+        "+" => Some("plus"),
+        "-" => Some("minus"),
+        "*" => Some("times"),
+        "/" => Some("divide"),
+        "%" => Some("modulo"),
+        "**" => Some("power"),
+        ">" => Some("greater"),
+        "<" => Some("less"),
+        _ => None,
+    }
+}
+
+/// An argument in a stack trace: strings in quotes, like in a list, and cut after
+/// `TRACE_ARGUMENT_LEN` characters so a long string or list doesn't hide the trace
+fn trace_argument(value: &Value) -> String {
+    let text = match value {
+        Value::String(s) => format!("{s:?}"),
+        value => value.to_string(),
+    };
+    if text.chars().count() <= TRACE_ARGUMENT_LEN {
+        return text;
+    }
+    let mut text: String = text.chars().take(TRACE_ARGUMENT_LEN - 3).collect();
+    text.push_str("...");
+    text
+}
+
+/// The path which tells the modules apart, however they are imported
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The arithmetic and the comparisons of two integers, or `None` when the result isn't one, e.g.
+/// it overflows, or it's a division or a negative power, which the float arithmetic computes
+fn int_operator(op: &str, a: i64, b: i64) -> Option<Value> {
+    Some(match op {
+        "+" => Value::Int(a.checked_add(b)?),
+        "-" => Value::Int(a.checked_sub(b)?),
+        "*" => Value::Int(a.checked_mul(b)?),
+        // Also `None` for a zero divisor, which the floats tell apart
+        "%" => Value::Int(a.checked_rem(b)?),
+        "**" => Value::Int(a.checked_pow(u32::try_from(b).ok()?)?),
+        ">" => Value::Bool(a > b),
+        "<" => Value::Bool(a < b),
+        _ => return None,
+    })
+}
+
+/// The integer of an operand of the bitwise operators, see [`to_integer`] for the floats
+fn integer_of(value: &Value) -> Option<i64> {
+    match value {
+        Value::Int(n) => Some(*n),
+        Value::Number(n) => Some(to_integer(*n)),
+        _ => None,
+    }
+}
+
+/// Convert a number to a 64-bit integer for the bitwise operators, like JavaScript's `ToInt32`
+/// but with 64 bits: the fraction is dropped, the value wraps around modulo 2^64, and NaN and the
+/// infinities become 0
+fn to_integer(v: f64) -> i64 {
+    const TWO_POW_63: f64 = 9_223_372_036_854_775_808.0;
+    const TWO_POW_64: f64 = 18_446_744_073_709_551_616.0;
+    if !v.is_finite() {
+        return 0;
+    }
+    // `%` is exact for floats, so the result is an integer with |v| < 2^64
+    let mut v = v.trunc() % TWO_POW_64;
+    if v >= TWO_POW_63 {
+        v -= TWO_POW_64;
+    } else if v < -TWO_POW_63 {
+        v += TWO_POW_64;
+    }
+    v as i64
+}
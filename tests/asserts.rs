@@ -0,0 +1,118 @@
+//! The natives aborting the script: `assert`, `error` and `exit`, also through the interpreter
+mod common;
+
+use common::{error, quiet_vm};
+use rustlox::LoxError;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Write the script into a new directory, and return the path of the file
+fn script(name: &str, source: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustlox-asserts-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("main.lox");
+    fs::write(&file, source).unwrap();
+    file
+}
+
+/// Run the interpreter with the arguments, and return its exit code and its stdout
+fn interpreter(args: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rustlox"))
+        .args(args)
+        .output()
+        .expect("The interpreter runs");
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn a_failing_assert_shows_its_message_and_line() {
+    let source = "var x = 1;\nassert(x == 1, \"fine\");\nassert(x > 1, \"x is too small\");\n";
+    let Err(LoxError::RuntimeError {
+        message,
+        stack_trace,
+    }) = quiet_vm().interpret(source)
+    else {
+        panic!("Expected a runtime error");
+    };
+    assert_eq!(message, "Assertion failed: x is too small");
+    assert!(stack_trace[0].starts_with("[line 3:"), "{stack_trace:?}");
+    assert_eq!(error("assert(nil, 42);"), "Assertion failed: 42");
+    assert!(quiet_vm()
+        .interpret("assert(0, \"zero is truthy\");")
+        .is_ok());
+}
+
+#[test]
+fn error_aborts_with_the_value() {
+    assert_eq!(
+        error("error(\"Expect a positive number.\");"),
+        "Expect a positive number."
+    );
+    assert_eq!(error("error(42);"), "42");
+    assert_eq!(error("error([1, \"a\"]);"), "[1, \"a\"]");
+    assert_eq!(
+        error("fun check(n) { if (n < 0) error(\"negative\"); return n; } check(1); check(-1);"),
+        "negative"
+    );
+}
+
+#[test]
+fn exit_stops_the_script_with_the_code() {
+    let mut vm = quiet_vm();
+    assert!(matches!(
+        vm.interpret("exit(3); print \"unreachable\";"),
+        Err(LoxError::Exit(3))
+    ));
+    assert_eq!(error("exit(1.5);"), "Expect an integer exit code, got 1.5.");
+    assert_eq!(error("exit(\"1\");"), "Expect a number, got string.");
+}
+
+#[test]
+fn the_interpreter_exits_with_the_code_of_the_script() {
+    let file = script("exit", "print \"before\";\nexit(7);\nprint \"after\";\n");
+    let file = file.to_str().unwrap();
+    assert_eq!(interpreter(&[file]), (Some(7), "before\n".to_string()));
+
+    let file = script("zero", "exit(0);\n");
+    assert_eq!(interpreter(&[file.to_str().unwrap()]).0, Some(0));
+
+    // A runtime error, e.g. a failing assert, is 70 like clox
+    let file = script("assert", "assert(false, \"no\");\n");
+    assert_eq!(interpreter(&[file.to_str().unwrap()]).0, Some(70));
+
+    // Unless the sandbox denies the process natives
+    let file = script("sandbox", "exit(7);\n");
+    assert_eq!(
+        interpreter(&["--sandbox", file.to_str().unwrap()]).0,
+        Some(70)
+    );
+}
+
+#[test]
+fn the_test_runner_reports_the_failing_asserts() {
+    let passing = script("runner", "assert(1 + 1 == 2, \"math\");\n");
+    let dir = passing.parent().unwrap();
+    fs::write(
+        dir.join("failing.lox"),
+        "\nassert(1 + 1 == 3, \"math is off\");\n",
+    )
+    .unwrap();
+    let (code, out) = interpreter(&["test", dir.to_str().unwrap()]);
+    assert_ne!(code, Some(0));
+    assert!(
+        out.contains(&format!("PASS {}", passing.display())),
+        "{out}"
+    );
+    let failing = dir.join("failing.lox");
+    assert!(
+        out.contains(&format!("FAIL {}", failing.display())),
+        "{out}"
+    );
+    assert!(out.contains("Assertion failed: math is off"), "{out}");
+    assert!(out.contains(&format!("[{}:2:", failing.display())), "{out}");
+}
@@ -0,0 +1,96 @@
+//! The syntax tree, and compiling it
+use rustlox::ast::{parse, NodeKind};
+use rustlox::bytecode;
+use rustlox::compiler::Compiler;
+use rustlox::value::FunctionType;
+
+/// The bytecode of the source code, compiled directly and through the tree
+fn both_ways(source: &str) -> (Vec<u8>, Vec<u8>) {
+    let direct = Compiler::new(FunctionType::Script).compile(source).unwrap();
+    let tree = parse(source).unwrap();
+    let lowered = Compiler::new(FunctionType::Script)
+        .compile_ast(&tree)
+        .unwrap();
+    (bytecode::serialize(&direct), bytecode::serialize(&lowered))
+}
+
+#[test]
+fn the_tree_shows_the_structure() {
+    let tree = parse("var x = 1 + 2 * 3;\nprint -x;").unwrap();
+    let expected = "\
+Program
+  VarDecl x
+    Binary +
+      Literal 1
+      Binary *
+        Literal 2
+        Literal 3
+  PrintStmt
+    Unary -
+      Variable x
+";
+    assert_eq!(tree.to_string(), expected);
+    let kinds: Vec<_> = tree.root.nodes().map(|node| node.kind).collect();
+    assert_eq!(kinds, [NodeKind::VarDecl, NodeKind::PrintStmt]);
+}
+
+#[test]
+fn optional_chains_cover_the_rest_of_the_chain() {
+    let tree = parse("var a; print a?.b.c(1) ?? 2;").unwrap();
+    let expected = "\
+Program
+  VarDecl a
+  PrintStmt
+    Coalesce ??
+      OptionalChain
+        Invoke c
+          Get ?. b
+            Variable a
+          Arguments
+            Literal 1
+      Literal 2
+";
+    assert_eq!(tree.to_string(), expected);
+}
+
+#[test]
+fn the_tree_compiles_to_the_same_bytecode() {
+    let source = r#"
+class Base { init(x) { this.x = x; } }
+class Point < Base {
+  init(x, y = 2, ...rest) { super.init(x); this.y = y; }
+  area { return this.x * this.y; }
+  set width(w) { this.x = w; }
+  static origin = nil;
+  static make() { return Point(0, 0); }
+}
+fun counter() {
+  var i = 0;
+  return fun() { i++; return i; };
+}
+var xs = [1, ...[2, 3]];
+xs[0] = xs[1];
+for (var i = 0; i < 3; i = i + 1) { if (i == 1) continue; print xs[i:]; }
+for (x in 1..=3) { while (false) break; print -x ** 2 ?? x?.y.z(); }
+var p = Point(1);
+p.width = 3;
+print p.area and !(p.y > 1) or ~xs[0] | 1 << 2;
+"#;
+    let (direct, lowered) = both_ways(source);
+    assert_eq!(direct, lowered);
+}
+
+#[test]
+fn the_benchmarks_compile_to_the_same_bytecode() {
+    for entry in std::fs::read_dir("benches/lox").unwrap() {
+        let source = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+        let (direct, lowered) = both_ways(&source);
+        assert_eq!(direct, lowered);
+    }
+}
+
+#[test]
+fn code_which_does_not_compile_has_no_tree() {
+    let errors = parse("print 1 +;").unwrap_err();
+    assert_eq!(errors[0].message, "Expect expression.");
+}
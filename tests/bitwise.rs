@@ -0,0 +1,39 @@
+//! The bitwise operators, on the numbers truncated to 64-bit integers
+mod common;
+
+use common::run;
+use rustlox::VM;
+
+#[test]
+fn the_operators_work_on_the_bits() {
+    let source = "print 6 & 3; print 6 | 3; print 6 ^ 3; print ~5; print 1 << 4; print -16 >> 2;";
+    assert_eq!(run(source), "2\n7\n5\n-6\n16\n-4\n");
+}
+
+#[test]
+fn the_numbers_are_truncated_and_wrapped() {
+    let source = r#"
+print 5.9 & 7; print -5.9 | 0;
+print (0 / 0) | 0; print (1 / 0) | 0;
+print (2 ** 64 + 4096) | 0; print 1 << 65;
+"#;
+    assert_eq!(run(source), "5\n-5\n0\n0\n4096\n2\n");
+}
+
+#[test]
+fn the_precedence_is_between_equality_and_comparison() {
+    let source = r#"
+print 1 | 2 == 3; print 6 & 3 == 2;
+print 1 | 6 ^ 3 & 5;
+print 1 << 2 < 5; print 1 << 1 + 1;
+"#;
+    assert_eq!(run(source), "true\ntrue\n7\ntrue\n4\n");
+}
+
+#[test]
+fn only_numbers_have_bits() {
+    let mut vm = VM::new();
+    for source in [r#"1 & "a";"#, "~nil;", "true << 1;"] {
+        assert!(vm.interpret(source).is_err(), "{source}");
+    }
+}
@@ -0,0 +1,124 @@
+//! The `.loxc` files: a compiled script runs the same after saving and loading it, and a damaged
+//! file is an error rather than a panic
+use rustlox::bytecode::{self, BytecodeError, MAGIC, VERSION};
+use rustlox::output::SharedBuffer;
+use rustlox::value::Function;
+use rustlox::{CollectingReporter, VM};
+
+const SCRIPT: &str = r#"
+class Counter {
+  init(start) { this.n = start; }
+  next() { this.n = this.n + 1; return this.n; }
+}
+class Twice < Counter {
+  next() { super.next(); return super.next(); }
+}
+fun adder(x) { fun add(y) { return x + y; } return add; }
+var c = Twice(10);
+print c.next(); print adder(1)(2.5);
+var xs = [1, "two", nil, true];
+for (var x in xs) print x;
+print "done";
+"#;
+
+/// The output of running the function
+fn run(function: Function) -> String {
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(out.clone()));
+    vm.run_function(function).expect("The script runs");
+    out.contents()
+}
+
+fn compile(source: &str) -> Function {
+    VM::check(source).expect("The script compiles").function
+}
+
+/// A file with the payload and the right checksum for it, so the damage gets past the checksum
+fn with_payload(payload: &[u8]) -> Vec<u8> {
+    let checksum = payload.iter().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    });
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.extend(checksum.to_le_bytes());
+    bytes.extend(payload);
+    bytes
+}
+
+#[test]
+fn a_loaded_script_runs_like_the_compiled_one() {
+    let bytes = bytecode::serialize(&compile(SCRIPT));
+    assert!(bytecode::is_bytecode(&bytes));
+    let loaded = bytecode::deserialize(&bytes).expect("The file loads");
+    // Nothing is lost on the way
+    assert_eq!(bytecode::serialize(&loaded), bytes);
+    assert_eq!(run(loaded), run(compile(SCRIPT)));
+}
+
+#[test]
+fn the_header_is_checked() {
+    let bytes = bytecode::serialize(&compile(SCRIPT));
+    assert_eq!(
+        bytecode::deserialize(SCRIPT.as_bytes()).unwrap_err(),
+        BytecodeError::NotBytecode
+    );
+    assert_eq!(
+        bytecode::deserialize(&bytes[..7]).unwrap_err(),
+        BytecodeError::Truncated
+    );
+    let mut old = bytes.clone();
+    old[4..6].copy_from_slice(&(VERSION - 1).to_le_bytes());
+    assert_eq!(
+        bytecode::deserialize(&old).unwrap_err(),
+        BytecodeError::UnsupportedVersion(VERSION - 1)
+    );
+    let mut damaged = bytes.clone();
+    *damaged.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        bytecode::deserialize(&damaged).unwrap_err(),
+        BytecodeError::ChecksumMismatch
+    );
+}
+
+#[test]
+fn a_truncated_file_is_an_error() {
+    let bytes = bytecode::serialize(&compile(SCRIPT));
+    for len in 0..bytes.len() {
+        assert!(bytecode::deserialize(&bytes[..len]).is_err(), "{len}");
+    }
+    // Also when the checksum matches what's left
+    let payload = &bytes[10..];
+    for len in 0..payload.len() {
+        let error = bytecode::deserialize(&with_payload(&payload[..len])).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                BytecodeError::Truncated | BytecodeError::Malformed(_)
+            ),
+            "{len}: {error:?}"
+        );
+    }
+}
+
+#[test]
+fn a_corrupted_file_is_an_error_or_valid_bytecode() {
+    let bytes = bytecode::serialize(&compile(SCRIPT));
+    let payload = &bytes[10..];
+    let mut loaded = 0;
+    for idx in 0..payload.len() {
+        for flip in [0x01, 0xff] {
+            let mut corrupted = payload.to_vec();
+            corrupted[idx] ^= flip;
+            // The verifier accepts only the code the VM runs without misbehaving
+            if let Ok(function) = bytecode::deserialize(&with_payload(&corrupted)) {
+                let mut vm = VM::with_reporter(Box::new(CollectingReporter::default()));
+                vm.set_stdout(Box::new(SharedBuffer::new()));
+                let _ = vm.run_function(function);
+                loaded += 1;
+            }
+        }
+    }
+    // Most of the damage is noticed, e.g. a changed number constant isn't
+    assert!(loaded < payload.len() * 2);
+}
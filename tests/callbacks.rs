@@ -0,0 +1,158 @@
+//! The natives calling back into Lox, and the host calling the functions of the scripts
+use rustlox::shared::{Rc, RefCell};
+use rustlox::{CollectingReporter, LoxError, Value, VM};
+use std::any::type_name;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// A VM keeping its runtime errors, with an `each(list, f)` native calling `f` on every element
+fn vm_with_each() -> (VM, Rc<RefCell<CollectingReporter>>) {
+    let reporter = Rc::new(RefCell::new(CollectingReporter::default()));
+    let mut vm = VM::with_reporter(Box::new(Rc::clone(&reporter)));
+    vm.register_native("each", 2, |vm, args| {
+        let elements = Vec::<Value>::try_from(&args[0])?;
+        for element in elements {
+            vm.call_value(&args[1], &[element])?;
+        }
+        Ok(Value::Nil)
+    });
+    (vm, reporter)
+}
+
+#[test]
+fn a_native_calls_the_lox_functions() {
+    let (mut vm, _) = vm_with_each();
+    let source = r#"
+var total = 0;
+each([1, 2, 3], fun (n) { total = total + n; });
+class Counter { init(n) { total = total + n * 100; } }
+each([4], Counter);
+each([5], str);
+"#;
+    vm.interpret(source).unwrap();
+    assert_eq!(vm.get_global("total"), Some(Value::Number(406.0)));
+}
+
+#[test]
+fn the_error_of_a_callback_is_reported_once() {
+    let (mut vm, reporter) = vm_with_each();
+    let result = vm.interpret("fun check(n) { if (n > 1) nil(); }\neach([1, 2, 3], check);");
+    assert!(matches!(
+        result,
+        Err(LoxError::RuntimeError { ref message, .. }) if message == "Can only call functions and classes."
+    ));
+    let errors = &reporter.borrow().runtime_errors;
+    assert_eq!(errors.len(), 1);
+    // The trace goes through the callback and the code calling the native
+    assert!(errors[0].stack_trace[0].contains("in check(2)"));
+    assert!(errors[0].stack_trace[1].contains("in <script>"));
+    // The VM is ready for more
+    vm.interpret("var after = 1;").unwrap();
+    assert_eq!(vm.get_global("after"), Some(Value::Number(1.0)));
+}
+
+#[test]
+fn the_host_calls_the_functions_it_got() {
+    let (mut vm, reporter) = vm_with_each();
+    vm.interpret("var handlers = [fun (x) { return x * 2; }, fun (x) { return x.nope; }];")
+        .unwrap();
+    let handlers = Vec::<Value>::try_from(&vm.get_global("handlers").unwrap()).unwrap();
+    assert_eq!(
+        vm.call_value(&handlers[0], &[Value::Number(21.0)]),
+        Ok(Value::Number(42.0))
+    );
+    assert!(vm.call_value(&handlers[1], &[Value::Number(1.0)]).is_err());
+    assert!(vm.call_value(&Value::Nil, &[]).is_err());
+    assert_eq!(reporter.borrow().runtime_errors.len(), 2);
+    assert_eq!(
+        vm.call_value(&handlers[0], &[Value::Number(1.0)]),
+        Ok(Value::Number(2.0))
+    );
+}
+
+#[test]
+fn the_natives_nest_only_so_deep() {
+    let (mut vm, _) = vm_with_each();
+    vm.set_max_frames(10_000);
+    let source = "fun dive(n) { each([n + 1], dive); } dive(0);";
+    match vm.interpret(source) {
+        Err(LoxError::RuntimeError { message, .. }) => {
+            assert_eq!(message, "Too many natives calling back into Lox.");
+        }
+        result => panic!("Expected a runtime error, got {result:?}"),
+    }
+    // A shallow recursion is fine
+    let source =
+        "var depth = 0; fun down(n) { depth = n; if (n < 20) each([n + 1], down); } down(0);";
+    vm.interpret(source).unwrap();
+    assert_eq!(vm.get_global("depth"), Some(Value::Number(20.0)));
+}
+
+/// An object of the host, which the scripts get as a userdata
+struct Account {
+    balance: AtomicI64,
+}
+
+/// Another type of userdata, which the natives of `Account` don't take
+struct Logger;
+
+#[test]
+fn the_userdata_goes_through_the_script_and_back_to_the_host() {
+    let (mut vm, reporter) = vm_with_each();
+    let account = Account {
+        balance: AtomicI64::new(10),
+    };
+    vm.set_global("account", Value::user_data(account));
+    vm.set_global("logger", Value::user_data(Logger));
+    vm.register_native("deposit", 2, |_vm, args| {
+        let account: &Account = args[0].downcast_ref()?;
+        let amount = f64::try_from(&args[1])? as i64;
+        Ok(Value::Int(
+            account.balance.fetch_add(amount, Ordering::Relaxed) + amount,
+        ))
+    });
+    let source = r#"
+class Wallet { init(account) { this.account = account; } }
+var wallet = Wallet(account);
+deposit(wallet.account, 5);
+var accounts = [account];
+each(accounts, fun (a) { deposit(a, 1); });
+var balance = deposit([account][0], 0);
+var same = accounts[0] == account;
+var shown = str(account);
+"#;
+    vm.interpret(source).unwrap();
+    assert_eq!(vm.get_global("balance"), Some(Value::Int(16)));
+    assert_eq!(vm.get_global("same"), Some(Value::Bool(true)));
+    assert_eq!(
+        vm.get_global("shown"),
+        Some(
+            format!("<userdata {}>", type_name::<Account>())
+                .as_str()
+                .into()
+        )
+    );
+    let account = vm.get_global("account").unwrap();
+    let account = account.downcast_ref::<Account>().unwrap();
+    assert_eq!(account.balance.load(Ordering::Relaxed), 16);
+
+    // The wrong type is a runtime error of the script, not a panic of the host
+    for (source, got) in [
+        (
+            "deposit(logger, 1);",
+            format!("a {}", type_name::<Logger>()),
+        ),
+        ("deposit(1, 1);", "number".to_string()),
+    ] {
+        match vm.interpret(source) {
+            Err(LoxError::RuntimeError { message, .. }) => assert_eq!(
+                message,
+                format!("Expect a {}, got {got}.", type_name::<Account>())
+            ),
+            result => panic!("Expected a runtime error, got {result:?}"),
+        }
+    }
+    assert_eq!(reporter.borrow().runtime_errors.len(), 2);
+    let logger = vm.get_global("logger").unwrap();
+    assert!(logger.downcast_ref::<Account>().is_err());
+    assert!(logger.downcast_ref::<Logger>().is_ok());
+}
@@ -0,0 +1,32 @@
+//! The capabilities of the natives reaching outside of the VM
+mod common;
+
+use common::quiet_vm;
+use rustlox::vm::Capability;
+use rustlox::LoxError;
+
+#[test]
+fn a_new_vm_allows_the_natives() {
+    let mut vm = quiet_vm();
+    for capability in Capability::ALL {
+        assert!(vm.is_allowed(capability));
+    }
+    vm.interpret("getEnv(\"HOME\"); clock(); timeMillis();")
+        .unwrap();
+}
+
+#[test]
+fn a_denied_capability_is_a_runtime_error() {
+    let mut vm = quiet_vm();
+    vm.deny(Capability::Env);
+    let Err(LoxError::RuntimeError { message, .. }) = vm.interpret("getEnv(\"HOME\");") else {
+        panic!("Expect a runtime error");
+    };
+    assert_eq!(
+        message,
+        "'getEnv' needs the env capability, which the VM denies."
+    );
+    vm.interpret("clock();").unwrap();
+    vm.allow(Capability::Env);
+    vm.interpret("getEnv(\"HOME\");").unwrap();
+}
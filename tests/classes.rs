@@ -0,0 +1,121 @@
+//! The members of the classes
+mod common;
+
+use common::run;
+use rustlox::VM;
+
+#[test]
+fn static_methods_are_called_on_the_class() {
+    let source = r#"
+class Math {
+  static square(x) { return x * x; }
+  static cube(x) { return Math.square(x) * x; }
+}
+var square = Math.square;
+print Math.square(2); print Math.cube(3); print square(5);
+"#;
+    assert_eq!(run(source), "4\n27\n25\n");
+}
+
+#[test]
+fn class_level_fields_are_assignable() {
+    let source = r#"
+class Counter {
+  static count = 0;
+  init() { Counter.count = Counter.count + 1; }
+}
+Counter(); Counter();
+Counter.label = "made";
+print Counter.count; print Counter.label;
+"#;
+    assert_eq!(run(source), "2\nmade\n");
+}
+
+#[test]
+fn subclasses_inherit_the_statics() {
+    let source = r#"
+class Shape { static sides() { return 0; } }
+class Square < Shape { static sides() { return 4; } }
+class Blob < Shape {}
+print Square.sides(); print Blob.sides();
+"#;
+    assert_eq!(run(source), "4\n0\n");
+}
+
+#[test]
+fn static_members_have_no_instance() {
+    let mut vm = VM::new();
+    assert!(vm
+        .interpret("class A { static f() { return this; } }")
+        .is_err());
+    assert!(vm
+        .interpret("class B {} class C < B { static f() { return super.f(); } }")
+        .is_err());
+    assert!(vm.interpret("class D {} print D.missing;").is_err());
+}
+
+#[test]
+fn getters_and_setters_run_on_property_access() {
+    let source = r#"
+class Circle {
+  init(r) { this.r = r; }
+  area { return 3 * this.r * this.r; }
+  diameter { return this.r * 2; }
+  set diameter(d) { this.r = d / 2; }
+}
+var c = Circle(2);
+print c.area;
+print c.diameter = 10;
+print c.r; print c.diameter;
+"#;
+    assert_eq!(run(source), "12\n10\n5\n10\n");
+}
+
+#[test]
+fn getters_are_inherited_and_reachable_through_super() {
+    let source = r#"
+class A { name { return "A"; } adder { return fun(x) { return x + 1; }; } }
+class B < A { name { return super.name + "B"; } }
+var b = B();
+print b.name; print b.adder(1);
+"#;
+    assert_eq!(run(source), "AB\n2\n");
+}
+
+#[test]
+fn a_setter_takes_one_parameter_and_returns_nothing() {
+    let mut vm = VM::new();
+    assert!(vm.interpret("class A { set x(a, b) {} }").is_err());
+    assert!(vm.interpret("class B { set x(v) { return v; } }").is_err());
+    // `set` followed by a parameter list is an ordinary method
+    assert!(vm
+        .interpret("class C { set(v) { return v; } } C().set(1);")
+        .is_ok());
+}
+
+#[test]
+fn instances_overload_the_operators() {
+    let source = r#"
+class Vec {
+  init(x, y) { this.x = x; this.y = y; }
+  plus(other) { return Vec(this.x + other.x, this.y + other.y); }
+  times(k) { return Vec(this.x * k, this.y * k); }
+  less(other) { return this.x < other.x; }
+  equals(other) { return this.x == other.x and this.y == other.y; }
+  toString() { return "(" + str(this.x) + ", " + str(this.y) + ")"; }
+}
+var a = Vec(1, 2);
+print a + Vec(3, 4); print a * 2;
+print a == Vec(1, 2); print a != Vec(1, 2);
+print a < Vec(5, 0); print a >= Vec(5, 0);
+"#;
+    assert_eq!(run(source), "(4, 6)\n(2, 4)\ntrue\nfalse\ntrue\nfalse\n");
+}
+
+#[test]
+fn instances_without_the_methods_keep_the_builtin_operators() {
+    let mut vm = VM::new();
+    assert!(vm.interpret("class P {} print P() + 1;").is_err());
+    let source = "class Q {} var q = Q(); print q == q; print q != Q();";
+    assert_eq!(run(source), "true\ntrue\n");
+}
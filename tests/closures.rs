@@ -0,0 +1,106 @@
+//! The variables captured by the closures, in particular by the ones created in loops
+mod common;
+
+use common::run;
+
+#[test]
+fn a_closed_upvalue_outlives_its_scope() {
+    let source = r#"
+fun counter() {
+  var n = 0;
+  fun inc() { n = n + 1; return n; }
+  return inc;
+}
+var a = counter(); var b = counter();
+print a(); print a(); print b();
+"#;
+    assert_eq!(run(source), "1\n2\n1\n");
+}
+
+#[test]
+fn the_closures_of_one_scope_share_the_closed_variable() {
+    let source = r#"
+var get; var set;
+{
+  var x = "before";
+  fun g() { return x; }
+  fun s(v) { x = v; }
+  get = g; set = s;
+  x = "assigned in scope";
+}
+print get();
+set("after");
+print get();
+"#;
+    assert_eq!(run(source), "assigned in scope\nafter\n");
+}
+
+#[test]
+fn each_iteration_has_its_own_loop_variable() {
+    let source = r#"
+var f0; var f1; var f2;
+for (var i = 0; i < 3; i = i + 1) {
+  fun f() { return i; }
+  if (i == 0) f0 = f;
+  if (i == 1) f1 = f;
+  if (i == 2) f2 = f;
+}
+print f0(); print f1(); print f2();
+"#;
+    assert_eq!(run(source), "0\n1\n2\n");
+}
+
+#[test]
+fn continue_and_break_keep_the_iterations_apart() {
+    let source = r#"
+var f0; var f2;
+for (var i = 0; i < 4; i = i + 1) {
+  if (i == 1) continue;
+  fun f() { return i; }
+  if (i == 0) f0 = f;
+  if (i == 2) { f2 = f; break; }
+}
+print f0(); print f2();
+"#;
+    assert_eq!(run(source), "0\n2\n");
+}
+
+#[test]
+fn the_body_assigning_the_loop_variable_changes_the_loop() {
+    let source = r#"
+var n = 0;
+for (var i = 0; i < 10; i = i + 1) { i = i + 1; n = n + 1; }
+print n;
+"#;
+    assert_eq!(run(source), "5\n");
+}
+
+#[test]
+fn a_closure_assigning_its_copy_leaves_the_loop_alone() {
+    let source = r#"
+var inc;
+var n = 0;
+for (var i = 0; i < 3; i = i + 1) {
+  fun g() { i = i + 100; return i; }
+  if (i == 0) inc = g;
+  n = n + 1;
+}
+print inc(); print inc(); print n;
+"#;
+    assert_eq!(run(source), "100\n200\n3\n");
+}
+
+#[test]
+fn each_iteration_of_a_block_has_its_own_locals() {
+    let source = r#"
+var w = 0; var g0; var g1;
+while (w < 2) {
+  var c = w;
+  fun h() { return c; }
+  if (w == 0) g0 = h; else g1 = h;
+  w = w + 1;
+}
+print g0(); print g1();
+"#;
+    assert_eq!(run(source), "0\n1\n");
+}
@@ -0,0 +1,43 @@
+//! The line comments and the nested block comments
+mod common;
+
+use common::run;
+use rustlox::{LoxError, VM};
+
+#[test]
+fn block_comments_nest() {
+    let source = r#"
+/* outer /* inner */ still a comment */
+print 1; /* trailing */ print /* inline */ 2;
+/* /* /* deep */ */ */ print 3; // a line comment
+"#;
+    assert_eq!(run(source), "1\n2\n3\n");
+}
+
+#[test]
+fn a_slash_alone_divides() {
+    assert_eq!(run("print 6 / 3; print 8/2;"), "2\n4\n");
+}
+
+#[test]
+fn block_comments_count_their_lines() {
+    let mut vm = VM::new();
+    let source = "/* one\ntwo\nthree */\nnil();";
+    match vm.interpret(source) {
+        Err(LoxError::RuntimeError { stack_trace, .. }) => {
+            assert!(stack_trace[0].starts_with("[line 4:"), "{stack_trace:?}")
+        }
+        other => panic!("Expected a runtime error, got {other:?}"),
+    }
+}
+
+#[test]
+fn an_unterminated_block_comment_is_an_error() {
+    let mut vm = VM::new();
+    match vm.interpret("print 1; /* open /* nested */ never closed") {
+        Err(LoxError::CompileError(diagnostics)) => {
+            assert_eq!(diagnostics[0].message, "Unterminated block comment.")
+        }
+        other => panic!("Expected a compile error, got {other:?}"),
+    }
+}
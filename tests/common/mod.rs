@@ -0,0 +1,39 @@
+//! The helpers the integration tests share, each test file with `mod common;`
+// Every test file is its own crate, which uses only some of them
+#![allow(dead_code)]
+
+use rustlox::output::SharedBuffer;
+use rustlox::{LoxError, WriterReporter, VM};
+use std::io;
+
+/// The output of the script
+pub fn run(source: &str) -> String {
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(out.clone()));
+    vm.interpret(source).expect("The script runs");
+    out.contents()
+}
+
+/// A VM which doesn't report the errors, for the tests expecting them
+pub fn quiet_vm() -> VM {
+    VM::with_reporter(Box::new(WriterReporter::new(Box::new(io::sink()))))
+}
+
+/// The message of the runtime error of the script
+pub fn error(source: &str) -> String {
+    match quiet_vm().interpret(source) {
+        Err(LoxError::RuntimeError { message, .. }) => message,
+        result => panic!("Expected a runtime error, got {result:?}"),
+    }
+}
+
+/// The messages of the compile errors of the script
+pub fn compile_errors(source: &str) -> Vec<String> {
+    match quiet_vm().interpret(source) {
+        Err(LoxError::CompileError(diagnostics)) => {
+            diagnostics.into_iter().map(|d| d.message).collect()
+        }
+        result => panic!("Expected a compile error, got {result:?}"),
+    }
+}
@@ -0,0 +1,77 @@
+//! The conformance runner, over the tests in tests/suite, and over the test suite of Crafting
+//! Interpreters when `LOX_TEST_SUITE` is its `test` directory
+use rustlox::conformance::{run_test, test_files, Expectations};
+use std::path::Path;
+
+fn run_suite(dir: &Path) {
+    let mut failed = vec![];
+    for file in test_files(dir).unwrap() {
+        let failures = run_test(&std::fs::read_to_string(&file).unwrap());
+        if !failures.is_empty() {
+            failed.push(format!(
+                "{}:\n    {}",
+                file.display(),
+                failures.join("\n    ")
+            ));
+        }
+    }
+    assert!(failed.is_empty(), "{}", failed.join("\n"));
+}
+
+#[test]
+fn the_bundled_suite_passes() {
+    run_suite(Path::new("tests/suite"));
+}
+
+#[test]
+fn the_official_suite_passes() {
+    if let Some(dir) = std::env::var_os("LOX_TEST_SUITE") {
+        run_suite(Path::new(&dir));
+    }
+}
+
+#[test]
+fn the_comments_tell_the_expectations() {
+    let source = "\
+print 1; // expect: 1
+print; // Error at ';': Expect expression.
+// [java line 3] Error at end: Expect ';'.
+// [c line 4] Error at end: Expect ';'.
+foo(); // expect runtime error: Undefined variable 'foo'.
+";
+    let expectations = Expectations::parse(source);
+    assert_eq!(expectations.output, ["1"]);
+    assert_eq!(
+        expectations.compile_errors,
+        [
+            "[line 2] Error at ';': Expect expression.",
+            "[line 4] Error at end: Expect ';'.",
+        ]
+    );
+    assert_eq!(
+        expectations.runtime_error,
+        Some(("Undefined variable 'foo'.".to_string(), 5))
+    );
+    assert_eq!(expectations.exit_code(), 65);
+}
+
+#[test]
+fn the_differences_are_reported() {
+    let failures = run_test("print 1; // expect: 2\nprint 3;\n");
+    assert_eq!(
+        failures,
+        [
+            "Expected output '2' but got '1'.",
+            "Got output '3' when none was expected.",
+        ]
+    );
+    let failures = run_test("print nil(); // expect: nil\n");
+    assert_eq!(
+        failures,
+        [
+            "Missing expected output 'nil'.",
+            "Unexpected runtime error 'Can only call functions and classes.' on line 1.",
+            "Expected exit code 0 but got 70.",
+        ]
+    );
+}
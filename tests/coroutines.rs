@@ -0,0 +1,191 @@
+//! The coroutines, which a function with a `yield` makes when called, and `resume` runs
+mod common;
+
+use common::{compile_errors, error, quiet_vm, run};
+use rustlox::output::SharedBuffer;
+use rustlox::value::{CoroutineStatus, NativeError};
+use rustlox::{Value, VM};
+
+#[test]
+fn yield_and_resume_pass_values_both_ways() {
+    let source = r#"
+fun accumulate(total) {
+  while (true) {
+    var n = yield total;
+    if (n == nil) return "total " + str(total);
+    total = total + n;
+  }
+}
+var co = accumulate(10);
+print resume(co, "ignored");
+print resume(co, 1);
+print resume(co, 2);
+print isDone(co);
+print resume(co, nil);
+print isDone(co);
+"#;
+    assert_eq!(run(source), "10\n11\n13\nfalse\ntotal 13\ntrue\n");
+}
+
+#[test]
+fn a_bare_yield_yields_nil() {
+    let source = r#"
+fun gen() { yield; return 1; }
+var co = gen();
+print resume(co, nil);
+print resume(co, nil);
+"#;
+    assert_eq!(run(source), "nil\n1\n");
+}
+
+#[test]
+fn resuming_a_finished_coroutine_is_a_runtime_error() {
+    let source = r#"
+fun once() { yield 1; }
+var co = once();
+resume(co, nil);
+resume(co, nil);
+resume(co, nil);
+"#;
+    assert_eq!(error(source), "Can't resume a finished coroutine.");
+    assert_eq!(
+        error("fun gen() { yield 1; } resume(gen, nil);"),
+        "Expect a coroutine, got function."
+    );
+}
+
+#[test]
+fn a_coroutine_aborted_by_an_error_is_done() {
+    let source = r#"
+fun broken() { yield 1; return nil + 1; }
+var co = broken();
+resume(co, nil);
+resume(co, nil);
+"#;
+    assert_eq!(
+        error(source),
+        "Operands must be two numbers or two strings, got nil and number for '+'."
+    );
+    let mut vm = quiet_vm();
+    assert!(vm.interpret(source).is_err());
+    let Some(Value::Coroutine(coroutine)) = vm.get_global("co") else {
+        panic!("Expected a coroutine");
+    };
+    assert_eq!(coroutine.status(), CoroutineStatus::Done);
+}
+
+#[test]
+fn yield_outside_a_function_is_a_compile_error() {
+    assert_eq!(
+        compile_errors("yield 1;"),
+        ["Can't yield from top-level code."]
+    );
+    assert_eq!(
+        compile_errors("class A { init() { yield 1; } }"),
+        ["Can't yield from an initializer."]
+    );
+}
+
+#[test]
+fn coroutines_nest() {
+    let source = r#"
+fun inner(n) {
+  for (var i = 0; i < n; i++) yield "inner " + str(i);
+}
+fun outer() {
+  var co = inner(2);
+  var value = resume(co, nil);
+  while (!isDone(co)) {
+    yield value;
+    value = resume(co, nil);
+  }
+  yield "outer";
+}
+var co = outer();
+var value = resume(co, nil);
+while (!isDone(co)) {
+  print value;
+  value = resume(co, nil);
+}
+"#;
+    assert_eq!(run(source), "inner 0\ninner 1\nouter\n");
+}
+
+#[test]
+fn a_coroutine_shares_its_captured_variables() {
+    let source = r#"
+fun counter() {
+  var count = 0;
+  fun gen() {
+    while (true) {
+      count = count + 1;
+      yield count;
+    }
+  }
+  fun peek() { return count; }
+  return [gen(), peek];
+}
+var parts = counter();
+var co = parts[0];
+var peek = parts[1];
+print resume(co, nil);
+print resume(co, nil);
+print peek();
+
+fun local() {
+  var seen = [];
+  fun remember(x) { seen.push(x); }
+  fun gen() {
+    var n = 1;
+    fun add() { n = n * 10; return n; }
+    remember(yield add());
+    remember(yield add());
+  }
+  var co = gen();
+  resume(co, nil);
+  resume(co, "a");
+  resume(co, "b");
+  return seen;
+}
+print local();
+"#;
+    assert_eq!(run(source), "1\n2\n2\n[\"a\", \"b\"]\n");
+}
+
+#[test]
+fn the_status_goes_from_new_to_suspended_to_running_to_done() {
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(out.clone()));
+    vm.register_native("status", 1, |_vm, args| match &args[0] {
+        Value::Coroutine(coroutine) => Ok(format!("{:?}", coroutine.status()).as_str().into()),
+        value => Err(NativeError::new(format!(
+            "Expect a coroutine, got {}.",
+            value.type_name()
+        ))),
+    });
+    let source = r#"
+var co;
+fun gen() {
+  print status(co);
+  yield 1;
+}
+co = gen();
+print status(co);
+resume(co, nil);
+print status(co);
+resume(co, nil);
+print status(co);
+"#;
+    vm.interpret(source).expect("The script runs");
+    assert_eq!(out.contents(), "New\nRunning\nSuspended\nDone\n");
+    let Some(Value::Coroutine(coroutine)) = vm.get_global("co") else {
+        panic!("Expected a coroutine");
+    };
+    assert_eq!(coroutine.status(), CoroutineStatus::Done);
+
+    assert_eq!(
+        error("var co; fun gen() { resume(co, nil); yield; } co = gen(); resume(co, nil);"),
+        "Can't resume a running coroutine."
+    );
+}
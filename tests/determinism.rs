@@ -0,0 +1,58 @@
+//! The deterministic mode, whose runs give the same output every time
+use rustlox::output::SharedBuffer;
+use rustlox::vm::Capability;
+use rustlox::VM;
+
+/// The output of the script on a new VM, deterministic with `seed` if given
+fn run(source: &str, seed: Option<u64>) -> String {
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.allow(Capability::Time);
+    vm.set_deterministic(seed);
+    vm.set_stdout(Box::new(out.clone()));
+    vm.interpret(source).expect("The script runs");
+    out.contents()
+}
+
+const RANDOM: &str = "for (var i = 0; i < 5; i = i + 1) print random();";
+
+#[test]
+fn the_random_numbers_follow_the_seed() {
+    assert_eq!(run(RANDOM, Some(1)), run(RANDOM, Some(1)));
+    assert_ne!(run(RANDOM, Some(1)), run(RANDOM, Some(2)));
+    assert_ne!(run(RANDOM, None), run(RANDOM, None));
+    for line in run(RANDOM, Some(3)).lines() {
+        let number: f64 = line.parse().unwrap();
+        assert!((0.0..1.0).contains(&number));
+    }
+}
+
+#[test]
+fn the_clock_is_virtual() {
+    let source = r#"
+print clock();
+var start = timeMillis();
+sleep(3600);
+print timeMillis() - start;
+// Waiting for the clock ends
+var until = clock() + 1;
+while (clock() < until) {}
+print formatTime(clock(), "%Y-%m-%d %H:%M:%S");
+"#;
+    assert_eq!(
+        run(source, Some(0)),
+        "0.001\n3600001\n1970-01-01 01:00:01\n"
+    );
+}
+
+#[test]
+fn turning_it_off_goes_back_to_the_system_clock() {
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.allow(Capability::Time);
+    vm.set_stdout(Box::new(out.clone()));
+    vm.set_deterministic(Some(0));
+    vm.set_deterministic(None);
+    vm.interpret("print clock() > 1000000000;").unwrap();
+    assert_eq!(out.contents(), "true\n");
+}
@@ -0,0 +1,252 @@
+//! The errors and the warnings as the reporters get them
+mod common;
+
+use common::quiet_vm;
+use rustlox::output::SharedBuffer;
+use rustlox::shared::{Rc, RefCell};
+use rustlox::vm::Capability;
+use rustlox::{
+    CollectingReporter, Diagnostic, JsonReporter, LoxError, Severity, WriterReporter, VM,
+};
+use std::fs;
+
+/// What the JSON reporter prints for the script, which runs as the file `main.lox`
+fn json_errors(source: &str) -> String {
+    let errors = SharedBuffer::new();
+    let mut vm = VM::with_reporter(Box::new(JsonReporter::new(Box::new(errors.clone()))));
+    let _ = vm.interpret_file("main.lox", source);
+    errors.contents()
+}
+
+#[test]
+fn compile_errors_and_warnings_are_json_lines() {
+    assert_eq!(
+        json_errors("var a = 1;\nprint a +;"),
+        r#"{"severity":"error","message":"Expect expression.","file":"main.lox","line":2,"column":10,"span":{"start":20,"end":21}}"#
+            .to_string()
+            + "\n"
+    );
+    assert_eq!(
+        json_errors("{ var \"é\"; var y; }"),
+        concat!(
+            r#"{"severity":"error","message":"Expect variable name.","file":"main.lox","line":1,"column":7,"span":{"start":6,"end":10}}"#,
+            "\n"
+        )
+    );
+    assert_eq!(
+        json_errors("{ var y; }"),
+        concat!(
+            r#"{"severity":"warning","message":"Unused local variable 'y'.","file":"main.lox","line":1,"column":7,"span":{"start":6,"end":7}}"#,
+            "\n"
+        )
+    );
+}
+
+#[test]
+fn runtime_errors_have_the_innermost_location_and_the_stack_trace() {
+    let output = json_errors("fun f(x) {\n  return \"é\" + x;\n}\nf(1);");
+    assert!(
+        output.starts_with(
+            r#"{"severity":"error","message":"Operands must be two numbers or two strings, got string and number for '+'.","file":"main.lox","line":2,"column":16,"span":{"start":27,"end":28},"stack_trace":["[main.lox:2:16] in f"#
+        ),
+        "{output}"
+    );
+    assert_eq!(output.lines().count(), 1);
+}
+
+#[test]
+fn the_instructions_map_back_to_their_tokens() {
+    let source = "var point = 1;\nfun f() {\n  return point.longer;\n}\nf();";
+    let mut vm = quiet_vm();
+    let Err(LoxError::RuntimeError { stack_trace, .. }) = vm.interpret(source) else {
+        panic!("Expected a runtime error");
+    };
+    assert_eq!(
+        stack_trace[0],
+        "[line 3:16] in f()\n      return point.longer;\n                   ^^^^^^"
+    );
+
+    let script = VM::check(source).unwrap().function;
+    let texts: Vec<_> = (0..script.chunk.code.len())
+        .filter_map(|offset| script.source_text(offset))
+        .collect();
+    // `Constant 1; DefineGlobal point; Closure f; DefineGlobal f; GetGlobal f; Call 0; Pop`, and
+    // the `Nil; Return` at the end of the code
+    assert_eq!(
+        texts,
+        ["1", "1", ";", ";", "}", "}", "}", "}", "f", "f", ")", ")", ";", "", ""]
+    );
+    let offset = script.chunk.code.len() - 1;
+    assert_eq!(script.source_line(offset), Some("f();"));
+}
+
+/// The stack trace of the runtime error of `source`, without the lines of code under the entries
+fn stack_trace(vm: &mut VM, source: &str) -> Vec<String> {
+    let Err(LoxError::RuntimeError { stack_trace, .. }) = vm.interpret(source) else {
+        panic!("Expected a runtime error");
+    };
+    stack_trace
+        .into_iter()
+        .map(|entry| entry.lines().next().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn the_stack_trace_shows_the_arguments() {
+    let mut vm = quiet_vm();
+    let source = r#"
+class A { m(x) { return x + nil; } }
+fun f(a, b, c) { return A().m(c); }
+f("a string", [1, "2", nil], "a string longer than the rest");
+"#;
+    assert_eq!(
+        stack_trace(&mut vm, source),
+        [
+            r#"[line 2:29] in m("a string longer than...)"#,
+            r#"[line 3:32] in f("a string", [1, "2", nil], "a string longer than...)"#,
+            "[line 4:61] in <script>",
+        ]
+    );
+}
+
+#[test]
+fn deep_recursion_is_collapsed() {
+    let mut vm = quiet_vm();
+    vm.set_max_frames(1000);
+    let source =
+        "fun count(n) {\n  if (n == 0) return nil();\n  return count(n - 1);\n}\ncount(500);";
+    assert_eq!(
+        stack_trace(&mut vm, source),
+        [
+            "[line 2:26] in count(0)",
+            "[line 3:21] in count(1)",
+            "[line 3:21] in count(2)",
+            "[line 3:21] in count(3)",
+            "... 497 more frames like this",
+            "[line 5:10] in <script>",
+        ]
+    );
+
+    vm.set_max_trace_frames(Some(3));
+    assert_eq!(
+        stack_trace(&mut vm, source),
+        [
+            "[line 2:26] in count(0)",
+            "[line 3:21] in count(1)",
+            "[line 3:21] in count(2)",
+            "... 499 more frames",
+        ]
+    );
+}
+
+#[test]
+fn the_errors_name_the_file_of_the_code() {
+    let dir = std::env::temp_dir().join(format!("rustlox-files-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("lib.lox"), "fun half(x) {\n  return x / nil;\n}\n").unwrap();
+    let main = dir.join("main.lox").display().to_string();
+    let lib = dir.join("lib.lox").display().to_string();
+
+    let mut vm = quiet_vm();
+    vm.allow(Capability::Fs);
+    let Err(LoxError::RuntimeError { stack_trace, .. }) =
+        vm.interpret_file(&main, "import \"lib.lox\";\nhalf(4);")
+    else {
+        panic!("Expected a runtime error");
+    };
+    let frames: Vec<_> = stack_trace
+        .iter()
+        .map(|entry| entry.lines().next())
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            Some(format!("[{lib}:2:14] in half(4)").as_str()),
+            Some(format!("[{main}:2:7] in <script>").as_str()),
+        ]
+    );
+
+    let Err(error) = vm.interpret_file(&main, "print 1 +;") else {
+        panic!("Expected a compile error");
+    };
+    assert!(
+        error
+            .to_string()
+            .starts_with(&format!("[{main}:1:10] Error at ';': Expect expression.")),
+        "{error}"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// The notes of the warnings and the runtime error of `source`, linted
+fn notes(source: &str) -> Vec<String> {
+    let reporter = Rc::new(RefCell::new(CollectingReporter::default()));
+    let mut vm = VM::with_reporter(Box::new(Rc::clone(&reporter)));
+    vm.set_lint(true);
+    let _ = vm.interpret(source);
+    let reporter = reporter.borrow();
+    let warnings = reporter.diagnostics.iter().map(|d| &d.notes);
+    let errors = reporter.runtime_errors.iter().map(|e| &e.notes);
+    warnings.chain(errors).flatten().cloned().collect()
+}
+
+#[test]
+fn misspelled_names_get_a_hint() {
+    assert_eq!(
+        notes("var counter = 1;\nprint conter;"),
+        [
+            "did you mean 'counter'?",
+            // The runtime error has the same hint
+            "did you mean 'counter'?"
+        ]
+    );
+    assert_eq!(
+        notes("class A { init() { this.width = 1; } area() {} }\nprint A().widht;"),
+        ["did you mean 'width'?"]
+    );
+    assert_eq!(
+        notes("class A { area() {} }\nA().aera();"),
+        ["did you mean 'area'?"]
+    );
+    // Nothing is close enough
+    assert!(notes("var counter = 1;\nprint total;").is_empty());
+    assert!(json_errors("var counter = 1;\nprint conter;")
+        .contains(r#""notes":["did you mean 'counter'?"]"#));
+}
+
+#[test]
+fn diagnostics_may_be_colored() {
+    let diagnostic = Diagnostic {
+        severity: Severity::Warning,
+        file: Some("main.lox".to_string()),
+        line: 1,
+        column: 5,
+        span: rustlox::scanner::Span {
+            start: 4,
+            len: 1,
+            line: 1,
+        },
+        location: " at 'y'".to_string(),
+        message: "Unused local variable 'y'.".to_string(),
+        source_line: "var y;".to_string(),
+        notes: vec!["prefix it with an underscore".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        diagnostic.to_string(),
+        "[main.lox:1:5] Warning at 'y': Unused local variable 'y'.\n    var y;\n        ^\n    = note: prefix it with an underscore"
+    );
+    assert_eq!(
+        diagnostic.render(true),
+        "\x1b[1;34m[main.lox:1:5]\x1b[0m \x1b[1;33mWarning\x1b[0m\x1b[1m at 'y': Unused local variable 'y'.\x1b[0m\n    var y;\n        \x1b[1;33m^\x1b[0m\n    \x1b[1;36m= note\x1b[0m: prefix it with an underscore"
+    );
+
+    let errors = rustlox::output::SharedBuffer::new();
+    let reporter = WriterReporter::new(Box::new(errors.clone())).with_color(true);
+    let mut vm = VM::with_reporter(Box::new(reporter));
+    let _ = vm.interpret("nil();");
+    assert_eq!(
+        errors.contents(),
+        "\x1b[1;31mCan only call functions and classes.\x1b[0m\n\x1b[1;34m[line 1:5]\x1b[0m in <script>\n    nil();\n        \x1b[1;31m^\x1b[0m\n"
+    );
+}
@@ -0,0 +1,59 @@
+//! The disassembler, which the `dis` native shows a function with
+mod common;
+
+use common::run;
+
+#[test]
+fn dis_shows_the_instructions_of_a_function() {
+    let source = r#"
+fun pick(n) {
+  if (n > 1) return "big";
+  return nil;
+}
+print dis(pick);
+"#;
+    let out = run(source);
+    let lines: Vec<_> = out.lines().map(str::trim_end).collect();
+    assert_eq!(
+        lines,
+        [
+            "== <fn pick> ==",
+            "0000    3 OP_GET_LOCAL     0001",
+            "0002    | OP_CONSTANT      0000 '1'",
+            "0004    | OP_GREATER",
+            "0005    | OP_JUMP_IF_ELSE  0005 -> 15",
+            "0008    | OP_POP",
+            "0009    | OP_CONSTANT      0001 'big'",
+            "0011    | OP_RETURN",
+            "0012    | OP_JUMP          0012 -> 16",
+            "0015    | OP_POP",
+            "0016    4 OP_NIL",
+            "0017    | OP_RETURN",
+            "0018    5 OP_NIL",
+            "0019    | OP_RETURN",
+            "",
+        ]
+    );
+}
+
+#[test]
+fn dis_shows_a_loop_jumping_back() {
+    let source = r#"
+fun count() {
+  var i = 0;
+  while (i < 3) i = i + 1;
+}
+print dis(count);
+"#;
+    let out = run(source);
+    assert!(
+        out.lines()
+            .any(|line| line.contains("OP_LOOP") && line.ends_with("-> 2")),
+        "{out}"
+    );
+}
+
+#[test]
+fn dis_of_anything_but_a_function_is_nil() {
+    assert_eq!(run("print dis(1); print dis(clock);"), "nil\nnil\n");
+}
@@ -0,0 +1,74 @@
+//! The equality semantics of Lox, across all the types of values
+use rustlox::value::Value;
+use rustlox::VM;
+
+const PRELUDE: &str = r#"
+fun f() {}
+fun g() {}
+class A { m() {} }
+class B {}
+var a = A();
+var a2 = A();
+var nan = 0 / 0;
+"#;
+
+/// Evaluate the condition after the prelude
+fn check(condition: &str) -> bool {
+    let mut vm = VM::new();
+    let source = format!("{PRELUDE}\nvar result = {condition};");
+    vm.interpret(&source).expect("The condition runs");
+    match vm.get_global("result") {
+        Some(Value::Bool(result)) => result,
+        other => panic!("{condition} isn't a boolean: {other:?}"),
+    }
+}
+
+#[test]
+fn equal() {
+    let cases = [
+        "nil == nil",
+        "true == true",
+        "false == false",
+        "1 == 1",
+        "0 == -0",
+        r#""abc" == "abc""#,
+        r#""ab" + "c" == "abc""#,
+        "f == f",
+        "A == A",
+        "a == a",
+        "a.m == a.m",
+        "clock == clock",
+        "1 != 2",
+        "nil != false",
+        "a != a2",
+        "nan != nan",
+    ];
+    for case in cases {
+        assert!(check(case), "{case}");
+    }
+}
+
+#[test]
+fn not_equal() {
+    let cases = [
+        "nil == false",
+        "nil == 0",
+        r#"nil == """#,
+        "false == nil",
+        "0 == false",
+        r#""1" == 1"#,
+        r#""abc" == "abd""#,
+        "nan == nan",
+        "f == g",
+        "A == B",
+        "a == a2",
+        "a.m == a2.m",
+        "a == A",
+        "f == clock",
+        "nil != nil",
+        "f != f",
+    ];
+    for case in cases {
+        assert!(!check(case), "{case}");
+    }
+}
@@ -0,0 +1,77 @@
+//! `format` and `printf`, which fill the placeholders of a format string
+mod common;
+
+use common::{error, run};
+use rustlox::{Value, VM};
+
+#[test]
+fn the_placeholders_take_the_arguments_in_order() {
+    let source = r#"
+print format("{} + {} = {}", 1, 2, 1 + 2);
+print format("{} {} {} {{}}", "text", nil, [true, 1.5]);
+print format("no placeholders");
+"#;
+    assert_eq!(
+        run(source),
+        "1 + 2 = 3\ntext nil [true, 1.5] {}\nno placeholders\n"
+    );
+}
+
+#[test]
+fn the_specs_pad_and_round() {
+    let source = r#"
+print format("{:.2}|{:.0}|{:8.3}|{:.3}", PI, 2.5, 2 / 3, "abcdef");
+print format("[{:6}] [{:6}] [{:<6}] [{:>6}] [{:^7}] [{:-^7}]", 42, "ab", 42, "ab", "mid", "x");
+print format("{:05}|{:05.1}|{:03}", -7, 3.14159, 12345);
+"#;
+    assert_eq!(
+        run(source),
+        "3.14|2|   0.667|abc\n\
+         [    42] [ab    ] [42    ] [    ab] [  mid  ] [---x---]\n\
+         -0007|003.1|12345\n"
+    );
+}
+
+#[test]
+fn printf_prints_without_a_newline() {
+    let source = r#"
+for (var i = 1; i <= 3; i = i + 1) printf("{:>3}", i * i);
+print "";
+"#;
+    assert_eq!(run(source), "  1  4  9\n");
+}
+
+#[test]
+fn the_placeholders_must_match_the_arguments() {
+    assert_eq!(
+        error(r#"format("{} {}", 1);"#),
+        "Expect an argument for every placeholder of the format, got 1."
+    );
+    assert_eq!(
+        error(r#"format("{}", 1, 2);"#),
+        "Expect 1 arguments for the placeholders of the format, got 2."
+    );
+    assert_eq!(error(r#"format("{:x}", 1);"#), "Invalid format spec 'x'.");
+    assert_eq!(
+        error(r#"format("{", 1);"#),
+        "Unterminated '{' in the format."
+    );
+    assert_eq!(
+        error(r#"format("}");"#),
+        "Unmatched '}' in the format, write '}}'."
+    );
+    assert_eq!(
+        error("format();"),
+        "Expected at least 1 arguments but got 0."
+    );
+}
+
+#[test]
+fn the_host_registers_variadic_natives() {
+    let mut vm = VM::new();
+    vm.register_variadic_native("count", 0, |_vm, args| Ok(Value::Number(args.len() as f64)));
+    vm.interpret("var none = count(); var three = count(1, nil, \"x\");")
+        .unwrap();
+    assert_eq!(vm.get_global("none"), Some(Value::Number(0.0)));
+    assert_eq!(vm.get_global("three"), Some(Value::Number(3.0)));
+}
@@ -0,0 +1,57 @@
+//! The formatter of the source code
+use rustlox::formatter::format;
+
+#[test]
+fn statements_are_indented_and_spaced() {
+    let source = r#"
+class Point<Base{init(x,y){this.x=x;this.y=y;}
+area{return this.x*this.y;}}
+fun f(a,...rest){if(a>-1 and !false)print a;else{print-a;}
+for(var i=0;i<3;i++){}
+var xs=[1,2,3][0:2];xs[0]++;--xs[1];
+var g=fun(x){return x**2;};
+print xs?.length()??g(...rest);for(x in 0..=10)print x;}
+"#;
+    let expected = r#"class Point < Base {
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+  }
+  area {
+    return this.x * this.y;
+  }
+}
+fun f(a, ...rest) {
+  if (a > -1 and !false) print a;
+  else {
+    print -a;
+  }
+  for (var i = 0; i < 3; i++) {}
+  var xs = [1, 2, 3][0:2];
+  xs[0]++;
+  --xs[1];
+  var g = fun(x) {
+    return x ** 2;
+  };
+  print xs?.length() ?? g(...rest);
+  for (x in 0..=10) print x;
+}
+"#;
+    assert_eq!(format(source).unwrap(), expected);
+    assert_eq!(format(expected).unwrap(), expected);
+}
+
+#[test]
+fn comments_and_blank_lines_are_kept() {
+    let source = "// header\n/* block\n   comment */\nvar a = 1;   // trailing\n\n\n\nvar b = \"two\nlines\"; print a;\n{\n\n  // inside\n  print b;\n\n}\n";
+    let expected = "// header\n/* block\n   comment */\nvar a = 1; // trailing\n\nvar b = \"two\nlines\";\nprint a;\n{\n  // inside\n  print b;\n}\n";
+    assert_eq!(format(source).unwrap(), expected);
+    assert_eq!(format(expected).unwrap(), expected);
+}
+
+#[test]
+fn scanner_errors_are_returned() {
+    let diagnostic = format("print 1;\nprint \"oops;").unwrap_err();
+    assert_eq!(diagnostic.message, "Unterminated string.");
+    assert_eq!(diagnostic.line, 2);
+}
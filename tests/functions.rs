@@ -0,0 +1,82 @@
+//! The parameters of the functions
+mod common;
+
+use common::run;
+use rustlox::{LoxError, VM};
+
+#[test]
+fn default_values_fill_the_missing_arguments() {
+    let source = r#"
+fun greet(name, greeting = "hi", end = greeting == "hi" and "!" or ".") {
+  return greeting + ", " + name + end;
+}
+print greet("lox"); print greet("lox", "bye"); print greet("lox", "bye", "?");
+"#;
+    assert_eq!(run(source), "hi, lox!\nbye, lox.\nbye, lox?\n");
+}
+
+#[test]
+fn a_nil_argument_is_not_missing() {
+    let source = r#"
+fun f(a = 1) { return a; }
+print f(nil);
+class Point { init(x = 0, y = 0) { this.x = x; this.y = y; } }
+print Point(3).x + Point(3).y;
+"#;
+    assert_eq!(run(source), "nil\n3\n");
+}
+
+#[test]
+fn the_arguments_must_be_in_the_arity_range() {
+    let mut vm = VM::new();
+    vm.interpret("fun f(a, b = 2) { return a + b; }").unwrap();
+    assert!(vm.interpret("f();").is_err());
+    assert!(vm.interpret("f(1, 2, 3);").is_err());
+    assert!(vm.interpret("fun g(a = 1, b) {}").is_err());
+}
+
+#[test]
+fn the_rest_parameter_collects_the_extra_arguments() {
+    let source = r#"
+fun count(first, ...rest) { return len(rest); }
+fun tail(a, b = 2, ...rest) { return [a, b, rest]; }
+print count(1); print count(1, 2, 3);
+print tail(1); print tail(1, 3, 4, 5);
+"#;
+    assert_eq!(run(source), "0\n2\n[1, 2, []]\n[1, 3, [4, 5]]\n");
+}
+
+#[test]
+fn a_list_spreads_into_the_arguments() {
+    let source = r#"
+fun add(a, b, c) { return a + b + c; }
+var xs = [1, 2];
+print add(...xs, 3); print add(0, ...[5], ...[6]);
+class A { sum(a, b) { return a + b; } }
+class B < A { sum(...xs) { return super.sum(...xs) * 10; } }
+print B().sum(...xs);
+"#;
+    assert_eq!(run(source), "6\n11\n30\n");
+}
+
+#[test]
+fn only_lists_spread() {
+    let mut vm = VM::new();
+    vm.interpret("fun f(...xs) { return xs; }").unwrap();
+    assert!(vm.interpret("f(...1);").is_err());
+    assert!(vm.interpret("fun g(...xs, y) {}").is_err());
+}
+
+#[test]
+fn deep_recursion_overflows_the_fixed_stack() {
+    let mut vm = VM::new();
+    let capacity = vm.stack.len();
+    match vm.interpret("fun f(n) { return f(n + 1); } f(0);") {
+        Err(LoxError::RuntimeError { message, .. }) => assert_eq!(message, "Stack overflow."),
+        other => panic!("Expected a stack overflow, got {other:?}"),
+    }
+    assert_eq!(vm.stack.len(), capacity);
+    // The VM is usable after the overflow
+    vm.interpret("fun g(n) { if (n == 0) return 0; return g(n - 1); } g(50);")
+        .expect("The recursion fits");
+}
@@ -0,0 +1,75 @@
+//! The inputs found by fuzzing, which used to crash the process
+use rustlox::chunk::OpCode;
+use rustlox::fuzz::{fuzz_bytecode, fuzz_interpret};
+use rustlox::VM;
+
+/// The messages of the compile errors
+fn compile_errors(source: &str) -> Vec<String> {
+    VM::check(source)
+        .unwrap_err()
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect()
+}
+
+#[test]
+fn deeply_nested_code_is_a_compile_error() {
+    let expression = format!("print {}1{};", "(".repeat(10_000), ")".repeat(10_000));
+    assert_eq!(compile_errors(&expression), ["Code nested too deeply."]);
+    let blocks = format!("{}{}", "{".repeat(10_000), "}".repeat(10_000));
+    assert_eq!(compile_errors(&blocks), ["Code nested too deeply."]);
+    let functions = "fun f() {".repeat(10_000);
+    assert_eq!(compile_errors(&functions), ["Code nested too deeply."]);
+    // Not too deep
+    let expression = format!("print {}1{};", "(".repeat(100), ")".repeat(100));
+    assert!(VM::check(&expression).is_ok());
+}
+
+#[test]
+fn arbitrary_input_does_not_panic() {
+    let inputs: [&[u8]; 6] = [
+        b"\xff\xfe print 1;",
+        b"while (true) {}",
+        b"fun f() { return f(); } f();",
+        b"print \"ab\".repeat(1e19);",
+        b"var xs = []; while (true) xs.push(xs);",
+        b"class A < A {} print A().x;",
+    ];
+    for input in inputs {
+        fuzz_interpret(input);
+    }
+}
+
+#[test]
+fn arbitrary_bytecode_does_not_panic() {
+    let (pop, ret) = (OpCode::Pop as u8, OpCode::Return as u8);
+    let inputs: [&[u8]; 8] = [
+        // Returning with less on the stack than the frame started with
+        &[pop, pop, ret],
+        &[ret, ret],
+        &[OpCode::Jump as u8, 0xff, 0xff, ret],
+        &[OpCode::JumpLong as u8, 0xff, 0xff, 0xff, 0xff],
+        &[OpCode::LoopLong as u8, 0xff, 0xff, 0xff, 0xff],
+        &[OpCode::ArgMissing as u8, 0xff, ret],
+        &[OpCode::GetUpvalue as u8, 3, ret],
+        &[
+            OpCode::GetLocal as u8,
+            0xff,
+            OpCode::GetLocalLong as u8,
+            0xff,
+            0xff,
+            ret,
+        ],
+    ];
+    for input in inputs {
+        fuzz_bytecode(input);
+    }
+}
+
+#[test]
+fn there_is_no_frame_outside_of_a_run() {
+    let mut vm = VM::new();
+    vm.interpret("var a = 1;").unwrap();
+    assert!(vm.current_frame().is_none());
+    assert!(vm.current_closure().is_none());
+}
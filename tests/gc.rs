@@ -0,0 +1,146 @@
+//! The garbage collector frees the objects which are only kept alive by reference cycles
+use rustlox::output::SharedBuffer;
+use rustlox::shared::Rc;
+use rustlox::{Value, VM};
+
+/// Whether the object of a value has been freed, checked through a weak reference
+fn freed_check(value: Value) -> Box<dyn Fn() -> bool> {
+    match value {
+        Value::List(list) => {
+            let weak = Rc::downgrade(&list);
+            Box::new(move || weak.upgrade().is_none())
+        }
+        Value::Instance(instance) => {
+            let weak = Rc::downgrade(&instance);
+            Box::new(move || weak.upgrade().is_none())
+        }
+        Value::Closure(closure) => {
+            let weak = Rc::downgrade(&closure);
+            Box::new(move || weak.upgrade().is_none())
+        }
+        Value::Class(class) => {
+            let weak = Rc::downgrade(&class);
+            Box::new(move || weak.upgrade().is_none())
+        }
+        other => panic!("Not an object: {other:?}"),
+    }
+}
+
+/// The scripts which leave a cycle in the global `cycle`
+const CYCLES: [&str; 4] = [
+    // A list containing itself
+    "var cycle = []; cycle.push(cycle); cycle.push([cycle]);",
+    // An instance pointing to itself through another instance
+    "class Node {} var cycle = Node(); cycle.next = Node(); cycle.next.next = cycle;",
+    // A closure capturing the variable which holds it
+    "fun make() { var f; fun g() { return f; } f = g; return g; } var cycle = make();",
+    // A class whose method captures the class
+    "var cycle; { class B { me() { return B; } } cycle = B; }",
+];
+
+#[test]
+fn collecting_frees_the_unreachable_cycles() {
+    for source in CYCLES {
+        let mut vm = VM::new();
+        vm.interpret(source).unwrap();
+        let freed = freed_check(vm.get_global("cycle").unwrap());
+        // Still reachable from the global
+        vm.collect_garbage();
+        assert!(!freed(), "{source}");
+
+        vm.interpret("cycle = nil;").unwrap();
+        // `Rc` can't free a cycle on its own
+        assert!(!freed(), "{source}");
+        assert!(vm.collect_garbage() > 0, "{source}");
+        assert!(freed(), "{source}");
+    }
+}
+
+#[test]
+fn the_stress_mode_frees_the_cycles_as_it_goes() {
+    for source in CYCLES {
+        let mut vm = VM::new();
+        vm.set_gc_stress(true);
+        vm.interpret(source).unwrap();
+        let freed = freed_check(vm.get_global("cycle").unwrap());
+        // The next object created collects the garbage
+        vm.interpret("cycle = nil; var more = [];").unwrap();
+        assert!(freed(), "{source}");
+    }
+}
+
+#[test]
+fn the_stress_mode_keeps_the_reachable_objects() {
+    let source = r#"
+fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+var next = counter();
+class Tree {
+  init(depth) {
+    this.children = [];
+    if (depth > 0) { this.children.push(Tree(depth - 1)); this.children.push(Tree(depth - 1)); }
+  }
+  size() { var n = 1; for (var c in this.children) n = n + c.size(); return n; }
+}
+var tree = Tree(4);
+var self = []; self.push(self);
+next(); next();
+print tree.size(); print next(); print len(self[0][0]);
+"#;
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(out.clone()));
+    vm.set_gc_stress(true);
+    vm.interpret(source).unwrap();
+    assert_eq!(out.contents(), "31\n3\n1\n");
+}
+
+#[test]
+fn the_values_held_by_the_host_are_roots() {
+    let mut vm = VM::new();
+    vm.interpret("var cycle = []; cycle.push(cycle);").unwrap();
+    let held = vm.get_global("cycle").unwrap();
+    vm.interpret("cycle = nil;").unwrap();
+    assert_eq!(vm.collect_garbage(), 0);
+    let Value::List(list) = &held else {
+        unreachable!()
+    };
+    assert_eq!(list.borrow().len(), 1);
+}
+
+#[test]
+fn a_self_referential_instance_and_a_closure_cycle_are_reclaimed() {
+    let source = r#"
+class Node { init() { this.me = this; } }
+var node = Node();
+fun pair() {
+  var even;
+  var odd;
+  fun isEven(n) { if (n == 0) return true; return odd(n - 1); }
+  fun isOdd(n) { if (n == 0) return false; return even(n - 1); }
+  even = isEven;
+  odd = isOdd;
+  return isEven;
+}
+var closure = pair();
+print closure(4);
+"#;
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(out.clone()));
+    vm.interpret(source).unwrap();
+    assert_eq!(out.contents(), "true\n");
+    let (node, closure) = match (vm.get_global("node"), vm.get_global("closure")) {
+        (Some(Value::Instance(node)), Some(Value::Closure(closure))) => {
+            (Rc::downgrade(&node), Rc::downgrade(&closure))
+        }
+        globals => panic!("Expected an instance and a closure, got {globals:?}"),
+    };
+
+    vm.interpret("node = nil; closure = nil;").unwrap();
+    // Only the cycles keep them alive
+    assert_eq!(node.strong_count(), 1);
+    assert!(closure.strong_count() > 0);
+    assert!(vm.collect_garbage() > 0);
+    assert_eq!(node.strong_count(), 0);
+    assert_eq!(closure.strong_count(), 0);
+}
@@ -0,0 +1,55 @@
+//! The token stream for highlighting the source code
+use rustlox::scanner::{tokenize, TokenKind};
+
+/// The text and the kind of every piece of the source code
+fn pieces(source: &str) -> Vec<(&str, TokenKind)> {
+    tokenize(source)
+        .into_iter()
+        .map(|(kind, span)| (&source[span.start..span.end()], kind))
+        .collect()
+}
+
+#[test]
+fn the_pieces_cover_the_whole_source_code() {
+    let source = "/* é */\nvar s = \"a\nb\";\n\tprint s.length() + 1; // done\n";
+    let tokens = tokenize(source);
+    let text: String = tokens
+        .iter()
+        .map(|(_, span)| &source[span.start..span.end()])
+        .collect();
+    assert_eq!(text, source);
+    let lines: Vec<_> = tokens.iter().map(|(_, span)| span.line).collect();
+    assert_eq!(lines[..5], [1, 1, 2, 2, 2]);
+    assert_eq!(
+        tokens
+            .iter()
+            .find(|(kind, _)| *kind == TokenKind::Number)
+            .unwrap()
+            .1
+            .line,
+        4
+    );
+}
+
+#[test]
+fn the_tokens_are_classified_and_errors_dont_stop_it() {
+    assert_eq!(
+        pieces("if (!x) @ nil ?? \"oops"),
+        vec![
+            ("if", TokenKind::Keyword),
+            (" ", TokenKind::Whitespace),
+            ("(", TokenKind::Punctuation),
+            ("!", TokenKind::Operator),
+            ("x", TokenKind::Identifier),
+            (")", TokenKind::Punctuation),
+            (" ", TokenKind::Whitespace),
+            ("@", TokenKind::Error),
+            (" ", TokenKind::Whitespace),
+            ("nil", TokenKind::Keyword),
+            (" ", TokenKind::Whitespace),
+            ("??", TokenKind::Operator),
+            (" ", TokenKind::Whitespace),
+            ("\"oops", TokenKind::Error),
+        ]
+    );
+}
@@ -0,0 +1,103 @@
+//! The callbacks of the host, which watch the running code
+mod common;
+
+use common::quiet_vm;
+use rustlox::hooks::VmHooks;
+use rustlox::shared::{Rc, RefCell};
+use rustlox::value::{Function, NativeError};
+use rustlox::{Value, VM};
+
+/// Write down every call, return and error, and count the instructions
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>,
+    instructions: usize,
+    /// Stop the script after this many instructions
+    budget: Option<usize>,
+}
+
+impl VmHooks for Recorder {
+    fn on_instruction(&mut self, function: &Function, offset: usize) -> Result<(), NativeError> {
+        assert!(offset < function.chunk.code.len());
+        self.instructions += 1;
+        match self.budget {
+            Some(budget) if self.instructions > budget => Err(NativeError::new("Out of budget.")),
+            _ => Ok(()),
+        }
+    }
+
+    fn on_call(&mut self, name: &str, args: &[Value]) {
+        let args: Vec<_> = args.iter().map(Value::to_string).collect();
+        self.events
+            .push(format!("call {name}({})", args.join(", ")));
+    }
+
+    fn on_return(&mut self, name: &str, result: &Value) {
+        self.events.push(format!("return {name} {result}"));
+    }
+
+    fn on_error(&mut self, message: &str, stack_trace: &[String]) {
+        self.events
+            .push(format!("error {message} in {} frames", stack_trace.len()));
+    }
+}
+
+fn recording_vm(budget: Option<usize>) -> (VM, Rc<RefCell<Recorder>>) {
+    let recorder = Rc::new(RefCell::new(Recorder {
+        budget,
+        ..Default::default()
+    }));
+    let mut vm = quiet_vm();
+    vm.set_hooks(Some(Box::new(Rc::clone(&recorder))));
+    (vm, recorder)
+}
+
+#[test]
+fn the_hooks_see_the_calls_and_the_returns() {
+    let (mut vm, recorder) = recording_vm(None);
+    let source = r#"
+class Point { init(x) { this.x = x; } }
+fun twice(n) { return str(n * 2); }
+var p = Point(1);
+twice(p.x);
+"#;
+    vm.interpret(source).unwrap();
+    assert_eq!(
+        recorder.borrow().events,
+        [
+            "call init(1)",
+            "return init Point instance",
+            "call twice(1)",
+            "call str(2)",
+            "return str 2",
+            "return twice 2",
+        ]
+    );
+    assert!(recorder.borrow().instructions > 10);
+}
+
+#[test]
+fn an_instruction_hook_may_stop_the_script() {
+    let (mut vm, recorder) = recording_vm(Some(1000));
+    let Err(error) = vm.interpret("fun spin() { while (true) {} } spin();") else {
+        panic!("Expected the hook to stop the script");
+    };
+    assert!(error.to_string().starts_with("Out of budget."), "{error}");
+    assert_eq!(recorder.borrow().instructions, 1001);
+    assert_eq!(
+        recorder.borrow().events,
+        ["call spin()", "error Out of budget. in 2 frames"]
+    );
+    // The VM still runs code afterwards, the hooks decide
+    recorder.borrow_mut().budget = None;
+    assert!(vm.interpret("var a = 1;").is_ok());
+}
+
+#[test]
+fn the_hooks_are_removed_by_none() {
+    let (mut vm, recorder) = recording_vm(None);
+    vm.set_hooks(None);
+    vm.interpret("fun f() {} f(); nil();").unwrap_err();
+    assert!(recorder.borrow().events.is_empty());
+    assert_eq!(recorder.borrow().instructions, 0);
+}
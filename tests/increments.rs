@@ -0,0 +1,52 @@
+//! The `++` and `--` operators
+mod common;
+
+use common::{compile_errors, run};
+
+#[test]
+fn variables_are_incremented_and_decremented() {
+    let source = r#"
+var g = 1;
+print g++; print g; print ++g; print --g; print g--; print g;
+{
+  var l = 0.1;
+  print l++; print l;
+  fun closure() { return l++; }
+  print closure(); print -l--; print l;
+}
+for (var i = 0; i < 3; i++) print i;
+"#;
+    assert_eq!(
+        run(source),
+        "1\n2\n3\n2\n2\n1\n0.1\n1.1\n1.1\n-2.1\n1.1\n0\n1\n2\n"
+    );
+}
+
+#[test]
+fn properties_and_indexes_are_incremented_and_decremented() {
+    let source = r#"
+class Counter { init() { this.count = 0; } }
+var c = Counter();
+print c.count++; print ++c.count; print c.count--; print c.count;
+var xs = [10, 20];
+var i = 0;
+print xs[i++]++; print xs; print --xs[i]; print xs; print i;
+"#;
+    assert_eq!(run(source), "0\n2\n2\n1\n10\n[11, 20]\n19\n[11, 19]\n1\n");
+}
+
+#[test]
+fn only_variables_properties_and_indexes_are_incremented() {
+    for source in [
+        "1++;",
+        "++f();",
+        "var a; a++ ++;",
+        "var a; var b; ++(a and b);",
+    ] {
+        assert_eq!(
+            compile_errors(source),
+            vec!["Invalid increment or decrement target."],
+            "{source}"
+        );
+    }
+}
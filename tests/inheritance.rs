@@ -0,0 +1,77 @@
+//! The subclasses and the `super` calls
+mod common;
+
+use common::run;
+use rustlox::{LoxError, VM};
+
+#[test]
+fn subclasses_inherit_the_methods() {
+    let source = r#"
+class A { hello() { return "A.hello"; } name() { return "A"; } }
+class B < A { name() { return "B"; } }
+var b = B();
+print b.hello(); print b.name();
+"#;
+    assert_eq!(run(source), "A.hello\nB\n");
+}
+
+#[test]
+fn super_calls_the_method_of_the_superclass() {
+    let source = r#"
+class A { describe() { return "A"; } }
+class B < A { describe() { return super.describe() + "B"; } }
+class C < B { describe() { return super.describe() + "C"; } }
+print C().describe();
+"#;
+    assert_eq!(run(source), "ABC\n");
+}
+
+#[test]
+fn super_binds_this_to_the_receiver() {
+    let source = r#"
+class A {
+  init(x) { this.x = x; }
+  show() { return this.x; }
+}
+class B < A {
+  init(x, y) { super.init(x); this.y = y; }
+  show() { var get = super.show; return get() + this.y; }
+}
+print B(1, 2).show();
+"#;
+    assert_eq!(run(source), "3\n");
+}
+
+#[test]
+fn super_is_resolved_where_the_method_is_declared() {
+    let source = r#"
+class A { f() { return "A"; } }
+class B < A { g() { return super.f(); } }
+class C < B { f() { return "C"; } }
+print C().g();
+"#;
+    assert_eq!(run(source), "A\n");
+}
+
+#[test]
+fn only_classes_are_inherited_from() {
+    let mut vm = VM::new();
+    match vm.interpret("var NotAClass = 1; class A < NotAClass {}") {
+        Err(LoxError::RuntimeError { message, .. }) => {
+            assert_eq!(message, "Superclass must be a class.")
+        }
+        other => panic!("Expected a runtime error, got {other:?}"),
+    }
+    assert!(matches!(
+        vm.interpret("class A < A {}"),
+        Err(LoxError::CompileError(_))
+    ));
+    assert!(matches!(
+        vm.interpret("class A { f() { return super.f(); } }"),
+        Err(LoxError::CompileError(_))
+    ));
+    assert!(matches!(
+        vm.interpret("print super.f;"),
+        Err(LoxError::CompileError(_))
+    ));
+}
@@ -0,0 +1,125 @@
+//! Every VM has its own globals, natives and modules, so a host may run several side by side,
+//! and with the `threadsafe` feature on their own threads
+mod common;
+
+use common::quiet_vm;
+use rustlox::output::SharedBuffer;
+use rustlox::{Value, VM};
+
+fn vm_with_output() -> (VM, SharedBuffer) {
+    let out = SharedBuffer::new();
+    let mut vm = quiet_vm();
+    vm.set_stdout(Box::new(out.clone()));
+    (vm, out)
+}
+
+#[test]
+fn two_vms_run_independently() {
+    let (mut first, first_out) = vm_with_output();
+    let (mut second, second_out) = vm_with_output();
+    first.register_native("name", 0, |_vm, _args| Ok("first".into()));
+    second.register_native("name", 0, |_vm, _args| Ok("second".into()));
+    first.interpret("var count = 1; class A {}").unwrap();
+    second.interpret("var count = 10;").unwrap();
+    first
+        .interpret("count = count + 1; print name() + \" \" + str(count);")
+        .unwrap();
+    second
+        .interpret("count = count + 1; print name() + \" \" + str(count);")
+        .unwrap();
+    assert_eq!(first_out.contents(), "first 2\n");
+    assert_eq!(second_out.contents(), "second 11\n");
+    // The class of the first VM isn't defined in the second
+    assert!(second.interpret("A();").is_err());
+    assert!(second.get_global("A").is_none());
+    // An error in one doesn't touch the other
+    assert!(first.interpret("nil();").is_err());
+    assert!(matches!(
+        second.call_function("name", &[]),
+        Ok(Value::String(_))
+    ));
+}
+
+#[cfg(feature = "threadsafe")]
+#[test]
+fn vms_run_on_their_own_threads() {
+    fn assert_send<T: Send>() {}
+    assert_send::<VM>();
+    assert_send::<Value>();
+
+    let workers: Vec<_> = (0..4)
+        .map(|n| {
+            std::thread::spawn(move || {
+                let (mut vm, out) = vm_with_output();
+                vm.set_global("n", Value::Number(n as f64));
+                let source = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+                              var xs = []; for (var i = 0; i < 3; i = i + 1) append(xs, fib(n + 10));
+                              print xs;";
+                vm.interpret(source).unwrap();
+                out.contents()
+            })
+        })
+        .collect();
+    let outputs: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+    assert_eq!(
+        outputs,
+        [
+            "[55, 55, 55]\n",
+            "[89, 89, 89]\n",
+            "[144, 144, 144]\n",
+            "[233, 233, 233]\n"
+        ]
+    );
+
+    // A VM moves to another thread with its globals
+    let (mut vm, out) = vm_with_output();
+    vm.interpret("var greeting = \"hello\";").unwrap();
+    let worker = std::thread::spawn(move || {
+        vm.interpret("print greeting;").unwrap();
+        vm
+    });
+    let vm = worker.join().unwrap();
+    assert!(vm.get_global("greeting").is_some());
+    assert_eq!(out.contents(), "hello\n");
+}
+
+#[cfg(feature = "threadsafe")]
+#[test]
+fn a_value_is_shared_by_the_vms_of_several_threads() {
+    let (mut vm, _) = vm_with_output();
+    vm.interpret("var xs = [];").unwrap();
+    let xs = vm.get_global("xs").unwrap();
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let xs = xs.clone();
+            std::thread::spawn(move || {
+                let (mut vm, _) = vm_with_output();
+                vm.set_global("xs", xs);
+                vm.interpret("for (var i = 0; i < 1000; i = i + 1) append(xs, len(xs));")
+                    .unwrap();
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    let Value::List(xs) = xs else {
+        panic!("Expect a list");
+    };
+    assert_eq!(xs.borrow().len(), 4000);
+}
+
+#[cfg(feature = "threadsafe")]
+#[test]
+fn set_env_is_a_runtime_error_with_several_threads() {
+    let (mut vm, _) = vm_with_output();
+    let Err(rustlox::LoxError::RuntimeError { message, .. }) =
+        vm.interpret("setEnv(\"RUSTLOX_TEST\", \"1\");")
+    else {
+        panic!("Expect a runtime error");
+    };
+    assert_eq!(
+        message,
+        "'setEnv' isn't available with the threadsafe feature."
+    );
+}
@@ -0,0 +1,146 @@
+//! The list values
+mod common;
+
+use common::run;
+use rustlox::output::SharedBuffer;
+use rustlox::{Value, VM};
+
+#[test]
+fn lists_are_indexed_and_assigned() {
+    let source = r#"
+var xs = [1, "two", nil, [3]];
+xs[0] = xs[0] + 10;
+append(xs, true);
+print xs; print xs[3][0]; print len(xs);
+"#;
+    assert_eq!(run(source), "[11, \"two\", nil, [3], true]\n3\n5\n");
+}
+
+#[test]
+fn lists_are_shared_and_compare_by_identity() {
+    let source = r#"
+var a = [1]; var b = a;
+append(b, 2);
+print a; print a == b; print [1] == [1];
+append(a, a); print a;
+"#;
+    assert_eq!(run(source), "[1, 2]\ntrue\nfalse\n[1, 2, [...]]\n");
+}
+
+#[test]
+fn the_index_must_be_in_bounds() {
+    let mut vm = VM::new();
+    vm.interpret("var xs = [1, 2];").unwrap();
+    assert!(vm.interpret("xs[2];").is_err());
+    assert!(vm.interpret("xs[-1];").is_err());
+    assert!(vm.interpret("xs[0.5] = 1;").is_err());
+    assert!(vm.interpret("1[0];").is_err());
+}
+
+#[test]
+fn the_host_converts_the_lists() {
+    let mut vm = VM::new();
+    vm.set_global("xs", vec![Value::Number(1.0), "a".into()].into());
+    vm.interpret("append(xs, nil);").unwrap();
+    let xs = Vec::<Value>::try_from(&vm.get_global("xs").unwrap()).unwrap();
+    assert_eq!(xs, vec![Value::Number(1.0), "a".into(), Value::Nil]);
+}
+
+#[test]
+fn lists_and_strings_are_sliced() {
+    let source = r#"
+var xs = [1, 2, 3, 4, 5];
+var ys = xs[1:4];
+ys[0] = 0;
+print ys; print xs[:2]; print xs[3:]; print xs[:] == xs; print xs[2:2];
+print "héllo"[1:3]; print "lox"[:];
+"#;
+    assert_eq!(
+        run(source),
+        "[0, 3, 4]\n[1, 2]\n[4, 5]\nfalse\n[]\nél\nlox\n"
+    );
+}
+
+#[test]
+fn the_slice_must_be_in_bounds() {
+    let mut vm = VM::new();
+    vm.interpret("var xs = [1, 2];").unwrap();
+    assert!(vm.interpret("xs[0:3];").is_err());
+    assert!(vm.interpret("xs[2:1];").is_err());
+    assert!(vm.interpret("xs[0.5:];").is_err());
+    assert!(vm.interpret("xs[:nil];").is_ok());
+    assert!(vm.interpret("nil[0:];").is_err());
+}
+
+#[test]
+fn the_callbacks_transform_the_lists() {
+    let source = r#"
+var xs = [3, 1, 2];
+print xs.map(fun (x) { return x * 2; }); print xs.map(str);
+print xs.filter(fun (x) { return x > 1; });
+print xs.reduce(fun (sum, x) { return sum + x; });
+print xs.reduce(fun (sum, x) { return sum + x; }, 10);
+print [].reduce(fun (sum, x) { return sum + x; }, 0);
+print xs;
+"#;
+    assert_eq!(
+        run(source),
+        "[6, 2, 4]\n[\"3\", \"1\", \"2\"]\n[3, 2]\n6\n16\n0\n[3, 1, 2]\n"
+    );
+}
+
+#[test]
+fn lists_are_sorted_and_searched_in_place() {
+    let source = r#"
+var xs = [3, 1, 2];
+print xs.sort(); print xs; print xs.reverse(); print xs;
+print ["b", "c", "a"].sort();
+print xs.sort(fun (a, b) { return b - a; });
+class P { init(name, age) { this.name = name; this.age = age; } }
+var people = [P("a", 30), P("b", 20), P("c", 30), P("d", 20)];
+// Stable, so the names stay in order within the same age
+people.sort(fun (p, q) { return p.age - q.age; });
+print people.map(fun (p) { return p.name; });
+print xs.contains(2); print xs.contains("2"); print xs.indexOf(1); print xs.indexOf(4);
+"#;
+    assert_eq!(
+        run(source),
+        "[1, 2, 3]\n[1, 2, 3]\n[3, 2, 1]\n[3, 2, 1]\n[\"a\", \"b\", \"c\"]\n[3, 2, 1]\n\
+         [\"b\", \"d\", \"a\", \"c\"]\ntrue\nfalse\n2\n-1\n"
+    );
+}
+
+#[test]
+fn the_callbacks_errors_abort_the_script() {
+    let mut vm = VM::new();
+    assert!(vm.interpret("[1].map(fun (x) { return x.y; });").is_err());
+    assert!(vm.interpret("[1, \"a\"].sort();").is_err());
+    assert!(vm
+        .interpret("[2, 1].sort(fun (a, b) { return nil; });")
+        .is_err());
+    assert!(vm
+        .interpret("[].reduce(fun (a, b) { return a; });")
+        .is_err());
+    assert!(vm.interpret("[1].sort(nil, nil);").is_err());
+    // An inconsistent comparator doesn't break the sort
+    let source = "var xs = [5, 3, 1, 4, 2]; xs.sort(fun (a, b) { return 1; }); print len(xs);";
+    assert_eq!(run(source), "5\n");
+}
+
+#[test]
+fn the_arguments_of_the_script_are_a_list() {
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(out.clone()));
+    vm.set_args(vec!["input.txt".to_string(), "-v".to_string()]);
+    let source = r#"
+var xs = args();
+append(xs, "changed");
+print xs; print args(); print len(args());
+"#;
+    vm.interpret(source).unwrap();
+    assert_eq!(
+        out.contents(),
+        "[\"input.txt\", \"-v\", \"changed\"]\n[\"input.txt\", \"-v\"]\n2\n"
+    );
+}
@@ -0,0 +1,69 @@
+//! The `break` and `continue` statements
+mod common;
+
+use common::run;
+use rustlox::{LoxError, VM};
+
+#[test]
+fn break_exits_the_innermost_loop() {
+    let source = r#"
+for (var i = 0; i < 3; i = i + 1) {
+  var j = 0;
+  while (true) {
+    if (j == 2) break;
+    print i * 10 + j;
+    j = j + 1;
+  }
+  if (i == 1) break;
+}
+print "done";
+"#;
+    assert_eq!(run(source), "0\n1\n10\n11\ndone\n");
+}
+
+#[test]
+fn continue_skips_to_the_next_iteration() {
+    let source = r#"
+for (var i = 0; i < 5; i = i + 1) {
+  if (i == 1 or i == 3) continue;
+  print i;
+}
+var n = 0;
+while (n < 4) {
+  n = n + 1;
+  if (n == 2) continue;
+  print n;
+}
+"#;
+    assert_eq!(run(source), "0\n2\n4\n1\n3\n4\n");
+}
+
+#[test]
+fn the_locals_of_the_loop_body_are_popped() {
+    let source = r#"
+var before = "outer";
+for (var i = 0; i < 3; i = i + 1) {
+  var a = "a"; var b = "b";
+  { var c = "c"; if (i == 0) continue; if (i == 2) break; }
+  print a + b;
+}
+print before;
+"#;
+    assert_eq!(run(source), "ab\nouter\n");
+}
+
+#[test]
+fn break_and_continue_belong_in_a_loop() {
+    let mut vm = VM::new();
+    for source in [
+        "break;",
+        "continue;",
+        "fun f() { break; }",
+        "while (true) { fun g() { continue; } }",
+    ] {
+        assert!(
+            matches!(vm.interpret(source), Err(LoxError::CompileError(_))),
+            "{source}"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Running bytecode which the compiler never emits, e.g. from a corrupt `.loxc` file
+mod common;
+
+use common::quiet_vm;
+use rustlox::chunk::OpCode;
+use rustlox::fuzz::fuzz_bytecode;
+use rustlox::scanner::Span;
+use rustlox::{LoxError, VM};
+use std::io;
+
+/// Run the script with its code replaced, and return the message of the runtime error
+fn run_code(source: &str, code: Vec<u8>) -> String {
+    let mut script = VM::check(source).unwrap();
+    let chunk = &mut script.function.chunk;
+    chunk.lines = vec![1; code.len()];
+    chunk.columns = vec![1; code.len()];
+    chunk.spans = vec![Span::default(); code.len()];
+    chunk.code = code;
+    let mut vm = quiet_vm();
+    vm.set_stdout(Box::new(io::sink()));
+    match vm.run_function(script.function) {
+        Err(LoxError::RuntimeError { message, .. }) => message,
+        result => panic!("Expected a runtime error, got {result:?}"),
+    }
+}
+
+#[test]
+fn malformed_code_is_a_runtime_error() {
+    let invalid = "Invalid bytecode.";
+    // Not an opcode
+    assert_eq!(run_code("", vec![0xff]), invalid);
+    // Past the end of the code
+    assert_eq!(run_code("", vec![OpCode::Nil as u8]), invalid);
+    assert_eq!(run_code("", vec![OpCode::Jump as u8, 0]), invalid);
+    // Not a constant
+    assert_eq!(run_code("", vec![OpCode::Constant as u8, 9]), invalid);
+    // Below the bottom of the stack
+    let pops = vec![OpCode::Pop as u8; 3];
+    assert_eq!(run_code("", pops), invalid);
+    let call = vec![
+        OpCode::Call as u8,
+        200,
+        OpCode::Nil as u8,
+        OpCode::Return as u8,
+    ];
+    assert_eq!(run_code("", call), invalid);
+    // Beyond the slots of the stack
+    let get = vec![OpCode::GetLocalLong as u8, 0xff, 0xff, OpCode::Return as u8];
+    assert_eq!(run_code("", get), invalid);
+    let get = vec![OpCode::GetUpvalue as u8, 0, OpCode::Return as u8];
+    assert_eq!(run_code("", get), invalid);
+    // Before the start of the code
+    assert_eq!(run_code("", vec![OpCode::Loop as u8, 0, 9]), invalid);
+    // The wrong kind of constant
+    let define = vec![OpCode::Nil as u8, OpCode::DefineGlobal as u8, 0];
+    assert_eq!(run_code("print 1;", define), invalid);
+}
+
+#[test]
+fn every_corrupted_byte_is_handled() {
+    let source = "var a = 1;\nfun f(x) { return fun() { return x + a; }; }\nprint f(2)();";
+    let code = VM::check(source).unwrap().function.chunk.code;
+    for idx in 0..code.len() {
+        for byte in [0, 1, 0x7f, 0xff] {
+            let mut corrupted = code.clone();
+            corrupted[idx] = byte;
+            fuzz_bytecode(&corrupted);
+        }
+    }
+    fuzz_bytecode(&code[..code.len() / 2]);
+}
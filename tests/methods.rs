@@ -0,0 +1,81 @@
+//! The built-in methods of the strings, numbers and lists
+mod common;
+
+use common::{error, run};
+use rustlox::{Value, VM};
+
+#[test]
+fn strings_have_methods() {
+    let source = r#"
+print "hello".length();
+print "a,b,c".split(",");
+print "abc".split("");
+print "  Lox ".trim().upper();
+print "héllo".indexOf("l");
+print "lox".indexOf("x!");
+print "ab".repeat(3);
+print "a-b-c".replace("-", "+");
+print "rustlox".startsWith("rust") and "rustlox".endsWith("lox");
+"#;
+    assert_eq!(
+        run(source),
+        "5\n[\"a\", \"b\", \"c\"]\n[\"a\", \"b\", \"c\"]\nLOX\n2\n-1\nababab\na+b+c\ntrue\n"
+    );
+}
+
+#[test]
+fn every_value_has_to_string() {
+    let source = r#"
+print (42).toString() + "!";
+print true.toString() + nil.toString();
+print [1, "a"].toString();
+print (3.14159).toFixed(2);
+"#;
+    assert_eq!(run(source), "42!\ntruenil\n[1, \"a\"]\n3.14\n");
+}
+
+#[test]
+fn lists_have_methods() {
+    let source = r#"
+var xs = [1, 2];
+xs.push(3);
+print xs.length();
+print xs.pop();
+print xs;
+"#;
+    assert_eq!(run(source), "3\n3\n[1, 2]\n");
+}
+
+#[test]
+fn a_method_read_as_a_property_keeps_its_receiver() {
+    let source = r#"
+var upper = "abc".upper;
+print upper();
+var push = [].push;
+push(1);
+"#;
+    assert_eq!(run(source), "ABC\n");
+}
+
+#[test]
+fn the_methods_check_their_arguments() {
+    assert_eq!(error("\"x\".nope();"), "Undefined method 'nope' of string.");
+    assert_eq!(error("\"x\".split();"), "Expected 1 arguments but got 0.");
+    assert_eq!(error("[].pop();"), "Can't pop from an empty list.");
+    assert_eq!(
+        error("fun f() {} f.toString();"),
+        "Only instances and classes have methods."
+    );
+}
+
+#[test]
+fn the_host_adds_methods() {
+    let mut vm = VM::new();
+    vm.register_method("string", "shout", 0, |_vm, args| {
+        let s: &str = (&args[0]).try_into()?;
+        Ok(Value::string(format!("{s}!")))
+    });
+    vm.interpret("var s = \"hi\".shout();")
+        .expect("The script runs");
+    assert_eq!(vm.get_global("s"), Some(Value::string("hi!".to_string())));
+}
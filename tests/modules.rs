@@ -0,0 +1,144 @@
+//! `import`, of the files next to the importing one and of the modules the host registers
+mod common;
+
+use common::quiet_vm;
+use rustlox::output::SharedBuffer;
+use rustlox::value::NativeFunction;
+use rustlox::{LoxError, Value, VM};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A new directory for the files of a test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustlox-modules-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write the files, whose names may have directories, into `dir`
+fn write_files(dir: &Path, files: &[(&str, &str)]) {
+    for (name, source) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+}
+
+/// Run `main.lox` of `dir` with a quiet VM, and return its output and its result
+fn run_main(dir: &Path) -> (String, Result<(), LoxError>) {
+    let out = SharedBuffer::new();
+    let mut vm = quiet_vm();
+    vm.set_stdout(Box::new(out.clone()));
+    let main = dir.join("main.lox");
+    let source = fs::read_to_string(&main).unwrap();
+    let result = vm.interpret_file(&main.display().to_string(), &source);
+    (out.contents(), result)
+}
+
+#[test]
+fn a_module_is_found_next_to_the_file_importing_it() {
+    let dir = temp_dir("relative");
+    write_files(
+        &dir,
+        &[
+            ("main.lox", "import \"lib/shapes\";\nprint area(2);\n"),
+            (
+                "lib/shapes.lox",
+                "import \"square.lox\";\nfun area(n) { return square(n); }\n",
+            ),
+            ("lib/square.lox", "fun square(n) { return n * n; }\n"),
+        ],
+    );
+    let (out, result) = run_main(&dir);
+    assert!(result.is_ok(), "{result:?}");
+    assert_eq!(out, "4\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_missing_module_is_a_runtime_error_showing_where_it_was_looked_for() {
+    let dir = temp_dir("missing");
+    write_files(&dir, &[("main.lox", "import \"nowhere\";\n")]);
+    let (_, result) = run_main(&dir);
+    let Err(LoxError::RuntimeError { message, .. }) = result else {
+        panic!("Expected a runtime error, got {result:?}");
+    };
+    assert!(
+        message.starts_with("Module 'nowhere' not found, searched:"),
+        "{message}"
+    );
+    assert!(
+        message.contains(&dir.join("nowhere.lox").display().to_string()),
+        "{message}"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_cycle_of_imports_is_a_runtime_error_instead_of_a_stack_overflow() {
+    let dir = temp_dir("cycle");
+    write_files(
+        &dir,
+        &[
+            ("main.lox", "import \"a\";\n"),
+            ("a.lox", "import \"b\";\n"),
+            ("b.lox", "import \"a\";\n"),
+        ],
+    );
+    let (_, result) = run_main(&dir);
+    let Err(LoxError::RuntimeError { message, .. }) = result else {
+        panic!("Expected a runtime error, got {result:?}");
+    };
+    let chain: Vec<_> = message
+        .strip_prefix("Circular import: ")
+        .and_then(|chain| chain.strip_suffix('.'))
+        .unwrap_or_else(|| panic!("Expected the cycle, got {message}"))
+        .split(" -> ")
+        .map(|file| Path::new(file).file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(chain, ["a.lox", "b.lox", "a.lox"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_native_module_defines_its_natives() {
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(out.clone()));
+    vm.register_module(
+        "math2",
+        [
+            NativeFunction::new("twice", 1, |_vm, args| {
+                Ok(Value::Number(f64::try_from(&args[0])? * 2.0))
+            }),
+            NativeFunction::new("answer", 0, |_vm, _args| Ok(Value::Int(42))),
+        ],
+    );
+    vm.interpret("import \"math2\";\nprint twice(answer());\n")
+        .expect("The script runs");
+    assert_eq!(out.contents(), "84\n");
+}
+
+#[test]
+fn a_module_imported_twice_runs_once() {
+    let dir = temp_dir("once");
+    write_files(
+        &dir,
+        &[
+            (
+                "main.lox",
+                "import \"counter\";\nimport \"user\";\nimport \"counter.lox\";\nprint count;\n",
+            ),
+            ("user.lox", "import \"counter\";\n"),
+            (
+                "counter.lox",
+                "print \"loading\";\nvar count = 0;\ncount = count + 1;\n",
+            ),
+        ],
+    );
+    let (out, result) = run_main(&dir);
+    assert!(result.is_ok(), "{result:?}");
+    assert_eq!(out, "loading\n1\n");
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,44 @@
+//! The `??` and `?.` operators
+mod common;
+
+use common::{compile_errors, run};
+
+#[test]
+fn coalescing_only_evaluates_the_default_for_nil() {
+    let source = r#"
+fun loud(x) { print "evaluated"; return x; }
+print nil ?? 1;
+print false ?? loud(2);
+print 0 ?? 3;
+print nil ?? nil ?? "last";
+print nil ?? 1 + 2;
+print (nil ?? 1) == 1 or false;
+"#;
+    assert_eq!(run(source), "1\nfalse\n0\nlast\n3\ntrue\n");
+}
+
+#[test]
+fn optional_chaining_skips_the_rest_of_the_chain() {
+    let source = r#"
+class Node {
+  init(next) { this.next = next; this.value = 1; }
+  get() { return this.value; }
+}
+var a = Node(Node(nil));
+var missing = nil;
+print a?.next.value;
+print a.next?.next?.value;
+print missing?.next.next.get();
+print a?.get();
+print missing?.get() ?? "default";
+"#;
+    assert_eq!(run(source), "1\nnil\nnil\n1\ndefault\n");
+}
+
+#[test]
+fn optional_chaining_cannot_be_assigned() {
+    assert_eq!(
+        compile_errors("var a; a?.b = 1;"),
+        vec!["Invalid assignment target."]
+    );
+}
@@ -0,0 +1,253 @@
+//! The arithmetic of the numbers, the 64-bit integers and the floats they turn into when they
+//! overflow
+mod common;
+
+use common::{error, quiet_vm, run};
+use rustlox::disassembler::instructions;
+use rustlox::vm::DivisionByZero;
+use rustlox::{LoxError, VM};
+
+#[test]
+fn the_integers_print_and_compare_like_the_other_numbers() {
+    let source = r#"
+var total = 0;
+for (var i = 0; i < 10; i++) total = total + i * 3 - 1;
+print total;
+print 1 == 1.0;
+print 7 / 2;
+print 0.1 + 0.2;
+print 2 ** 62;
+print 2 ** 70;
+print [10, 20, 30][2.0];
+print 3 > 2.5;
+"#;
+    assert_eq!(
+        run(source),
+        "125\ntrue\n3.5\n0.30000000000000004\n4611686018427387904\n1.1805916207174113e+21\n30\ntrue\n"
+    );
+}
+
+#[test]
+fn the_integers_overflow_into_floats() {
+    // The floats print their shortest digits, so the promotion shows
+    let source = r#"
+var max = 9223372036854775807;
+print max;
+print max + 1;
+print max * 2;
+print -max - 2;
+print 2 ** 63;
+var counter = max;
+counter++;
+print counter;
+print -(-max - 1);
+print 9223372036854775808;
+print 2 ** -1;
+"#;
+    assert_eq!(
+        run(source),
+        "9223372036854775807\n9223372036854776000\n18446744073709552000\n-9223372036854776000\n\
+         9223372036854776000\n9223372036854776000\n9223372036854776000\n9223372036854776000\n0.5\n"
+    );
+}
+
+#[test]
+fn the_integers_equal_the_same_floats() {
+    let source = r#"
+print 1 == 1.0;
+print 0 == -0.0;
+print 1 != 1.5;
+print 2 ** 53 + 1 == 2.0 ** 53;
+print 2 ** 53 == 2.0 ** 53;
+print [1, 2].indexOf(2.0);
+print 6 / 3 == 2;
+print 2 < 2.5 and 3 > 2.5;
+"#;
+    assert_eq!(
+        run(source),
+        "true\ntrue\ntrue\nfalse\ntrue\n1\ntrue\ntrue\n"
+    );
+}
+
+#[test]
+fn the_integers_print_without_a_fraction() {
+    let source = r#"
+print 6 / 2;
+print 7 % 3;
+print -7 % 3;
+print 2 ** 62;
+print 2.0 ** 62;
+print 1.0;
+print 3 & 6 | 8;
+print len("abc") * 2;
+print num("42") + 1;
+print num(" 2.5 ");
+print [1, 2.5, 3];
+print format("{:.2}", 3);
+"#;
+    assert_eq!(
+        run(source),
+        "3\n1\n-1\n4611686018427387904\n4611686018427388000\n1\n10\n6\n43\n2.5\n[1, 2.5, 3]\n3.00\n"
+    );
+}
+
+#[test]
+fn the_remainder_has_the_sign_of_the_dividend() {
+    let source = r#"
+print 7 % 3;
+print -7 % 3;
+print 7 % -3;
+print -7 % -3;
+print -6 % 3;
+print -7.5 % 2;
+"#;
+    assert_eq!(run(source), "1\n-1\n1\n-1\n0\n-1.5\n");
+}
+
+#[test]
+fn the_remainder_of_the_floats_keeps_the_fraction() {
+    let source = r#"
+print 5.5 % 2;
+print 7 % 2.5;
+print 0.75 % 0.5;
+print 1 % (1 / 0);
+print isNan((1 / 0) % 2);
+"#;
+    assert_eq!(run(source), "1.5\n2\n0.25\n1\ntrue\n");
+}
+
+#[test]
+fn the_remainder_by_zero_is_nan_unless_it_is_an_error() {
+    let source = "print isNan(5 % 0); print isNan(5.5 % 0); print isNan(0 % 0.0);";
+    assert_eq!(run(source), "true\ntrue\ntrue\n");
+
+    let mut vm = quiet_vm();
+    vm.set_division_by_zero(DivisionByZero::Error);
+    for source in ["5 % 0;", "5.5 % 0.0;"] {
+        match vm.interpret(source) {
+            Err(LoxError::RuntimeError { message, .. }) => {
+                assert_eq!(message, "Division by zero for '%'.")
+            }
+            result => panic!("Expected a runtime error, got {result:?}"),
+        }
+    }
+    assert!(vm.interpret("var r = 5 % 2;").is_ok());
+}
+
+#[test]
+fn the_remainder_of_other_values_is_a_runtime_error() {
+    assert_eq!(
+        error("print \"7\" % 2;"),
+        "Operands must be numbers, got string and number for '%'."
+    );
+    assert_eq!(
+        error("print 7 % nil;"),
+        "Operands must be numbers, got number and nil for '%'."
+    );
+    assert_eq!(
+        error("print [1] % true;"),
+        "Operands must be numbers, got list and boolean for '%'."
+    );
+}
+
+#[test]
+fn the_math_natives_compute_like_the_floats() {
+    let source = r#"
+print sqrt(16);
+print abs(-3) + abs(2.5);
+print floor(2.7) + ceil(2.1);
+print round(2.5) + round(-2.5);
+print sin(0) + cos(0);
+print log(E) + exp(0);
+print min(3, 1.5);
+print max(-1, 2);
+print pow(2, 10);
+print PI;
+print isNan(sqrt(-1));
+"#;
+    assert_eq!(
+        run(source),
+        "4\n5.5\n5\n0\n1\n2\n1.5\n2\n1024\n3.141592653589793\ntrue\n"
+    );
+}
+
+#[test]
+fn the_math_natives_take_only_numbers() {
+    assert_eq!(error("sqrt(\"4\");"), "Expect a number, got string.");
+    assert_eq!(error("min(1, nil);"), "Expect a number, got nil.");
+    assert_eq!(error("pow([2], 2);"), "Expect a number, got list.");
+    assert_eq!(error("sqrt();"), "Expected 1 arguments but got 0.");
+}
+
+#[test]
+fn num_reads_the_numbers_of_the_strings() {
+    let source = r#"
+print num("  12 ") + num("0.5");
+print num("-7") * 2;
+print num("1e3");
+print num(3) == 3;
+print num(2.5);
+print str(12) + "px";
+"#;
+    assert_eq!(run(source), "12.5\n-14\n1000\ntrue\n2.5\n12px\n");
+    assert_eq!(error("num(\"abc\");"), "Can't convert 'abc' to a number.");
+    assert_eq!(error("num(\"\");"), "Can't convert '' to a number.");
+    assert_eq!(error("num(nil);"), "Expect a string, got nil.");
+    assert_eq!(error("num([1]);"), "Expect a string, got list.");
+}
+
+#[test]
+fn the_integers_are_exact_up_to_the_max_safe_integer() {
+    let source = r#"
+print MAX_SAFE_INTEGER;
+print MAX_SAFE_INTEGER + 1 == MAX_SAFE_INTEGER + 2;
+print MAX_SAFE_INTEGER - 1 == MAX_SAFE_INTEGER - 2;
+print isInteger(3) and isInteger(-0) and isInteger(2 ** 60);
+print isInteger(0.5) or isInteger(1 / 0) or isInteger(0 / 0);
+"#;
+    assert_eq!(run(source), "9007199254740991\ntrue\nfalse\ntrue\nfalse\n");
+    assert_eq!(error("isInteger(\"1\");"), "Expect a number, got string.");
+}
+
+#[test]
+fn the_other_operands_take_the_slow_path() {
+    let source = r#"
+class Money {
+  init(cents) { this.cents = cents; }
+  plus(other) { return Money(this.cents + other); }
+  less(other) { return this.cents < other; }
+}
+var m = Money(5);
+m++;
+print m.cents;
+print (m + 10).cents;
+print m < 7;
+print "a" + "b";
+"#;
+    assert_eq!(run(source), "6\n16\ntrue\nab\n");
+    assert_eq!(
+        error("print 1 + \"a\";"),
+        "Operands must be two numbers or two strings, got number and string for '+'."
+    );
+    assert_eq!(
+        error("var s = \"a\"; s++;"),
+        "Operands must be two numbers or two strings, got string and number for '+'."
+    );
+    assert_eq!(
+        error("print nil < 1;"),
+        "Operands must be numbers, got nil and number for the comparison."
+    );
+}
+
+#[test]
+fn the_disassembler_shows_the_constants_like_print() {
+    let script = VM::check("print 1 + 2.5; print \"s\";").unwrap();
+    let constants: Vec<_> = instructions(&script.function.chunk)
+        .into_iter()
+        .filter_map(|instruction| {
+            let (_, constant) = instruction.text.split_once('\'')?;
+            Some(constant.trim_end().trim_end_matches('\'').to_string())
+        })
+        .collect();
+    assert_eq!(constants, ["1", "2.5", "s"]);
+}
@@ -0,0 +1,148 @@
+//! The peephole optimizer changes the bytecode, but not what it does
+use rustlox::chunk::{Chunk, OpCode};
+use rustlox::compiler::Compiler;
+use rustlox::disassembler::{instructions, Instruction};
+use rustlox::output::SharedBuffer;
+use rustlox::value::{Function, FunctionType, Value};
+use rustlox::verifier::verify;
+use rustlox::VM;
+
+/// The script compiled with or without the optimizer, which must pass the verifier
+fn compile(source: &str, optimize: bool) -> Function {
+    let mut compiler = Compiler::new(FunctionType::Script);
+    if optimize {
+        compiler = compiler.optimized();
+    }
+    let function = compiler.compile(source).expect("The script compiles");
+    verify(&function).expect("The script is valid bytecode");
+    function
+}
+
+/// The output of the script, with or without the optimizer
+fn run(source: &str, optimize: bool) -> String {
+    let out = SharedBuffer::new();
+    let mut vm = VM::new();
+    vm.set_stdout(Box::new(out.clone()));
+    vm.set_optimize(optimize);
+    vm.interpret(source).expect("The script runs");
+    out.contents()
+}
+
+/// Run the script both ways, which must print the same, and return the optimized script
+fn optimize(source: &str) -> Function {
+    assert_eq!(run(source, false), run(source, true));
+    compile(source, true)
+}
+
+fn opcodes(chunk: &Chunk) -> Vec<OpCode> {
+    instructions(chunk).iter().map(|i| i.op).collect()
+}
+
+/// The offset the jump at `instruction` lands on
+fn jump_target(instruction: &Instruction) -> usize {
+    let operands = &instruction.operands;
+    let end = instruction.offset + 1 + operands.len();
+    match instruction.op {
+        OpCode::Jump | OpCode::JumpIfFalse => {
+            end + u16::from_be_bytes([operands[0], operands[1]]) as usize
+        }
+        OpCode::JumpLong | OpCode::JumpIfFalseLong => {
+            end + u32::from_be_bytes(operands[..].try_into().unwrap()) as usize
+        }
+        op => panic!("Not a forward jump: {op:?}"),
+    }
+}
+
+#[test]
+fn equal_and_not_are_fused() {
+    let source = "var a = 1; var b = 2; print !(a == b); print !(a == 1);";
+    let ops = opcodes(&optimize(source).chunk);
+    assert_eq!(ops.iter().filter(|op| **op == OpCode::NotEqual).count(), 2);
+    assert!(!ops.contains(&OpCode::Equal));
+    assert!(!ops.contains(&OpCode::Not));
+    assert!(opcodes(&compile(source, false).chunk).contains(&OpCode::Equal));
+}
+
+#[test]
+fn adding_one_is_an_increment() {
+    let source = "var x = 41; print x + 1; print x + 2; print \"a\" + \"b\";";
+    let ops = opcodes(&optimize(source).chunk);
+    assert_eq!(ops.iter().filter(|op| **op == OpCode::Increment).count(), 1);
+    assert_eq!(ops.iter().filter(|op| **op == OpCode::Add).count(), 2);
+}
+
+#[test]
+fn an_increment_of_a_string_is_still_a_type_error() {
+    let mut vm = VM::new();
+    vm.set_optimize(true);
+    assert!(vm.interpret("var s = \"a\"; print s + 1;").is_err());
+}
+
+#[test]
+fn the_jumps_to_jumps_are_threaded() {
+    let source = r#"
+for (var a = 0; a < 2; a = a + 1) {
+  for (var b = 0; b < 2; b = b + 1) {
+    if (a == 0) { if (b == 0) print "00"; else print "01"; } else print "1";
+  }
+}
+"#;
+    let unoptimized = compile(source, false);
+    let optimized = optimize(source);
+    let jumps_to_jumps = |chunk: &Chunk| {
+        let instructions = instructions(chunk);
+        instructions
+            .iter()
+            .filter(|i| matches!(i.op, OpCode::Jump | OpCode::JumpIfFalse))
+            .filter(|i| {
+                let target = jump_target(i);
+                instructions
+                    .iter()
+                    .any(|t| t.offset == target && t.op == OpCode::Jump)
+            })
+            .count()
+    };
+    assert!(jumps_to_jumps(&unoptimized.chunk) > 0);
+    assert_eq!(jumps_to_jumps(&optimized.chunk), 0);
+}
+
+#[test]
+fn the_code_after_return_is_removed() {
+    let source = "fun f() { return 1; print 2; } print f();";
+    let script = optimize(source);
+    let Some(Value::Func(f)) = script
+        .chunk
+        .constants
+        .values
+        .iter()
+        .find(|constant| matches!(constant, Value::Func(_)))
+    else {
+        panic!("The script defines a function");
+    };
+    assert_eq!(opcodes(&f.chunk), [OpCode::Constant, OpCode::Return]);
+}
+
+#[test]
+fn the_jumps_are_narrowed_when_the_code_shrinks_enough() {
+    // Each statement is 8 bytes, and 6 once `+ 1` is an increment: the body needs the wide jump
+    // only before the optimization
+    let body = "x = x + 1;\n".repeat(9_000);
+    let source = format!("var x = 0;\nif (x == 0) {{\n{body}}}\nprint x;");
+    let unoptimized = opcodes(&compile(&source, false).chunk);
+    assert!(unoptimized.contains(&OpCode::JumpIfFalseLong));
+    let optimized = opcodes(&optimize(&source).chunk);
+    assert!(optimized.contains(&OpCode::JumpIfFalse));
+    assert!(!optimized.contains(&OpCode::JumpIfFalseLong));
+    assert!(!optimized.contains(&OpCode::JumpLong));
+}
+
+#[test]
+fn the_jumps_stay_wide_when_the_code_is_still_too_long() {
+    let body = "x = x + 1;\n".repeat(20_000);
+    let source = format!(
+        "var x = 0;\nfor (var i = 0; i < 2; i = i + 1) {{\nif (x >= 0) {{\n{body}}}\n}}\nprint x;"
+    );
+    let optimized = opcodes(&optimize(&source).chunk);
+    assert!(optimized.contains(&OpCode::JumpIfFalseLong));
+    assert!(optimized.contains(&OpCode::LoopLong));
+}
@@ -0,0 +1,89 @@
+//! The profiler, which counts the calls of the functions and the executed opcodes
+use rustlox::chunk::OpCode;
+use rustlox::VM;
+
+/// A VM which ran the script with the profiler on
+fn profiled(source: &str) -> VM {
+    let mut vm = VM::new();
+    vm.set_profiling(true);
+    vm.interpret(source).expect("The script runs");
+    vm
+}
+
+const SQUARES: &str = r#"
+fun square(n) { return n * n; }
+var total = 0;
+for (var i = 0; i < 7; i++) total = total + square(i);
+"#;
+
+#[test]
+fn the_profile_counts_the_calls_of_every_function() {
+    let vm = profiled(SQUARES);
+    let profile = vm.profile().expect("Profiling is on");
+    let calls: Vec<_> = profile
+        .functions()
+        .into_iter()
+        .map(|(name, function)| (name.to_string(), function.calls))
+        .collect();
+    assert_eq!(calls.len(), 2, "{calls:?}");
+    assert!(
+        calls.contains(&("square (line 2)".to_string(), 7)),
+        "{calls:?}"
+    );
+    assert!(calls.contains(&("<script>".to_string(), 1)), "{calls:?}");
+}
+
+#[test]
+fn the_profile_counts_the_opcodes() {
+    let vm = profiled(SQUARES);
+    let opcodes = vm.profile().expect("Profiling is on").opcodes();
+    let count = |op| {
+        opcodes
+            .iter()
+            .find(|(other, _)| *other == op)
+            .map(|(_, n)| *n)
+    };
+    assert_eq!(count(OpCode::Call), Some(7));
+    assert_eq!(count(OpCode::Multiply), Some(7));
+    assert_eq!(count(OpCode::Divide), None);
+    // The most frequent first
+    assert!(opcodes.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+}
+
+#[test]
+fn the_report_and_the_json_show_the_counts() {
+    let vm = profiled(SQUARES);
+    let profile = vm.profile().expect("Profiling is on");
+    let report = profile.report();
+    let line = report
+        .lines()
+        .find(|line| line.starts_with("square (line 2)"))
+        .unwrap_or_else(|| panic!("No line of square in {report}"));
+    assert_eq!(line.split_whitespace().nth(3), Some("7"), "{line}");
+    assert!(report
+        .lines()
+        .any(|line| line.split_whitespace().eq(["Multiply", "7"])));
+
+    let json = profile.to_json();
+    assert!(
+        json.contains(r#"{"name":"square (line 2)","calls":7,"#),
+        "{json}"
+    );
+    assert!(json.contains(r#"{"opcode":"Call","count":7}"#), "{json}");
+}
+
+#[test]
+fn turning_the_profiler_off_drops_the_counts() {
+    let mut vm = profiled(SQUARES);
+    vm.set_profiling(false);
+    assert!(vm.profile().is_none());
+    vm.set_profiling(true);
+    vm.interpret("square(1);").unwrap();
+    let profile = vm.profile().expect("Profiling is on");
+    let square = profile
+        .functions()
+        .into_iter()
+        .find(|(name, _)| name.starts_with("square"))
+        .map(|(_, function)| function.calls);
+    assert_eq!(square, Some(1));
+}
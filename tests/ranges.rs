@@ -0,0 +1,68 @@
+//! The ranges and the `for (x in ...)` loops
+mod common;
+
+use common::{error, run};
+
+#[test]
+fn for_loops_iterate_over_ranges_lists_and_strings() {
+    let source = r#"
+for (i in 1..3) print i;
+for (var i in 1..=2) print -i;
+for (x in ["a", nil]) print x;
+for (ch in "hé") print ch;
+for (i in 3..1) print "never";
+"#;
+    assert_eq!(run(source), "1\n2\n-1\n-2\na\nnil\nh\né\n");
+}
+
+#[test]
+fn ranges_are_values() {
+    let source = r#"
+var r = 0..10;
+print r; print 1..=3;
+print r == (0..10); print r == (0..=10);
+"#;
+    assert_eq!(run(source), "0..10\n1..=3\ntrue\nfalse\n");
+}
+
+#[test]
+fn ranges_slice_lists_and_strings() {
+    let source = r#"
+var xs = [1, 2, 3, 4];
+var ys = xs[1..3];
+ys[0] = 0;
+print ys; print xs; print xs[2..=3]; print xs[4..4];
+print "héllo"[1..=3];
+"#;
+    assert_eq!(run(source), "[0, 3]\n[1, 2, 3, 4]\n[3, 4]\n[]\néll\n");
+}
+
+#[test]
+fn break_continue_and_closures_in_for_in_loops() {
+    let source = r#"
+var fs = [];
+for (i in 0..10) {
+  if (i == 1) continue;
+  if (i == 3) break;
+  append(fs, fun() { return i; });
+}
+for (f in fs) print f();
+"#;
+    assert_eq!(run(source), "0\n2\n");
+}
+
+#[test]
+fn ranges_are_checked() {
+    assert_eq!(
+        error("print 0.5..2;"),
+        "Range bounds must be integers, got 0.5 and 2."
+    );
+    assert_eq!(
+        error("print [1, 2][1..3];"),
+        "Range 1..3 is out of bounds for a list of length 2."
+    );
+    assert_eq!(
+        error("for (x in nil) {}"),
+        "Can only iterate over lists, strings and ranges, got nil."
+    );
+}
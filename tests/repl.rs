@@ -0,0 +1,129 @@
+//! A runtime error aborts the line typed in the REPL, and the next lines run as if it never
+//! happened, with the globals defined so far
+mod common;
+
+use common::quiet_vm;
+use rustlox::output::SharedBuffer;
+use rustlox::vm::Capability;
+use rustlox::VM;
+use std::fs;
+
+/// Run the lines one by one like the REPL does, and return what each printed or `None` if it
+/// failed
+fn session(vm: &mut VM, lines: &[&str]) -> Vec<Option<String>> {
+    let out = SharedBuffer::new();
+    vm.set_stdout(Box::new(out.clone()));
+    let mut printed = 0;
+    lines
+        .iter()
+        .map(|line| {
+            let result = vm.interpret_repl(line);
+            let contents = out.contents();
+            let output = contents[printed..].to_string();
+            printed = contents.len();
+            result.ok().map(|_| output)
+        })
+        .collect()
+}
+
+#[test]
+fn the_globals_and_the_closures_survive_a_runtime_error() {
+    let mut vm = quiet_vm();
+    let lines = [
+        "fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }",
+        "var next = counter(); print next();",
+        // The error happens while `y` is still on the stack, captured by `saved`
+        "var saved; fun keep() { var y = 7; fun k() { return y; } saved = k; nil(); } keep();",
+        "print saved(); print next();",
+        "var defined = 1; nil(); var skipped = 2;",
+        "print defined;",
+        "print skipped;",
+    ];
+    assert_eq!(
+        session(&mut vm, &lines),
+        [
+            Some(String::new()),
+            Some("1\n".to_string()),
+            None,
+            Some("7\n2\n".to_string()),
+            None,
+            Some("1\n".to_string()),
+            None,
+        ]
+    );
+}
+
+#[test]
+fn the_calls_aborted_by_an_error_leave_no_frames_behind() {
+    let mut vm = quiet_vm();
+    let lines = [
+        "fun deep(n) { return deep(n + 1); } deep(0);",
+        "fun sum(n) { if (n == 0) return 0; return n + sum(n - 1); } print sum(50);",
+        "class Vec { plus(other) { return other.missing; } length { return nil(); } }",
+        "print Vec() + Vec();",
+        "print Vec().length;",
+        "fun gen() { yield 1; nil(); } var g = gen(); print resume(g, nil);",
+        "print resume(g, nil);",
+        "print isDone(g); print 1 + 2;",
+    ];
+    assert_eq!(
+        session(&mut vm, &lines),
+        [
+            None,
+            Some("1275\n".to_string()),
+            Some(String::new()),
+            None,
+            None,
+            Some("1\n".to_string()),
+            None,
+            Some("true\n3\n".to_string()),
+        ]
+    );
+    assert!(vm.frames.is_empty());
+}
+
+#[test]
+fn a_module_aborted_by_an_error_runs_again_when_imported_again() {
+    let dir = std::env::temp_dir().join(format!("rustlox-repl-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let module = dir.join("flaky.lox");
+    fs::write(&module, "var loaded = 1;\nfail();\nvar finished = true;\n").unwrap();
+    let import = format!("import {:?};", module.display().to_string());
+
+    let mut vm = quiet_vm();
+    vm.allow(Capability::Fs);
+    let results = session(&mut vm, &[&import, "print loaded;"]);
+    assert_eq!(results, [None, Some("1\n".to_string())]);
+    // Fixed, so importing it again runs it to the end
+    fs::write(&module, "var loaded = 2;\nvar finished = true;\n").unwrap();
+    let results = session(&mut vm, &[&import, "print loaded; print finished;"]);
+    assert_eq!(
+        results,
+        [Some(String::new()), Some("2\ntrue\n".to_string())]
+    );
+    // Imported to the end, so it doesn't run a third time
+    let results = session(&mut vm, &["loaded = 3;", &import, "print loaded;"]);
+    assert_eq!(results[2], Some("3\n".to_string()));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn only_the_code_cut_off_at_the_end_waits_for_more_lines() {
+    for incomplete in [
+        "fun f() {",
+        "fun f() {\n  print 1;",
+        "print (1 +",
+        "var s = \"two\nlines",
+        "/* a comment",
+    ] {
+        assert!(VM::is_incomplete(incomplete), "{incomplete:?}");
+    }
+    // A syntax error doesn't go away with more lines, so it's reported right away
+    for complete in ["print (;", "print (; {", "var 1 = (", "1 + 2", ""] {
+        assert!(!VM::is_incomplete(complete), "{complete:?}");
+    }
+
+    let mut vm = quiet_vm();
+    let results = session(&mut vm, &["print (;", "print 1;"]);
+    assert_eq!(results, [None, Some("1\n".to_string())]);
+}
@@ -0,0 +1,111 @@
+//! Running a script a few instructions at a time, interleaved with the host
+use rustlox::error::Limit;
+use rustlox::output::SharedBuffer;
+use rustlox::vm::Limits;
+use rustlox::{LoxError, StepResult, WriterReporter, VM};
+use std::io;
+
+fn quiet_vm(out: &SharedBuffer) -> VM {
+    let mut vm = VM::with_reporter(Box::new(WriterReporter::new(Box::new(io::sink()))));
+    vm.set_stdout(Box::new(out.clone()));
+    vm
+}
+
+#[test]
+fn the_script_yields_between_the_steps() {
+    let out = SharedBuffer::new();
+    let mut vm = quiet_vm(&out);
+    vm.start("for (var i = 0; i < 100; i = i + 1) print i;")
+        .unwrap();
+    let mut steps = 0;
+    loop {
+        let before = out.contents().lines().count();
+        match vm.run_steps(10) {
+            StepResult::Yielded => {
+                // A loop iteration takes more than a couple of instructions
+                assert!(out.contents().lines().count() - before <= 5);
+                steps += 1;
+            }
+            StepResult::Done => break,
+            StepResult::Error(error) => panic!("{error:?}"),
+        }
+    }
+    assert!(steps > 20);
+    let expected: String = (0..100).map(|i| format!("{i}\n")).collect();
+    assert_eq!(out.contents(), expected);
+    // Nothing is left to run
+    assert!(matches!(vm.run_steps(10), StepResult::Done));
+}
+
+#[test]
+fn zero_steps_run_nothing() {
+    let out = SharedBuffer::new();
+    let mut vm = quiet_vm(&out);
+    vm.start("print 1;").unwrap();
+    assert!(matches!(vm.run_steps(0), StepResult::Yielded));
+    assert_eq!(out.contents(), "");
+    assert!(matches!(vm.run_steps(100), StepResult::Done));
+    assert_eq!(out.contents(), "1\n");
+}
+
+#[test]
+fn a_runtime_error_ends_the_script() {
+    let out = SharedBuffer::new();
+    let mut vm = quiet_vm(&out);
+    vm.start("print 1; print nil + 1; print 2;").unwrap();
+    let result = loop {
+        match vm.run_steps(1) {
+            StepResult::Yielded => {}
+            result => break result,
+        }
+    };
+    assert!(
+        matches!(result, StepResult::Error(LoxError::RuntimeError { ref message, .. }) if message.starts_with("Operands must be"))
+    );
+    assert_eq!(out.contents(), "1\n");
+    // The VM is ready for the next script
+    vm.interpret("print 3;").unwrap();
+    assert_eq!(out.contents(), "1\n3\n");
+}
+
+#[test]
+fn the_host_runs_code_while_the_script_is_paused() {
+    let out = SharedBuffer::new();
+    let mut vm = quiet_vm(&out);
+    vm.interpret("var count = 0; fun bump() { count = count + 1; return count; }")
+        .unwrap();
+    vm.start("while (count < 3) {} print \"done\";").unwrap();
+    assert!(vm.start("print 1;").is_err());
+    for _ in 0..3 {
+        assert!(matches!(vm.run_steps(50), StepResult::Yielded));
+        vm.call_function("bump", &[]).unwrap();
+        // An error of the host's code doesn't end the script
+        assert!(vm.interpret("nil();").is_err());
+    }
+    assert!(matches!(vm.run_steps(50), StepResult::Done));
+    assert_eq!(out.contents(), "done\n");
+}
+
+#[test]
+fn the_limits_apply_across_the_steps() {
+    let out = SharedBuffer::new();
+    let mut vm = quiet_vm(&out);
+    vm.set_limits(Limits {
+        max_instructions: Some(1000),
+        ..Limits::default()
+    });
+    vm.start("while (true) {}").unwrap();
+    let mut steps = 0;
+    let result = loop {
+        match vm.run_steps(100) {
+            StepResult::Yielded => steps += 1,
+            result => break result,
+        }
+    };
+    // The ten steps run in full, and the next one stops at its first instruction
+    assert_eq!(steps, 10);
+    assert!(matches!(
+        result,
+        StepResult::Error(LoxError::LimitExceeded(Limit::Instructions(1000)))
+    ));
+}
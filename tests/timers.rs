@@ -0,0 +1,100 @@
+//! The timers of `setTimeout` and `setInterval`, and the event loop running them
+mod common;
+
+use common::quiet_vm;
+use rustlox::output::SharedBuffer;
+use rustlox::vm::Capability;
+use rustlox::{LoxError, VM};
+
+/// A VM on the virtual clock, so the timers run the same way every time
+fn timer_vm(out: &SharedBuffer) -> VM {
+    let mut vm = quiet_vm();
+    vm.allow(Capability::Time);
+    vm.set_deterministic(Some(0));
+    vm.set_stdout(Box::new(out.clone()));
+    vm
+}
+
+#[test]
+fn the_timers_run_in_the_order_they_are_due() {
+    let out = SharedBuffer::new();
+    let mut vm = timer_vm(&out);
+    let source = r#"
+var start = timeMillis();
+fun log(what) { print format("{} at {}", what, round((timeMillis() - start) / 100) * 100); }
+setTimeout(fun () { log("300"); }, 300);
+setTimeout(fun () { log("100"); setTimeout(fun () { log("nested"); }, 50); }, 100);
+var ticks = 0;
+var id = setInterval(fun () {
+  ticks = ticks + 1;
+  log("tick");
+  if (ticks == 3) clearTimer(id);
+}, 120);
+setTimeout(fun () { log("never"); }, 10);
+clearTimer(4);
+print "script";
+"#;
+    vm.interpret(source).unwrap();
+    assert_eq!(out.contents(), "script\n");
+    vm.run_event_loop().unwrap();
+    assert_eq!(
+        out.contents(),
+        "script\n100 at 100\ntick at 100\nnested at 200\ntick at 200\n300 at 300\ntick at 400\n"
+    );
+    // Nothing is left
+    vm.run_event_loop().unwrap();
+    assert_eq!(out.contents().lines().count(), 7);
+}
+
+#[test]
+fn a_host_runs_the_due_timers_in_its_own_loop() {
+    let out = SharedBuffer::new();
+    let mut vm = timer_vm(&out);
+    vm.interpret(
+        "setTimeout(fun () { print \"late\"; }, 1000); setTimeout(fun () { print \"soon\"; }, 0);",
+    )
+    .unwrap();
+    assert!(vm.run_due_timers().unwrap());
+    assert_eq!(out.contents(), "soon\n");
+    assert!(vm.run_due_timers().unwrap());
+    assert_eq!(out.contents(), "soon\n");
+    vm.interpret("sleep(1);").unwrap();
+    assert!(!vm.run_due_timers().unwrap());
+    assert_eq!(out.contents(), "soon\nlate\n");
+}
+
+#[test]
+fn an_error_stops_the_event_loop() {
+    let out = SharedBuffer::new();
+    let mut vm = timer_vm(&out);
+    let source = r#"
+setTimeout(fun () { print "first"; }, 10);
+setTimeout(fun () { nil(); }, 20);
+setTimeout(fun () { print "third"; }, 30);
+"#;
+    vm.interpret(source).unwrap();
+    assert!(matches!(
+        vm.run_event_loop(),
+        Err(LoxError::RuntimeError { message, .. }) if message == "Can only call functions and classes."
+    ));
+    assert_eq!(out.contents(), "first\n");
+    // The rest is still pending
+    vm.run_event_loop().unwrap();
+    assert_eq!(out.contents(), "first\nthird\n");
+}
+
+#[test]
+fn the_timers_check_their_arguments() {
+    let out = SharedBuffer::new();
+    let mut vm = timer_vm(&out);
+    assert!(vm.interpret("setTimeout(nil, 10);").is_err());
+    assert!(vm.interpret("setInterval(clock, -1);").is_err());
+    assert!(vm
+        .interpret("print clearTimer(42); print clearTimer(0.5);")
+        .is_ok());
+    assert_eq!(out.contents(), "false\nfalse\n");
+    // The timers read the clock
+    let mut sandboxed = quiet_vm();
+    sandboxed.deny(Capability::Time);
+    assert!(sandboxed.interpret("setTimeout(clock, 10);").is_err());
+}
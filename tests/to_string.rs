@@ -0,0 +1,62 @@
+//! The conversion of the values to strings, which the `toString` methods of the classes take over
+mod common;
+
+use common::{error, run};
+
+const POINT: &str = r#"
+class Point {
+  init(x, y) { this.x = x; this.y = y; }
+  toString() { return "(" + str(this.x) + ", " + str(this.y) + ")"; }
+}
+class Plain {}
+"#;
+
+#[test]
+fn every_conversion_calls_the_method() {
+    let source = r#"
+var p = Point(1, 2);
+print p;
+print str(p) + " " + toString(p);
+print format("{} {:>8}", p, p);
+print [p, [Point(3, 4)], "p", Plain()];
+print [p].toString();
+"#;
+    assert_eq!(
+        run(&format!("{POINT}{source}")),
+        "(1, 2)\n(1, 2) (1, 2)\n(1, 2)   (1, 2)\n[(1, 2), [(3, 4)], \"p\", Plain instance]\n[(1, 2)]\n"
+    );
+}
+
+#[test]
+fn every_value_converts() {
+    let source = r#"
+fun f() {}
+class C { m() {} }
+print toString(1.5) + toString(nil) + toString(true) + toString("s");
+print toString(f) + " " + toString(C().m) + " " + toString(C) + " " + toString(clock);
+var list = [1]; list.push(list);
+print toString(list);
+"#;
+    assert_eq!(
+        run(source),
+        "1.5niltrues\n<fn f> <fn m> C <native fn>\n[1, [...]]\n"
+    );
+}
+
+#[test]
+fn the_method_must_return_a_string() {
+    let source = "class Bad { toString() { return 1; } }";
+    assert_eq!(
+        error(&format!("{source} print Bad();")),
+        "'toString' must return a string, got number."
+    );
+    assert_eq!(
+        error(&format!("{source} str([Bad()]);")),
+        "'toString' must return a string, got number."
+    );
+    // The error of the method is the error of the script
+    assert_eq!(
+        error("class E { toString() { return nil.x; } } print str(E());"),
+        "Undefined method 'x' of nil."
+    );
+}
@@ -0,0 +1,160 @@
+//! The verifier of the bytecode loaded from `.loxc` files
+use rustlox::bytecode::{self, BytecodeError};
+use rustlox::chunk::OpCode;
+use rustlox::scanner::Span;
+use rustlox::value::{Function, Value};
+use rustlox::verifier::verify;
+use rustlox::VM;
+
+fn compile(source: &str) -> Function {
+    VM::check(source).unwrap().function
+}
+
+/// The message of the verifier about the script with its code replaced
+fn verify_code(source: &str, code: Vec<u8>) -> String {
+    let mut script = compile(source);
+    script.chunk.lines = vec![1; code.len()];
+    script.chunk.columns = vec![1; code.len()];
+    script.chunk.spans = vec![Span::default(); code.len()];
+    script.chunk.code = code;
+    verify(&script).unwrap_err().message
+}
+
+#[test]
+fn the_compiled_code_passes() {
+    let source = r#"
+class A { init(x) { this.x = x; } get { return this.x; } }
+class B < A { init() { super.init(1); } static make(...xs) { return B(); } }
+fun counter(start = 0) {
+  var i = start;
+  return fun() { i++; return i; };
+}
+var xs = [1, ...[2, 3]];
+for (x in xs[1:]) { if (x == 2) continue; print x ?? B.make()?.get; }
+for (var i = 0; i < 3; i = i + 1) { while (true) break; print counter(i)(); }
+print 1 < 2 and !(3 > 4) or xs[0] == 1;
+"#;
+    assert_eq!(verify(&compile(source)), Ok(()));
+    for entry in std::fs::read_dir("benches/lox").unwrap() {
+        let source = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+        assert_eq!(verify(&compile(&source)), Ok(()));
+    }
+}
+
+#[test]
+fn malformed_code_is_rejected() {
+    let nil = OpCode::Nil as u8;
+    let ret = OpCode::Return as u8;
+    assert_eq!(verify_code("", vec![0xff]), "Unknown opcode 255.");
+    assert_eq!(
+        verify_code("", vec![nil, OpCode::Jump as u8, 0]),
+        "The operands run past the end of the code."
+    );
+    assert_eq!(verify_code("", vec![nil]), "Runs past the end of the code.");
+    assert_eq!(
+        verify_code(
+            "",
+            vec![OpCode::Jump as u8, 0, 1, OpCode::Dup as u8, 0, ret]
+        ),
+        "Jumps into the middle of an instruction."
+    );
+    assert_eq!(
+        verify_code("", vec![OpCode::Loop as u8, 0, 9]),
+        "Jumps before the start of the code."
+    );
+    assert_eq!(
+        verify_code("", vec![OpCode::Constant as u8, 0, ret]),
+        "No constant 0."
+    );
+    assert_eq!(
+        verify_code(
+            "print 1;",
+            vec![nil, OpCode::DefineGlobal as u8, 0, nil, ret]
+        ),
+        "Constant 0 isn't a name."
+    );
+    assert_eq!(
+        verify_code("", vec![OpCode::GetLocal as u8, 1, ret]),
+        "No such local variable."
+    );
+    assert_eq!(
+        verify_code("", vec![OpCode::GetUpvalue as u8, 0, ret]),
+        "No such upvalue."
+    );
+    assert_eq!(
+        verify_code("", vec![OpCode::Pop as u8, nil, ret]),
+        "Pops more values than the stack has."
+    );
+    // `if (true) nil;` without popping the condition on one of the paths
+    let code = vec![
+        OpCode::True as u8,
+        OpCode::JumpIfFalse as u8,
+        0,
+        1,
+        OpCode::Pop as u8,
+        nil,
+        ret,
+    ];
+    assert_eq!(
+        verify_code("", code),
+        "The paths to the instruction leave different values on the stack."
+    );
+}
+
+#[test]
+fn the_functions_in_the_constants_are_verified() {
+    let mut script = compile("fun f() { return 1; }");
+    let Some(Value::Func(f)) = script
+        .chunk
+        .constants
+        .values
+        .iter_mut()
+        .find(|c| matches!(c, Value::Func(_)))
+    else {
+        panic!("The function is a constant");
+    };
+    let mut function = (**f).clone();
+    function.chunk.code[0] = OpCode::Pop.into();
+    *f = function.into();
+    let error = verify(&script).unwrap_err();
+    assert_eq!(error.function, "f");
+    assert_eq!(
+        error.to_string(),
+        "Pops more values than the stack has. (at 0 in f)"
+    );
+}
+
+#[test]
+fn loading_a_file_verifies_it() {
+    let mut script = compile("print 1;");
+    assert!(bytecode::deserialize(&bytecode::serialize(&script)).is_ok());
+    // `Constant 0; Print; Nil; Return` without the return
+    script.chunk.code[4] = OpCode::Pop.into();
+    let error = bytecode::deserialize(&bytecode::serialize(&script)).unwrap_err();
+    assert_eq!(
+        error,
+        BytecodeError::Malformed("Runs past the end of the code. (at 4 in <script>)".to_string())
+    );
+}
+
+#[test]
+fn a_variadic_function_has_the_rest_parameter() {
+    let mut script = compile("fun f(...rest) { return rest; }");
+    assert_eq!(verify(&script), Ok(()));
+    let Some(Value::Func(f)) = script
+        .chunk
+        .constants
+        .values
+        .iter_mut()
+        .find(|c| matches!(c, Value::Func(_)))
+    else {
+        panic!("The function is a constant");
+    };
+    let mut function = (**f).clone();
+    function.arity = 0;
+    *f = function.into();
+    assert_eq!(
+        verify(&script).unwrap_err().message,
+        "Invalid number of parameters."
+    );
+}
@@ -0,0 +1,135 @@
+//! The compile warnings, e.g. about the unused variables
+use rustlox::compiler::Warnings;
+use rustlox::lint;
+use rustlox::shared::{Rc, RefCell};
+use rustlox::{CollectingReporter, LoxError, Severity, VM};
+
+/// The messages of the warnings about the code, which compiles
+fn warnings(source: &str) -> Vec<String> {
+    let script = VM::check(source).expect("The script compiles");
+    assert!(script
+        .warnings
+        .iter()
+        .all(|warning| warning.severity == Severity::Warning));
+    script
+        .warnings
+        .into_iter()
+        .map(|warning| warning.message)
+        .collect()
+}
+
+#[test]
+fn unused_locals_and_parameters_are_reported() {
+    let source = r#"
+fun f(a, _ignored, used) {
+  var unused = 1;
+  var written;
+  written = 2;
+  fun captured() { return used; }
+  return captured;
+}
+{
+  var read = 1; print read;
+  for (var i = 0; i < 2; i = i + 1) {}
+  for (x in [1]) {}
+}
+"#;
+    assert_eq!(
+        warnings(source),
+        vec![
+            "Unused parameter 'a'.",
+            "Unused local variable 'unused'.",
+            "Local variable 'written' is assigned but never read.",
+            "Unused local variable 'x'.",
+        ]
+    );
+}
+
+#[test]
+fn code_after_return_is_reported_once() {
+    let source = r#"
+fun f() {
+  return 1;
+  print "never";
+  print "never either";
+}
+fun g(x) {
+  if (x) return 1;
+  return 2;
+}
+"#;
+    assert_eq!(warnings(source), vec!["Unreachable code after 'return'."]);
+}
+
+#[test]
+fn warnings_are_reported_denied_or_ignored() {
+    let source = "fun f() { var unused; } f();";
+    let reporter = Rc::new(RefCell::new(CollectingReporter::default()));
+    let mut vm = VM::with_reporter(Box::new(Rc::clone(&reporter)));
+    vm.interpret(source)
+        .expect("Warnings don't stop the script");
+    assert_eq!(reporter.borrow().diagnostics.len(), 1);
+
+    vm.set_warnings(Warnings::Deny);
+    let Err(LoxError::CompileError(errors)) = vm.interpret(source) else {
+        panic!("Denied warnings are compile errors");
+    };
+    assert_eq!(errors[0].severity, Severity::Error);
+    assert_eq!(errors[0].message, "Unused local variable 'unused'.");
+
+    vm.set_warnings(Warnings::Off);
+    reporter.borrow_mut().diagnostics.clear();
+    vm.interpret(source).expect("The script runs");
+    assert!(reporter.borrow().diagnostics.is_empty());
+}
+
+#[test]
+fn the_lints_report_shadowed_locals_and_undefined_globals() {
+    let source = r#"
+fun f(x) {
+  { var x = 2; print x; }
+  fun g() { var _x = 1; return _x; }
+  print clock() + hostValue + g() + later;
+  return prnt(x);
+}
+var later = 1;
+"#;
+    let reporter = Rc::new(RefCell::new(CollectingReporter::default()));
+    let mut vm = VM::with_reporter(Box::new(Rc::clone(&reporter)));
+    vm.set_global("hostValue", 1.0.into());
+    vm.compile(source).expect("The script compiles");
+    assert!(reporter.borrow().diagnostics.is_empty());
+
+    vm.set_lint(true);
+    vm.compile(source).expect("The script compiles");
+    let messages: Vec<_> = reporter
+        .borrow()
+        .diagnostics
+        .iter()
+        .map(|warning| (warning.line, warning.message.clone()))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            (
+                3,
+                "Local variable 'x' shadows a variable in an outer scope.".to_string()
+            ),
+            (6, "Global variable 'prnt' is never defined.".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn the_lint_skips_a_global_which_isnt_a_name() {
+    let mut script = VM::check("print prnt;")
+        .expect("The script compiles")
+        .function;
+    assert_eq!(
+        lint::undefined_globals(&script, |_| false)[0].message,
+        "Global variable 'prnt' is never defined."
+    );
+    // `GetGlobal` reading a number instead of the name, e.g. from a hand-written `.loxc` file
+    script.chunk.constants.values[0] = 1.0.into();
+    assert!(lint::undefined_globals(&script, |_| false).is_empty());
+}
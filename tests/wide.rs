@@ -0,0 +1,168 @@
+//! The wide instructions of the large scripts: more than 256 constants, globals or locals, and
+//! jumps over more than 65535 bytes of code
+mod common;
+
+use common::run;
+use rustlox::chunk::OpCode;
+use rustlox::disassembler::instructions;
+use rustlox::value::{Function, Value};
+use rustlox::verifier::verify;
+use rustlox::VM;
+
+/// The opcodes of the top level of the script, which must pass the verifier
+fn opcodes(source: &str) -> Vec<OpCode> {
+    let script = VM::check(source).expect("The script compiles");
+    verify(&script.function).expect("The script is valid bytecode");
+    instructions(&script.function.chunk)
+        .iter()
+        .map(|instruction| instruction.op)
+        .collect()
+}
+
+/// The opcodes of the function and of the functions defined in it
+fn all_opcodes(function: &Function) -> Vec<OpCode> {
+    let mut ops: Vec<_> = instructions(&function.chunk)
+        .iter()
+        .map(|instruction| instruction.op)
+        .collect();
+    for constant in &function.chunk.constants.values {
+        if let Value::Func(inner) = constant {
+            ops.extend(all_opcodes(inner));
+        }
+    }
+    ops
+}
+
+/// Statements using 300 constants of the chunk they are in
+fn constants() -> String {
+    (1000..1300)
+        .map(|n| {
+            format!(
+                "{n};
+"
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn more_than_256_constants_take_the_wide_instruction() {
+    let numbers: Vec<_> = (0..300).map(|n| (n + 1000).to_string()).collect();
+    let source = format!(
+        "var xs = [{}];\nprint xs[0]; print xs[299];",
+        numbers.join(", ")
+    );
+    assert_eq!(run(&source), "1000\n1299\n");
+    assert!(opcodes(&source).contains(&OpCode::ConstantLong));
+}
+
+#[test]
+fn more_than_256_globals_take_the_wide_instructions() {
+    let mut source: String = (0..300).map(|n| format!("var g{n} = {n};\n")).collect();
+    source.push_str("g299 = g299 + g1; print g299; print g0;");
+    assert_eq!(run(&source), "300\n0\n");
+    let ops = opcodes(&source);
+    for op in [
+        OpCode::DefineGlobalLong,
+        OpCode::GetGlobalLong,
+        OpCode::SetGlobalLong,
+    ] {
+        assert!(ops.contains(&op), "{op:?}");
+    }
+}
+
+#[test]
+fn more_than_256_locals_take_the_wide_instructions() {
+    let locals: String = (0..300).map(|n| format!("var l{n} = {n};\n")).collect();
+    let source = format!("{{\n{locals}l299 = l299 + l1; print l299; print l0;\n}}");
+    assert_eq!(run(&source), "300\n0\n");
+    let ops = opcodes(&source);
+    assert!(ops.contains(&OpCode::GetLocalLong));
+    assert!(ops.contains(&OpCode::SetLocalLong));
+}
+
+#[test]
+fn long_jumps_take_the_wide_instructions() {
+    let body = "x = x + 1;\n".repeat(20_000);
+    let source = format!(
+        "var x = 0;\nfor (var i = 0; i < 2; i = i + 1) {{\n{body}}}\nif (x > 0) {{\n{body}}}\nprint x;"
+    );
+    assert_eq!(run(&source), "60000\n");
+    let ops = opcodes(&source);
+    assert!(ops.contains(&OpCode::JumpIfFalseLong));
+    assert!(ops.contains(&OpCode::LoopLong));
+}
+
+#[test]
+fn the_classes_and_their_members_take_the_wide_instructions() {
+    let filler = constants();
+    let source = format!(
+        r#"{filler}
+class A {{
+  init(x) {{ this.x = x; }}
+  m() {{ return this.x; }}
+  double {{ return this.x * 2; }}
+  set double(v) {{ this.x = v / 2; }}
+  static make() {{ return A(1); }}
+}}
+class B < A {{
+  m() {{ {filler} return super.m() + 1; }}
+  get {{ {filler} return super.m; }}
+  spread() {{ {filler} return super.m(...[]); }}
+}}
+fun h() {{ return "h"; }}
+var a = A(2);
+a.x = a.x + 1;
+print a.m(); print a.double; a.double = 10; print a.x; print a.m(...[]);
+print A.make().x;
+var b = B(5);
+print b.m(); print b.get(); print b.spread();
+print h();
+"#
+    );
+    assert_eq!(run(&source), "3\n6\n5\n5\n1\n6\n5\n5\nh\n");
+    let script = VM::check(&source).expect("The script compiles");
+    verify(&script.function).expect("The script is valid bytecode");
+    let ops = all_opcodes(&script.function);
+    for op in [
+        OpCode::ClosureLong,
+        OpCode::ClassLong,
+        OpCode::MethodLong,
+        OpCode::GetterLong,
+        OpCode::SetterLong,
+        OpCode::StaticLong,
+        OpCode::GetPropertyLong,
+        OpCode::SetPropertyLong,
+        OpCode::InvokeLong,
+        OpCode::InvokeSpreadLong,
+        OpCode::GetSuperLong,
+        OpCode::SuperInvokeLong,
+        OpCode::SuperInvokeSpreadLong,
+    ] {
+        assert!(ops.contains(&op), "{op:?}");
+    }
+}
+
+#[test]
+fn a_function_with_300_locals_defines_a_closure() {
+    let locals: String = (0..300).map(|n| format!("var l{n} = {n};\n")).collect();
+    let source =
+        format!("fun f() {{\n{locals}fun g() {{ return l299 + l0; }}\nreturn g;\n}}\nprint f()();");
+    assert_eq!(run(&source), "299\n");
+    let script = VM::check(&source).expect("The script compiles");
+    verify(&script.function).expect("The script is valid bytecode");
+    assert!(all_opcodes(&script.function).contains(&OpCode::ClosureLong));
+}
+
+#[test]
+fn an_import_takes_the_wide_instruction() {
+    let source = format!("{}import \"missing\";", constants());
+    assert!(opcodes(&source).contains(&OpCode::ImportLong));
+    let mut vm = VM::with_reporter(Box::new(rustlox::WriterReporter::new(Box::new(
+        std::io::sink(),
+    ))));
+    let Err(rustlox::LoxError::RuntimeError { message, .. }) = vm.interpret(&source) else {
+        panic!("Expect a runtime error");
+    };
+    assert!(message.contains("missing"), "{message}");
+}
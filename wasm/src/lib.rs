@@ -0,0 +1,62 @@
+//! The API for JavaScript, e.g. for a Lox playground in the browser. It's a crate of its own, so
+//! only the WebAssembly module is a `cdylib`. Build it in this directory with
+//! `wasm-pack build --target web`, then
+//!
+//! ```js
+//! import init, { interpret } from "./pkg/rustlox_wasm.js";
+//!
+//! await init();
+//! const result = interpret("print 1 + 2;");
+//! console.log(result.output, result.errors, result.ok);
+//! ```
+use rustlox::output::SharedBuffer;
+use rustlox::vm::Capability;
+use rustlox::{WriterReporter, VM};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// What running a script printed
+#[wasm_bindgen]
+pub struct Interpretation {
+    output: String,
+    errors: String,
+    ok: bool,
+}
+
+#[wasm_bindgen]
+impl Interpretation {
+    /// The output of `print`
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> String {
+        self.output.clone()
+    }
+
+    /// The compile errors or the runtime error, as the command line prints them
+    #[wasm_bindgen(getter)]
+    pub fn errors(&self) -> String {
+        self.errors.clone()
+    }
+
+    /// Whether the script ran to the end without errors
+    #[wasm_bindgen(getter)]
+    pub fn ok(&self) -> bool {
+        self.ok
+    }
+}
+
+/// Run the source code in a new VM and capture what it prints
+#[wasm_bindgen]
+pub fn interpret(source: &str) -> Interpretation {
+    let (output, errors) = (SharedBuffer::new(), SharedBuffer::new());
+    let mut vm = VM::with_reporter(Box::new(WriterReporter::new(Box::new(errors.clone()))));
+    vm.set_stdout(Box::new(output.clone()));
+    // The page runs whatever its visitor types
+    for capability in Capability::ALL {
+        vm.deny(capability);
+    }
+    let ok = vm.interpret(source).is_ok();
+    Interpretation {
+        output: output.contents(),
+        errors: errors.contents(),
+        ok,
+    }
+}